@@ -0,0 +1,57 @@
+use std::fmt;
+
+use crate::index::IndexKey;
+use crate::schema::ValidationErrors;
+
+/// Errors returned by the write path of a [`Collection`](super::Collection).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CollectionError {
+    /// the document does not match the collection schema.
+    Invalid {
+        id: String,
+        errors: ValidationErrors,
+    },
+    /// `update` was called for a document that does not exist.
+    NotFound { id: String },
+    /// `create` was called for a document that already exists.
+    Exists { id: String },
+    /// documents already stored do not match a newly attached schema.
+    ExistingInvalid(Vec<(String, ValidationErrors)>),
+    /// writing `id` would give it the same key as `existing` in a unique index.
+    UniqueViolation {
+        id: String,
+        index: String,
+        key: IndexKey,
+        existing: String,
+    },
+}
+
+impl fmt::Display for CollectionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CollectionError::Invalid { id, errors } => {
+                write!(f, "document `{id}` does not match the schema: {errors}")
+            }
+            CollectionError::NotFound { id } => write!(f, "document `{id}` does not exist"),
+            CollectionError::Exists { id } => write!(f, "document `{id}` already exists"),
+            CollectionError::UniqueViolation {
+                id,
+                index,
+                key,
+                existing,
+            } => write!(
+                f,
+                "document `{id}` has key {key} in unique index `{index}`, which `{existing}` already has"
+            ),
+            CollectionError::ExistingInvalid(docs) => {
+                write!(f, "{} stored documents do not match the schema", docs.len())?;
+                if let Some((id, errors)) = docs.first() {
+                    write!(f, ", first is `{id}`: {errors}")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for CollectionError {}
@@ -0,0 +1,540 @@
+//! Named collections of JSON documents built on [`RwMap`].
+//!
+//! A [`Collection`] is the single writer of a `RwMap<String, serde_json::Value>`. Unlike a bare
+//! [`WriteHandle`], it checks every write against the collection's rules before the write is
+//! appended to the oplog, so readers never observe a document that breaks them.
+mod error;
+mod unique;
+
+use std::ops::Deref;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock, Weak};
+
+use ahash::RandomState;
+use hashbrown::{HashMap, HashSet};
+use serde_json::Value;
+
+use crate::index::IndexDef;
+use crate::rwmap::{ReadHandle, RwMap, WriteHandle};
+use crate::schema::Schema;
+
+pub use error::CollectionError;
+use unique::UniqueKeys;
+
+/// The read handle type of a [`Collection`].
+pub type Reader = ReadHandle<String, Value, (), RandomState>;
+
+/// What to do with documents that are already stored when a schema is attached.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExistingDocuments {
+    /// check every stored document and refuse the schema if any of them fails.
+    Validate,
+    /// only check documents written from now on.
+    Skip,
+}
+
+/// The indexes and schema of a collection as seen by query planning, shared with readers.
+#[derive(Debug, Default)]
+pub(crate) struct Catalog {
+    /// changes whenever published indexes or the schema change.
+    version: AtomicU64,
+    schema: RwLock<Option<Schema>>,
+    /// the ids gathered by each [`Changes`] still alive.
+    watchers: Mutex<Vec<Weak<Mutex<HashSet<String>>>>>,
+}
+
+impl Catalog {
+    pub(crate) fn version(&self) -> u64 {
+        self.version.load(Ordering::SeqCst)
+    }
+
+    /// Call `f` with the current schema.
+    pub(crate) fn with_schema<T>(&self, f: impl FnOnce(Option<&Schema>) -> T) -> T {
+        f(self.schema.read().unwrap().as_ref())
+    }
+
+    fn changed(&self) {
+        self.version.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// Start gathering the ids written by each publish.
+    pub(crate) fn watch(&self) -> Changes {
+        let ids = Arc::default();
+        self.watchers.lock().unwrap().push(Arc::downgrade(&ids));
+        Changes(ids)
+    }
+
+    /// Hand the ids written by a publish to every watcher.
+    fn published<'a>(&self, ids: impl Iterator<Item = &'a String> + Clone) {
+        self.watchers.lock().unwrap().retain(|watcher| {
+            let Some(watcher) = watcher.upgrade() else {
+                return false;
+            };
+            watcher.lock().unwrap().extend(ids.clone().cloned());
+            true
+        });
+    }
+}
+
+/// The ids of the documents a collection's publishes wrote to, inserted or removed, gathered
+/// until they are taken. See [`Collection::watch`].
+#[derive(Debug)]
+pub struct Changes(Arc<Mutex<HashSet<String>>>);
+
+impl Changes {
+    /// The ids written since the last call, or since watching started, in no particular
+    /// order.
+    pub fn take(&self) -> Vec<String> {
+        std::mem::take(&mut *self.0.lock().unwrap())
+            .into_iter()
+            .collect()
+    }
+}
+
+/// A named, validated set of JSON documents keyed by id.
+pub struct Collection {
+    name: String,
+    handle: WriteHandle<String, Value, (), RandomState>,
+    reader: Reader,
+    schema: Option<Schema>,
+    unique: Vec<UniqueKeys>,
+    /// ids written since the last publish, mapped to whether the id exists afterwards.
+    pending: HashMap<String, bool>,
+    /// odd while a publish is in progress, shared by the collections of a
+    /// [`Database`](crate::database::Database).
+    generation: Arc<AtomicU64>,
+    catalog: Arc<Catalog>,
+    /// indexes were created or dropped since the last publish.
+    indexes_changed: bool,
+}
+
+impl Collection {
+    /// Create an empty collection and a handle to read from it.
+    pub fn new(name: impl Into<String>) -> (Collection, Reader) {
+        Collection::create_empty(name.into(), false)
+    }
+
+    /// Create an empty collection that also keeps its ids in order, so that pages and streams
+    /// sorted by id alone read from where the previous one stopped, at the cost of maintaining
+    /// the order on every write.
+    pub fn with_key_order(name: impl Into<String>) -> (Collection, Reader) {
+        Collection::create_empty(name.into(), true)
+    }
+
+    fn create_empty(name: String, key_order: bool) -> (Collection, Reader) {
+        let (mut handle, reader) = RwMap::default::<String, Value>();
+        if key_order {
+            handle.order_keys();
+        }
+        let collection = Collection {
+            name,
+            handle,
+            reader: reader.clone(),
+            schema: None,
+            unique: Vec::new(),
+            pending: HashMap::new(),
+            generation: Arc::default(),
+            catalog: Arc::default(),
+            indexes_changed: false,
+        };
+        (collection, reader)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns a new handle to read from the collection.
+    pub fn reader(&self) -> Reader {
+        self.reader.clone()
+    }
+
+    /// Gather the ids written by the publishes from now on, which readers can take to only
+    /// look at the documents that changed.
+    pub fn watch(&self) -> Changes {
+        self.catalog.watch()
+    }
+
+    pub fn schema(&self) -> Option<&Schema> {
+        self.schema.as_ref()
+    }
+
+    /// Attach a schema that every following write has to match.
+    ///
+    /// With [`ExistingDocuments::Validate`] pending writes are published first and every stored
+    /// document is checked. If any of them fails the schema is not attached and the failures are
+    /// returned.
+    pub fn set_schema(
+        &mut self,
+        schema: Schema,
+        existing: ExistingDocuments,
+    ) -> Result<&mut Self, CollectionError> {
+        if existing == ExistingDocuments::Validate {
+            self.publish();
+            if let Some(map) = self.reader.enter() {
+                let failures: Vec<_> = map
+                    .iter()
+                    .filter_map(|(id, doc)| {
+                        let errors = schema.validate(doc.as_ref()).err()?;
+                        Some((id.clone(), errors))
+                    })
+                    .collect();
+                if !failures.is_empty() {
+                    return Err(CollectionError::ExistingInvalid(failures));
+                }
+            }
+        }
+        *self.catalog.schema.write().unwrap() = Some(schema.clone());
+        self.catalog.changed();
+        self.schema = Some(schema);
+        Ok(self)
+    }
+
+    /// Detach the schema, returning it.
+    pub fn clear_schema(&mut self) -> Option<Schema> {
+        *self.catalog.schema.write().unwrap() = None;
+        self.catalog.changed();
+        self.schema.take()
+    }
+
+    /// Returns true if `id` exists once pending writes are published.
+    pub fn exists(&self, id: &str) -> bool {
+        match self.pending.get(id) {
+            Some(exists) => *exists,
+            None => self.reader.contains_key(id),
+        }
+    }
+
+    fn check(&self, id: &str, doc: &Value) -> Result<(), CollectionError> {
+        if let Some(schema) = &self.schema {
+            schema
+                .validate(doc)
+                .map_err(|errors| CollectionError::Invalid {
+                    id: id.to_owned(),
+                    errors,
+                })?;
+        }
+        Ok(())
+    }
+
+    /// Insert or replace the document stored under `id`.
+    ///
+    /// Fails if the document does not match the schema, or conflicts with another document in a
+    /// unique index, in which case nothing is written. The write will only be visible to readers
+    /// after the next call to [`publish`](Self::publish).
+    pub fn insert(&mut self, id: String, doc: Value) -> Result<&mut Self, CollectionError> {
+        self.check(&id, &doc)?;
+        let keys = self
+            .unique
+            .iter()
+            .map(|unique| unique.check(&id, &doc))
+            .collect::<Result<Vec<_>, _>>()?;
+        for (unique, key) in self.unique.iter_mut().zip(keys) {
+            unique.set(&id, key);
+        }
+        self.pending.insert(id.clone(), true);
+        self.handle.insert(id, doc);
+        Ok(self)
+    }
+
+    /// Replace the document stored under `id`, failing if there is none.
+    pub fn update(&mut self, id: String, doc: Value) -> Result<&mut Self, CollectionError> {
+        if !self.exists(&id) {
+            return Err(CollectionError::NotFound { id });
+        }
+        self.insert(id, doc)
+    }
+
+    /// Store a new document under `id`, failing if there already is one.
+    pub fn create(&mut self, id: String, doc: Value) -> Result<&mut Self, CollectionError> {
+        if self.exists(&id) {
+            return Err(CollectionError::Exists { id });
+        }
+        self.insert(id, doc)
+    }
+
+    /// Remove the document stored under `id`, if any.
+    pub fn remove(&mut self, id: String) -> &mut Self {
+        for unique in &mut self.unique {
+            unique.remove(&id);
+        }
+        self.pending.insert(id.clone(), false);
+        self.handle.remove(id);
+        self
+    }
+
+    /// Put `doc` back under `id`, or remove it if `None`, without checking the schema or the
+    /// unique indexes. Undoes checked writes in reverse order, which the schema attached since
+    /// may no longer accept.
+    pub(crate) fn restore(&mut self, id: String, doc: Option<Value>) {
+        let Some(doc) = doc else {
+            self.remove(id);
+            return;
+        };
+        for unique in &mut self.unique {
+            let key = unique.key_of(&doc);
+            unique.set(&id, key);
+        }
+        self.pending.insert(id.clone(), true);
+        self.handle.insert(id, doc);
+    }
+
+    /// Create a secondary index, see [`WriteHandle::create_index`].
+    ///
+    /// For a [unique](IndexDef::unique) index pending writes are published first, and the index
+    /// is refused if two stored documents already share a key.
+    pub fn create_index(&mut self, def: IndexDef) -> Result<&mut Self, CollectionError> {
+        let unique = if def.unique {
+            self.publish();
+            let map = self.reader.enter();
+            let docs = map.iter().flat_map(|map| map.iter());
+            Some(UniqueKeys::build(
+                def.clone(),
+                docs.map(|(id, doc)| (id, doc.as_ref())),
+            )?)
+        } else {
+            None
+        };
+        self.unique.retain(|u| u.name() != def.name);
+        self.unique.extend(unique);
+        self.handle.create_index(def);
+        self.indexes_changed = true;
+        Ok(self)
+    }
+
+    /// Remove the secondary index called `name`.
+    pub fn drop_index(&mut self, name: &str) -> &mut Self {
+        self.unique.retain(|u| u.name() != name);
+        self.handle.drop_index(name);
+        self.indexes_changed = true;
+        self
+    }
+
+    /// Publish all writes since the last call to `publish` to make them visible to readers.
+    pub fn publish(&mut self) -> &mut Self {
+        let generation = self.generation.clone();
+        generation.fetch_add(1, Ordering::SeqCst);
+        self.publish_unmarked();
+        generation.fetch_add(1, Ordering::SeqCst);
+        self
+    }
+
+    /// Publish without marking the generation, for callers that mark it themselves.
+    pub(crate) fn publish_unmarked(&mut self) {
+        self.handle.publish();
+        if !self.pending.is_empty() {
+            self.catalog.published(self.pending.keys());
+        }
+        self.pending.clear();
+        if std::mem::take(&mut self.indexes_changed) {
+            self.catalog.changed();
+        }
+    }
+
+    pub(crate) fn catalog(&self) -> &Arc<Catalog> {
+        &self.catalog
+    }
+
+    pub(crate) fn generation(&self) -> &Arc<AtomicU64> {
+        &self.generation
+    }
+
+    pub(crate) fn set_generation(&mut self, generation: Arc<AtomicU64>) {
+        self.generation = generation;
+    }
+
+    /// Returns true if there are writes that have not yet been exposed to readers.
+    pub fn has_pending(&self) -> bool {
+        self.handle.has_pending()
+    }
+}
+
+// allow using the collection for reads
+impl Deref for Collection {
+    type Target = Reader;
+    fn deref(&self) -> &Self::Target {
+        &self.reader
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::*;
+
+    fn schema() -> Schema {
+        Schema::compile(&json!({
+            "type": "object",
+            "required": ["email"],
+            "properties": {"email": {"type": "string"}, "age": {"minimum": 0}}
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn rejects_invalid_writes() {
+        let (mut users, r) = Collection::new("users");
+        users.set_schema(schema(), ExistingDocuments::Skip).unwrap();
+        users.insert("1".into(), json!({"email": "a@b.c"})).unwrap();
+
+        let err = users.insert("2".into(), json!({"age": -1})).err().unwrap();
+        match err {
+            CollectionError::Invalid { id, errors } => {
+                assert_eq!(id, "2");
+                assert_eq!(errors.len(), 2);
+            }
+            other => panic!("unexpected error {other}"),
+        }
+        users.publish();
+        assert_eq!(r.len(), 1);
+        assert!(!users.has_pending());
+    }
+
+    #[test]
+    fn update_requires_existing_document() {
+        let (mut users, _r) = Collection::new("users");
+        assert_eq!(
+            users.update("1".into(), json!({})).err(),
+            Some(CollectionError::NotFound { id: "1".into() })
+        );
+        users.insert("1".into(), json!({"n": 1})).unwrap();
+        // pending inserts count as existing
+        users.update("1".into(), json!({"n": 2})).unwrap();
+        users.publish();
+        assert_eq!(users.get("1").unwrap().as_ref(), &json!({"n": 2}));
+        users.remove("1".into());
+        assert!(users.update("1".into(), json!({})).is_err());
+        users.create("1".into(), json!({"n": 3})).unwrap();
+        assert_eq!(
+            users.create("1".into(), json!({})).err(),
+            Some(CollectionError::Exists { id: "1".into() })
+        );
+    }
+
+    #[test]
+    fn validates_existing_documents() {
+        let (mut users, _r) = Collection::new("users");
+        users.insert("1".into(), json!({"email": 3})).unwrap();
+        users.insert("2".into(), json!({"email": "x"})).unwrap();
+
+        let err = users
+            .set_schema(schema(), ExistingDocuments::Validate)
+            .err()
+            .unwrap();
+        match err {
+            CollectionError::ExistingInvalid(docs) => {
+                assert_eq!(docs.len(), 1);
+                assert_eq!(docs[0].0, "1");
+                assert_eq!(docs[0].1.errors()[0].path.to_string(), "email");
+            }
+            other => panic!("unexpected error {other}"),
+        }
+        assert!(users.schema().is_none());
+
+        users.remove("1".into());
+        users
+            .set_schema(schema(), ExistingDocuments::Validate)
+            .unwrap();
+        assert!(users.schema().is_some());
+    }
+
+    #[test]
+    fn unique_index_rejects_conflicts() {
+        let (mut users, r) = Collection::new("users");
+        let email = crate::path::Path::parse("email").unwrap();
+        users
+            .create_index(IndexDef::hash("email", email).unique())
+            .unwrap();
+        users.insert("1".into(), json!({"email": "a@x"})).unwrap();
+
+        let err = users.insert("2".into(), json!({"email": "a@x"})).err();
+        assert_eq!(
+            err,
+            Some(CollectionError::UniqueViolation {
+                id: "2".into(),
+                index: "email".into(),
+                key: "a@x".into(),
+                existing: "1".into(),
+            })
+        );
+        // rewriting the same document keeps its own key
+        users
+            .update("1".into(), json!({"email": "a@x", "n": 1}))
+            .unwrap();
+        // a key freed by a pending write can be taken
+        users.insert("1".into(), json!({"email": "b@x"})).unwrap();
+        users.insert("2".into(), json!({"email": "a@x"})).unwrap();
+        users.remove("2".into());
+        users.insert("3".into(), json!({"email": "a@x"})).unwrap();
+        // nulls never conflict
+        users.insert("4".into(), json!({"email": null})).unwrap();
+        users.insert("5".into(), json!({"email": null})).unwrap();
+        users.publish();
+
+        let map = r.enter().unwrap();
+        let index = map.index("email").unwrap();
+        assert_eq!(index.count(&"a@x".into()), 1);
+        assert_eq!(index.count(&crate::index::IndexKey::Null), 2);
+    }
+
+    #[test]
+    fn composite_unique_index() {
+        let (mut users, _r) = Collection::new("users");
+        let path = |p| crate::path::Path::parse(p).unwrap();
+        users
+            .insert("1".into(), json!({"tenant": 1, "email": "a"}))
+            .unwrap();
+        users
+            .insert("2".into(), json!({"tenant": 2, "email": "a"}))
+            .unwrap();
+        users
+            .insert("3".into(), json!({"tenant": 2, "email": "a"}))
+            .unwrap();
+
+        let def = IndexDef::hash("tenant_email", path("tenant"))
+            .and(path("email"))
+            .unique();
+        let err = users.create_index(def.clone()).err().unwrap();
+        assert!(matches!(err, CollectionError::UniqueViolation { .. }));
+
+        users.remove("3".into());
+        users.create_index(def).unwrap();
+        let err = users
+            .insert("4".into(), json!({"tenant": 1, "email": "a"}))
+            .err()
+            .unwrap();
+        match err {
+            CollectionError::UniqueViolation { key, existing, .. } => {
+                assert_eq!(key.to_string(), r#"[1,"a"]"#);
+                assert_eq!(existing, "1");
+            }
+            other => panic!("unexpected error {other}"),
+        }
+        users
+            .insert("4".into(), json!({"tenant": 3, "email": "a"}))
+            .unwrap();
+    }
+
+    #[test]
+    fn partial_unique_expression_index() {
+        use crate::index::{KeyExpr, Predicate};
+
+        let (mut users, _r) = Collection::new("users");
+        let def = IndexDef::hash("email_ci", KeyExpr::parse("lower(email)").unwrap())
+            .unique()
+            .filter(Predicate::Not(Box::new(Predicate::Exists(
+                KeyExpr::parse("deleted").unwrap(),
+            ))));
+        users.create_index(def).unwrap();
+        users.insert("1".into(), json!({"email": "Ann@x"})).unwrap();
+        assert!(users.insert("2".into(), json!({"email": "ann@X"})).is_err());
+        users
+            .insert("2".into(), json!({"email": "ann@X", "deleted": true}))
+            .unwrap();
+        users
+            .update("1".into(), json!({"email": "Ann@x", "deleted": true}))
+            .unwrap();
+        users.insert("3".into(), json!({"email": "ANN@x"})).unwrap();
+    }
+}
@@ -0,0 +1,74 @@
+use hashbrown::HashMap;
+use serde_json::Value;
+
+use super::CollectionError;
+use crate::index::{IndexDef, IndexKey};
+
+/// Writer side copy of a unique index.
+///
+/// The index inside the map only reflects published writes, so the collection keeps its own
+/// key to id mapping that includes pending writes and checks new documents against it.
+pub(super) struct UniqueKeys {
+    def: IndexDef,
+    owners: HashMap<IndexKey, String>,
+    keys: HashMap<String, IndexKey>,
+}
+
+impl UniqueKeys {
+    /// Build from the documents currently stored, failing on the first duplicate.
+    pub(super) fn build<'a>(
+        def: IndexDef,
+        docs: impl IntoIterator<Item = (&'a String, &'a Value)>,
+    ) -> Result<Self, CollectionError> {
+        let mut unique = UniqueKeys {
+            def,
+            owners: HashMap::new(),
+            keys: HashMap::new(),
+        };
+        for (id, doc) in docs {
+            let key = unique.check(id, doc)?;
+            unique.set(id, key);
+        }
+        Ok(unique)
+    }
+
+    pub(super) fn name(&self) -> &str {
+        &self.def.name
+    }
+
+    /// Returns the key `doc` would take if written under `id`, or the conflicting document.
+    pub(super) fn check(&self, id: &str, doc: &Value) -> Result<Option<IndexKey>, CollectionError> {
+        let Some(key) = self.def.unique_key_of(doc) else {
+            return Ok(None);
+        };
+        match self.owners.get(&key) {
+            Some(owner) if owner != id => Err(CollectionError::UniqueViolation {
+                id: id.to_owned(),
+                index: self.def.name.clone(),
+                key,
+                existing: owner.clone(),
+            }),
+            _ => Ok(Some(key)),
+        }
+    }
+
+    /// The key of `doc`, without checking it for conflicts.
+    pub(super) fn key_of(&self, doc: &Value) -> Option<IndexKey> {
+        self.def.unique_key_of(doc)
+    }
+
+    /// Record that `id` now holds `key`.
+    pub(super) fn set(&mut self, id: &str, key: Option<IndexKey>) {
+        self.remove(id);
+        if let Some(key) = key {
+            self.owners.insert(key.clone(), id.to_owned());
+            self.keys.insert(id.to_owned(), key);
+        }
+    }
+
+    pub(super) fn remove(&mut self, id: &str) {
+        if let Some(old) = self.keys.remove(id) {
+            self.owners.remove(&old);
+        }
+    }
+}
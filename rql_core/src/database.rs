@@ -0,0 +1,769 @@
+//! A set of [collections](Collection) that are published and read together.
+//!
+//! Every collection is its own [`RwMap`](crate::rwmap::RwMap), so entering two of them one after
+//! the other may see one before and the other after a publish. A [`Database`] counts its
+//! publishes, and a [`Snapshot`] enters all of its collections between two publishes, so a query
+//! reading several collections sees all of them at the same epoch.
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use ahash::RandomState;
+use hashbrown::HashMap;
+use serde_json::Value;
+
+use crate::collection::{Catalog, Collection, Reader};
+use crate::query::{
+    self, Affected, Budget, Cursor, Limits, LiveQuery, Mode, Mutation, OwnedRow, Page, Params,
+    Plan, PlanCache, Prepared, Query, QueryError, Row, RowStream,
+};
+use crate::rwmap::MapReadRef;
+
+/// The documents of one collection inside a [`Snapshot`].
+pub type CollectionRef<'r> = MapReadRef<'r, String, Value, (), RandomState>;
+
+/// Named collections sharing one publish counter.
+#[derive(Default)]
+pub struct Database {
+    collections: HashMap<String, Collection>,
+    /// twice the number of publishes, plus one while a publish is in progress.
+    generation: Arc<AtomicU64>,
+    plans: PlanCache,
+}
+
+impl Database {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the collection called `name`, creating it empty if there is none.
+    ///
+    /// A new collection is published right away, and becomes visible to readers made after
+    /// this call.
+    pub fn create_collection(&mut self, name: &str) -> &mut Collection {
+        if !self.collections.contains_key(name) {
+            let (mut collection, _) = Collection::new(name);
+            collection.set_generation(self.generation.clone());
+            collection.publish();
+            self.collections.insert(name.to_owned(), collection);
+        }
+        self.collections.get_mut(name).unwrap()
+    }
+
+    pub fn collection(&self, name: &str) -> Option<&Collection> {
+        self.collections.get(name)
+    }
+
+    /// Returns the collection called `name` to write to.
+    ///
+    /// Publishing the collection by itself is allowed and counts as a publish of the
+    /// database, but only [`Database::publish`] makes writes to several collections visible at
+    /// once.
+    pub fn collection_mut(&mut self, name: &str) -> Option<&mut Collection> {
+        self.collections.get_mut(name)
+    }
+
+    /// Iterate over the names of all collections.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.collections.keys().map(String::as_str)
+    }
+
+    /// Publish the pending writes of every collection as one step, so no [`Snapshot`] sees
+    /// some of them but not the others.
+    pub fn publish(&mut self) -> &mut Self {
+        self.generation.fetch_add(1, Ordering::SeqCst);
+        for collection in self.collections.values_mut() {
+            collection.publish_unmarked();
+        }
+        self.generation.fetch_add(1, Ordering::SeqCst);
+        self
+    }
+
+    /// Apply a write statement to the collection it names, see [`query::mutate`].
+    ///
+    /// The statement is published as one step, together with any pending writes of that
+    /// collection.
+    pub fn mutate(&mut self, mutation: &Mutation) -> Result<Affected, QueryError> {
+        let name = mutation.collection();
+        let collection = self
+            .collections
+            .get_mut(name)
+            .ok_or_else(|| QueryError::UnknownCollection(name.to_owned()))?;
+        query::mutate(mutation, collection)
+    }
+
+    /// The number of publishes so far, by the database or any of its collections.
+    pub fn epoch(&self) -> u64 {
+        self.generation.load(Ordering::SeqCst) / 2
+    }
+
+    /// The cache of prepared queries shared by all readers.
+    pub fn plan_cache(&self) -> &PlanCache {
+        &self.plans
+    }
+
+    /// Returns a handle to read from the collections that exist now.
+    pub fn reader(&self) -> DatabaseReader {
+        DatabaseReader {
+            generation: self.generation.clone(),
+            readers: self
+                .collections
+                .iter()
+                .map(|(name, collection)| (name.clone(), collection.reader()))
+                .collect(),
+            catalogs: self
+                .collections
+                .iter()
+                .map(|(name, collection)| (name.clone(), collection.catalog().clone()))
+                .collect(),
+            plans: self.plans.clone(),
+        }
+    }
+}
+
+/// A handle to read from the collections of a [`Database`], which may be sent to other
+/// threads.
+#[derive(Clone)]
+pub struct DatabaseReader {
+    generation: Arc<AtomicU64>,
+    readers: Vec<(String, Reader)>,
+    catalogs: HashMap<String, Arc<Catalog>>,
+    plans: PlanCache,
+}
+
+impl DatabaseReader {
+    /// Enter every collection at the same epoch.
+    ///
+    /// If a publish is in progress this waits for it to finish. As with
+    /// [`ReadHandle::enter`](crate::rwmap::ReadHandle::enter), publishes block while the
+    /// snapshot lives, so a thread must not hold a snapshot while it waits for another one.
+    pub fn enter(&self) -> Snapshot<'_> {
+        loop {
+            let before = self.generation.load(Ordering::SeqCst);
+            if before & 1 == 0 {
+                let maps = self
+                    .readers
+                    .iter()
+                    .filter_map(|(name, reader)| Some((name.as_str(), reader.enter()?)))
+                    .collect();
+                if self.generation.load(Ordering::SeqCst) == before {
+                    return Snapshot {
+                        epoch: before / 2,
+                        maps,
+                    };
+                }
+            }
+            std::thread::yield_now();
+        }
+    }
+
+    /// Stream the rows of `query`, which may read any collection, entering a new [`Snapshot`]
+    /// for each chunk, see [`RowStream`].
+    pub fn stream(&self, query: &Query, mode: Mode) -> RowStream<'_> {
+        RowStream::with_fetch(
+            query,
+            mode,
+            Box::new(move |query, cursor| Ok(self.enter().execute_page(query, cursor)?.into())),
+        )
+    }
+
+    /// Start keeping the result of `query`, which may read any collection, up to date with
+    /// the publishes of the database, returning the live query and the current result. See
+    /// [`LiveQuery`].
+    pub fn live(&self, query: &Query) -> Result<(LiveQuery<'_>, Vec<OwnedRow>), QueryError> {
+        LiveQuery::with_run(
+            query,
+            Box::new(move |query| Ok(query::owned_rows(self.enter().execute_page(query, None)?))),
+            |name| Some(self.catalogs.get(name)?.watch()),
+        )
+    }
+
+    /// The [prepared](Prepared) form of `source`, from the database's [`PlanCache`] if another
+    /// reader prepared it since the indexes and schemas of the collections it reads last
+    /// changed.
+    ///
+    /// Parameters compared with a path get the types the collection's schema declares for it.
+    pub fn prepare(&self, source: &str) -> Result<Arc<Prepared>, QueryError> {
+        let version = |name: &str| Some(self.catalogs.get(name)?.version());
+        self.plans.get_or_prepare(source, &version, |query| {
+            Prepared::with_schemas(query, &|collection, path| {
+                self.catalogs
+                    .get(collection)?
+                    .with_schema(|schema| Some(schema?.types_at(path)?.to_vec()))
+            })
+        })
+    }
+}
+
+/// Every collection of a [`Database`] as of one epoch.
+pub struct Snapshot<'r> {
+    epoch: u64,
+    maps: HashMap<&'r str, CollectionRef<'r>>,
+}
+
+impl<'r> Snapshot<'r> {
+    /// The number of publishes the snapshot includes.
+    pub fn epoch(&self) -> u64 {
+        self.epoch
+    }
+
+    pub fn collection(&self, name: &str) -> Option<&CollectionRef<'r>> {
+        self.maps.get(name)
+    }
+
+    /// Run `query`, which may join any collection of the snapshot, see
+    /// [`query::execute`].
+    pub fn execute(&self, query: &Query) -> Result<Vec<Row<'_>>, QueryError> {
+        Ok(self.execute_page(query, None)?.rows)
+    }
+
+    /// Run `query` starting after `cursor`, see [`query::execute_page`].
+    pub fn execute_page(
+        &self,
+        query: &Query,
+        cursor: Option<&Cursor>,
+    ) -> Result<Page<'_>, QueryError> {
+        self.execute_page_limited(query, cursor, &Limits::new())
+    }
+
+    /// [`execute_page`](Self::execute_page) within `limits`, see
+    /// [`query::execute_page_limited`].
+    ///
+    /// The snapshot blocks publishes to its collections until it is dropped, so a caller that
+    /// gets [`QueryError::Aborted`] should drop it rather than run more queries on it.
+    pub fn execute_page_limited(
+        &self,
+        query: &Query,
+        cursor: Option<&Cursor>,
+        limits: &Limits,
+    ) -> Result<Page<'_>, QueryError> {
+        let budget = Budget::new(limits);
+        query::run(query, &|name| self.collection(name), cursor, &budget)
+    }
+
+    /// Run a prepared query with `params`, see [`Prepared::execute`].
+    pub fn execute_prepared(
+        &self,
+        prepared: &Prepared,
+        params: &Params,
+    ) -> Result<Vec<Row<'_>>, QueryError> {
+        Ok(self.execute_prepared_page(prepared, params, None)?.rows)
+    }
+
+    /// Run a prepared query with `params` starting after `cursor`, see
+    /// [`Prepared::execute_page`].
+    pub fn execute_prepared_page(
+        &self,
+        prepared: &Prepared,
+        params: &Params,
+        cursor: Option<&Cursor>,
+    ) -> Result<Page<'_>, QueryError> {
+        self.execute_prepared_page_limited(prepared, params, cursor, &Limits::new())
+    }
+
+    /// [`execute_prepared_page`](Self::execute_prepared_page) within `limits`, see
+    /// [`execute_page_limited`](Self::execute_page_limited).
+    pub fn execute_prepared_page_limited(
+        &self,
+        prepared: &Prepared,
+        params: &Params,
+        cursor: Option<&Cursor>,
+        limits: &Limits,
+    ) -> Result<Page<'_>, QueryError> {
+        let budget = Budget::new(limits);
+        prepared.run(&|name| self.collection(name), params, cursor, &budget)
+    }
+
+    /// Describe how `query` runs, see [`query::explain`].
+    pub fn explain(&self, query: &Query, analyze: bool) -> Result<Plan, QueryError> {
+        let budget = Budget::unlimited();
+        query::explain_in(query, &|name| self.collection(name), analyze, &budget)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::AtomicBool;
+    use std::thread;
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::collection::ExistingDocuments;
+    use crate::index::IndexDef;
+    use crate::path::Path;
+    use crate::query::{parse, Abort, CancelToken};
+    use crate::schema::{JsonType, Schema};
+
+    fn shop() -> Database {
+        let mut db = Database::new();
+        let customers = db.create_collection("customers");
+        for (id, name, email) in [
+            ("c1", "Ada", "ada@x"),
+            ("c2", "Bob", "bob@x"),
+            ("c3", "Cy", "cy@x"),
+        ] {
+            customers
+                .insert(id.into(), json!({ "name": name, "email": email }))
+                .unwrap();
+        }
+        let orders = db.create_collection("orders");
+        for (id, customer, total) in [
+            ("o1", "c1", 10),
+            ("o2", "c2", 5),
+            ("o3", "c1", 7),
+            ("o4", "c9", 1),
+        ] {
+            orders
+                .insert(id.into(), json!({ "customer": customer, "total": total }))
+                .unwrap();
+        }
+        let notes = db.create_collection("notes");
+        notes
+            .create_index(IndexDef::hash("by_email", Path::parse("email").unwrap()))
+            .unwrap();
+        notes
+            .insert("n1".into(), json!({"email": "ada@x", "text": "vip"}))
+            .unwrap();
+        db.publish();
+        db
+    }
+
+    #[test]
+    fn joins_collections() {
+        let db = shop();
+        let reader = db.reader();
+        let snapshot = reader.enter();
+        let run = |source: &str| {
+            snapshot
+                .execute(&parse(source).unwrap())
+                .unwrap()
+                .into_iter()
+                .map(Row::into_value)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            run(
+                "from orders as o join customers as c on id(c) = o.customer \
+                 select id(o) as id, c.name, o.total order by id"
+            ),
+            [
+                json!({"id": "o1", "c": {"name": "Ada"}, "o": {"total": 10}}),
+                json!({"id": "o2", "c": {"name": "Bob"}, "o": {"total": 5}}),
+                json!({"id": "o3", "c": {"name": "Ada"}, "o": {"total": 7}}),
+            ]
+        );
+        assert_eq!(
+            run(
+                "from customers as c left join orders as o on o.customer = id(c) \
+                 left join notes as n on n.email = c.email \
+                 group by c.name select c.name, count(o) as orders, sum(o.total) as total, \
+                 max(n.text) as note order by c.name"
+            ),
+            [
+                json!({"c": {"name": "Ada"}, "orders": 2, "total": 17, "note": "vip"}),
+                json!({"c": {"name": "Bob"}, "orders": 1, "total": 5, "note": null}),
+                json!({"c": {"name": "Cy"}, "orders": 0, "total": null, "note": null}),
+            ]
+        );
+        assert_eq!(
+            run(
+                "from orders join customers on customers.name = 'Bob' and orders.total > 6 \
+                 order by orders.total desc"
+            ),
+            [
+                json!({"orders": {"customer": "c1", "total": 10}, "customers": {"name": "Bob", "email": "bob@x"}}),
+                json!({"orders": {"customer": "c1", "total": 7}, "customers": {"name": "Bob", "email": "bob@x"}}),
+            ]
+        );
+        assert_eq!(
+            snapshot.execute(&parse("from orders join nope on id(nope) = orders.x").unwrap()),
+            Err(QueryError::UnknownCollection("nope".into()))
+        );
+    }
+
+    #[test]
+    fn unnests_into_joins() {
+        let mut db = shop();
+        let baskets = db.create_collection("baskets");
+        for (id, items) in [
+            ("b1", json!([{"c": "c1", "n": 2}, {"c": "c2", "n": 1}])),
+            ("b2", json!([{"c": "c1", "n": 3}, {"c": "c9", "n": 4}])),
+            ("b3", json!([])),
+        ] {
+            baskets
+                .insert(id.into(), json!({ "items": items }))
+                .unwrap();
+        }
+        db.publish();
+        let reader = db.reader();
+        let snapshot = reader.enter();
+        let run = |source: &str| {
+            snapshot
+                .execute(&parse(source).unwrap())
+                .unwrap()
+                .into_iter()
+                .map(Row::into_value)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            run(
+                "from baskets as b unnest b.items as i join customers as c on id(c) = i.c \
+                 group by c.name select c.name, sum(i.n) as n, count(*) as lines order by c.name"
+            ),
+            [
+                json!({"c": {"name": "Ada"}, "n": 5, "lines": 2}),
+                json!({"c": {"name": "Bob"}, "n": 1, "lines": 1}),
+            ]
+        );
+        assert_eq!(
+            run(
+                "from baskets as b unnest b.items as i left join customers as c on id(c) = i.c \
+                 where i.n > 1 select id(b) as b, id(i) as line, c.name order by b, line"
+            ),
+            [
+                json!({"b": "b1", "line": 0, "c": {"name": "Ada"}}),
+                json!({"b": "b2", "line": 0, "c": {"name": "Ada"}}),
+                json!({"b": "b2", "line": 1}),
+            ]
+        );
+        assert!(matches!(
+            snapshot.execute(
+                &parse("from baskets as b unnest items as i join customers on true").unwrap()
+            ),
+            Err(QueryError::Invalid(_))
+        ));
+        assert!(matches!(
+            snapshot.execute(
+                &parse("from baskets as b unnest b.items as b join customers on true").unwrap()
+            ),
+            Err(QueryError::Invalid(_))
+        ));
+    }
+
+    #[test]
+    fn explains_joins() {
+        let db = shop();
+        let reader = db.reader();
+        let snapshot = reader.enter();
+        let query = parse(
+            "from orders as o join customers as c on id(c) = o.customer \
+             left join notes as n on n.email = c.email where id(o) = 'o1' select c.name, n.text",
+        )
+        .unwrap();
+        assert_eq!(
+            snapshot.explain(&query, false).unwrap().to_string(),
+            "select c.name, n.text (rows=1)\n\
+             -> filter id(o) = \"o1\" (rows=1)\n   \
+                -> left join notes as n on n.email = c.email using index by_email (rows=1)\n      \
+                   -> join customers as c on id(c) = o.customer by id (rows=1)\n         \
+                      -> get orders as o by id (\"o1\") (rows=1)"
+        );
+        let plan = snapshot.explain(&query, true).unwrap();
+        assert_eq!(plan.actual_rows, Some(1));
+        assert!(plan.elapsed.is_some());
+    }
+
+    #[test]
+    fn runs_subqueries_on_one_epoch() {
+        let mut db = shop();
+        let reader = db.reader();
+        let snapshot = reader.enter();
+        db.collection_mut("orders")
+            .unwrap()
+            .insert("o5".into(), json!({"customer": "c2", "total": 50}))
+            .unwrap();
+        db.publish();
+        let run = |source: &str| {
+            snapshot
+                .execute(&parse(source).unwrap())
+                .unwrap()
+                .into_iter()
+                .map(Row::into_value)
+                .collect::<Vec<_>>()
+        };
+
+        // users whose order count exceeds the average, without the order published since
+        assert_eq!(
+            run(
+                "with counts as (from orders group by customer select customer, count(*) as n) \
+                 from customers as c \
+                 where (from counts where customer = id(c) select n) > (from counts select avg(n)) \
+                 select name"
+            ),
+            [json!({"name": "Ada"})]
+        );
+        assert_eq!(
+            run(
+                "from customers as c left join notes as n on n.email = c.email \
+                 where id(c) not in (from orders select customer) select c.name"
+            ),
+            [json!({"c": {"name": "Cy"}})]
+        );
+
+        let prepared = reader
+            .prepare(
+                "from customers where id() in (from orders where total > $min select customer) \
+                 select name order by name",
+            )
+            .unwrap();
+        let names = |min: i64| {
+            snapshot
+                .execute_prepared(&prepared, &Params::new().set("min", min))
+                .unwrap()
+                .into_iter()
+                .map(Row::into_value)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(names(6), [json!({"name": "Ada"})]);
+        assert_eq!(names(0), [json!({"name": "Ada"}), json!({"name": "Bob"})]);
+    }
+
+    #[test]
+    fn mutations_publish_once() {
+        let mut db = shop();
+        let reader = db.reader();
+        let epoch = db.epoch();
+        let mutation: Mutation = "update orders set total = total * 2 where customer = 'c1'"
+            .parse()
+            .unwrap();
+        assert_eq!(db.mutate(&mutation).unwrap().updated, 2);
+        assert_eq!(db.epoch(), epoch + 1);
+        let snapshot = reader.enter();
+        let totals = snapshot
+            .execute(&parse("from orders where total > 10 select sum(total) as n").unwrap())
+            .unwrap();
+        assert_eq!(totals[0].value["n"], json!(34));
+        assert_eq!(
+            db.mutate(&"delete from nope".parse().unwrap()),
+            Err(QueryError::UnknownCollection("nope".into()))
+        );
+    }
+
+    #[test]
+    fn prepares_once_per_catalog() {
+        let mut db = shop();
+        db.collection_mut("orders")
+            .unwrap()
+            .set_schema(
+                Schema::compile(&json!({"properties": {"total": {"type": "integer"}}})).unwrap(),
+                ExistingDocuments::Validate,
+            )
+            .unwrap();
+        let reader = db.reader();
+        let source = "from orders as o join customers as c on id(c) = o.customer \
+                      where o.total >= $min select id(o) as id order by id";
+        let prepared = reader.prepare(source).unwrap();
+        assert_eq!(prepared.params()[0].types, Some(vec![JsonType::Number]));
+        assert!(Arc::ptr_eq(&prepared, &reader.prepare(source).unwrap()));
+        let ids = |prepared: &Prepared, min| {
+            reader
+                .enter()
+                .execute_prepared(prepared, &Params::new().set("min", min))
+                .unwrap()
+                .into_iter()
+                .map(Row::into_value)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            ids(&prepared, 7),
+            [json!({"id": "o1"}), json!({"id": "o3"})]
+        );
+        assert!(matches!(
+            reader
+                .enter()
+                .execute_prepared(&prepared, &Params::new().set("min", "7")),
+            Err(QueryError::ParamType { .. })
+        ));
+
+        // a new index is only seen by plans made after it is published
+        let orders = db.collection_mut("orders").unwrap();
+        orders
+            .create_index(IndexDef::ordered("by_total", Path::parse("total").unwrap()))
+            .unwrap();
+        assert!(Arc::ptr_eq(&prepared, &reader.prepare(source).unwrap()));
+        db.publish();
+        let replanned = reader.prepare(source).unwrap();
+        assert!(!Arc::ptr_eq(&prepared, &replanned));
+        assert_eq!(ids(&replanned, 10), [json!({"id": "o1"})]);
+
+        db.collection_mut("orders").unwrap().clear_schema();
+        let untyped = reader.prepare(source).unwrap();
+        // without a schema an ordered parameter only has to be a scalar
+        assert_eq!(
+            untyped.params()[0].types,
+            Some(vec![
+                JsonType::Null,
+                JsonType::Boolean,
+                JsonType::Number,
+                JsonType::String
+            ])
+        );
+        assert_eq!(db.plan_cache().len(), 1);
+    }
+
+    #[test]
+    fn replans_queries_over_collections_created_since() {
+        let mut db = shop();
+        let source = "from late where n >= $min";
+        let early = db.reader().prepare(source).unwrap();
+        db.create_collection("late")
+            .set_schema(
+                Schema::compile(&json!({"properties": {"n": {"type": "integer"}}})).unwrap(),
+                ExistingDocuments::Validate,
+            )
+            .unwrap();
+        let prepared = db.reader().prepare(source).unwrap();
+        assert!(!Arc::ptr_eq(&early, &prepared));
+        assert_eq!(prepared.params()[0].types, Some(vec![JsonType::Number]));
+    }
+
+    #[test]
+    fn streams_joins_between_publishes() {
+        let mut db = shop();
+        let reader = db.reader();
+        let query = parse(
+            "from orders as o join customers as c on id(c) = o.customer \
+             select id(o) as id, c.name order by id",
+        )
+        .unwrap();
+        let mut stream = reader.stream(&query, Mode::Chunks(1));
+        assert_eq!(
+            stream.next().unwrap().unwrap().value,
+            json!({"id": "o1", "c": {"name": "Ada"}})
+        );
+        db.collection_mut("customers")
+            .unwrap()
+            .insert("c2".into(), json!({"name": "Bo"}))
+            .unwrap();
+        db.publish();
+        let rest: Vec<_> = stream.map(|row| row.unwrap().value).collect();
+        assert_eq!(
+            rest,
+            [
+                json!({"id": "o2", "c": {"name": "Bo"}}),
+                json!({"id": "o3", "c": {"name": "Ada"}}),
+            ]
+        );
+        let all = reader.stream(&query, Mode::Snapshot).count();
+        assert_eq!(all, 3);
+    }
+
+    #[test]
+    fn diffs_live_joins_on_publish() {
+        let mut db = shop();
+        let reader = db.reader();
+        let query = parse(
+            "from orders as o join customers as c on id(c) = o.customer \
+             select id(o) as id, c.name order by id",
+        )
+        .unwrap();
+        let (mut live, rows) = reader.live(&query).unwrap();
+        assert_eq!(rows.len(), 3);
+
+        db.collection_mut("notes")
+            .unwrap()
+            .insert("n2".into(), json!({"email": "cy@x"}))
+            .unwrap();
+        db.publish();
+        assert!(live.refresh().unwrap().is_empty());
+
+        db.collection_mut("customers")
+            .unwrap()
+            .update("c2".into(), json!({"name": "Bo"}))
+            .unwrap();
+        db.collection_mut("orders").unwrap().remove("o3".into());
+        db.publish();
+        let diff = live.refresh().unwrap();
+        let values =
+            |rows: Vec<OwnedRow>| -> Vec<Value> { rows.into_iter().map(|row| row.value).collect() };
+        assert_eq!(
+            values(diff.added),
+            [json!({"id": "o2", "c": {"name": "Bo"}})]
+        );
+        assert_eq!(
+            values(diff.removed),
+            [
+                json!({"id": "o3", "c": {"name": "Ada"}}),
+                json!({"id": "o2", "c": {"name": "Bob"}}),
+            ]
+        );
+    }
+
+    #[test]
+    fn cancelled_queries_let_publishes_through() {
+        let mut db = Database::new();
+        let big = db.create_collection("big");
+        for i in 0..2000 {
+            big.insert(format!("{i}"), json!({ "n": i })).unwrap();
+        }
+        db.publish();
+        let reader = db.reader();
+        let token = CancelToken::new();
+        let (entered, started) = std::sync::mpsc::channel();
+        let runaway = {
+            let limits = Limits::new().cancel_on(token.clone());
+            thread::spawn(move || {
+                let snapshot = reader.enter();
+                entered.send(()).unwrap();
+                // a nested loop over every pair of documents
+                let query = parse("from big as a join big as b on a.n + b.n < 0").unwrap();
+                snapshot
+                    .execute_page_limited(&query, None, &limits)
+                    .map(|page| page.rows.len())
+            })
+        };
+        started.recv().unwrap();
+        thread::sleep(std::time::Duration::from_millis(20));
+        token.cancel();
+        // waits for the snapshot to be dropped
+        db.publish();
+        assert_eq!(
+            runaway.join().unwrap(),
+            Err(QueryError::Aborted(Abort::Cancelled))
+        );
+        assert_eq!(db.epoch(), 3);
+    }
+
+    #[test]
+    fn snapshots_never_mix_epochs() {
+        let mut db = Database::new();
+        db.create_collection("a");
+        db.create_collection("b");
+        db.publish();
+        let reader = db.reader();
+        let done = Arc::new(AtomicBool::new(false));
+        let checker = {
+            let done = done.clone();
+            thread::spawn(move || {
+                let query = parse("from a join b on id(b) = id(a)").unwrap();
+                let mut checked = 0;
+                loop {
+                    let finished = done.load(Ordering::SeqCst);
+                    let snapshot = reader.enter();
+                    let a = snapshot.collection("a").unwrap().len();
+                    let b = snapshot.collection("b").unwrap().len();
+                    assert_eq!(a, b, "mixed epochs at {}", snapshot.epoch());
+                    assert_eq!(snapshot.execute(&query).unwrap().len(), a);
+                    checked += 1;
+                    if finished {
+                        return checked;
+                    }
+                }
+            })
+        };
+        for i in 0..200 {
+            for name in ["a", "b"] {
+                db.collection_mut(name)
+                    .unwrap()
+                    .insert(format!("{i}"), json!(i))
+                    .unwrap();
+            }
+            db.publish();
+        }
+        done.store(true, Ordering::SeqCst);
+        assert!(checker.join().unwrap() > 0);
+        assert_eq!(db.epoch(), 203);
+    }
+}
@@ -0,0 +1,149 @@
+use std::io::{self, Write};
+
+use serde_json::Value;
+
+use super::{cell_text, Columns, EncodeError, Encoder, UnknownColumns};
+use crate::path::Path;
+
+/// Writes the rows as comma separated values, after a header naming the columns.
+///
+/// Nested objects are flattened: each value that is not an object gets a column named by its
+/// path, such as `address.city`, and arrays are written as JSON. A row that is not an object
+/// is one column named `$`. Missing values and nulls are empty cells.
+///
+/// The columns are every path found in the first [`sample`](Self::sample) rows, in the order
+/// they are first seen, and those rows are held until the header is written. Values of later
+/// rows at other paths go in a last `#rest` column by default, see
+/// [`unknown_columns`](Self::unknown_columns).
+pub struct Csv<W: Write> {
+    out: ::csv::Writer<W>,
+    columns: Columns,
+}
+
+impl<W: Write> Csv<W> {
+    /// The number of rows the columns are found in by default.
+    pub const SAMPLE: usize = 1000;
+
+    pub fn new(out: W) -> Self {
+        Self::with_delimiter(out, b',')
+    }
+
+    /// Separate cells with `delimiter` instead of a comma.
+    pub fn with_delimiter(out: W, delimiter: u8) -> Self {
+        Csv {
+            out: ::csv::WriterBuilder::new()
+                .delimiter(delimiter)
+                .from_writer(out),
+            columns: Columns::new(Self::SAMPLE),
+        }
+    }
+
+    /// Find the columns in the first `rows` rows instead.
+    pub fn sample(mut self, rows: usize) -> Self {
+        self.columns.sample = rows;
+        self
+    }
+
+    /// Write these columns, in this order, instead of finding them.
+    pub fn columns(mut self, paths: Vec<Path>) -> Self {
+        self.columns.give(paths);
+        self
+    }
+
+    /// Handle values at paths that are not columns as `policy` says, instead of writing them
+    /// in a last `#rest` column.
+    pub fn unknown_columns(mut self, policy: UnknownColumns) -> Self {
+        self.columns.unknown = policy;
+        self
+    }
+
+    /// Write the header and the rows held for it, if that was not done yet. `more` tells
+    /// whether rows follow.
+    fn start(&mut self, more: bool) -> Result<(), EncodeError> {
+        let Some(held) = self.columns.fix(more) else {
+            return Ok(());
+        };
+        let header = self.columns.names();
+        self.out.write_record(header).map_err(io::Error::from)?;
+        held.iter().try_for_each(|row| self.write(row))
+    }
+
+    fn write(&mut self, row: &Value) -> Result<(), EncodeError> {
+        let cells = self.columns.cells(row)?;
+        let record = cells
+            .into_iter()
+            .map(|cell| cell.as_deref().map(cell_text).unwrap_or_default());
+        Ok(self.out.write_record(record).map_err(io::Error::from)?)
+    }
+}
+
+impl<W: Write> Encoder for Csv<W> {
+    fn row(&mut self, row: &Value) -> Result<(), EncodeError> {
+        if self.columns.hold(row) {
+            return Ok(());
+        }
+        self.start(true)?;
+        self.write(row)
+    }
+
+    fn finish(&mut self) -> Result<(), EncodeError> {
+        self.start(false)?;
+        Ok(self.out.flush()?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::*;
+    use crate::encode::test::encoded;
+
+    #[test]
+    fn infers_the_header() {
+        let rows = [
+            json!({"id": "u1", "name": "Ann", "address": {"city": "Oslo"}}),
+            json!({"id": "u2", "name": "Bo, \"B\"", "tags": ["a", 1], "age": null}),
+            json!({"id": "u3", "address": {"city": "Rome"}}),
+            json!({"id": "u4", "extra": {"x": 1}, "note": null}),
+        ];
+        let mut out = Vec::new();
+        encoded(Csv::new(&mut out).sample(2), &rows).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "address.city,id,name,age,tags,#rest\n\
+             Oslo,u1,Ann,,,\n\
+             ,u2,\"Bo, \"\"B\"\"\",,\"[\"\"a\"\",1]\",\n\
+             Rome,u3,,,,\n\
+             ,u4,,,,\"{\"\"extra.x\"\":1}\"\n"
+        );
+
+        let mut out = Vec::new();
+        encoded(Csv::new(&mut out).sample(4), &rows[..3]).unwrap();
+        assert!(String::from_utf8(out)
+            .unwrap()
+            .starts_with("address.city,id,name,age,tags\n"));
+
+        let mut out = Vec::new();
+        let csv = Csv::new(&mut out)
+            .sample(1)
+            .unknown_columns(UnknownColumns::Fail);
+        let err = encoded(csv, &rows).unwrap_err();
+        assert!(matches!(err, EncodeError::UnknownColumn(path) if path == "age"));
+
+        let mut out = Vec::new();
+        let columns = vec![Path::parse("name").unwrap()];
+        encoded(
+            Csv::with_delimiter(&mut out, b';')
+                .columns(columns)
+                .unknown_columns(UnknownColumns::Drop),
+            &[json!({"name": "a;b", "other": 1})],
+        )
+        .unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "name\n\"a;b\"\n");
+
+        let mut out = Vec::new();
+        encoded(Csv::new(&mut out), &[json!(1), json!("x")]).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "$\n1\nx\n");
+    }
+}
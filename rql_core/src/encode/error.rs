@@ -0,0 +1,54 @@
+use std::fmt;
+use std::io;
+
+use crate::query::QueryError;
+
+/// Errors returned while encoding query results.
+#[derive(Debug)]
+pub enum EncodeError {
+    /// writing the output failed.
+    Io(io::Error),
+    /// the query failed while its rows were read.
+    Query(QueryError),
+    /// with [`UnknownColumns::Fail`](super::UnknownColumns::Fail), a row has a value at a path
+    /// the columns, fixed by the rows before it, do not hold.
+    UnknownColumn(String),
+    /// no encoder has this name.
+    UnknownFormat(String),
+}
+
+impl fmt::Display for EncodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EncodeError::Io(err) => write!(f, "writing the result failed: {err}"),
+            EncodeError::Query(err) => err.fmt(f),
+            EncodeError::UnknownColumn(path) => write!(
+                f,
+                "a row has a value at `{path}`, which is not one of the columns"
+            ),
+            EncodeError::UnknownFormat(name) => write!(f, "unknown result format `{name}`"),
+        }
+    }
+}
+
+impl std::error::Error for EncodeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            EncodeError::Io(err) => Some(err),
+            EncodeError::Query(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for EncodeError {
+    fn from(err: io::Error) -> Self {
+        EncodeError::Io(err)
+    }
+}
+
+impl From<QueryError> for EncodeError {
+    fn from(err: QueryError) -> Self {
+        EncodeError::Query(err)
+    }
+}
@@ -0,0 +1,91 @@
+use std::io::Write;
+
+use serde_json::Value;
+
+use super::{EncodeError, Encoder};
+
+/// Writes the rows as one JSON array, one row per line.
+#[derive(Debug)]
+pub struct Json<W> {
+    out: W,
+    rows: u64,
+}
+
+impl<W: Write> Json<W> {
+    pub fn new(out: W) -> Self {
+        Json { out, rows: 0 }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+}
+
+impl<W: Write> Encoder for Json<W> {
+    fn row(&mut self, row: &Value) -> Result<(), EncodeError> {
+        self.out
+            .write_all(if self.rows == 0 { b"[\n" } else { b",\n" })?;
+        serde_json::to_writer(&mut self.out, row).map_err(std::io::Error::from)?;
+        self.rows += 1;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), EncodeError> {
+        self.out
+            .write_all(if self.rows == 0 { b"[]\n" } else { b"\n]\n" })?;
+        Ok(self.out.flush()?)
+    }
+}
+
+/// Writes each row as JSON on its own line, also known as JSON Lines.
+#[derive(Debug)]
+pub struct NdJson<W> {
+    out: W,
+}
+
+impl<W: Write> NdJson<W> {
+    pub fn new(out: W) -> Self {
+        NdJson { out }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+}
+
+impl<W: Write> Encoder for NdJson<W> {
+    fn row(&mut self, row: &Value) -> Result<(), EncodeError> {
+        serde_json::to_writer(&mut self.out, row).map_err(std::io::Error::from)?;
+        Ok(self.out.write_all(b"\n")?)
+    }
+
+    fn finish(&mut self) -> Result<(), EncodeError> {
+        Ok(self.out.flush()?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::*;
+    use crate::encode::test::encoded;
+
+    #[test]
+    fn writes_arrays_and_lines() {
+        let rows = [json!({"a": 1}), json!("x\ny")];
+        let mut out = Vec::new();
+        encoded(Json::new(&mut out), &rows).unwrap();
+        assert_eq!(out, b"[\n{\"a\":1},\n\"x\\ny\"\n]\n");
+        let parsed: Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(parsed, json!(rows));
+
+        out.clear();
+        encoded(Json::new(&mut out), &[]).unwrap();
+        assert_eq!(out, b"[]\n");
+
+        out.clear();
+        encoded(NdJson::new(&mut out), &rows).unwrap();
+        assert_eq!(out, b"{\"a\":1}\n\"x\\ny\"\n");
+    }
+}
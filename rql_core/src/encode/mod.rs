@@ -0,0 +1,336 @@
+//! Writing query results in other formats than [`Value`]s.
+//!
+//! An [`Encoder`] writes rows to an [`io::Write`] as they are handed to it, so an export only
+//! holds the rows of the format's header sample in memory, never the whole result. Pick one
+//! by [`Format`], or implement [`Encoder`] for another format, then feed it a
+//! [`RowStream`](crate::query::RowStream) with [`encode`]:
+//!
+//! ```
+//! # use rql_core::collection::Collection;
+//! # use rql_core::encode::{encode, Format};
+//! # use rql_core::query::{parse, Mode, RowStream};
+//! # use serde_json::json;
+//! let (mut users, reader) = Collection::new("users");
+//! users.insert("u1".into(), json!({"name": "Ann", "address": {"city": "Oslo"}})).unwrap();
+//! users.publish();
+//!
+//! let query = parse("from users select name, address").unwrap();
+//! let mut out = Vec::new();
+//! let mut csv = Format::Csv.encoder(&mut out);
+//! encode(RowStream::new(&query, &reader, Mode::Chunks(1000)), &mut *csv).unwrap();
+//! drop(csv);
+//! assert_eq!(String::from_utf8(out).unwrap(), "address.city,name\nOslo,Ann\n");
+//! ```
+//!
+//! Apart from [`Csv`], encoders write straight to their writer, so a file or socket should be
+//! wrapped in a [`BufWriter`](std::io::BufWriter).
+mod csv;
+mod error;
+mod json;
+mod msgpack;
+mod table;
+
+use std::borrow::Cow;
+use std::io;
+use std::str::FromStr;
+
+use serde_json::{Map, Value};
+
+use crate::path::{Path, Segment};
+use crate::query::{OwnedRow, QueryError};
+
+pub use self::csv::Csv;
+pub use error::EncodeError;
+pub use json::{Json, NdJson};
+pub use msgpack::MessagePack;
+pub use table::Table;
+
+/// Writes result rows in one format, one row at a time.
+pub trait Encoder {
+    /// Write `row`, or hold it until the format's header is known.
+    fn row(&mut self, row: &Value) -> Result<(), EncodeError>;
+
+    /// Write the rows still held and whatever follows the last row, and flush the writer.
+    /// Called once, after the last row.
+    fn finish(&mut self) -> Result<(), EncodeError>;
+}
+
+/// The built-in encoders.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// one JSON array holding every row, see [`Json`].
+    Json,
+    /// one JSON value per line, see [`NdJson`].
+    NdJson,
+    /// comma separated values with a header, see [`Csv`].
+    Csv,
+    /// one MessagePack value per row, see [`MessagePack`].
+    MessagePack,
+    /// a table drawn with ASCII characters, see [`Table`].
+    Table,
+}
+
+impl Format {
+    /// An encoder of this format writing to `out`, with the default options.
+    pub fn encoder<'w, W: io::Write + 'w>(self, out: W) -> Box<dyn Encoder + 'w> {
+        match self {
+            Format::Json => Box::new(Json::new(out)),
+            Format::NdJson => Box::new(NdJson::new(out)),
+            Format::Csv => Box::new(Csv::new(out)),
+            Format::MessagePack => Box::new(MessagePack::new(out)),
+            Format::Table => Box::new(Table::new(out)),
+        }
+    }
+
+    /// The media type of the encoded output.
+    pub fn media_type(self) -> &'static str {
+        match self {
+            Format::Json => "application/json",
+            Format::NdJson => "application/x-ndjson",
+            Format::Csv => "text/csv",
+            Format::MessagePack => "application/msgpack",
+            Format::Table => "text/plain",
+        }
+    }
+}
+
+impl FromStr for Format {
+    type Err = EncodeError;
+
+    /// Parse the name of a format: `json`, `ndjson`, `csv`, `msgpack` or `table`.
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name.to_ascii_lowercase().as_str() {
+            "json" => Ok(Format::Json),
+            "ndjson" | "jsonl" => Ok(Format::NdJson),
+            "csv" => Ok(Format::Csv),
+            "msgpack" | "messagepack" => Ok(Format::MessagePack),
+            "table" => Ok(Format::Table),
+            _ => Err(EncodeError::UnknownFormat(name.to_owned())),
+        }
+    }
+}
+
+/// What formats that lay rows out in cells do with a value at a path that is not one of
+/// their columns, which a row after the ones the columns were found in may have.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum UnknownColumns {
+    /// fail with [`EncodeError::UnknownColumn`], after the rows before it were written.
+    Fail,
+    /// leave the value out.
+    Drop,
+    /// write the values in a last column named `#rest`, as a JSON object by path. The column
+    /// is only there if rows may follow the ones the columns were found in.
+    #[default]
+    Rest,
+}
+
+/// The name of the column of [`UnknownColumns::Rest`].
+const REST: &str = "#rest";
+
+/// Write every row of `rows` with `encoder` and finish it, returning the number of rows.
+///
+/// Stops at the first error, without finishing the encoder.
+pub fn encode<I>(rows: I, encoder: &mut dyn Encoder) -> Result<u64, EncodeError>
+where
+    I: IntoIterator<Item = Result<OwnedRow, QueryError>>,
+{
+    let mut count = 0;
+    for row in rows {
+        encoder.row(&row?.value)?;
+        count += 1;
+    }
+    encoder.finish()?;
+    Ok(count)
+}
+
+/// The columns of formats that lay rows out in cells: the paths to the values of `row` that
+/// are not objects, or the root path if `row` is not an object.
+///
+/// Arrays and empty objects are one cell each.
+fn flatten(row: &Value) -> Vec<(Path, &Value)> {
+    fn visit<'v>(value: &'v Value, path: &mut Path, cells: &mut Vec<(Path, &'v Value)>) {
+        match value {
+            Value::Object(fields) if !fields.is_empty() => {
+                for (key, value) in fields {
+                    path.push(Segment::Key(key.clone()));
+                    visit(value, path, cells);
+                    path.pop();
+                }
+            }
+            _ => cells.push((path.clone(), value)),
+        }
+    }
+
+    let mut cells = Vec::new();
+    visit(row, &mut Path::root(), &mut cells);
+    cells
+}
+
+/// The text of a cell: nothing for null, strings unquoted and everything else as JSON.
+fn cell_text(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+/// The columns of a cell layout, found in the first rows.
+///
+/// Holds up to `sample` rows, adding their paths as columns in the order they are first seen,
+/// until the columns are fixed for the rest of the rows.
+#[derive(Debug)]
+struct Columns {
+    sample: usize,
+    paths: Vec<Path>,
+    held: Vec<Value>,
+    /// the columns are not fixed yet.
+    open: bool,
+    unknown: UnknownColumns,
+    /// the columns end with the [`REST`] column.
+    rest: bool,
+}
+
+impl Columns {
+    fn new(sample: usize) -> Self {
+        Columns {
+            sample,
+            paths: Vec::new(),
+            held: Vec::new(),
+            open: true,
+            unknown: UnknownColumns::default(),
+            rest: false,
+        }
+    }
+
+    /// Use columns given up front, so no row is held.
+    fn give(&mut self, paths: Vec<Path>) {
+        self.sample = 0;
+        self.paths = paths;
+    }
+
+    /// Hold `row` and add its columns, unless the sample is full or the columns are fixed.
+    fn hold(&mut self, row: &Value) -> bool {
+        if !self.open || self.held.len() >= self.sample {
+            return false;
+        }
+        for (path, _) in flatten(row) {
+            if !self.paths.contains(&path) {
+                self.paths.push(path);
+            }
+        }
+        self.held.push(row.clone());
+        true
+    }
+
+    /// Fix the columns, returning the rows held until now, or `None` if they were already
+    /// fixed. `more` tells whether rows follow the held ones.
+    fn fix(&mut self, more: bool) -> Option<Vec<Value>> {
+        if !std::mem::take(&mut self.open) {
+            return None;
+        }
+        self.rest = more && self.unknown == UnknownColumns::Rest;
+        Some(std::mem::take(&mut self.held))
+    }
+
+    /// The names of the columns, for a header.
+    fn names(&self) -> Vec<String> {
+        let mut names: Vec<_> = self.paths.iter().map(Path::to_string).collect();
+        if self.rest {
+            names.push(REST.to_owned());
+        }
+        names
+    }
+
+    /// The cells of `row`, one per column. A value no column holds is handled as `unknown`
+    /// says, leaving out nulls for the [`REST`] column.
+    fn cells<'v>(&self, row: &'v Value) -> Result<Vec<Option<Cow<'v, Value>>>, EncodeError> {
+        let mut cells = vec![None; self.paths.len() + usize::from(self.rest)];
+        let mut rest = Map::new();
+        for (path, value) in flatten(row) {
+            match self.paths.iter().position(|column| *column == path) {
+                Some(i) => cells[i] = Some(Cow::Borrowed(value)),
+                None if self.unknown == UnknownColumns::Fail => {
+                    return Err(EncodeError::UnknownColumn(path.to_string()))
+                }
+                None if self.rest && !value.is_null() => {
+                    rest.insert(path.to_string(), value.clone());
+                }
+                None => {}
+            }
+        }
+        if !rest.is_empty() {
+            cells[self.paths.len()] = Some(Cow::Owned(Value::Object(rest)));
+        }
+        Ok(cells)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::*;
+
+    /// Encode `rows` and finish.
+    pub(super) fn encoded(mut encoder: impl Encoder, rows: &[Value]) -> Result<(), EncodeError> {
+        for row in rows {
+            encoder.row(row)?;
+        }
+        encoder.finish()
+    }
+
+    #[test]
+    fn flattens_nested_objects() {
+        let row = json!({"a": {"b": 1, "c d": {"e": [1, 2]}}, "f": {}, "g": null});
+        let paths: Vec<_> = flatten(&row)
+            .into_iter()
+            .map(|(path, value)| (path.to_string(), value.clone()))
+            .collect();
+        assert_eq!(
+            paths,
+            [
+                ("a.b".to_owned(), json!(1)),
+                (r#"a["c d"].e"#.to_owned(), json!([1, 2])),
+                ("f".to_owned(), json!({})),
+                ("g".to_owned(), json!(null)),
+            ]
+        );
+        assert_eq!(flatten(&json!(3))[0].0.to_string(), "$");
+
+        let mut columns = Columns::new(2);
+        assert!(columns.hold(&json!({"a": 1})));
+        assert!(columns.hold(&json!({"b": {"c": 2}})));
+        assert!(!columns.hold(&json!({"a": 3})));
+        columns.unknown = UnknownColumns::Fail;
+        assert_eq!(columns.fix(true).unwrap().len(), 2);
+        assert_eq!(columns.fix(true), None);
+        let a = json!(3);
+        assert_eq!(
+            columns.cells(&json!({"a": 3})).unwrap(),
+            [Some(Cow::Borrowed(&a)), None]
+        );
+        assert!(matches!(
+            columns.cells(&json!({"b": 4})),
+            Err(EncodeError::UnknownColumn(path)) if path == "b"
+        ));
+    }
+
+    #[test]
+    fn names_formats() {
+        for format in [
+            Format::Json,
+            Format::NdJson,
+            Format::Csv,
+            Format::MessagePack,
+            Format::Table,
+        ] {
+            let name = format!("{format:?}");
+            assert_eq!(name.parse::<Format>().unwrap(), format);
+        }
+        assert!(matches!(
+            "xml".parse::<Format>(),
+            Err(EncodeError::UnknownFormat(_))
+        ));
+    }
+}
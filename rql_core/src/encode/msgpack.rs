@@ -0,0 +1,102 @@
+use std::io::{self, Write};
+
+use rmp::encode;
+use serde_json::Value;
+
+use super::{EncodeError, Encoder};
+
+/// Writes each row as one MessagePack value, one after the other.
+///
+/// Integers are written as the smallest MessagePack integer holding them, other numbers as
+/// 64-bit floats, and objects as maps with string keys.
+#[derive(Debug)]
+pub struct MessagePack<W> {
+    out: W,
+}
+
+impl<W: Write> MessagePack<W> {
+    pub fn new(out: W) -> Self {
+        MessagePack { out }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+}
+
+impl<W: Write> Encoder for MessagePack<W> {
+    fn row(&mut self, row: &Value) -> Result<(), EncodeError> {
+        Ok(write(&mut self.out, row)?)
+    }
+
+    fn finish(&mut self) -> Result<(), EncodeError> {
+        Ok(self.out.flush()?)
+    }
+}
+
+/// Write `value` as MessagePack.
+fn write(out: &mut impl Write, value: &Value) -> io::Result<()> {
+    match value {
+        Value::Null => encode::write_nil(out)?,
+        Value::Bool(b) => encode::write_bool(out, *b)?,
+        Value::Number(n) => {
+            if let Some(n) = n.as_u64() {
+                encode::write_uint(out, n)?;
+            } else if let Some(n) = n.as_i64() {
+                encode::write_sint(out, n)?;
+            } else {
+                encode::write_f64(out, n.as_f64().unwrap_or(f64::NAN))?;
+            }
+        }
+        Value::String(s) => encode::write_str(out, s)?,
+        Value::Array(items) => {
+            encode::write_array_len(out, len(items.len())?)?;
+            for item in items {
+                write(out, item)?;
+            }
+        }
+        Value::Object(fields) => {
+            encode::write_map_len(out, len(fields.len())?)?;
+            for (key, value) in fields {
+                encode::write_str(out, key)?;
+                write(out, value)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The length of an array or map, which MessagePack limits to 32 bits.
+fn len(len: usize) -> io::Result<u32> {
+    u32::try_from(len).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            "MessagePack arrays and maps hold at most 2^32 - 1 items",
+        )
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::*;
+    use crate::encode::test::encoded;
+
+    #[test]
+    fn writes_values() {
+        let mut out = Vec::new();
+        encoded(
+            MessagePack::new(&mut out),
+            &[json!({"a": [1, -1, 300, 1.5]}), json!(null), json!("hi")],
+        )
+        .unwrap();
+        assert_eq!(
+            out,
+            [
+                0x81, 0xa1, b'a', 0x94, 0x01, 0xff, 0xcd, 0x01, 0x2c, 0xcb, 0x3f, 0xf8, 0, 0, 0, 0,
+                0, 0, 0xc0, 0xa2, b'h', b'i',
+            ]
+        );
+    }
+}
@@ -0,0 +1,224 @@
+use std::io::Write;
+
+use serde_json::Value;
+
+use super::{cell_text, Columns, EncodeError, Encoder, UnknownColumns};
+
+/// Writes the rows as a table drawn with ASCII characters, for people to read.
+///
+/// ```text
+/// +----+--------------+
+/// | n  | address.city |
+/// +----+--------------+
+/// |  1 | Oslo         |
+/// | 20 |              |
+/// +----+--------------+
+/// (2 rows)
+/// ```
+///
+/// Columns are found like [`Csv`](super::Csv) finds them, including the `#rest` column, and
+/// the width of each is that of its widest cell in the same first [`sample`](Self::sample)
+/// rows, up to [`max_width`](Self::max_width) and at least 3. Longer cells are cut short,
+/// ending in `...`, and numbers are aligned right. Nulls and missing values are empty cells.
+pub struct Table<W> {
+    out: W,
+    columns: Columns,
+    max_width: usize,
+    /// the width of each column, known once the columns are fixed.
+    widths: Vec<usize>,
+    rows: u64,
+}
+
+impl<W: Write> Table<W> {
+    /// The number of rows the columns and their widths are found in by default.
+    pub const SAMPLE: usize = 100;
+
+    pub fn new(out: W) -> Self {
+        Table {
+            out,
+            columns: Columns::new(Self::SAMPLE),
+            max_width: 40,
+            widths: Vec::new(),
+            rows: 0,
+        }
+    }
+
+    /// Find the columns and their widths in the first `rows` rows instead.
+    pub fn sample(mut self, rows: usize) -> Self {
+        self.columns.sample = rows;
+        self
+    }
+
+    /// Handle values at paths that are not columns as `policy` says, instead of writing them
+    /// in a last `#rest` column.
+    pub fn unknown_columns(mut self, policy: UnknownColumns) -> Self {
+        self.columns.unknown = policy;
+        self
+    }
+
+    /// Make no column wider than `chars` characters, 40 by default.
+    pub fn max_width(mut self, chars: usize) -> Self {
+        self.max_width = chars.max(3);
+        self
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+
+    /// Write the header and the rows held for it, if that was not done yet. `more` tells
+    /// whether rows follow.
+    fn start(&mut self, more: bool) -> Result<(), EncodeError> {
+        let Some(held) = self.columns.fix(more) else {
+            return Ok(());
+        };
+        let header = self.columns.names();
+        self.widths = header.iter().map(|name| width(name)).collect();
+        for row in &held {
+            for (i, cell) in self.columns.cells(row)?.into_iter().enumerate() {
+                let cell = cell.as_deref().map(cell_text).unwrap_or_default();
+                self.widths[i] = self.widths[i].max(width(&cell));
+            }
+        }
+        for width in &mut self.widths {
+            // room for `...`
+            *width = (*width).clamp(3, self.max_width);
+        }
+        if header.is_empty() {
+            return Ok(());
+        }
+        self.border()?;
+        let header = header.iter().map(|name| (name.as_str(), false));
+        self.line(header)?;
+        self.border()?;
+        held.iter().try_for_each(|row| self.write(row))
+    }
+
+    fn write(&mut self, row: &Value) -> Result<(), EncodeError> {
+        let cells: Vec<_> = self
+            .columns
+            .cells(row)?
+            .into_iter()
+            .map(|cell| {
+                (
+                    cell.as_deref().map(cell_text).unwrap_or_default(),
+                    cell.as_deref().is_some_and(Value::is_number),
+                )
+            })
+            .collect();
+        self.line(cells.iter().map(|(text, right)| (text.as_str(), *right)))?;
+        self.rows += 1;
+        Ok(())
+    }
+
+    /// Write `+----+----+`.
+    fn border(&mut self) -> Result<(), EncodeError> {
+        let mut line = String::from("+");
+        for width in &self.widths {
+            line.extend(std::iter::repeat_n('-', width + 2));
+            line.push('+');
+        }
+        line.push('\n');
+        Ok(self.out.write_all(line.as_bytes())?)
+    }
+
+    /// Write `| a | b |`, each cell fit to its column and aligned right if it says so.
+    fn line<'c>(
+        &mut self,
+        cells: impl Iterator<Item = (&'c str, bool)>,
+    ) -> Result<(), EncodeError> {
+        let mut line = String::from("|");
+        for ((text, right), &width) in cells.zip(&self.widths) {
+            let text = fit(text, width);
+            let pad = " ".repeat(width - self::width(&text));
+            let (left, right) = if right {
+                (pad, String::new())
+            } else {
+                (String::new(), pad)
+            };
+            line.push_str(&format!(" {left}{text}{right} |"));
+        }
+        line.push('\n');
+        Ok(self.out.write_all(line.as_bytes())?)
+    }
+}
+
+impl<W: Write> Encoder for Table<W> {
+    fn row(&mut self, row: &Value) -> Result<(), EncodeError> {
+        if self.columns.hold(row) {
+            return Ok(());
+        }
+        self.start(true)?;
+        self.write(row)
+    }
+
+    fn finish(&mut self) -> Result<(), EncodeError> {
+        self.start(false)?;
+        if !self.widths.is_empty() {
+            self.border()?;
+        }
+        let rows = self.rows;
+        let noun = if rows == 1 { "row" } else { "rows" };
+        writeln!(self.out, "({rows} {noun})")?;
+        Ok(self.out.flush()?)
+    }
+}
+
+/// The number of characters `text` takes.
+fn width(text: &str) -> usize {
+    text.chars().count()
+}
+
+/// `text` on one line, cut to `width` characters.
+fn fit(text: &str, width: usize) -> String {
+    let text: String = text
+        .chars()
+        .map(|c| if c.is_control() { ' ' } else { c })
+        .collect();
+    if self::width(&text) <= width {
+        return text;
+    }
+    let mut cut: String = text.chars().take(width.saturating_sub(3)).collect();
+    cut.push_str("...");
+    cut
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::*;
+    use crate::encode::test::encoded;
+
+    #[test]
+    fn draws_a_table() {
+        let rows = [
+            json!({"n": 1, "address": {"city": "Oslo"}}),
+            json!({"n": 20, "note": "two\nlines"}),
+            json!({"n": 300, "note": "a rather long note"}),
+        ];
+        let mut out = Vec::new();
+        let table = Table::new(&mut out)
+            .sample(2)
+            .max_width(9)
+            .unknown_columns(UnknownColumns::Drop);
+        encoded(table, &rows).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "\
++-----------+-----+-----------+
+| addres... | n   | note      |
++-----------+-----+-----------+
+| Oslo      |   1 |           |
+|           |  20 | two lines |
+|           | 300 | a rath... |
++-----------+-----+-----------+
+(3 rows)
+"
+        );
+
+        let mut out = Vec::new();
+        encoded(Table::new(&mut out), &[]).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "(0 rows)\n");
+    }
+}
@@ -0,0 +1,231 @@
+use std::cmp::Ordering;
+use std::fmt;
+
+use serde_json::Value;
+
+use super::IndexKey;
+use crate::path::{Path, PathError};
+
+/// A value computed from a document to be used as (part of) an index key.
+///
+/// Written as a path, `email`, or a function applied to a key expression, `lower(email)`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum KeyExpr {
+    Path(Path),
+    /// lowercase of a string.
+    Lower(Box<KeyExpr>),
+    /// uppercase of a string.
+    Upper(Box<KeyExpr>),
+    /// a string without leading and trailing whitespace.
+    Trim(Box<KeyExpr>),
+    /// number of characters in a string or items in an array.
+    Length(Box<KeyExpr>),
+}
+
+impl KeyExpr {
+    /// Parse a key expression such as `lower(trim(email))`.
+    pub fn parse(s: &str) -> Result<Self, PathError> {
+        let s = s.trim();
+        let Some(open) = s.find('(') else {
+            return Path::parse(s).map(KeyExpr::Path);
+        };
+        let inner = s[open + 1..].strip_suffix(')').ok_or_else(|| PathError {
+            offset: s.len(),
+            message: "expected ')'".to_owned(),
+        })?;
+        let inner = Box::new(KeyExpr::parse(inner)?);
+        Ok(match s[..open].trim() {
+            "lower" => KeyExpr::Lower(inner),
+            "upper" => KeyExpr::Upper(inner),
+            "trim" => KeyExpr::Trim(inner),
+            "length" => KeyExpr::Length(inner),
+            name => {
+                return Err(PathError {
+                    offset: 0,
+                    message: format!("unknown key function `{name}`"),
+                })
+            }
+        })
+    }
+
+    /// Returns the key for `doc`, or `None` if the expression does not apply to it.
+    pub fn eval(&self, doc: &Value) -> Option<IndexKey> {
+        let string = |inner: &KeyExpr, f: fn(&str) -> String| match inner.eval(doc)? {
+            IndexKey::String(s) => Some(IndexKey::String(f(&s))),
+            _ => None,
+        };
+        match self {
+            KeyExpr::Path(path) => path.get(doc).and_then(IndexKey::from_json),
+            KeyExpr::Lower(inner) => string(inner, str::to_lowercase),
+            KeyExpr::Upper(inner) => string(inner, str::to_uppercase),
+            KeyExpr::Trim(inner) => string(inner, |s| s.trim().to_owned()),
+            KeyExpr::Length(inner) => match inner.eval(doc)? {
+                IndexKey::String(s) => Some(IndexKey::from(s.chars().count() as i64)),
+                IndexKey::Array(items) => Some(IndexKey::from(items.len() as i64)),
+                _ => None,
+            },
+        }
+    }
+
+    /// the path the expression reads from.
+    pub fn path(&self) -> &Path {
+        match self {
+            KeyExpr::Path(path) => path,
+            KeyExpr::Lower(inner)
+            | KeyExpr::Upper(inner)
+            | KeyExpr::Trim(inner)
+            | KeyExpr::Length(inner) => inner.path(),
+        }
+    }
+}
+
+impl From<Path> for KeyExpr {
+    fn from(path: Path) -> Self {
+        KeyExpr::Path(path)
+    }
+}
+
+impl fmt::Display for KeyExpr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeyExpr::Path(path) => write!(f, "{path}"),
+            KeyExpr::Lower(inner) => write!(f, "lower({inner})"),
+            KeyExpr::Upper(inner) => write!(f, "upper({inner})"),
+            KeyExpr::Trim(inner) => write!(f, "trim({inner})"),
+            KeyExpr::Length(inner) => write!(f, "length({inner})"),
+        }
+    }
+}
+
+/// Comparison operators usable in a [`Predicate`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Cmp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Cmp {
+    /// Apply the operator. Ordering comparisons between keys of different types are false.
+    pub fn test(&self, left: &IndexKey, right: &IndexKey) -> bool {
+        let same_type = std::mem::discriminant(left) == std::mem::discriminant(right);
+        let ordering = left.cmp(right);
+        match self {
+            Cmp::Eq => ordering == Ordering::Equal,
+            Cmp::Ne => ordering != Ordering::Equal,
+            Cmp::Lt => same_type && ordering == Ordering::Less,
+            Cmp::Le => same_type && ordering != Ordering::Greater,
+            Cmp::Gt => same_type && ordering == Ordering::Greater,
+            Cmp::Ge => same_type && ordering != Ordering::Less,
+        }
+    }
+}
+
+impl fmt::Display for Cmp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Cmp::Eq => "=",
+            Cmp::Ne => "!=",
+            Cmp::Lt => "<",
+            Cmp::Le => "<=",
+            Cmp::Gt => ">",
+            Cmp::Ge => ">=",
+        })
+    }
+}
+
+/// The filter of a partial index. Only documents matching it are indexed.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Predicate {
+    /// the expression has a value, i.e. the path exists and is not an object.
+    Exists(KeyExpr),
+    Compare(KeyExpr, Cmp, IndexKey),
+    And(Vec<Predicate>),
+    Or(Vec<Predicate>),
+    Not(Box<Predicate>),
+}
+
+impl Predicate {
+    pub fn matches(&self, doc: &Value) -> bool {
+        match self {
+            Predicate::Exists(expr) => expr.eval(doc).is_some(),
+            Predicate::Compare(expr, cmp, key) => {
+                expr.eval(doc).is_some_and(|value| cmp.test(&value, key))
+            }
+            Predicate::And(predicates) => predicates.iter().all(|p| p.matches(doc)),
+            Predicate::Or(predicates) => predicates.iter().any(|p| p.matches(doc)),
+            Predicate::Not(predicate) => !predicate.matches(doc),
+        }
+    }
+}
+
+impl fmt::Display for Predicate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let join = |f: &mut fmt::Formatter<'_>, predicates: &[Predicate], op: &str| {
+            f.write_str("(")?;
+            for (i, p) in predicates.iter().enumerate() {
+                if i > 0 {
+                    write!(f, " {op} ")?;
+                }
+                write!(f, "{p}")?;
+            }
+            f.write_str(")")
+        };
+        match self {
+            Predicate::Exists(expr) => write!(f, "{expr} exists"),
+            Predicate::Compare(expr, cmp, key) => write!(f, "{expr} {cmp} {key}"),
+            Predicate::And(predicates) => join(f, predicates, "and"),
+            Predicate::Or(predicates) => join(f, predicates, "or"),
+            Predicate::Not(predicate) => write!(f, "not {predicate}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn parse_and_eval() {
+        let expr = KeyExpr::parse("lower(trim(email))").unwrap();
+        assert_eq!(expr.to_string(), "lower(trim(email))");
+        assert_eq!(
+            expr.eval(&json!({"email": "  Ann@X.com "})),
+            Some("ann@x.com".into())
+        );
+        assert_eq!(expr.eval(&json!({"email": 3})), None);
+        assert_eq!(
+            KeyExpr::parse("length(tags)")
+                .unwrap()
+                .eval(&json!({"tags": [1, 2]})),
+            Some(IndexKey::from(2))
+        );
+        assert!(KeyExpr::parse("reverse(email)").is_err());
+        assert!(KeyExpr::parse("lower(email").is_err());
+    }
+
+    #[test]
+    fn predicates() {
+        let active = Predicate::And(vec![
+            Predicate::Compare(KeyExpr::parse("status").unwrap(), Cmp::Eq, "active".into()),
+            Predicate::Not(Box::new(Predicate::Exists(
+                KeyExpr::parse("deleted_at").unwrap(),
+            ))),
+        ]);
+        assert!(active.matches(&json!({"status": "active"})));
+        assert!(!active.matches(&json!({"status": "active", "deleted_at": 1})));
+        assert!(
+            !Predicate::Compare(KeyExpr::parse("n").unwrap(), Cmp::Lt, "a".into())
+                .matches(&json!({"n": 1}))
+        );
+        assert_eq!(
+            active.to_string(),
+            r#"(status = "active" and not deleted_at exists)"#
+        );
+    }
+}
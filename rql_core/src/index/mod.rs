@@ -0,0 +1,323 @@
+//! Secondary index definitions over JSON documents.
+//!
+//! Indexes are declared with an [`IndexDef`] and live inside the [`RwMap`](crate::rwmap::RwMap)
+//! next to the documents, so they are updated by the same publish that changes the documents.
+//! See [`MapReadRef::index`](crate::rwmap::MapReadRef::index) for reading them.
+mod expr;
+pub mod text;
+
+use std::cmp::Ordering;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+
+use serde_json::Value;
+
+pub use expr::{Cmp, KeyExpr, Predicate};
+
+/// A JSON number with a total order, used inside [`IndexKey`].
+///
+/// Numbers are compared as `f64`, so `1` and `1.0` are the same key. Integers beyond 2^53 lose
+/// precision.
+#[derive(Debug, Clone, Copy)]
+pub struct Number(f64);
+
+impl Number {
+    pub fn new(n: f64) -> Self {
+        // -0.0 and 0.0 are the same key
+        Number(if n == 0.0 { 0.0 } else { n })
+    }
+
+    pub fn get(&self) -> f64 {
+        self.0
+    }
+
+    /// Convert back to JSON, preferring an integer when the value has no fractional part.
+    pub fn to_json(&self) -> Value {
+        const EXACT: f64 = (1u64 << 53) as f64;
+        if self.0.fract() == 0.0 && self.0.abs() <= EXACT {
+            Value::from(self.0 as i64)
+        } else {
+            serde_json::Number::from_f64(self.0)
+                .map(Value::Number)
+                .unwrap_or(Value::Null)
+        }
+    }
+}
+
+impl PartialEq for Number {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Number {}
+
+impl PartialOrd for Number {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Number {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+impl Hash for Number {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.to_bits().hash(state);
+    }
+}
+
+/// A totally ordered value extracted from a document to be stored in an index.
+///
+/// Keys of different types order as `null < booleans < numbers < strings < arrays`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum IndexKey {
+    Null,
+    Bool(bool),
+    Number(Number),
+    String(String),
+    Array(Vec<IndexKey>),
+}
+
+impl IndexKey {
+    /// Convert a JSON value into a key. Objects can not be indexed and return `None`.
+    pub fn from_json(value: &Value) -> Option<Self> {
+        Some(match value {
+            Value::Null => IndexKey::Null,
+            Value::Bool(b) => IndexKey::Bool(*b),
+            Value::Number(n) => IndexKey::Number(Number::new(n.as_f64()?)),
+            Value::String(s) => IndexKey::String(s.clone()),
+            Value::Array(items) => IndexKey::Array(
+                items
+                    .iter()
+                    .map(IndexKey::from_json)
+                    .collect::<Option<_>>()?,
+            ),
+            Value::Object(_) => return None,
+        })
+    }
+
+    pub fn to_json(&self) -> Value {
+        match self {
+            IndexKey::Null => Value::Null,
+            IndexKey::Bool(b) => Value::Bool(*b),
+            IndexKey::Number(n) => n.to_json(),
+            IndexKey::String(s) => Value::String(s.clone()),
+            IndexKey::Array(items) => Value::Array(items.iter().map(IndexKey::to_json).collect()),
+        }
+    }
+}
+
+impl From<&str> for IndexKey {
+    fn from(s: &str) -> Self {
+        IndexKey::String(s.to_owned())
+    }
+}
+
+impl From<String> for IndexKey {
+    fn from(s: String) -> Self {
+        IndexKey::String(s)
+    }
+}
+
+impl From<bool> for IndexKey {
+    fn from(b: bool) -> Self {
+        IndexKey::Bool(b)
+    }
+}
+
+impl From<f64> for IndexKey {
+    fn from(n: f64) -> Self {
+        IndexKey::Number(Number::new(n))
+    }
+}
+
+impl From<i64> for IndexKey {
+    fn from(n: i64) -> Self {
+        IndexKey::Number(Number::new(n as f64))
+    }
+}
+
+impl fmt::Display for IndexKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.to_json())
+    }
+}
+
+/// How an index stores its keys.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IndexKind {
+    /// supports equality lookups only.
+    Hash,
+    /// keeps keys sorted and also supports range scans.
+    Ordered,
+    /// a full-text index over string fields, see [`text`].
+    Text,
+}
+
+/// The declaration of a secondary index over one or more JSON paths or [key
+/// expressions](KeyExpr).
+///
+/// An index over several keys is a composite index, whose key is an [`IndexKey::Array`] of
+/// the component values in declaration order. Documents where a key is missing, or points at an
+/// object, are left out of the index, as are documents not matching the [filter](Self::filter)
+/// of a partial index.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexDef {
+    pub name: String,
+    pub kind: IndexKind,
+    pub keys: Vec<KeyExpr>,
+    /// reject writes that would store two documents under the same key.
+    pub unique: bool,
+    /// only index documents matching this predicate.
+    pub filter: Option<Predicate>,
+}
+
+impl IndexDef {
+    pub fn hash(name: impl Into<String>, key: impl Into<KeyExpr>) -> Self {
+        Self::new(name, IndexKind::Hash, key.into())
+    }
+
+    pub fn ordered(name: impl Into<String>, key: impl Into<KeyExpr>) -> Self {
+        Self::new(name, IndexKind::Ordered, key.into())
+    }
+
+    /// A full-text index over the string at `key`. Use [`and`](Self::and) to index more
+    /// fields; arrays of strings are indexed element by element.
+    pub fn text(name: impl Into<String>, key: impl Into<KeyExpr>) -> Self {
+        Self::new(name, IndexKind::Text, key.into())
+    }
+
+    fn new(name: impl Into<String>, kind: IndexKind, key: KeyExpr) -> Self {
+        IndexDef {
+            name: name.into(),
+            kind,
+            keys: vec![key],
+            unique: false,
+            filter: None,
+        }
+    }
+
+    /// Add another key, making this a composite index.
+    pub fn and(mut self, key: impl Into<KeyExpr>) -> Self {
+        self.keys.push(key.into());
+        self
+    }
+
+    /// Make this a partial index, holding only the documents matching `predicate`.
+    ///
+    /// A partial unique index only enforces uniqueness among the matching documents.
+    pub fn filter(mut self, predicate: Predicate) -> Self {
+        self.filter = Some(predicate);
+        self
+    }
+
+    /// Make this a unique index.
+    ///
+    /// Uniqueness is only enforced by the write path of a
+    /// [`Collection`](crate::collection::Collection). Keys that are `null`, or composite keys
+    /// with a `null` component, never conflict.
+    pub fn unique(mut self) -> Self {
+        self.unique = true;
+        self
+    }
+
+    pub fn is_composite(&self) -> bool {
+        self.keys.len() > 1
+    }
+
+    pub fn is_partial(&self) -> bool {
+        self.filter.is_some()
+    }
+
+    /// Returns true if `doc` passes the filter of a partial index.
+    pub fn covers(&self, doc: &Value) -> bool {
+        self.filter
+            .as_ref()
+            .is_none_or(|filter| filter.matches(doc))
+    }
+
+    /// the key `doc` is stored under, if it is indexed at all.
+    ///
+    /// For text indexes this is the array of field strings.
+    pub fn key_of(&self, doc: &Value) -> Option<IndexKey> {
+        if !self.covers(doc) {
+            return None;
+        }
+        if self.kind == IndexKind::Text {
+            let mut texts = Vec::new();
+            for key in &self.keys {
+                match key {
+                    KeyExpr::Path(path) => match path.get(doc) {
+                        Some(Value::String(s)) => texts.push(IndexKey::String(s.clone())),
+                        Some(Value::Array(items)) => {
+                            texts.extend(items.iter().filter_map(Value::as_str).map(IndexKey::from))
+                        }
+                        _ => {}
+                    },
+                    expr => texts.extend(
+                        expr.eval(doc)
+                            .filter(|key| matches!(key, IndexKey::String(_))),
+                    ),
+                }
+            }
+            return (!texts.is_empty()).then_some(IndexKey::Array(texts));
+        }
+        if let [key] = self.keys.as_slice() {
+            return key.eval(doc);
+        }
+        let components = self
+            .keys
+            .iter()
+            .map(|key| key.eval(doc))
+            .collect::<Option<_>>()?;
+        Some(IndexKey::Array(components))
+    }
+
+    /// the key `doc` has to be unique under, if any.
+    pub fn unique_key_of(&self, doc: &Value) -> Option<IndexKey> {
+        let key = self.key_of(doc)?;
+        let has_null = match &key {
+            IndexKey::Null => true,
+            IndexKey::Array(components) if self.is_composite() => {
+                components.contains(&IndexKey::Null)
+            }
+            _ => false,
+        };
+        (!has_null).then_some(key)
+    }
+}
+
+/// Key extractor for JSON maps, see [`WriteHandle::create_index`](crate::rwmap::WriteHandle::create_index).
+pub(crate) fn extract(def: &IndexDef, doc: &Value) -> Option<IndexKey> {
+    def.key_of(doc)
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn key_order() {
+        let mut keys: Vec<_> = [json!("a"), json!(2), json!(null), json!(1.5), json!(true)]
+            .iter()
+            .map(|v| IndexKey::from_json(v).unwrap())
+            .collect();
+        keys.sort();
+        let sorted: Vec<_> = keys.iter().map(IndexKey::to_json).collect();
+        assert_eq!(
+            sorted,
+            vec![json!(null), json!(true), json!(1.5), json!(2), json!("a")]
+        );
+        assert_eq!(
+            IndexKey::from_json(&json!(1)),
+            IndexKey::from_json(&json!(1.0))
+        );
+        assert_eq!(IndexKey::from_json(&json!({"a": 1})), None);
+    }
+}
@@ -0,0 +1,609 @@
+//! Full-text search over string fields: analysis, queries and BM25 ranking.
+//!
+//! Text is split into words on anything that is not alphanumeric, lowercased, stripped of
+//! English stop words and reduced to its stem, so `Running` and `runs` both become `run`.
+//! Stop words keep their position, so phrases match the original word distances.
+use std::fmt;
+use std::hash::Hash;
+use std::ops::Range;
+use std::sync::OnceLock;
+
+use hashbrown::{HashMap, HashSet};
+use rust_stemmers::{Algorithm, Stemmer};
+
+use super::IndexKey;
+
+const STOP_WORDS: &[&str] = &[
+    "a", "an", "and", "are", "as", "at", "be", "but", "by", "for", "if", "in", "into", "is", "it",
+    "no", "not", "of", "on", "or", "such", "that", "the", "their", "then", "there", "these",
+    "they", "this", "to", "was", "will", "with",
+];
+
+/// Gap left between the positions of separate fields, so phrases never span two fields.
+const FIELD_GAP: u32 = 100;
+
+const K1: f64 = 1.2;
+const B: f64 = 0.75;
+
+/// A single analyzed word.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Token {
+    pub term: String,
+    /// word position, counting stop words.
+    pub position: u32,
+    /// byte range of the original word.
+    pub span: Range<usize>,
+}
+
+/// Split `text` into indexable terms.
+pub fn analyze(text: &str) -> Vec<Token> {
+    static STEMMER: OnceLock<Stemmer> = OnceLock::new();
+    let stemmer = STEMMER.get_or_init(|| Stemmer::create(Algorithm::English));
+    let mut tokens = Vec::new();
+    for (position, (start, word)) in words(text).enumerate() {
+        let lower = word.to_lowercase();
+        if !STOP_WORDS.contains(&lower.as_str()) {
+            tokens.push(Token {
+                term: stemmer.stem(&lower).into_owned(),
+                position: position as u32,
+                span: start..start + word.len(),
+            });
+        }
+    }
+    tokens
+}
+
+fn words(text: &str) -> impl Iterator<Item = (usize, &str)> {
+    let mut start = None;
+    let mut chars = text
+        .char_indices()
+        .chain(std::iter::once((text.len(), ' ')));
+    std::iter::from_fn(move || {
+        for (i, c) in chars.by_ref() {
+            match (start, c.is_alphanumeric()) {
+                (None, true) => start = Some(i),
+                (Some(s), false) => {
+                    start = None;
+                    return Some((s, &text[s..i]));
+                }
+                _ => {}
+            }
+        }
+        None
+    })
+}
+
+/// The field texts stored in a text index key, see [`IndexDef::text`](super::IndexDef::text).
+pub(crate) fn field_texts(key: &IndexKey) -> Vec<&str> {
+    match key {
+        IndexKey::String(s) => vec![s.as_str()],
+        IndexKey::Array(items) => items.iter().flat_map(field_texts).collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Analyze every field, offsetting positions so that fields do not touch.
+fn analyze_fields(key: &IndexKey) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut offset = 0;
+    for text in field_texts(key) {
+        let field = analyze(text);
+        let end = field.last().map_or(offset, |t| offset + t.position + 1);
+        tokens.extend(field.into_iter().map(|mut t| {
+            t.position += offset;
+            t
+        }));
+        offset = end + FIELD_GAP;
+    }
+    tokens
+}
+
+/// A search over a text index.
+///
+/// Queries are written as words, `"quoted phrases"`, `AND`, `OR`, `NOT` (or a leading `-`) and
+/// parentheses. Words next to each other must all match, as if joined by `AND`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TextQuery {
+    /// an analyzed term.
+    Term(String),
+    /// analyzed terms with their position relative to the first one.
+    Phrase(Vec<(u32, String)>),
+    And(Vec<TextQuery>),
+    Or(Vec<TextQuery>),
+    Not(Box<TextQuery>),
+}
+
+/// Error returned when a text query does not parse.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextQueryError {
+    pub offset: usize,
+    pub message: String,
+}
+
+impl fmt::Display for TextQueryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "invalid text query at offset {}: {}",
+            self.offset, self.message
+        )
+    }
+}
+
+impl std::error::Error for TextQueryError {}
+
+impl TextQuery {
+    /// Parse a query string, see the [type docs](TextQuery).
+    pub fn parse(query: &str) -> Result<Self, TextQueryError> {
+        let mut parser = QueryParser { src: query, pos: 0 };
+        let parsed = parser.or()?;
+        parser.skip_ws();
+        if parser.pos < query.len() {
+            return Err(parser.error("unexpected input"));
+        }
+        Ok(parsed.unwrap_or(TextQuery::And(Vec::new())))
+    }
+
+    /// A query matching `text` as a phrase, or as a single term if it is one word.
+    pub fn phrase(text: &str) -> Self {
+        let tokens = analyze(text);
+        match tokens.as_slice() {
+            [] => TextQuery::And(Vec::new()),
+            [token] => TextQuery::Term(token.term.clone()),
+            [first, ..] => TextQuery::Phrase(
+                tokens
+                    .iter()
+                    .map(|t| (t.position - first.position, t.term.clone()))
+                    .collect(),
+            ),
+        }
+    }
+
+    /// The terms that contribute to ranking, i.e. those not under a `NOT`.
+    pub fn terms(&self) -> Vec<&str> {
+        let mut terms = Vec::new();
+        self.collect_terms(&mut terms);
+        terms
+    }
+
+    fn collect_terms<'a>(&'a self, terms: &mut Vec<&'a str>) {
+        match self {
+            TextQuery::Term(term) => terms.push(term),
+            TextQuery::Phrase(phrase) => terms.extend(phrase.iter().map(|(_, t)| t.as_str())),
+            TextQuery::And(queries) | TextQuery::Or(queries) => {
+                queries.iter().for_each(|q| q.collect_terms(terms))
+            }
+            TextQuery::Not(_) => {}
+        }
+    }
+}
+
+struct QueryParser<'a> {
+    src: &'a str,
+    pos: usize,
+}
+
+impl<'a> QueryParser<'a> {
+    fn error(&self, message: &str) -> TextQueryError {
+        TextQueryError {
+            offset: self.pos,
+            message: message.to_owned(),
+        }
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.src[self.pos..]
+    }
+
+    fn skip_ws(&mut self) {
+        let rest = self.rest();
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    fn keyword(&mut self, keyword: &str) -> bool {
+        self.skip_ws();
+        let Some(after) = self.rest().strip_prefix(keyword) else {
+            return false;
+        };
+        let boundary = after
+            .chars()
+            .next()
+            .is_none_or(|c| c.is_whitespace() || c == '(' || c == '"');
+        if boundary {
+            self.pos += keyword.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    // `None` stands for a query made only of stop words, which matches everything.
+    fn or(&mut self) -> Result<Option<TextQuery>, TextQueryError> {
+        let mut parts: Vec<_> = self.and()?.into_iter().collect();
+        while self.keyword("OR") {
+            parts.extend(self.and()?);
+        }
+        Ok(match parts.len() {
+            0 => None,
+            1 => parts.pop(),
+            _ => Some(TextQuery::Or(parts)),
+        })
+    }
+
+    fn and(&mut self) -> Result<Option<TextQuery>, TextQueryError> {
+        let mut parts = Vec::new();
+        loop {
+            self.skip_ws();
+            let rest = self.rest();
+            if rest.is_empty() || rest.starts_with(')') || self.peek_keyword("OR") {
+                break;
+            }
+            self.keyword("AND");
+            parts.extend(self.unary()?);
+        }
+        Ok(match parts.len() {
+            0 => None,
+            1 => parts.pop(),
+            _ => Some(TextQuery::And(parts)),
+        })
+    }
+
+    fn peek_keyword(&mut self, keyword: &str) -> bool {
+        let pos = self.pos;
+        let found = self.keyword(keyword);
+        self.pos = pos;
+        found
+    }
+
+    fn unary(&mut self) -> Result<Option<TextQuery>, TextQueryError> {
+        self.skip_ws();
+        if self.keyword("NOT") || self.rest().starts_with('-') {
+            if self.rest().starts_with('-') {
+                self.pos += 1;
+            }
+            let inner = self.unary()?;
+            return Ok(inner.map(|q| TextQuery::Not(Box::new(q))));
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<Option<TextQuery>, TextQueryError> {
+        let rest = self.rest();
+        if rest.starts_with('(') {
+            self.pos += 1;
+            let inner = self.or()?;
+            self.skip_ws();
+            if !self.rest().starts_with(')') {
+                return Err(self.error("expected ')'"));
+            }
+            self.pos += 1;
+            return Ok(inner);
+        }
+        if let Some(quoted) = rest.strip_prefix('"') {
+            let end = quoted
+                .find('"')
+                .ok_or_else(|| self.error("unclosed phrase"))?;
+            self.pos += end + 2;
+            return Ok(Self::non_empty(TextQuery::phrase(&quoted[..end])));
+        }
+        let end = rest
+            .find(|c: char| c.is_whitespace() || c == '(' || c == ')' || c == '"')
+            .unwrap_or(rest.len());
+        if end == 0 {
+            return Err(self.error("expected a word"));
+        }
+        self.pos += end;
+        Ok(Self::non_empty(TextQuery::phrase(&rest[..end])))
+    }
+
+    fn non_empty(query: TextQuery) -> Option<TextQuery> {
+        match query {
+            TextQuery::And(parts) if parts.is_empty() => None,
+            query => Some(query),
+        }
+    }
+}
+
+/// A fragment of a document around the first matching word of a query.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snippet {
+    pub text: String,
+    /// byte ranges of the matching words inside `text`.
+    pub matches: Vec<Range<usize>>,
+}
+
+impl Snippet {
+    /// Render the snippet with every match wrapped in `pre` and `post`, e.g. `<em>` and `</em>`.
+    pub fn highlight(&self, pre: &str, post: &str) -> String {
+        let mut out = String::new();
+        let mut last = 0;
+        for range in &self.matches {
+            out.push_str(&self.text[last..range.start]);
+            out.push_str(pre);
+            out.push_str(&self.text[range.clone()]);
+            out.push_str(post);
+            last = range.end;
+        }
+        out.push_str(&self.text[last..]);
+        out
+    }
+}
+
+/// Build a snippet of at most `max_words` words for the fields in `key`, taken from the field
+/// with the most matching words.
+pub(crate) fn snippet(key: &IndexKey, query: &TextQuery, max_words: usize) -> Option<Snippet> {
+    let terms: HashSet<&str> = query.terms().into_iter().collect();
+    let (text, tokens) = field_texts(key)
+        .into_iter()
+        .map(|text| {
+            let mut tokens = analyze(text);
+            tokens.retain(|t| terms.contains(t.term.as_str()));
+            (text, tokens)
+        })
+        .filter(|(_, tokens)| !tokens.is_empty())
+        .max_by_key(|(_, tokens)| tokens.len())?;
+
+    let words: Vec<_> = words(text).collect();
+    let hit = tokens[0].position as usize;
+    let max_words = max_words.max(1);
+    // start a little before the first match, but use the whole window when the text allows
+    let from = hit
+        .saturating_sub(max_words / 4)
+        .min(words.len().saturating_sub(max_words));
+    let to = (from + max_words).min(words.len());
+    let start = words[from].0;
+    let end = words[to - 1].0 + words[to - 1].1.len();
+
+    let prefix = if from > 0 { "… " } else { "" };
+    let suffix = if to < words.len() { " …" } else { "" };
+    let shift = prefix.len();
+    Some(Snippet {
+        text: format!("{prefix}{}{suffix}", &text[start..end]),
+        matches: tokens
+            .iter()
+            .filter(|t| t.span.start >= start && t.span.end <= end)
+            .map(|t| t.span.start - start + shift..t.span.end - start + shift)
+            .collect(),
+    })
+}
+
+/// An inverted index from terms to the positions they appear at in each value.
+pub(crate) struct Postings<K> {
+    terms: HashMap<String, HashMap<K, Vec<u32>>>,
+    /// number of terms in each value.
+    lengths: HashMap<K, u32>,
+    total_length: u64,
+}
+
+impl<K> Default for Postings<K> {
+    fn default() -> Self {
+        Postings {
+            terms: HashMap::new(),
+            lengths: HashMap::new(),
+            total_length: 0,
+        }
+    }
+}
+
+impl<K> Postings<K>
+where
+    K: Eq + Hash + Clone,
+{
+    /// Returns the number of indexed values.
+    pub(crate) fn len(&self) -> usize {
+        self.lengths.len()
+    }
+
+    pub(crate) fn add(&mut self, k: &K, key: &IndexKey) {
+        let tokens = analyze_fields(key);
+        if tokens.is_empty() {
+            return;
+        }
+        for token in &tokens {
+            self.terms
+                .entry(token.term.clone())
+                .or_default()
+                .entry(k.clone())
+                .or_default()
+                .push(token.position);
+        }
+        self.lengths.insert(k.clone(), tokens.len() as u32);
+        self.total_length += tokens.len() as u64;
+    }
+
+    pub(crate) fn remove(&mut self, k: &K, key: &IndexKey) {
+        let Some(length) = self.lengths.remove(k) else {
+            return;
+        };
+        self.total_length -= length as u64;
+        for token in analyze_fields(key) {
+            if let Some(docs) = self.terms.get_mut(&token.term) {
+                docs.remove(k);
+                if docs.is_empty() {
+                    self.terms.remove(&token.term);
+                }
+            }
+        }
+    }
+
+    /// Every value matching `query` with its BM25 score, best first.
+    pub(crate) fn search(&self, query: &TextQuery) -> Vec<(&K, f64)> {
+        let matched = self.matching(query);
+        let count = self.len() as f64;
+        let average = self.total_length as f64 / count.max(1.0);
+        let terms = query.terms();
+        let mut scored: Vec<_> = matched
+            .into_iter()
+            .map(|k| {
+                let length = self.lengths.get(k).copied().unwrap_or(0) as f64;
+                let score: f64 = terms
+                    .iter()
+                    .filter_map(|term| {
+                        let docs = self.terms.get(*term)?;
+                        let tf = docs.get(k)?.len() as f64;
+                        let df = docs.len() as f64;
+                        let idf = (1.0 + (count - df + 0.5) / (df + 0.5)).ln();
+                        Some(idf * tf * (K1 + 1.0) / (tf + K1 * (1.0 - B + B * length / average)))
+                    })
+                    .sum();
+                (k, score)
+            })
+            .collect();
+        scored.sort_by(|a, b| b.1.total_cmp(&a.1));
+        scored
+    }
+
+    fn docs(&self, term: &str) -> HashSet<&K> {
+        self.terms
+            .get(term)
+            .map(|docs| docs.keys().collect())
+            .unwrap_or_default()
+    }
+
+    fn matching(&self, query: &TextQuery) -> HashSet<&K> {
+        match query {
+            TextQuery::Term(term) => self.docs(term),
+            TextQuery::Phrase(phrase) => self.phrase(phrase),
+            TextQuery::And(queries) => {
+                let (negative, positive): (Vec<_>, Vec<_>) =
+                    queries.iter().partition(|q| matches!(q, TextQuery::Not(_)));
+                let mut docs = match positive.split_first() {
+                    Some((first, rest)) => rest.iter().fold(self.matching(first), |docs, q| {
+                        let other = self.matching(q);
+                        docs.into_iter().filter(|k| other.contains(k)).collect()
+                    }),
+                    None => self.lengths.keys().collect(),
+                };
+                for query in negative {
+                    if let TextQuery::Not(inner) = query {
+                        for k in self.matching(inner) {
+                            docs.remove(k);
+                        }
+                    }
+                }
+                docs
+            }
+            TextQuery::Or(queries) => queries.iter().flat_map(|q| self.matching(q)).collect(),
+            TextQuery::Not(inner) => {
+                let excluded = self.matching(inner);
+                self.lengths
+                    .keys()
+                    .filter(|k| !excluded.contains(k))
+                    .collect()
+            }
+        }
+    }
+
+    fn phrase(&self, phrase: &[(u32, String)]) -> HashSet<&K> {
+        let Some(((_, first), rest)) = phrase.split_first() else {
+            return HashSet::new();
+        };
+        let Some(starts) = self.terms.get(first) else {
+            return HashSet::new();
+        };
+        starts
+            .iter()
+            .filter(|(k, positions)| {
+                positions.iter().any(|start| {
+                    rest.iter().all(|(offset, term)| {
+                        self.terms
+                            .get(term)
+                            .and_then(|docs| docs.get(*k))
+                            .is_some_and(|p| p.contains(&(start + offset)))
+                    })
+                })
+            })
+            .map(|(k, _)| k)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn index(docs: &[(&str, &str)]) -> Postings<String> {
+        let mut postings = Postings::default();
+        for (id, text) in docs {
+            postings.add(&id.to_string(), &IndexKey::from(*text));
+        }
+        postings
+    }
+
+    fn ids(postings: &Postings<String>, query: &str) -> Vec<String> {
+        let query = TextQuery::parse(query).unwrap();
+        postings
+            .search(&query)
+            .into_iter()
+            .map(|(k, _)| k.clone())
+            .collect()
+    }
+
+    #[test]
+    fn analyzes_words() {
+        let terms: Vec<_> = analyze("The Quick foxes, running!")
+            .into_iter()
+            .map(|t| (t.term, t.position))
+            .collect();
+        assert_eq!(
+            terms,
+            vec![("quick".into(), 1), ("fox".into(), 2), ("run".into(), 3)]
+        );
+    }
+
+    #[test]
+    fn parses_queries() {
+        assert_eq!(
+            TextQuery::parse("red -\"the blue car\" OR green").unwrap(),
+            TextQuery::Or(vec![
+                TextQuery::And(vec![
+                    TextQuery::Term("red".into()),
+                    TextQuery::Not(Box::new(TextQuery::Phrase(vec![
+                        (0, "blue".into()),
+                        (1, "car".into())
+                    ])))
+                ]),
+                TextQuery::Term("green".into())
+            ])
+        );
+        assert!(TextQuery::parse("(red").is_err());
+        assert!(TextQuery::parse("\"red").is_err());
+    }
+
+    #[test]
+    fn boolean_and_phrase_search() {
+        let postings = index(&[
+            ("1", "a red car and a blue bike"),
+            ("2", "the blue car"),
+            ("3", "red bikes"),
+        ]);
+        assert_eq!(ids(&postings, "\"blue car\""), vec!["2"]);
+        let mut red: Vec<_> = ids(&postings, "red");
+        red.sort();
+        assert_eq!(red, vec!["1", "3"]);
+        assert_eq!(ids(&postings, "red -car"), vec!["3"]);
+        assert_eq!(ids(&postings, "car AND bike"), vec!["1"]);
+        assert_eq!(ids(&postings, "NOT red"), vec!["2"]);
+    }
+
+    #[test]
+    fn ranks_by_bm25() {
+        let postings = index(&[
+            (
+                "long",
+                "rust is a language and rust is fast but this text goes on and on",
+            ),
+            ("short", "rust rust"),
+            ("none", "python"),
+        ]);
+        assert_eq!(ids(&postings, "rust"), vec!["short", "long"]);
+    }
+
+    #[test]
+    fn builds_snippets() {
+        let key = IndexKey::from("one two three four five six Seven eight nine ten");
+        let query = TextQuery::parse("seven").unwrap();
+        let snippet = snippet(&key, &query, 4).unwrap();
+        assert_eq!(snippet.highlight("[", "]"), "… six [Seven] eight nine …");
+    }
+}
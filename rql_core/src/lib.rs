@@ -1,2 +1,9 @@
 #![allow(dead_code)]
+pub mod collection;
+pub mod database;
+pub mod encode;
+pub mod index;
+pub mod path;
+pub mod query;
 pub mod rwmap;
+pub mod schema;
@@ -0,0 +1,310 @@
+use std::fmt;
+use std::str::FromStr;
+
+use serde_json::Value;
+
+/// A single step of a [`Path`], either an object key or an array index.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Segment {
+    Key(String),
+    Index(usize),
+}
+
+/// A location inside a [`serde_json::Value`] document.
+///
+/// Paths are written in dotted form, `address.city` or `items[0].sku`. Keys that are not plain
+/// identifiers can be quoted, `meta["created at"]`. An empty path refers to the document itself
+/// and displays as `$`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Path(Vec<Segment>);
+
+/// Error returned when a string is not a valid [`Path`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PathError {
+    /// byte offset into the parsed string.
+    pub offset: usize,
+    pub message: String,
+}
+
+impl fmt::Display for PathError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "invalid path at offset {}: {}",
+            self.offset, self.message
+        )
+    }
+}
+
+impl std::error::Error for PathError {}
+
+impl Path {
+    /// the path pointing at the whole document.
+    pub fn root() -> Self {
+        Path(Vec::new())
+    }
+
+    pub fn from_segments(segments: Vec<Segment>) -> Self {
+        Path(segments)
+    }
+
+    /// Parse a dotted path such as `a.b[0]["c d"]`.
+    ///
+    /// Segments made only of digits are treated as array indexes, so `tags.0` and `tags[0]` are
+    /// the same path.
+    pub fn parse(s: &str) -> Result<Self, PathError> {
+        let bytes = s.as_bytes();
+        let mut segments = Vec::new();
+        let mut i = 0;
+        let err = |offset, message: &str| PathError {
+            offset,
+            message: message.to_owned(),
+        };
+        if s == "$" {
+            return Ok(Path::root());
+        }
+        let mut expect_key = true;
+        while i < bytes.len() {
+            match bytes[i] {
+                b'.' if !expect_key => {
+                    i += 1;
+                    expect_key = true;
+                    if i == bytes.len() {
+                        return Err(err(i, "expected a key after '.'"));
+                    }
+                }
+                b'.' => return Err(err(i, "empty key")),
+                b'[' => {
+                    let close =
+                        Self::closing_bracket(s, i).ok_or_else(|| err(i, "unclosed '['"))?;
+                    let inner = s[i + 1..close].trim();
+                    if inner.starts_with('"') {
+                        let key: String = serde_json::from_str(inner)
+                            .map_err(|_| err(i + 1, "invalid quoted key"))?;
+                        segments.push(Segment::Key(key));
+                    } else {
+                        let index = inner
+                            .parse::<usize>()
+                            .map_err(|_| err(i + 1, "expected an array index or quoted key"))?;
+                        segments.push(Segment::Index(index));
+                    }
+                    i = close + 1;
+                    expect_key = false;
+                }
+                _ if expect_key => {
+                    let end = s[i..].find(['.', '[']).map(|p| p + i).unwrap_or(s.len());
+                    segments.push(Self::plain_segment(&s[i..end]));
+                    i = end;
+                    expect_key = false;
+                }
+                _ => return Err(err(i, "expected '.' or '['")),
+            }
+        }
+        Ok(Path(segments))
+    }
+
+    /// The offset of the `]` closing the `[` at `open`, skipping a quoted key and its escapes.
+    fn closing_bracket(s: &str, open: usize) -> Option<usize> {
+        let bytes = s.as_bytes();
+        let mut i = open + 1;
+        while i < bytes.len() && bytes[i].is_ascii_whitespace() {
+            i += 1;
+        }
+        if bytes.get(i) == Some(&b'"') {
+            i += 1;
+            loop {
+                match bytes.get(i)? {
+                    b'\\' => i += 2,
+                    b'"' => break,
+                    _ => i += 1,
+                }
+            }
+        }
+        s[i..].find(']').map(|p| p + i)
+    }
+
+    fn plain_segment(key: &str) -> Segment {
+        if !key.is_empty() && key.bytes().all(|b| b.is_ascii_digit()) {
+            if let Ok(index) = key.parse() {
+                return Segment::Index(index);
+            }
+        }
+        Segment::Key(key.to_owned())
+    }
+
+    pub fn segments(&self) -> &[Segment] {
+        &self.0
+    }
+
+    pub fn is_root(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn push(&mut self, segment: Segment) {
+        self.0.push(segment);
+    }
+
+    pub fn pop(&mut self) -> Option<Segment> {
+        self.0.pop()
+    }
+
+    /// Returns a new path with `key` appended.
+    pub fn key(&self, key: impl Into<String>) -> Self {
+        let mut path = self.clone();
+        path.push(Segment::Key(key.into()));
+        path
+    }
+
+    /// Returns a new path with `index` appended.
+    pub fn index(&self, index: usize) -> Self {
+        let mut path = self.clone();
+        path.push(Segment::Index(index));
+        path
+    }
+
+    /// Returns true if `self` is `other` or one of its ancestors.
+    pub fn is_prefix_of(&self, other: &Path) -> bool {
+        other.0.starts_with(&self.0)
+    }
+
+    /// Look up the value this path points at.
+    ///
+    /// An index segment applied to an object looks up the key with the same digits.
+    pub fn get<'a>(&self, value: &'a Value) -> Option<&'a Value> {
+        self.0
+            .iter()
+            .try_fold(value, |value, segment| match segment {
+                Segment::Key(key) => value.as_object()?.get(key),
+                Segment::Index(index) => match value {
+                    Value::Array(items) => items.get(*index),
+                    Value::Object(map) => map.get(&index.to_string()),
+                    _ => None,
+                },
+            })
+    }
+
+    /// Mutable version of [`get`](Self::get).
+    pub fn get_mut<'a>(&self, value: &'a mut Value) -> Option<&'a mut Value> {
+        self.0
+            .iter()
+            .try_fold(value, |value, segment| match segment {
+                Segment::Key(key) => value.as_object_mut()?.get_mut(key),
+                Segment::Index(index) => match value {
+                    Value::Array(items) => items.get_mut(*index),
+                    Value::Object(map) => map.get_mut(&index.to_string()),
+                    _ => None,
+                },
+            })
+    }
+
+    /// Render the path as a [JSON pointer](https://www.rfc-editor.org/rfc/rfc6901).
+    pub fn to_pointer(&self) -> String {
+        let mut out = String::new();
+        for segment in &self.0 {
+            out.push('/');
+            match segment {
+                Segment::Key(key) => out.push_str(&key.replace('~', "~0").replace('/', "~1")),
+                Segment::Index(index) => out.push_str(&index.to_string()),
+            }
+        }
+        out
+    }
+}
+
+fn is_identifier(key: &str) -> bool {
+    let mut chars = key.chars();
+    matches!(chars.next(), Some(c) if c.is_alphabetic() || c == '_')
+        && chars.all(|c| c.is_alphanumeric() || c == '_')
+}
+
+impl fmt::Display for Path {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.0.is_empty() {
+            return f.write_str("$");
+        }
+        for (i, segment) in self.0.iter().enumerate() {
+            match segment {
+                Segment::Key(key) if is_identifier(key) => {
+                    if i > 0 {
+                        f.write_str(".")?;
+                    }
+                    f.write_str(key)?;
+                }
+                Segment::Key(key) => write!(f, "[{}]", Value::String(key.clone()))?,
+                Segment::Index(index) => write!(f, "[{index}]")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Path {
+    type Err = PathError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Path::parse(s)
+    }
+}
+
+impl From<Vec<Segment>> for Path {
+    fn from(segments: Vec<Segment>) -> Self {
+        Path(segments)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn parse_and_display() {
+        let path = Path::parse("items[0].sku").unwrap();
+        assert_eq!(
+            path.segments(),
+            &[
+                Segment::Key("items".into()),
+                Segment::Index(0),
+                Segment::Key("sku".into())
+            ]
+        );
+        assert_eq!(path.to_string(), "items[0].sku");
+        assert_eq!(Path::parse("items.0.sku").unwrap(), path);
+
+        let quoted = Path::parse(r#"meta["created at"]"#).unwrap();
+        assert_eq!(quoted.to_string(), r#"meta["created at"]"#);
+        assert_eq!(Path::root().to_string(), "$");
+        assert!(Path::parse("a..b").is_err());
+        assert!(Path::parse("a[").is_err());
+
+        let bracketed = Path::parse(r#"a["x]y"]["q\"]"].b"#).unwrap();
+        assert_eq!(
+            bracketed.segments(),
+            &[
+                Segment::Key("a".into()),
+                Segment::Key("x]y".into()),
+                Segment::Key("q\"]".into()),
+                Segment::Key("b".into())
+            ]
+        );
+        assert_eq!(Path::parse(&bracketed.to_string()).unwrap(), bracketed);
+        assert!(Path::parse(r#"a["x]"#).is_err());
+    }
+
+    #[test]
+    fn lookup() {
+        let doc = json!({"items": [{"sku": "x1"}], "a/b": 1});
+        let path = Path::parse("items[0].sku").unwrap();
+        assert_eq!(path.get(&doc), Some(&json!("x1")));
+        assert_eq!(Path::parse("items[3]").unwrap().get(&doc), None);
+        assert_eq!(Path::root().key("a/b").to_pointer(), "/a~1b");
+    }
+}
@@ -0,0 +1,413 @@
+//! The `group by` stage: folds matching documents into one row per group.
+//!
+//! Expressions in `select`, `having` and `order by` of an aggregating query do not see the
+//! source documents. They are rewritten to read from a synthetic row per group that holds the
+//! value of each `group by` expression and the result of each aggregate, so every path in them
+//! must be part of a `group by` expression or the argument of an aggregate.
+//!
+//! * `count(*)` counts rows, `count(x)` the rows where `x` is neither missing nor `null`.
+//! * `sum` and `avg` only look at numbers and give `null` when there are none. `sum` stays an
+//!   integer until it overflows.
+//! * `min` and `max` compare values with [`sort_cmp`] and skip `null`.
+//! * `array_agg` collects every value that is not missing, `null` included, in scan order.
+//! * `distinct` makes an aggregate skip values equal to one it has already seen.
+//!
+//! Groups are formed by equality of the `group by` values, so `1` and `1.0` share a group but a
+//! missing value and `null` do not. Apart from `distinct` and `array_agg`, which keep what they
+//! have seen, each group holds a fixed amount of state however many rows it folds.
+use std::borrow::Cow;
+
+use hashbrown::{HashMap, HashSet};
+use serde_json::{Map, Number, Value};
+
+use super::ast::{AggregateFunc, Expr, Query, Select};
+use super::eval::{canonical, check_prepared, eval};
+use super::{sort_cmp, QueryError};
+use crate::path::Path;
+
+#[derive(Debug, Clone, PartialEq)]
+struct Aggregate {
+    func: AggregateFunc,
+    arg: Option<Expr>,
+    distinct: bool,
+}
+
+/// One finished group: its synthetic row and a text that identifies it among the others.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Group {
+    pub(crate) key: String,
+    pub(crate) row: Value,
+}
+
+/// The `group by` expressions and aggregates of a query.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub(crate) struct Grouping {
+    keys: Vec<Expr>,
+    aggregates: Vec<Aggregate>,
+}
+
+impl Grouping {
+    /// Returns true if `query` folds rows into groups.
+    pub(crate) fn applies(query: &Query) -> bool {
+        let select = match &query.select {
+            Select::All => false,
+            Select::Fields(items) => items.iter().any(|item| item.expr.has_aggregate()),
+        };
+        !query.group_by.is_empty()
+            || query.having.is_some()
+            || select
+            || query
+                .order_by
+                .iter()
+                .any(|order| order.expr.has_aggregate())
+    }
+
+    pub(crate) fn new(group_by: &[Expr]) -> Result<Self, QueryError> {
+        for key in group_by {
+            check_prepared(key)?;
+        }
+        Ok(Grouping {
+            keys: group_by.to_vec(),
+            aggregates: Vec::new(),
+        })
+    }
+
+    /// Rewrite `expr` to evaluate against the synthetic row of a group.
+    pub(crate) fn rewrite(&mut self, expr: &Expr) -> Result<Expr, QueryError> {
+        if let Some(i) = self.keys.iter().position(|key| key == expr) {
+            return Ok(Expr::Path(Path::root().key(format!("#k{i}"))));
+        }
+        match expr {
+            Expr::Aggregate {
+                func,
+                arg,
+                distinct,
+            } => {
+                if let Some(arg) = arg {
+                    check_prepared(arg)?;
+                }
+                let aggregate = Aggregate {
+                    func: *func,
+                    arg: arg.as_deref().cloned(),
+                    distinct: *distinct,
+                };
+                let i = match self.aggregates.iter().position(|a| a == &aggregate) {
+                    Some(i) => i,
+                    None => {
+                        self.aggregates.push(aggregate);
+                        self.aggregates.len() - 1
+                    }
+                };
+                Ok(Expr::Path(Path::root().key(format!("#a{i}"))))
+            }
+            Expr::Path(_) | Expr::Exists { .. } | Expr::Quantified { .. } => {
+                Err(QueryError::Invalid(format!(
+                    "`{expr}` must be grouped by or used in an aggregate"
+                )))
+            }
+            _ => {
+                let expr = expr
+                    .clone()
+                    .try_map_children(|child| self.rewrite(&child))?;
+                check_prepared(&expr)?;
+                Ok(expr)
+            }
+        }
+    }
+
+    /// The grouping with `f` applied to its keys and the arguments of its aggregates.
+    pub(crate) fn try_map_exprs(
+        &self,
+        f: &mut impl FnMut(&Expr) -> Result<Expr, QueryError>,
+    ) -> Result<Self, QueryError> {
+        let keys = self.keys.iter().map(&mut *f).collect::<Result<_, _>>()?;
+        let aggregates = self
+            .aggregates
+            .iter()
+            .map(|aggregate| {
+                Ok(Aggregate {
+                    arg: aggregate.arg.as_ref().map(&mut *f).transpose()?,
+                    ..aggregate.clone()
+                })
+            })
+            .collect::<Result<_, QueryError>>()?;
+        Ok(Grouping { keys, aggregates })
+    }
+
+    /// Fold `docs` into groups, in the order each group is first seen.
+    ///
+    /// Without `group by` expressions all documents form one group, which exists even when
+    /// there are no documents.
+    pub(crate) fn run<'a>(&self, docs: impl Iterator<Item = Cow<'a, Value>>) -> Vec<Group> {
+        let mut index: HashMap<String, usize> = HashMap::new();
+        let mut groups: Vec<(String, Vec<Option<Value>>, Vec<Accumulator>)> = Vec::new();
+        if self.keys.is_empty() {
+            groups.push((String::new(), Vec::new(), self.accumulators()));
+        }
+        for doc in docs {
+            let i = if self.keys.is_empty() {
+                0
+            } else {
+                let keys: Vec<Option<Value>> = self
+                    .keys
+                    .iter()
+                    .map(|key| eval(key, &doc).map(|value| value.into_owned()))
+                    .collect();
+                let text = group_key(&keys);
+                match index.get(&text) {
+                    Some(i) => *i,
+                    None => {
+                        index.insert(text.clone(), groups.len());
+                        groups.push((text, keys, self.accumulators()));
+                        groups.len() - 1
+                    }
+                }
+            };
+            for (accumulator, aggregate) in groups[i].2.iter_mut().zip(&self.aggregates) {
+                accumulator.update(aggregate, &doc);
+            }
+        }
+        groups
+            .into_iter()
+            .map(|(key, keys, accumulators)| {
+                let mut row = Map::new();
+                for (i, value) in keys.into_iter().enumerate() {
+                    if let Some(value) = value {
+                        row.insert(format!("#k{i}"), value);
+                    }
+                }
+                for (i, accumulator) in accumulators.into_iter().enumerate() {
+                    row.insert(format!("#a{i}"), accumulator.finish());
+                }
+                Group {
+                    key,
+                    row: Value::Object(row),
+                }
+            })
+            .collect()
+    }
+
+    fn accumulators(&self) -> Vec<Accumulator> {
+        self.aggregates.iter().map(Accumulator::new).collect()
+    }
+}
+
+/// A text equal for two lists of `group by` values exactly when the values are equal.
+fn group_key(keys: &[Option<Value>]) -> String {
+    keys.iter()
+        .map(|key| key.as_ref().map_or_else(|| "-".to_owned(), canonical))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Sum {
+    Int(i64),
+    Float(f64),
+}
+
+impl Sum {
+    fn add(self, n: &Number) -> Sum {
+        match (self, n.as_i64()) {
+            (Sum::Int(a), Some(b)) => match a.checked_add(b) {
+                Some(sum) => Sum::Int(sum),
+                None => Sum::Float(a as f64 + b as f64),
+            },
+            (Sum::Int(a), None) => Sum::Float(a as f64 + n.as_f64().unwrap_or(0.0)),
+            (Sum::Float(a), _) => Sum::Float(a + n.as_f64().unwrap_or(0.0)),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum State {
+    Count(u64),
+    Sum(Option<Sum>),
+    Avg { total: f64, count: u64 },
+    Min(Option<Value>),
+    Max(Option<Value>),
+    ArrayAgg(Vec<Value>),
+}
+
+/// The running state of one aggregate in one group.
+#[derive(Debug, Clone)]
+struct Accumulator {
+    state: State,
+    /// the canonical text of every value seen so far, for `distinct`.
+    seen: Option<HashSet<String>>,
+}
+
+impl Accumulator {
+    fn new(aggregate: &Aggregate) -> Self {
+        let state = match aggregate.func {
+            AggregateFunc::Count => State::Count(0),
+            AggregateFunc::Sum => State::Sum(None),
+            AggregateFunc::Avg => State::Avg {
+                total: 0.0,
+                count: 0,
+            },
+            AggregateFunc::Min => State::Min(None),
+            AggregateFunc::Max => State::Max(None),
+            AggregateFunc::ArrayAgg => State::ArrayAgg(Vec::new()),
+        };
+        Accumulator {
+            state,
+            seen: aggregate.distinct.then(HashSet::new),
+        }
+    }
+
+    fn update(&mut self, aggregate: &Aggregate, doc: &Value) {
+        let Some(arg) = &aggregate.arg else {
+            if let State::Count(n) = &mut self.state {
+                *n += 1;
+            }
+            return;
+        };
+        let Some(value) = eval(arg, doc) else {
+            return;
+        };
+        if value.is_null() && !matches!(self.state, State::ArrayAgg(_)) {
+            return;
+        }
+        if let Some(seen) = &mut self.seen {
+            if !seen.insert(canonical(&value)) {
+                return;
+            }
+        }
+        match (&mut self.state, value.as_ref()) {
+            (State::Count(n), _) => *n += 1,
+            (State::Sum(sum), Value::Number(n)) => {
+                *sum = Some(sum.unwrap_or(Sum::Int(0)).add(n));
+            }
+            (State::Avg { total, count }, Value::Number(n)) => {
+                *total += n.as_f64().unwrap_or(0.0);
+                *count += 1;
+            }
+            (State::Min(min), _) => {
+                if min.as_ref().is_none_or(|min| sort_cmp(&value, min).is_lt()) {
+                    *min = Some(value.into_owned());
+                }
+            }
+            (State::Max(max), _) => {
+                if max.as_ref().is_none_or(|max| sort_cmp(&value, max).is_gt()) {
+                    *max = Some(value.into_owned());
+                }
+            }
+            (State::ArrayAgg(items), _) => items.push(value.into_owned()),
+            (State::Sum(_) | State::Avg { .. }, _) => {}
+        }
+    }
+
+    fn finish(self) -> Value {
+        match self.state {
+            State::Count(n) => Value::from(n),
+            State::Sum(None) => Value::Null,
+            State::Sum(Some(Sum::Int(n))) => Value::from(n),
+            State::Sum(Some(Sum::Float(f))) => {
+                Number::from_f64(f).map_or(Value::Null, Value::Number)
+            }
+            State::Avg { count: 0, .. } => Value::Null,
+            State::Avg { total, count } => {
+                Number::from_f64(total / count as f64).map_or(Value::Null, Value::Number)
+            }
+            State::Min(value) | State::Max(value) => value.unwrap_or(Value::Null),
+            State::ArrayAgg(items) => Value::Array(items),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::*;
+    use crate::query::{parse, parse_expr};
+
+    fn fold(query: &str, docs: &[Value]) -> Vec<Value> {
+        let query = parse(query).unwrap();
+        let mut grouping = Grouping::new(&query.group_by).unwrap();
+        let Select::Fields(items) = &query.select else {
+            panic!("expected a select list");
+        };
+        let exprs: Vec<Expr> = items
+            .iter()
+            .map(|item| grouping.rewrite(&item.expr).unwrap())
+            .collect();
+        grouping
+            .run(docs.iter().map(Cow::Borrowed))
+            .into_iter()
+            .map(|group| {
+                Value::Array(
+                    exprs
+                        .iter()
+                        .map(|expr| {
+                            eval(expr, &group.row).map_or(json!("missing"), |v| v.into_owned())
+                        })
+                        .collect(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn folds_groups() {
+        let docs = [
+            json!({"team": "a", "score": 3, "tag": "x"}),
+            json!({"team": "b", "score": 1.5, "tag": "y"}),
+            json!({"team": "a", "score": null, "tag": "x"}),
+            json!({"team": "a", "score": 4.0, "tag": "z"}),
+            json!({"score": 2}),
+        ];
+        assert_eq!(
+            fold(
+                "from t group by team select team, count(*), count(score), sum(score), \
+                 avg(score), min(tag), max(score), count(distinct tag), array_agg(score)",
+                &docs
+            ),
+            [
+                json!(["a", 3, 2, 7.0, 3.5, "x", 4.0, 2, [3, null, 4.0]]),
+                json!(["b", 1, 1, 1.5, 1.5, "y", 1.5, 1, [1.5]]),
+                json!(["missing", 1, 1, 2, 2.0, null, 2, 0, [2]]),
+            ]
+        );
+        assert_eq!(
+            fold("from t select count(*), sum(score) * 2, sum(nope)", &[]),
+            [json!([0, "missing", null])]
+        );
+        // 3 and 3.0 are the same value
+        assert_eq!(
+            fold(
+                "from t select count(distinct n), sum(distinct n)",
+                &[json!({"n": 3}), json!({"n": 3.0}), json!({"n": 1})]
+            ),
+            [json!([2, 4])]
+        );
+        assert_eq!(
+            fold(
+                "from t select sum(n)",
+                &[json!({"n": i64::MAX}), json!({"n": 1})]
+            ),
+            [json!([i64::MAX as f64 + 1.0])]
+        );
+    }
+
+    #[test]
+    fn rejects_ungrouped_paths() {
+        let query =
+            parse("from t group by coalesce(nick, name) select coalesce(nick, name) as n, age")
+                .unwrap();
+        let mut grouping = Grouping::new(&query.group_by).unwrap();
+        let Select::Fields(items) = &query.select else {
+            unreachable!()
+        };
+        assert!(grouping.rewrite(&items[0].expr).is_ok());
+        assert!(matches!(
+            grouping.rewrite(&items[1].expr),
+            Err(QueryError::Invalid(_))
+        ));
+        assert!(Grouping::new(&parse("from t group by count(*)").unwrap().group_by).is_err());
+        assert!(grouping
+            .rewrite(&parse_expr("sum(max(a))").unwrap())
+            .is_err());
+    }
+}
@@ -0,0 +1,1061 @@
+//! The typed syntax tree of RQL queries.
+//!
+//! Every node prints back as RQL text that parses to the same tree.
+use std::fmt;
+
+use serde_json::Value;
+
+use super::lexer::Keyword;
+use crate::path::{Path, Segment};
+
+/// A read query: `[explain [analyze]] [with <name> as (<query>), ...] from <collection>
+/// [unnest ...] [join ...] [where <expr>] [group by ...] [having <expr>] [select ...]
+/// [order by ...] [limit n] [offset n]`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Query {
+    /// describe how the query runs instead of returning its rows.
+    pub explain: Option<Explain>,
+    /// queries whose rows the rest of the statement reads by name, like collections.
+    pub with: Vec<Cte>,
+    pub from: Source,
+    pub unnest: Vec<Unnest>,
+    pub joins: Vec<Join>,
+    pub filter: Option<Expr>,
+    pub group_by: Vec<Expr>,
+    pub having: Option<Expr>,
+    pub select: Select,
+    pub order_by: Vec<OrderBy>,
+    pub limit: Option<u64>,
+    pub offset: Option<u64>,
+}
+
+impl Query {
+    /// `from <collection>`, returning every document unchanged.
+    pub fn new(collection: impl Into<String>) -> Self {
+        Query {
+            explain: None,
+            with: Vec::new(),
+            from: Source {
+                collection: collection.into(),
+                alias: None,
+            },
+            unnest: Vec::new(),
+            joins: Vec::new(),
+            filter: None,
+            group_by: Vec::new(),
+            having: None,
+            select: Select::All,
+            order_by: Vec::new(),
+            limit: None,
+            offset: None,
+        }
+    }
+
+    /// Replace every expression of the query with `f` of it, including join conditions and
+    /// `order by` keys naming a `select` alias.
+    pub fn try_map_exprs<E>(
+        mut self,
+        mut f: impl FnMut(&Expr) -> Result<Expr, E>,
+    ) -> Result<Query, E> {
+        for join in &mut self.joins {
+            join.on = f(&join.on)?;
+        }
+        self.filter = self.filter.as_ref().map(&mut f).transpose()?;
+        self.group_by = self.group_by.iter().map(&mut f).collect::<Result<_, _>>()?;
+        self.having = self.having.as_ref().map(&mut f).transpose()?;
+        if let Select::Fields(items) = &mut self.select {
+            for item in items {
+                item.expr = f(&item.expr)?;
+            }
+        }
+        for order in &mut self.order_by {
+            order.expr = f(&order.expr)?;
+        }
+        Ok(self)
+    }
+
+    /// Every expression of the query, in the order [`try_map_exprs`](Self::try_map_exprs)
+    /// visits them. Subqueries and `with` queries are not looked into.
+    pub fn exprs(&self) -> Vec<&Expr> {
+        let mut exprs: Vec<&Expr> = self.joins.iter().map(|join| &join.on).collect();
+        exprs.extend(&self.filter);
+        exprs.extend(&self.group_by);
+        exprs.extend(&self.having);
+        if let Select::Fields(items) = &self.select {
+            exprs.extend(items.iter().map(|item| &item.expr));
+        }
+        exprs.extend(self.order_by.iter().map(|order| &order.expr));
+        exprs
+    }
+}
+
+/// `<name> as (<query>)` in the `with` clause of a statement.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cte {
+    pub name: String,
+    pub query: Query,
+}
+
+/// A write to one collection, applied in a single publish.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Mutation {
+    Insert(Insert),
+    Update(Update),
+    Delete(Delete),
+}
+
+impl Mutation {
+    /// The collection written to.
+    pub fn collection(&self) -> &str {
+        match self {
+            Mutation::Insert(insert) => &insert.collection,
+            Mutation::Update(update) => &update.collection,
+            Mutation::Delete(delete) => &delete.collection,
+        }
+    }
+
+    /// Whether the written documents are returned along with the counts.
+    pub fn returning(&self) -> bool {
+        match self {
+            Mutation::Insert(insert) => insert.returning,
+            Mutation::Update(update) => update.returning,
+            Mutation::Delete(delete) => delete.returning,
+        }
+    }
+}
+
+/// `insert into <collection> values (<id>, <doc>), ... [returning]`, or `upsert into ...` to
+/// replace documents that already exist instead of failing.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Insert {
+    pub collection: String,
+    pub upsert: bool,
+    /// the id and document of each row, both constant.
+    pub rows: Vec<(Expr, Expr)>,
+    pub returning: bool,
+}
+
+/// `update <collection> set <path> = <expr>, ... [where <expr>] [returning]`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Update {
+    pub collection: String,
+    /// fields to assign, evaluated against the document before the update. A missing value
+    /// removes the field.
+    pub set: Vec<(Path, Expr)>,
+    pub filter: Option<Expr>,
+    pub returning: bool,
+}
+
+/// `delete from <collection> [where <expr>] [returning]`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Delete {
+    pub collection: String,
+    pub filter: Option<Expr>,
+    pub returning: bool,
+}
+
+/// What an `explain` query returns.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Explain {
+    /// the plan with estimated row counts.
+    Plan,
+    /// the plan after running the query, with actual row counts and timings.
+    Analyze,
+}
+
+/// The collection a query reads, optionally renamed with `as`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Source {
+    pub collection: String,
+    pub alias: Option<String>,
+}
+
+impl Source {
+    /// The name documents of this source are bound to in a join: the alias, or else the
+    /// collection name.
+    pub fn name(&self) -> &str {
+        self.alias.as_deref().unwrap_or(&self.collection)
+    }
+}
+
+/// `unnest <path> [as <name>]`: a row for each element of the array at `path`, holding the
+/// element at `path` itself or, with a name, under `name`. Rows where `path` is not an array,
+/// or an empty one, are dropped.
+///
+/// In a query with joins a name binds the element like a source, and `id(name)` is its
+/// position in the array.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Unnest {
+    pub path: Path,
+    pub alias: Option<String>,
+}
+
+impl Unnest {
+    /// Where each element goes in the row.
+    pub fn target(&self) -> Path {
+        match &self.alias {
+            Some(alias) => Path::root().key(alias.as_str()),
+            None => self.path.clone(),
+        }
+    }
+}
+
+/// `[inner | left] join <collection> [as alias] on <expr>`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Join {
+    pub kind: JoinKind,
+    pub source: Source,
+    pub on: Expr,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JoinKind {
+    /// keeps only rows with a match.
+    Inner,
+    /// keeps rows without a match, leaving the joined document missing.
+    Left,
+}
+
+/// The `select` clause.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Select {
+    /// `select *`, or no `select` clause at all.
+    All,
+    Fields(Vec<SelectItem>),
+}
+
+/// One `<expr> [as <alias>]` item of a `select` clause.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SelectItem {
+    pub expr: Expr,
+    pub alias: Option<String>,
+}
+
+/// One `<expr> [asc|desc] [nulls first|last]` key of an `order by` clause.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OrderBy {
+    pub expr: Expr,
+    pub descending: bool,
+    pub nulls: Option<Nulls>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Nulls {
+    First,
+    Last,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    /// a string, number, boolean or `null`.
+    Literal(Value),
+    /// a field of the current document.
+    Path(Path),
+    /// a `$name` placeholder, replaced by a value before the query runs.
+    Param(String),
+    /// `[a, b, ...]`
+    Array(Vec<Expr>),
+    /// `{key: value, ...}`, leaving out keys whose value is missing.
+    Object(Vec<(String, Expr)>),
+    Unary {
+        op: UnaryOp,
+        expr: Box<Expr>,
+    },
+    Binary {
+        left: Box<Expr>,
+        op: BinaryOp,
+        right: Box<Expr>,
+    },
+    /// `<expr> [not] in (<expr>, ...)`
+    In {
+        expr: Box<Expr>,
+        list: Vec<Expr>,
+        negated: bool,
+    },
+    /// `(<query>)`: the one value the query selects, missing if it returns no row.
+    Subquery(Box<Query>),
+    /// `<expr> [not] in (<query>)`, against the values the query selects.
+    InSubquery {
+        expr: Box<Expr>,
+        query: Box<Query>,
+        negated: bool,
+    },
+    /// `<expr> [not] between <low> and <high>`, bounds included.
+    Between {
+        expr: Box<Expr>,
+        low: Box<Expr>,
+        high: Box<Expr>,
+        negated: bool,
+    },
+    /// `<expr> is [not] null`
+    IsNull {
+        expr: Box<Expr>,
+        negated: bool,
+    },
+    /// `exists(<path>)`, or `missing(<path>)` when negated.
+    Exists {
+        path: Path,
+        negated: bool,
+    },
+    /// `any(<path>, <predicate>)` or `all(<path>, <predicate>)`: whether the predicate holds
+    /// for some or every element of the array at `path`, with `path` standing for the element
+    /// inside the predicate.
+    Quantified {
+        quantifier: Quantifier,
+        path: Path,
+        predicate: Box<Expr>,
+    },
+    /// `name(<expr>, ...)`
+    Call {
+        name: String,
+        args: Vec<Expr>,
+    },
+    /// `func([distinct] <expr>)`, or `count(*)` when `arg` is `None`.
+    Aggregate {
+        func: AggregateFunc,
+        arg: Option<Box<Expr>>,
+        distinct: bool,
+    },
+    /// `case when <cond> then <expr> ... [else <expr>] end`
+    Case {
+        branches: Vec<(Expr, Expr)>,
+        otherwise: Option<Box<Expr>>,
+    },
+    /// `<expr> collate <collation>`: compare and sort strings by their collation key.
+    Collate {
+        expr: Box<Expr>,
+        collation: Collation,
+    },
+}
+
+/// How strings compare. A comparison applies the collation of either side to both.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Collation {
+    /// by code point, the default.
+    Binary,
+    /// by code point after Unicode canonical composition (NFC), so `é` written as one or two
+    /// code points is equal.
+    Nfc,
+    /// like [`Nfc`](Collation::Nfc), ignoring case.
+    NoCase,
+}
+
+impl Collation {
+    const ALL: &'static [(&'static str, Collation)] = &[
+        ("binary", Collation::Binary),
+        ("nfc", Collation::Nfc),
+        ("nocase", Collation::NoCase),
+    ];
+
+    pub fn lookup(name: &str) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, collation)| *collation)
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        Self::ALL
+            .iter()
+            .find(|(_, collation)| collation == self)
+            .map(|(name, _)| *name)
+            .unwrap()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Quantifier {
+    /// true if the predicate is true for some element, false for an empty array.
+    Any,
+    /// true if the predicate is true for every element, true for an empty array.
+    All,
+}
+
+impl Quantifier {
+    pub fn lookup(name: &str) -> Option<Self> {
+        if name.eq_ignore_ascii_case("any") {
+            Some(Quantifier::Any)
+        } else if name.eq_ignore_ascii_case("all") {
+            Some(Quantifier::All)
+        } else {
+            None
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Quantifier::Any => "any",
+            Quantifier::All => "all",
+        }
+    }
+}
+
+/// Functions that fold the rows of a group into one value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AggregateFunc {
+    Count,
+    Sum,
+    Avg,
+    Min,
+    Max,
+    ArrayAgg,
+}
+
+impl AggregateFunc {
+    const ALL: &'static [(&'static str, AggregateFunc)] = &[
+        ("count", AggregateFunc::Count),
+        ("sum", AggregateFunc::Sum),
+        ("avg", AggregateFunc::Avg),
+        ("min", AggregateFunc::Min),
+        ("max", AggregateFunc::Max),
+        ("array_agg", AggregateFunc::ArrayAgg),
+    ];
+
+    pub fn lookup(name: &str) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, func)| *func)
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        Self::ALL
+            .iter()
+            .find(|(_, func)| func == self)
+            .map(|(name, _)| *name)
+            .unwrap()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnaryOp {
+    Not,
+    Neg,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
+    Or,
+    And,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    /// string concatenation, `||`.
+    Concat,
+}
+
+impl BinaryOp {
+    /// Binding strength, higher binds tighter. Every binary operator is left associative.
+    pub(crate) fn precedence(&self) -> u8 {
+        match self {
+            BinaryOp::Or => 1,
+            BinaryOp::And => 2,
+            BinaryOp::Eq
+            | BinaryOp::Ne
+            | BinaryOp::Lt
+            | BinaryOp::Le
+            | BinaryOp::Gt
+            | BinaryOp::Ge => 4,
+            BinaryOp::Add | BinaryOp::Sub | BinaryOp::Concat => 5,
+            BinaryOp::Mul | BinaryOp::Div | BinaryOp::Rem => 6,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BinaryOp::Or => "or",
+            BinaryOp::And => "and",
+            BinaryOp::Eq => "=",
+            BinaryOp::Ne => "!=",
+            BinaryOp::Lt => "<",
+            BinaryOp::Le => "<=",
+            BinaryOp::Gt => ">",
+            BinaryOp::Ge => ">=",
+            BinaryOp::Add => "+",
+            BinaryOp::Sub => "-",
+            BinaryOp::Mul => "*",
+            BinaryOp::Div => "/",
+            BinaryOp::Rem => "%",
+            BinaryOp::Concat => "||",
+        }
+    }
+}
+
+/// precedence of `not`, and of `in`, `between` and `is null` which sit with the comparisons.
+pub(crate) const NOT_PRECEDENCE: u8 = 3;
+pub(crate) const COMPARISON_PRECEDENCE: u8 = 4;
+/// precedence of unary minus.
+pub(crate) const PREFIX_PRECEDENCE: u8 = 7;
+/// precedence of `collate` and anything that never needs parentheses.
+pub(crate) const POSTFIX_PRECEDENCE: u8 = 8;
+
+impl Expr {
+    pub fn path(path: Path) -> Self {
+        Expr::Path(path)
+    }
+
+    pub fn literal(value: impl Into<Value>) -> Self {
+        Expr::Literal(value.into())
+    }
+
+    pub fn binary(left: Expr, op: BinaryOp, right: Expr) -> Self {
+        Expr::Binary {
+            left: Box::new(left),
+            op,
+            right: Box::new(right),
+        }
+    }
+
+    /// the direct subexpressions, in source order. The expressions of a subquery belong to it,
+    /// not to the expression holding it.
+    pub fn children(&self) -> Vec<&Expr> {
+        match self {
+            Expr::Literal(_)
+            | Expr::Path(_)
+            | Expr::Param(_)
+            | Expr::Exists { .. }
+            | Expr::Subquery(_) => Vec::new(),
+            Expr::Array(items) | Expr::Call { args: items, .. } => items.iter().collect(),
+            Expr::Object(fields) => fields.iter().map(|(_, value)| value).collect(),
+            Expr::Aggregate { arg, .. } => arg.iter().map(|arg| &**arg).collect(),
+            Expr::Unary { expr, .. }
+            | Expr::IsNull { expr, .. }
+            | Expr::InSubquery { expr, .. }
+            | Expr::Collate { expr, .. }
+            | Expr::Quantified {
+                predicate: expr, ..
+            } => vec![expr],
+            Expr::Binary { left, right, .. } => vec![left, right],
+            Expr::In { expr, list, .. } => std::iter::once(&**expr).chain(list).collect(),
+            Expr::Between {
+                expr, low, high, ..
+            } => vec![expr, low, high],
+            Expr::Case {
+                branches,
+                otherwise,
+            } => branches
+                .iter()
+                .flat_map(|(when, then)| [when, then])
+                .chain(otherwise.as_deref())
+                .collect(),
+        }
+    }
+
+    /// Rebuild this expression with every direct subexpression replaced by `f` of it.
+    pub fn try_map_children<E>(
+        self,
+        mut f: impl FnMut(Expr) -> Result<Expr, E>,
+    ) -> Result<Expr, E> {
+        let mut boxed = |expr: Box<Expr>| f(*expr).map(Box::new);
+        Ok(match self {
+            Expr::Literal(_)
+            | Expr::Path(_)
+            | Expr::Param(_)
+            | Expr::Exists { .. }
+            | Expr::Subquery(_) => self,
+            Expr::Array(items) => Expr::Array(
+                items
+                    .into_iter()
+                    .map(|e| boxed(Box::new(e)).map(|e| *e))
+                    .collect::<Result<_, E>>()?,
+            ),
+            Expr::Object(fields) => Expr::Object(
+                fields
+                    .into_iter()
+                    .map(|(key, value)| Ok((key, *boxed(Box::new(value))?)))
+                    .collect::<Result<_, E>>()?,
+            ),
+            Expr::Call { name, args } => Expr::Call {
+                name,
+                args: args
+                    .into_iter()
+                    .map(|e| boxed(Box::new(e)).map(|e| *e))
+                    .collect::<Result<_, E>>()?,
+            },
+            Expr::Aggregate {
+                func,
+                arg,
+                distinct,
+            } => Expr::Aggregate {
+                func,
+                arg: arg.map(&mut boxed).transpose()?,
+                distinct,
+            },
+            Expr::Unary { op, expr } => Expr::Unary {
+                op,
+                expr: boxed(expr)?,
+            },
+            Expr::IsNull { expr, negated } => Expr::IsNull {
+                expr: boxed(expr)?,
+                negated,
+            },
+            Expr::Collate { expr, collation } => Expr::Collate {
+                expr: boxed(expr)?,
+                collation,
+            },
+            Expr::Quantified {
+                quantifier,
+                path,
+                predicate,
+            } => Expr::Quantified {
+                quantifier,
+                path,
+                predicate: boxed(predicate)?,
+            },
+            Expr::Binary { left, op, right } => Expr::Binary {
+                left: boxed(left)?,
+                op,
+                right: boxed(right)?,
+            },
+            Expr::In {
+                expr,
+                list,
+                negated,
+            } => Expr::In {
+                expr: boxed(expr)?,
+                list: list
+                    .into_iter()
+                    .map(|e| boxed(Box::new(e)).map(|e| *e))
+                    .collect::<Result<_, E>>()?,
+                negated,
+            },
+            Expr::InSubquery {
+                expr,
+                query,
+                negated,
+            } => Expr::InSubquery {
+                expr: boxed(expr)?,
+                query,
+                negated,
+            },
+            Expr::Between {
+                expr,
+                low,
+                high,
+                negated,
+            } => Expr::Between {
+                expr: boxed(expr)?,
+                low: boxed(low)?,
+                high: boxed(high)?,
+                negated,
+            },
+            Expr::Case {
+                branches,
+                otherwise,
+            } => Expr::Case {
+                branches: branches
+                    .into_iter()
+                    .map(|(when, then)| Ok((*boxed(Box::new(when))?, *boxed(Box::new(then))?)))
+                    .collect::<Result<_, E>>()?,
+                otherwise: otherwise.map(&mut boxed).transpose()?,
+            },
+        })
+    }
+
+    /// Returns true if this expression contains an aggregate.
+    pub fn has_aggregate(&self) -> bool {
+        self.walk(&mut |expr| match expr {
+            Expr::Aggregate { .. } => Err(()),
+            _ => Ok(()),
+        })
+        .is_err()
+    }
+
+    /// Call `f` on this expression and every subexpression, stopping at the first error.
+    pub fn walk<E>(&self, f: &mut impl FnMut(&Expr) -> Result<(), E>) -> Result<(), E> {
+        f(self)?;
+        self.children()
+            .into_iter()
+            .try_for_each(|child| child.walk(f))
+    }
+
+    fn precedence(&self) -> u8 {
+        match self {
+            Expr::Binary { op, .. } => op.precedence(),
+            Expr::Unary {
+                op: UnaryOp::Not, ..
+            } => NOT_PRECEDENCE,
+            Expr::Unary {
+                op: UnaryOp::Neg, ..
+            } => PREFIX_PRECEDENCE,
+            Expr::In { .. }
+            | Expr::InSubquery { .. }
+            | Expr::Between { .. }
+            | Expr::IsNull { .. } => COMPARISON_PRECEDENCE,
+            // a negative number literal prints as `-n`
+            Expr::Literal(Value::Number(n)) if n.as_f64().is_some_and(|n| n < 0.0) => {
+                PREFIX_PRECEDENCE - 1
+            }
+            _ => POSTFIX_PRECEDENCE,
+        }
+    }
+}
+
+impl std::ops::Not for Expr {
+    type Output = Expr;
+
+    fn not(self) -> Expr {
+        Expr::Unary {
+            op: UnaryOp::Not,
+            expr: Box::new(self),
+        }
+    }
+}
+
+/// Writes `expr`, parenthesized when it binds looser than `min`.
+struct Operand<'a>(&'a Expr, u8);
+
+impl fmt::Display for Operand<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.0.precedence() < self.1 {
+            write!(f, "({})", self.0)
+        } else {
+            write!(f, "{}", self.0)
+        }
+    }
+}
+
+fn comma_separated<T: fmt::Display>(f: &mut fmt::Formatter<'_>, items: &[T]) -> fmt::Result {
+    for (i, item) in items.iter().enumerate() {
+        if i > 0 {
+            f.write_str(", ")?;
+        }
+        write!(f, "{item}")?;
+    }
+    Ok(())
+}
+
+/// Writes a field name, quoting it with backticks unless it is a plain identifier.
+pub(crate) struct Name<'a>(pub(crate) &'a str);
+
+impl fmt::Display for Name<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut chars = self.0.chars();
+        let plain = chars.next().is_some_and(|c| c.is_alphabetic() || c == '_')
+            && chars.all(|c| c.is_alphanumeric() || c == '_')
+            && Keyword::lookup(self.0).is_none();
+        if plain {
+            f.write_str(self.0)
+        } else {
+            write!(f, "`{}`", self.0)
+        }
+    }
+}
+
+/// Writes a path in RQL syntax, `a.b[0]`.
+pub(crate) struct RqlPath<'a>(pub(crate) &'a Path);
+
+impl fmt::Display for RqlPath<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, segment) in self.0.segments().iter().enumerate() {
+            match segment {
+                Segment::Key(key) if i == 0 => write!(f, "{}", Name(key))?,
+                Segment::Key(key) => write!(f, ".{}", Name(key))?,
+                Segment::Index(index) if i == 0 => write!(f, "$[{index}]")?,
+                Segment::Index(index) => write!(f, "[{index}]")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let comparison = COMPARISON_PRECEDENCE + 1;
+        match self {
+            Expr::Literal(value) => write!(f, "{value}"),
+            Expr::Path(path) => write!(f, "{}", RqlPath(path)),
+            Expr::Param(name) => write!(f, "${name}"),
+            Expr::Array(items) => {
+                f.write_str("[")?;
+                comma_separated(f, items)?;
+                f.write_str("]")
+            }
+            Expr::Object(fields) => {
+                f.write_str("{")?;
+                for (i, (key, value)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(", ")?;
+                    }
+                    write!(f, "{}: {value}", Name(key))?;
+                }
+                f.write_str("}")
+            }
+            Expr::Unary {
+                op: UnaryOp::Not,
+                expr,
+            } => write!(f, "not {}", Operand(expr, NOT_PRECEDENCE)),
+            Expr::Unary {
+                op: UnaryOp::Neg,
+                expr,
+            } => match **expr {
+                // `--` would start a comment
+                Expr::Unary {
+                    op: UnaryOp::Neg, ..
+                } => write!(f, "-({expr})"),
+                _ => write!(f, "-{}", Operand(expr, PREFIX_PRECEDENCE)),
+            },
+            Expr::Binary { left, op, right } => {
+                let precedence = op.precedence();
+                // comparisons do not chain, so both sides need to bind tighter
+                let left_min = if precedence == COMPARISON_PRECEDENCE {
+                    comparison
+                } else {
+                    precedence
+                };
+                write!(
+                    f,
+                    "{} {} {}",
+                    Operand(left, left_min),
+                    op.as_str(),
+                    Operand(right, precedence + 1)
+                )
+            }
+            Expr::In {
+                expr,
+                list,
+                negated,
+            } => {
+                write!(f, "{} ", Operand(expr, comparison))?;
+                if *negated {
+                    f.write_str("not ")?;
+                }
+                f.write_str("in (")?;
+                comma_separated(f, list)?;
+                f.write_str(")")
+            }
+            Expr::Subquery(query) => write!(f, "({query})"),
+            Expr::InSubquery {
+                expr,
+                query,
+                negated,
+            } => write!(
+                f,
+                "{} {}in ({query})",
+                Operand(expr, comparison),
+                if *negated { "not " } else { "" }
+            ),
+            Expr::Between {
+                expr,
+                low,
+                high,
+                negated,
+            } => write!(
+                f,
+                "{} {}between {} and {}",
+                Operand(expr, comparison),
+                if *negated { "not " } else { "" },
+                Operand(low, comparison),
+                Operand(high, comparison)
+            ),
+            Expr::IsNull { expr, negated } => write!(
+                f,
+                "{} is {}null",
+                Operand(expr, comparison),
+                if *negated { "not " } else { "" }
+            ),
+            Expr::Exists { path, negated } => write!(
+                f,
+                "{}({})",
+                if *negated { "missing" } else { "exists" },
+                RqlPath(path)
+            ),
+            Expr::Quantified {
+                quantifier,
+                path,
+                predicate,
+            } => write!(f, "{}({}, {predicate})", quantifier.as_str(), RqlPath(path)),
+            Expr::Call { name, args } => {
+                write!(f, "{}(", Name(name))?;
+                comma_separated(f, args)?;
+                f.write_str(")")
+            }
+            Expr::Aggregate {
+                func,
+                arg,
+                distinct,
+            } => {
+                write!(f, "{}(", func.as_str())?;
+                if *distinct {
+                    f.write_str("distinct ")?;
+                }
+                match arg {
+                    Some(arg) => write!(f, "{arg})"),
+                    None => f.write_str("*)"),
+                }
+            }
+            Expr::Case {
+                branches,
+                otherwise,
+            } => {
+                f.write_str("case")?;
+                for (when, then) in branches {
+                    write!(f, " when {when} then {then}")?;
+                }
+                if let Some(otherwise) = otherwise {
+                    write!(f, " else {otherwise}")?;
+                }
+                f.write_str(" end")
+            }
+            Expr::Collate { expr, collation } => write!(
+                f,
+                "{} collate {}",
+                Operand(expr, POSTFIX_PRECEDENCE),
+                collation.as_str()
+            ),
+        }
+    }
+}
+
+impl fmt::Display for SelectItem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.expr)?;
+        if let Some(alias) = &self.alias {
+            write!(f, " as {}", Name(alias))?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for OrderBy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.expr)?;
+        if self.descending {
+            f.write_str(" desc")?;
+        }
+        match self.nulls {
+            Some(Nulls::First) => f.write_str(" nulls first"),
+            Some(Nulls::Last) => f.write_str(" nulls last"),
+            None => Ok(()),
+        }
+    }
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", Name(&self.collection))?;
+        if let Some(alias) = &self.alias {
+            write!(f, " as {}", Name(alias))?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Unnest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unnest {}", RqlPath(&self.path))?;
+        if let Some(alias) = &self.alias {
+            write!(f, " as {}", Name(alias))?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Cte {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} as ({})", Name(&self.name), self.query)
+    }
+}
+
+impl fmt::Display for Join {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.kind == JoinKind::Left {
+            f.write_str("left ")?;
+        }
+        write!(f, "join {} on {}", self.source, self.on)
+    }
+}
+
+impl fmt::Display for Query {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.explain {
+            Some(Explain::Plan) => f.write_str("explain ")?,
+            Some(Explain::Analyze) => f.write_str("explain analyze ")?,
+            None => {}
+        }
+        if !self.with.is_empty() {
+            f.write_str("with ")?;
+            comma_separated(f, &self.with)?;
+            f.write_str(" ")?;
+        }
+        write!(f, "from {}", self.from)?;
+        for unnest in &self.unnest {
+            write!(f, " {unnest}")?;
+        }
+        for join in &self.joins {
+            write!(f, " {join}")?;
+        }
+        if let Some(filter) = &self.filter {
+            write!(f, " where {filter}")?;
+        }
+        if !self.group_by.is_empty() {
+            f.write_str(" group by ")?;
+            comma_separated(f, &self.group_by)?;
+        }
+        if let Some(having) = &self.having {
+            write!(f, " having {having}")?;
+        }
+        if let Select::Fields(items) = &self.select {
+            f.write_str(" select ")?;
+            comma_separated(f, items)?;
+        }
+        if !self.order_by.is_empty() {
+            f.write_str(" order by ")?;
+            comma_separated(f, &self.order_by)?;
+        }
+        if let Some(limit) = self.limit {
+            write!(f, " limit {limit}")?;
+        }
+        if let Some(offset) = self.offset {
+            write!(f, " offset {offset}")?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Mutation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let filter = match self {
+            Mutation::Insert(insert) => {
+                let verb = if insert.upsert { "upsert" } else { "insert" };
+                write!(f, "{verb} into {} values ", Name(&insert.collection))?;
+                for (i, (id, doc)) in insert.rows.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(", ")?;
+                    }
+                    write!(f, "({id}, {doc})")?;
+                }
+                None
+            }
+            Mutation::Update(update) => {
+                write!(f, "update {} set ", Name(&update.collection))?;
+                for (i, (path, value)) in update.set.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(", ")?;
+                    }
+                    write!(f, "{} = {value}", RqlPath(path))?;
+                }
+                update.filter.as_ref()
+            }
+            Mutation::Delete(delete) => {
+                write!(f, "delete from {}", Name(&delete.collection))?;
+                delete.filter.as_ref()
+            }
+        };
+        if let Some(filter) = filter {
+            write!(f, " where {filter}")?;
+        }
+        if self.returning() {
+            f.write_str(" returning")?;
+        }
+        Ok(())
+    }
+}
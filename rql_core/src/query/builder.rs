@@ -0,0 +1,693 @@
+//! Building queries in Rust, as the parser would build them from text.
+//!
+//! ```
+//! use rql_core::query::builder::*;
+//! use rql_core::query::{parse, Query};
+//!
+//! let query = Query::from("users")
+//!     .filter(field("age").gt(30).and(field("email").is_not_null()))
+//!     .select(field("name"))
+//!     .select(field("address.city").alias("city"))
+//!     .order_by(field("age").desc().nulls_last())
+//!     .limit(10);
+//! let text = "from users where age > 30 and email is not null \
+//!             select name, address.city as city order by age desc nulls last limit 10";
+//! assert_eq!(query, parse(text).unwrap());
+//! ```
+//!
+//! Values convert into literals, so `field("age").gt(30)` compares with the number 30 and
+//! `field("name").eq("x")` with the string `"x"`.
+use serde_json::Value;
+
+use super::ast::*;
+use super::parser::parse_path;
+use crate::path::Path;
+
+impl Query {
+    /// `from <collection>`, the same as [`Query::new`].
+    #[allow(clippy::should_implement_trait)]
+    pub fn from(collection: impl Into<String>) -> Self {
+        Query::new(collection)
+    }
+
+    /// Add a `with <name> as (<query>)` clause, readable by later ones and the query.
+    pub fn with(mut self, name: impl Into<String>, query: Query) -> Self {
+        self.with.push(Cte {
+            name: name.into(),
+            query,
+        });
+        self
+    }
+
+    /// Name the documents of the collection read from, for joins.
+    pub fn alias(mut self, alias: impl Into<String>) -> Self {
+        self.from.alias = Some(alias.into());
+        self
+    }
+
+    /// Add an `unnest` clause after any earlier ones.
+    pub fn unnest(mut self, unnest: impl Into<Unnest>) -> Self {
+        self.unnest.push(unnest.into());
+        self
+    }
+
+    pub fn join(self, source: impl Into<Source>, on: Expr) -> Self {
+        self.join_kind(JoinKind::Inner, source.into(), on)
+    }
+
+    pub fn left_join(self, source: impl Into<Source>, on: Expr) -> Self {
+        self.join_kind(JoinKind::Left, source.into(), on)
+    }
+
+    fn join_kind(mut self, kind: JoinKind, source: Source, on: Expr) -> Self {
+        self.joins.push(Join { kind, source, on });
+        self
+    }
+
+    /// Add a `where` condition, combined with any earlier one by `and`.
+    pub fn filter(mut self, condition: Expr) -> Self {
+        self.filter = Some(match self.filter {
+            Some(filter) => filter.and(condition),
+            None => condition,
+        });
+        self
+    }
+
+    /// Add a `group by` key after any earlier ones.
+    pub fn group_by(mut self, key: Expr) -> Self {
+        self.group_by.push(key);
+        self
+    }
+
+    /// Add a `having` condition, combined with any earlier one by `and`.
+    pub fn having(mut self, condition: Expr) -> Self {
+        self.having = Some(match self.having {
+            Some(having) => having.and(condition),
+            None => condition,
+        });
+        self
+    }
+
+    /// Add an item to the `select` clause.
+    pub fn select(mut self, item: impl Into<SelectItem>) -> Self {
+        match &mut self.select {
+            Select::All => self.select = Select::Fields(vec![item.into()]),
+            Select::Fields(items) => items.push(item.into()),
+        }
+        self
+    }
+
+    /// Add a sort key after any earlier ones.
+    pub fn order_by(mut self, key: impl Into<OrderBy>) -> Self {
+        self.order_by.push(key.into());
+        self
+    }
+
+    pub fn limit(mut self, limit: u64) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    pub fn offset(mut self, offset: u64) -> Self {
+        self.offset = Some(offset);
+        self
+    }
+
+    /// Describe how the query runs, with actual rows and timings if `analyze`.
+    pub fn explained(mut self, analyze: bool) -> Self {
+        self.explain = Some(if analyze {
+            Explain::Analyze
+        } else {
+            Explain::Plan
+        });
+        self
+    }
+}
+
+impl Source {
+    pub fn new(collection: impl Into<String>) -> Self {
+        Source {
+            collection: collection.into(),
+            alias: None,
+        }
+    }
+
+    pub fn alias(mut self, alias: impl Into<String>) -> Self {
+        self.alias = Some(alias.into());
+        self
+    }
+}
+
+impl From<&str> for Source {
+    fn from(collection: &str) -> Self {
+        Source::new(collection)
+    }
+}
+
+impl Unnest {
+    /// `unnest <path>`, see [`field`] for how paths are written.
+    ///
+    /// # Panics
+    ///
+    /// If `path` is not a valid path.
+    pub fn new(path: &str) -> Self {
+        Unnest {
+            path: path_of(path),
+            alias: None,
+        }
+    }
+
+    pub fn alias(mut self, alias: impl Into<String>) -> Self {
+        self.alias = Some(alias.into());
+        self
+    }
+}
+
+impl From<&str> for Unnest {
+    fn from(path: &str) -> Self {
+        Unnest::new(path)
+    }
+}
+
+/// A field of the document, written as in RQL: `a.b[0]`, with backticks around names that
+/// are not plain identifiers. A leading keyword such as `order` needs no backticks. Use
+/// [`Expr::path`] for paths built at runtime.
+///
+/// # Panics
+///
+/// If `path` is not a valid path.
+pub fn field(path: &str) -> Expr {
+    match parse_path(path) {
+        Ok(parsed) => Expr::Path(parsed),
+        Err(err) => panic!("invalid field path `{path}`: {err}"),
+    }
+}
+
+/// A literal value.
+pub fn lit(value: impl Into<Value>) -> Expr {
+    Expr::literal(value)
+}
+
+/// A `$name` parameter, bound when a [`Prepared`](super::Prepared) query runs.
+pub fn param(name: impl Into<String>) -> Expr {
+    Expr::Param(name.into())
+}
+
+/// `id()`, the id of the document in a query without joins.
+pub fn id() -> Expr {
+    call("id", [])
+}
+
+/// `id(source)`, the id of the document bound to `source` in a join.
+pub fn id_of(source: &str) -> Expr {
+    call("id", [field(source)])
+}
+
+/// A call of a built-in function, such as `coalesce`.
+pub fn call(name: &str, args: impl IntoIterator<Item = Expr>) -> Expr {
+    Expr::Call {
+        name: name.to_lowercase(),
+        args: args.into_iter().collect(),
+    }
+}
+
+/// `exists(<path>)`.
+pub fn exists(path: &str) -> Expr {
+    existence(path, false)
+}
+
+/// `missing(<path>)`.
+pub fn missing(path: &str) -> Expr {
+    existence(path, true)
+}
+
+fn existence(path: &str, negated: bool) -> Expr {
+    Expr::Exists {
+        path: path_of(path),
+        negated,
+    }
+}
+
+fn path_of(path: &str) -> Path {
+    match field(path) {
+        Expr::Path(path) => path,
+        _ => unreachable!(),
+    }
+}
+
+/// `any(<path>, <predicate>)`, where `path` in the predicate stands for each element.
+pub fn any(path: &str, predicate: Expr) -> Expr {
+    quantified(Quantifier::Any, path, predicate)
+}
+
+/// `all(<path>, <predicate>)`, where `path` in the predicate stands for each element.
+pub fn all(path: &str, predicate: Expr) -> Expr {
+    quantified(Quantifier::All, path, predicate)
+}
+
+fn quantified(quantifier: Quantifier, path: &str, predicate: Expr) -> Expr {
+    Expr::Quantified {
+        quantifier,
+        path: path_of(path),
+        predicate: Box::new(predicate),
+    }
+}
+
+/// `(<query>)`, the single value selected by a query returning at most one row.
+pub fn subquery(query: Query) -> Expr {
+    Expr::Subquery(Box::new(query))
+}
+
+/// `[a, b, ...]`
+pub fn array(items: impl IntoIterator<Item = Expr>) -> Expr {
+    Expr::Array(items.into_iter().collect())
+}
+
+/// `{key: value, ...}`
+pub fn object<K: Into<String>>(fields: impl IntoIterator<Item = (K, Expr)>) -> Expr {
+    Expr::Object(
+        fields
+            .into_iter()
+            .map(|(key, value)| (key.into(), value))
+            .collect(),
+    )
+}
+
+/// `case when <cond> then <expr> ... [else <expr>] end`
+pub fn case(branches: impl IntoIterator<Item = (Expr, Expr)>, otherwise: Option<Expr>) -> Expr {
+    Expr::Case {
+        branches: branches.into_iter().collect(),
+        otherwise: otherwise.map(Box::new),
+    }
+}
+
+fn aggregate(func: AggregateFunc, arg: Expr) -> Expr {
+    Expr::Aggregate {
+        func,
+        arg: Some(Box::new(arg)),
+        distinct: false,
+    }
+}
+
+/// `count(*)`
+pub fn count_all() -> Expr {
+    Expr::Aggregate {
+        func: AggregateFunc::Count,
+        arg: None,
+        distinct: false,
+    }
+}
+
+pub fn count(arg: Expr) -> Expr {
+    aggregate(AggregateFunc::Count, arg)
+}
+
+pub fn sum(arg: Expr) -> Expr {
+    aggregate(AggregateFunc::Sum, arg)
+}
+
+pub fn avg(arg: Expr) -> Expr {
+    aggregate(AggregateFunc::Avg, arg)
+}
+
+pub fn min(arg: Expr) -> Expr {
+    aggregate(AggregateFunc::Min, arg)
+}
+
+pub fn max(arg: Expr) -> Expr {
+    aggregate(AggregateFunc::Max, arg)
+}
+
+pub fn array_agg(arg: Expr) -> Expr {
+    aggregate(AggregateFunc::ArrayAgg, arg)
+}
+
+impl Expr {
+    fn compared(self, op: BinaryOp, other: impl Into<Expr>) -> Expr {
+        Expr::binary(self, op, other.into())
+    }
+
+    pub fn eq(self, other: impl Into<Expr>) -> Expr {
+        self.compared(BinaryOp::Eq, other)
+    }
+
+    pub fn ne(self, other: impl Into<Expr>) -> Expr {
+        self.compared(BinaryOp::Ne, other)
+    }
+
+    pub fn lt(self, other: impl Into<Expr>) -> Expr {
+        self.compared(BinaryOp::Lt, other)
+    }
+
+    pub fn le(self, other: impl Into<Expr>) -> Expr {
+        self.compared(BinaryOp::Le, other)
+    }
+
+    pub fn gt(self, other: impl Into<Expr>) -> Expr {
+        self.compared(BinaryOp::Gt, other)
+    }
+
+    pub fn ge(self, other: impl Into<Expr>) -> Expr {
+        self.compared(BinaryOp::Ge, other)
+    }
+
+    pub fn and(self, other: Expr) -> Expr {
+        Expr::binary(self, BinaryOp::And, other)
+    }
+
+    pub fn or(self, other: Expr) -> Expr {
+        Expr::binary(self, BinaryOp::Or, other)
+    }
+
+    /// `self || other`, string concatenation.
+    pub fn concat(self, other: impl Into<Expr>) -> Expr {
+        self.compared(BinaryOp::Concat, other)
+    }
+
+    /// `self in (a, b, ...)`
+    pub fn in_list<T: Into<Expr>>(self, list: impl IntoIterator<Item = T>) -> Expr {
+        self.membership(list, false)
+    }
+
+    /// `self not in (a, b, ...)`
+    pub fn not_in<T: Into<Expr>>(self, list: impl IntoIterator<Item = T>) -> Expr {
+        self.membership(list, true)
+    }
+
+    /// `self in (<query>)`, against the single value the query selects.
+    pub fn in_query(self, query: Query) -> Expr {
+        Expr::InSubquery {
+            expr: Box::new(self),
+            query: Box::new(query),
+            negated: false,
+        }
+    }
+
+    /// `self not in (<query>)`
+    pub fn not_in_query(self, query: Query) -> Expr {
+        Expr::InSubquery {
+            expr: Box::new(self),
+            query: Box::new(query),
+            negated: true,
+        }
+    }
+
+    fn membership<T: Into<Expr>>(self, list: impl IntoIterator<Item = T>, negated: bool) -> Expr {
+        Expr::In {
+            expr: Box::new(self),
+            list: list.into_iter().map(Into::into).collect(),
+            negated,
+        }
+    }
+
+    /// `self between low and high`, bounds included.
+    pub fn between(self, low: impl Into<Expr>, high: impl Into<Expr>) -> Expr {
+        self.range(low.into(), high.into(), false)
+    }
+
+    pub fn not_between(self, low: impl Into<Expr>, high: impl Into<Expr>) -> Expr {
+        self.range(low.into(), high.into(), true)
+    }
+
+    fn range(self, low: Expr, high: Expr, negated: bool) -> Expr {
+        Expr::Between {
+            expr: Box::new(self),
+            low: Box::new(low),
+            high: Box::new(high),
+            negated,
+        }
+    }
+
+    pub fn is_null(self) -> Expr {
+        Expr::IsNull {
+            expr: Box::new(self),
+            negated: false,
+        }
+    }
+
+    pub fn is_not_null(self) -> Expr {
+        Expr::IsNull {
+            expr: Box::new(self),
+            negated: true,
+        }
+    }
+
+    /// `self collate <collation>`, see [`Collation`].
+    pub fn collate(self, collation: Collation) -> Expr {
+        Expr::Collate {
+            expr: Box::new(self),
+            collation,
+        }
+    }
+
+    /// This expression as a `select` item named `alias`.
+    pub fn alias(self, alias: impl Into<String>) -> SelectItem {
+        SelectItem {
+            expr: self,
+            alias: Some(alias.into()),
+        }
+    }
+
+    /// This expression as an ascending sort key.
+    pub fn asc(self) -> OrderBy {
+        OrderBy::from(self)
+    }
+
+    /// This expression as a descending sort key.
+    pub fn desc(self) -> OrderBy {
+        OrderBy {
+            descending: true,
+            ..OrderBy::from(self)
+        }
+    }
+}
+
+impl OrderBy {
+    pub fn nulls_first(mut self) -> Self {
+        self.nulls = Some(Nulls::First);
+        self
+    }
+
+    pub fn nulls_last(mut self) -> Self {
+        self.nulls = Some(Nulls::Last);
+        self
+    }
+}
+
+impl From<Expr> for SelectItem {
+    fn from(expr: Expr) -> Self {
+        SelectItem { expr, alias: None }
+    }
+}
+
+impl From<Expr> for OrderBy {
+    fn from(expr: Expr) -> Self {
+        OrderBy {
+            expr,
+            descending: false,
+            nulls: None,
+        }
+    }
+}
+
+macro_rules! literals {
+    ($($t:ty),*) => {
+        $(impl From<$t> for Expr {
+            fn from(value: $t) -> Self {
+                Expr::literal(value)
+            }
+        })*
+    };
+}
+
+literals!(Value, bool, i32, i64, u32, u64, f64, &str, String);
+
+macro_rules! arithmetic {
+    ($($trait:ident $method:ident $op:ident),*) => {
+        $(impl<T: Into<Expr>> std::ops::$trait<T> for Expr {
+            type Output = Expr;
+
+            fn $method(self, other: T) -> Expr {
+                Expr::binary(self, BinaryOp::$op, other.into())
+            }
+        })*
+    };
+}
+
+arithmetic!(Add add Add, Sub sub Sub, Mul mul Mul, Div div Div, Rem rem Rem);
+
+impl std::ops::Neg for Expr {
+    type Output = Expr;
+
+    /// Negative number literals fold into a literal, as in the parser.
+    fn neg(self) -> Expr {
+        if let Expr::Literal(Value::Number(n)) = &self {
+            let negated = match n.as_i64().filter(|i| *i != 0) {
+                Some(i) => Some(Value::from(-i)),
+                None => n
+                    .as_f64()
+                    .and_then(|f| serde_json::Number::from_f64(-f))
+                    .map(Value::Number),
+            };
+            if let Some(value) = negated {
+                return Expr::Literal(value);
+            }
+        }
+        Expr::Unary {
+            op: UnaryOp::Neg,
+            expr: Box::new(self),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::query::{parse, parse_expr};
+
+    #[test]
+    fn builds_what_the_parser_builds() {
+        for (built, text) in [
+            (
+                Query::from("users")
+                    .filter(field("age").gt(30))
+                    .order_by(field("name")),
+                "from users where age > 30 order by name",
+            ),
+            (
+                Query::from("orders")
+                    .alias("o")
+                    .join(
+                        Source::new("customers").alias("c"),
+                        id_of("c").eq(field("o.customer")),
+                    )
+                    .left_join("notes", field("notes.email").eq(field("c.email")))
+                    .filter(field("o.total").ge(param("min")))
+                    .filter(!field("c.name").in_list(["x", "y"]))
+                    .select(id_of("o").alias("order"))
+                    .select(field("c.name"))
+                    .select(field("notes.text"))
+                    .order_by(field("o.total").desc().nulls_first())
+                    .limit(5)
+                    .offset(10),
+                "from orders as o join customers as c on id(c) = o.customer \
+                 left join notes on notes.email = c.email \
+                 where o.total >= $min and not c.name in ('x', 'y') \
+                 select id(o) as `order`, c.name, notes.text \
+                 order by o.total desc nulls first limit 5 offset 10",
+            ),
+            (
+                Query::from("orders")
+                    .filter(exists("paid").or(missing("refund")))
+                    .group_by(field("customer"))
+                    .having(count_all().gt(1))
+                    .select(field("customer"))
+                    .select((sum(field("total")) * 2).alias("spent"))
+                    .select(max(field("`first name`")))
+                    .explained(true),
+                "explain analyze from orders where exists(paid) or missing(refund) \
+                 group by customer having count(*) > 1 \
+                 select customer, sum(total) * 2 as spent, max(`first name`)",
+            ),
+            (
+                Query::from("baskets")
+                    .alias("b")
+                    .unnest(Unnest::new("b.items").alias("i"))
+                    .unnest("i.parts")
+                    .join("parts", id_of("parts").eq(field("i.parts")))
+                    .filter(any("b.tags", field("b.tags").eq("x")))
+                    .filter(all(
+                        "i.parts",
+                        call("array_length", [field("i.parts")]).gt(0),
+                    )),
+                "from baskets as b unnest b.items as i unnest i.parts \
+                 join parts on id(parts) = i.parts \
+                 where any(b.tags, b.tags = 'x') and all(i.parts, array_length(i.parts) > 0)",
+            ),
+            (
+                Query::from("users")
+                    .with(
+                        "counts",
+                        Query::from("orders")
+                            .group_by(field("customer"))
+                            .select(field("customer"))
+                            .select(count_all().alias("n")),
+                    )
+                    .alias("u")
+                    .filter(
+                        subquery(
+                            Query::from("counts")
+                                .filter(field("customer").eq(id_of("u")))
+                                .select(field("n")),
+                        )
+                        .gt(subquery(Query::from("counts").select(avg(field("n"))))),
+                    )
+                    .filter(id_of("u").not_in_query(Query::from("banned").select(field("user")))),
+                "with counts as (from orders group by customer select customer, count(*) as n) \
+                 from users as u where (from counts where customer = id(u) select n) > \
+                 (from counts select avg(n)) and id(u) not in (from banned select user)",
+            ),
+        ] {
+            assert_eq!(built, parse(text).unwrap(), "{text}");
+            assert_eq!(parse(&built.to_string()).unwrap(), built);
+        }
+    }
+
+    #[test]
+    fn builds_expressions() {
+        for (built, text) in [
+            (field("a") + 1 - field("b") * 2.5, "a + 1 - b * 2.5"),
+            (-lit(3) % -field("x"), "-3 % -x"),
+            (
+                field("a.b[0]")
+                    .between(1, 2)
+                    .and(field("c").not_between(-1, 1)),
+                "a.b[0] between 1 and 2 and c not between -1 and 1",
+            ),
+            (
+                field("s")
+                    .concat("x")
+                    .eq(call("COALESCE", [field("t"), lit("y")])),
+                "s || 'x' = coalesce(t, 'y')",
+            ),
+            (
+                field("n")
+                    .is_null()
+                    .or(field("m").not_in([1, 2]).and(id().ne("a"))),
+                "n is null or m not in (1, 2) and id() != 'a'",
+            ),
+            (
+                case([(field("a").lt(0), lit("neg"))], Some(lit(Value::Null))),
+                "case when a < 0 then 'neg' else null end",
+            ),
+            (
+                object([("k", array([lit(true), field("v")]))]),
+                "{k: [true, v]}",
+            ),
+            (
+                call("lower", [field("a")]).eq(lit("x").collate(Collation::NoCase)),
+                "lower(a) = 'x' collate nocase",
+            ),
+            (
+                field("a")
+                    .le(avg(field("b")))
+                    .or(field("c").lt(min(field("d")))),
+                "a <= avg(b) or c < min(d)",
+            ),
+        ] {
+            assert_eq!(built, parse_expr(text).unwrap(), "{text}");
+            assert_eq!(parse_expr(&built.to_string()).unwrap(), built);
+        }
+    }
+
+    #[test]
+    fn keywords_start_fields() {
+        assert_eq!(field("order.by"), parse_expr("`order`.by").unwrap());
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid field path")]
+    fn rejects_malformed_paths() {
+        field("a..b");
+    }
+}
@@ -0,0 +1,109 @@
+use std::fmt;
+use std::str::FromStr;
+
+use serde_json::{json, Value};
+
+use super::QueryError;
+
+const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+/// An opaque position in the results of a sorted query, handed out with each
+/// [`Page`](super::Page) to fetch the next one.
+///
+/// A cursor records the sort keys and id of the last row of a page rather than a row count, so
+/// the next page starts right after that row even if documents were written, removed or
+/// reordered by publishes in between: rows are never repeated, and only rows that moved across
+/// the position are skipped. Cursors are tied to the `order by` clause of the query that made
+/// them and are rejected by any other.
+///
+/// The text form is URL safe.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cursor {
+    pub(crate) order: String,
+    pub(crate) keys: Vec<Value>,
+    pub(crate) id: String,
+}
+
+impl Cursor {
+    fn invalid() -> QueryError {
+        QueryError::InvalidCursor
+    }
+}
+
+impl fmt::Display for Cursor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let payload = json!([self.order, self.keys, self.id]).to_string();
+        let bytes = payload.as_bytes();
+        let mut out = String::with_capacity(bytes.len() * 4 / 3 + 3);
+        for chunk in bytes.chunks(3) {
+            let n = chunk
+                .iter()
+                .enumerate()
+                .fold(0u32, |n, (i, b)| n | (*b as u32) << (16 - 8 * i));
+            for i in 0..=chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i) & 63) as usize] as char);
+            }
+        }
+        f.write_str(&out)
+    }
+}
+
+impl FromStr for Cursor {
+    type Err = QueryError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut bytes = Vec::with_capacity(s.len() * 3 / 4);
+        for chunk in s.as_bytes().chunks(4) {
+            if chunk.len() == 1 {
+                return Err(Self::invalid());
+            }
+            let mut n = 0u32;
+            for (i, c) in chunk.iter().enumerate() {
+                let digit = ALPHABET
+                    .iter()
+                    .position(|a| a == c)
+                    .ok_or_else(Self::invalid)?;
+                n |= (digit as u32) << (18 - 6 * i);
+            }
+            for i in 0..chunk.len() - 1 {
+                bytes.push((n >> (16 - 8 * i)) as u8);
+            }
+        }
+        let payload: Value = serde_json::from_slice(&bytes).map_err(|_| Self::invalid())?;
+        match payload {
+            Value::Array(mut parts) if parts.len() == 3 => {
+                let (Value::String(id), Value::Array(keys), Value::String(order)) =
+                    (parts.remove(2), parts.remove(1), parts.remove(0))
+                else {
+                    return Err(Self::invalid());
+                };
+                Ok(Cursor { order, keys, id })
+            }
+            _ => Err(Self::invalid()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn round_trips() {
+        for id in ["", "a", "ab", "abc", "ünï"] {
+            let cursor = Cursor {
+                order: "age desc first".into(),
+                keys: vec![json!(3), json!(null), json!({"x": "y"})],
+                id: id.into(),
+            };
+            let text = cursor.to_string();
+            assert!(text
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_'));
+            assert_eq!(text.parse::<Cursor>().unwrap(), cursor);
+        }
+        assert_eq!("e30".parse::<Cursor>(), Err(QueryError::InvalidCursor));
+        assert_eq!("!!".parse::<Cursor>(), Err(QueryError::InvalidCursor));
+        assert_eq!("A".parse::<Cursor>(), Err(QueryError::InvalidCursor));
+    }
+}
@@ -0,0 +1,194 @@
+use std::fmt;
+use std::ops::Range;
+
+use super::Abort;
+use crate::collection::CollectionError;
+use crate::schema::JsonType;
+
+/// A location in query source text. Lines and columns start at 1, columns count characters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Position {
+    /// byte offset into the source.
+    pub offset: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Position {
+    /// The position of byte `offset` in `source`.
+    pub fn at(source: &str, offset: usize) -> Self {
+        let offset = offset.min(source.len());
+        let before = &source[..offset];
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        Position {
+            offset,
+            line: before.matches('\n').count() + 1,
+            column: before[line_start..].chars().count() + 1,
+        }
+    }
+}
+
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+/// Error returned when query text can not be tokenized or parsed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyntaxError {
+    pub message: String,
+    /// first character of the offending text.
+    pub start: Position,
+    /// just past the offending text, equal to `start` at the end of input.
+    pub end: Position,
+}
+
+impl SyntaxError {
+    pub(crate) fn new(source: &str, span: Range<usize>, message: impl Into<String>) -> Self {
+        SyntaxError {
+            message: message.into(),
+            start: Position::at(source, span.start),
+            end: Position::at(source, span.end),
+        }
+    }
+
+    /// Render the error with the offending line of `source` and a caret underline, like
+    ///
+    /// ```text
+    /// error: expected expression, found `)`
+    ///  --> 1:18
+    ///   |
+    /// 1 | from users where )
+    ///   |                  ^
+    /// ```
+    pub fn render(&self, source: &str) -> String {
+        let line = source.lines().nth(self.start.line - 1).unwrap_or("");
+        let width = if self.end.line == self.start.line {
+            self.end.column.saturating_sub(self.start.column)
+        } else {
+            line.chars().count() + 1 - self.start.column
+        };
+        let number = self.start.line.to_string();
+        let gutter = " ".repeat(number.len());
+        format!(
+            "error: {}\n{gutter}--> {}\n{gutter} |\n{number} | {line}\n{gutter} | {}{}",
+            self.message,
+            self.start,
+            " ".repeat(self.start.column - 1),
+            "^".repeat(width.max(1)),
+        )
+    }
+}
+
+impl fmt::Display for SyntaxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at {}", self.message, self.start)
+    }
+}
+
+impl std::error::Error for SyntaxError {}
+
+/// Error returned when a query can not be run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum QueryError {
+    Syntax(SyntaxError),
+    UnknownFunction(String),
+    /// the query reads a collection that is not available.
+    UnknownCollection(String),
+    /// a function was called with the wrong number of arguments.
+    Arity {
+        function: String,
+        min: usize,
+        max: usize,
+        found: usize,
+    },
+    /// the query uses a feature that is not available here.
+    Unsupported(String),
+    /// the query is well formed but does not make sense, like selecting a field that is not
+    /// grouped by.
+    Invalid(String),
+    /// a [`Cursor`](super::Cursor) that is malformed or belongs to a query with another order.
+    InvalidCursor,
+    /// the query has a `$name` parameter that was given no value.
+    MissingParam(String),
+    /// a value was given for a parameter the query does not have.
+    UnknownParam(String),
+    /// a parameter was given a value of a type it can not be compared with.
+    ParamType {
+        name: String,
+        expected: Vec<JsonType>,
+        found: JsonType,
+    },
+    /// a write statement was refused by the collection, and none of its writes were applied.
+    Write(CollectionError),
+    /// the query exceeded one of its [`Limits`](super::Limits) or was cancelled.
+    Aborted(Abort),
+}
+
+impl From<SyntaxError> for QueryError {
+    fn from(err: SyntaxError) -> Self {
+        QueryError::Syntax(err)
+    }
+}
+
+impl From<CollectionError> for QueryError {
+    fn from(err: CollectionError) -> Self {
+        QueryError::Write(err)
+    }
+}
+
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            QueryError::Syntax(err) => write!(f, "{err}"),
+            QueryError::UnknownFunction(name) => write!(f, "unknown function `{name}`"),
+            QueryError::UnknownCollection(name) => write!(f, "unknown collection `{name}`"),
+            QueryError::Arity {
+                function,
+                min,
+                max,
+                found,
+            } => {
+                write!(f, "`{function}` takes ")?;
+                if min == max {
+                    write!(f, "{min}")?;
+                } else if *max == usize::MAX {
+                    write!(f, "at least {min}")?;
+                } else {
+                    write!(f, "{min} to {max}")?;
+                }
+                write!(f, " arguments but {found} were given")
+            }
+            QueryError::Unsupported(what) => write!(f, "{what} is not supported"),
+            QueryError::Invalid(message) => f.write_str(message),
+            QueryError::InvalidCursor => f.write_str("invalid cursor for this query"),
+            QueryError::MissingParam(name) => write!(f, "no value for parameter `${name}`"),
+            QueryError::UnknownParam(name) => write!(f, "the query has no parameter `${name}`"),
+            QueryError::ParamType {
+                name,
+                expected,
+                found,
+            } => {
+                let expected: Vec<_> = expected.iter().map(JsonType::to_string).collect();
+                write!(
+                    f,
+                    "parameter `${name}` must be {}, found {found}",
+                    expected.join(" or ")
+                )
+            }
+            QueryError::Write(err) => write!(f, "{err}"),
+            QueryError::Aborted(abort) => write!(f, "{abort}"),
+        }
+    }
+}
+
+impl std::error::Error for QueryError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            QueryError::Syntax(err) => Some(err),
+            QueryError::Write(err) => Some(err),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,669 @@
+//! Evaluation of RQL expressions against a single JSON document.
+//!
+//! # Type rules
+//!
+//! A path that does not resolve, because a key or index is absent or an intermediate value has
+//! the wrong type, is *missing*. Missing is different from JSON `null`, which is an ordinary
+//! value. Predicates use three-valued logic: a predicate is true, false or [unknown](Truth), and
+//! a `where` clause keeps only documents for which it is true.
+//!
+//! * `=` and `!=` compare any two values. Values of different JSON types are never equal, so
+//!   `"1" = 1` is false and `null = null` is true. Numbers compare by value (`1 = 1.0`), and
+//!   arrays and objects compare element by element with the same rules.
+//! * `<`, `<=`, `>` and `>=` order numbers by value, strings by code point and booleans with
+//!   `false < true`. Any other pair of types, including `null`, is unknown.
+//! * Every comparison with a missing operand is unknown, also for `!=`.
+//! * `x in (a, b)` is `x = a or x = b`, and `x between a and b` is `x >= a and x <= b`.
+//! * `and`, `or` and `not` follow Kleene logic: `false and unknown` is false, `true or unknown`
+//!   is true, and `not unknown` is unknown.
+//! * `x is null` is true when `x` is `null` or missing and never unknown. `exists(x)` and
+//!   `missing(x)` tell the two apart.
+//! * A path or other non-boolean expression used as a predicate is true or false when it
+//!   evaluates to a boolean and unknown otherwise; there is no truthiness.
+//! * Arithmetic is only defined on numbers; anything else makes the result missing, as does
+//!   division by zero. Integer operands give an integer result unless it overflows or, for
+//!   `/`, has a fractional part.
+//! * `||` concatenates strings, numbers and booleans, the latter two written as in JSON. Any
+//!   other operand, including `null`, makes the result missing.
+//! * `any(a, p)` is `p` for each element of the array `a` combined with `or`, and `all(a, p)`
+//!   combined with `and`, where `a` inside `p` stands for the element: `any(tags, tags = 'x')`,
+//!   `all(items, items.qty > 0)`. An empty array gives false for `any` and true for `all`;
+//!   anything but an array gives unknown.
+//! * `case` picks the first branch whose condition is true, or the `else` branch, or missing
+//!   when there is neither.
+//! * `x collate nocase` is the [collation](Collation) key of a string `x`, and `x` itself for
+//!   any other value. A comparison, `in` or `between` with a collated operand applies that
+//!   collation to all of its operands, so `name collate nocase = 'ADA'` matches `"Ada"`.
+//! * A subquery `(from ...)` is the one value its row selects, or missing without rows, and
+//!   `x in (from ...)` is `x in` the values of its rows. A subquery returning more rows is an
+//!   error.
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::hash::{BuildHasher, Hash};
+
+use serde_json::{Number, Value};
+use unicode_normalization::UnicodeNormalization;
+
+use super::ast::{BinaryOp, Collation, Expr, Quantifier, UnaryOp};
+use super::{func, parse_expr, QueryError};
+use crate::rwmap::MapReadRef;
+
+/// The result of a predicate under three-valued logic.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Truth {
+    True,
+    False,
+    Unknown,
+}
+
+impl Truth {
+    pub fn is_true(self) -> bool {
+        self == Truth::True
+    }
+
+    pub fn and(self, other: Truth) -> Truth {
+        match (self, other) {
+            (Truth::False, _) | (_, Truth::False) => Truth::False,
+            (Truth::True, Truth::True) => Truth::True,
+            _ => Truth::Unknown,
+        }
+    }
+
+    pub fn or(self, other: Truth) -> Truth {
+        match (self, other) {
+            (Truth::True, _) | (_, Truth::True) => Truth::True,
+            (Truth::False, Truth::False) => Truth::False,
+            _ => Truth::Unknown,
+        }
+    }
+
+    /// The JSON boolean of the truth, or `None` if it is unknown.
+    pub fn to_json(self) -> Option<Value> {
+        match self {
+            Truth::True => Some(Value::Bool(true)),
+            Truth::False => Some(Value::Bool(false)),
+            Truth::Unknown => None,
+        }
+    }
+}
+
+impl std::ops::Not for Truth {
+    type Output = Truth;
+
+    fn not(self) -> Truth {
+        match self {
+            Truth::True => Truth::False,
+            Truth::False => Truth::True,
+            Truth::Unknown => Truth::Unknown,
+        }
+    }
+}
+
+impl From<bool> for Truth {
+    fn from(b: bool) -> Self {
+        if b {
+            Truth::True
+        } else {
+            Truth::False
+        }
+    }
+}
+
+/// Compare two numbers by value, exactly for integers.
+pub(crate) fn cmp_numbers(a: &Number, b: &Number) -> Ordering {
+    if let (Some(a), Some(b)) = (a.as_i64(), b.as_i64()) {
+        return a.cmp(&b);
+    }
+    if let (Some(a), Some(b)) = (a.as_u64(), b.as_u64()) {
+        return a.cmp(&b);
+    }
+    let (a, b) = (
+        a.as_f64().unwrap_or(f64::NAN),
+        b.as_f64().unwrap_or(f64::NAN),
+    );
+    a.total_cmp(&b)
+}
+
+/// Equality under the type rules of this module.
+pub fn json_eq(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::Number(a), Value::Number(b)) => cmp_numbers(a, b) == Ordering::Equal,
+        (Value::Array(a), Value::Array(b)) => {
+            a.len() == b.len() && a.iter().zip(b).all(|(a, b)| json_eq(a, b))
+        }
+        (Value::Object(a), Value::Object(b)) => {
+            a.len() == b.len()
+                && a.iter()
+                    .all(|(key, a)| b.get(key).is_some_and(|b| json_eq(a, b)))
+        }
+        _ => a == b,
+    }
+}
+
+/// The JSON text of `value` with integral floats written as integers, so that values equal
+/// under [`json_eq`] have the same text.
+pub(crate) fn canonical(value: &Value) -> String {
+    fn normalize(value: &Value) -> Value {
+        match value {
+            Value::Number(n) if n.is_f64() => match n.as_f64() {
+                Some(f) if f.fract() == 0.0 && f.abs() < 9.2e18 => Value::from(f as i64),
+                _ => value.clone(),
+            },
+            Value::Array(items) => Value::Array(items.iter().map(normalize).collect()),
+            Value::Object(map) => Value::Object(
+                map.iter()
+                    .map(|(key, value)| (key.clone(), normalize(value)))
+                    .collect(),
+            ),
+            _ => value.clone(),
+        }
+    }
+    normalize(value).to_string()
+}
+
+/// Ordering under the type rules of this module, `None` for types that do not order.
+pub fn json_cmp(a: &Value, b: &Value) -> Option<Ordering> {
+    match (a, b) {
+        (Value::Number(a), Value::Number(b)) => Some(cmp_numbers(a, b)),
+        (Value::String(a), Value::String(b)) => Some(a.cmp(b)),
+        (Value::Bool(a), Value::Bool(b)) => Some(a.cmp(b)),
+        _ => None,
+    }
+}
+
+fn compare(op: BinaryOp, a: Option<&Value>, b: Option<&Value>) -> Truth {
+    let (Some(a), Some(b)) = (a, b) else {
+        return Truth::Unknown;
+    };
+    let ordering =
+        |test: fn(Ordering) -> bool| json_cmp(a, b).map_or(Truth::Unknown, |o| test(o).into());
+    match op {
+        BinaryOp::Eq => json_eq(a, b).into(),
+        BinaryOp::Ne => (!json_eq(a, b)).into(),
+        BinaryOp::Lt => ordering(Ordering::is_lt),
+        BinaryOp::Le => ordering(Ordering::is_le),
+        BinaryOp::Gt => ordering(Ordering::is_gt),
+        BinaryOp::Ge => ordering(Ordering::is_ge),
+        _ => unreachable!("{op:?} is not a comparison"),
+    }
+}
+
+fn arithmetic(op: BinaryOp, a: &Value, b: &Value) -> Option<Value> {
+    let (Value::Number(a), Value::Number(b)) = (a, b) else {
+        return None;
+    };
+    if let (Some(x), Some(y)) = (a.as_i64(), b.as_i64()) {
+        let exact = match op {
+            BinaryOp::Add => x.checked_add(y),
+            BinaryOp::Sub => x.checked_sub(y),
+            BinaryOp::Mul => x.checked_mul(y),
+            BinaryOp::Div if y != 0 && x % y == 0 => x.checked_div(y),
+            BinaryOp::Rem => x.checked_rem(y),
+            _ => None,
+        };
+        if let Some(n) = exact {
+            return Some(Value::from(n));
+        }
+    }
+    let (x, y) = (a.as_f64()?, b.as_f64()?);
+    let n = match op {
+        BinaryOp::Add => x + y,
+        BinaryOp::Sub => x - y,
+        BinaryOp::Mul => x * y,
+        BinaryOp::Div if y != 0.0 => x / y,
+        BinaryOp::Rem if y != 0.0 => x % y,
+        _ => return None,
+    };
+    Number::from_f64(n).map(Value::Number)
+}
+
+fn concat(a: &Value, b: &Value) -> Option<Value> {
+    let text = |value: &Value| match value {
+        Value::String(s) => Some(s.clone()),
+        Value::Number(_) | Value::Bool(_) => Some(value.to_string()),
+        _ => None,
+    };
+    Some(Value::String(text(a)? + &text(b)?))
+}
+
+fn negate(value: &Value) -> Option<Value> {
+    let Value::Number(n) = value else {
+        return None;
+    };
+    match n.as_i64().and_then(i64::checked_neg) {
+        Some(i) => Some(Value::from(i)),
+        None => Number::from_f64(-n.as_f64()?).map(Value::Number),
+    }
+}
+
+/// Evaluate `expr` as a value. `None` stands for missing, and for unknown predicates.
+pub fn eval<'a>(expr: &'a Expr, doc: &'a Value) -> Option<Cow<'a, Value>> {
+    match expr {
+        Expr::Literal(value) => Some(Cow::Borrowed(value)),
+        Expr::Path(path) => path.get(doc).map(Cow::Borrowed),
+        Expr::Param(_) => None,
+        Expr::Array(items) => Some(Cow::Owned(Value::Array(
+            items
+                .iter()
+                .map(|item| eval(item, doc).map_or(Value::Null, Cow::into_owned))
+                .collect(),
+        ))),
+        Expr::Object(fields) => Some(Cow::Owned(Value::Object(
+            fields
+                .iter()
+                .filter_map(|(key, value)| Some((key.clone(), eval(value, doc)?.into_owned())))
+                .collect(),
+        ))),
+        Expr::Unary {
+            op: UnaryOp::Neg,
+            expr,
+        } => negate(eval(expr, doc)?.as_ref()).map(Cow::Owned),
+        Expr::Binary {
+            left,
+            op: op @ (BinaryOp::Add | BinaryOp::Sub | BinaryOp::Mul | BinaryOp::Div | BinaryOp::Rem),
+            right,
+        } => arithmetic(*op, eval(left, doc)?.as_ref(), eval(right, doc)?.as_ref()).map(Cow::Owned),
+        Expr::Binary {
+            left,
+            op: BinaryOp::Concat,
+            right,
+        } => concat(eval(left, doc)?.as_ref(), eval(right, doc)?.as_ref()).map(Cow::Owned),
+        Expr::Call { name, args } => {
+            func::call(name, args.iter().map(|arg| eval(arg, doc)).collect())
+        }
+        // aggregates and subqueries are replaced by their results before rows are evaluated
+        Expr::Aggregate { .. } | Expr::Subquery(_) => None,
+        Expr::Case {
+            branches,
+            otherwise,
+        } => match branches.iter().find(|(when, _)| test(when, doc).is_true()) {
+            Some((_, then)) => eval(then, doc),
+            None => eval(otherwise.as_deref()?, doc),
+        },
+        Expr::Collate { expr, collation } => eval(expr, doc).map(|value| collation.key(value)),
+        _ => test(expr, doc).to_json().map(Cow::Owned),
+    }
+}
+
+impl Collation {
+    /// The collation key of a string, anything else unchanged.
+    pub fn key<'a>(&self, value: Cow<'a, Value>) -> Cow<'a, Value> {
+        let Value::String(s) = value.as_ref() else {
+            return value;
+        };
+        match self {
+            Collation::Binary => value,
+            Collation::Nfc => Cow::Owned(Value::String(s.nfc().collect())),
+            Collation::NoCase => Cow::Owned(Value::String(s.to_lowercase().nfc().collect())),
+        }
+    }
+}
+
+/// The collation of the first of `operands` that has one.
+pub(crate) fn collation_of<'e>(operands: impl IntoIterator<Item = &'e Expr>) -> Option<Collation> {
+    operands.into_iter().find_map(|operand| match operand {
+        Expr::Collate { collation, .. } => Some(*collation),
+        _ => None,
+    })
+}
+
+fn collated(collation: Option<Collation>, value: Option<Cow<'_, Value>>) -> Option<Cow<'_, Value>> {
+    match collation {
+        Some(collation) => value.map(|value| collation.key(value)),
+        None => value,
+    }
+}
+
+/// Evaluate `expr` as a predicate.
+pub fn test(expr: &Expr, doc: &Value) -> Truth {
+    match expr {
+        Expr::Unary {
+            op: UnaryOp::Not,
+            expr,
+        } => !test(expr, doc),
+        Expr::Binary {
+            left,
+            op: BinaryOp::And,
+            right,
+        } => match test(left, doc) {
+            Truth::False => Truth::False,
+            left => left.and(test(right, doc)),
+        },
+        Expr::Binary {
+            left,
+            op: BinaryOp::Or,
+            right,
+        } => match test(left, doc) {
+            Truth::True => Truth::True,
+            left => left.or(test(right, doc)),
+        },
+        Expr::Binary {
+            left,
+            op:
+                op @ (BinaryOp::Eq
+                | BinaryOp::Ne
+                | BinaryOp::Lt
+                | BinaryOp::Le
+                | BinaryOp::Gt
+                | BinaryOp::Ge),
+            right,
+        } => {
+            let collation = collation_of([&**left, &**right]);
+            compare(
+                *op,
+                collated(collation, eval(left, doc)).as_deref(),
+                collated(collation, eval(right, doc)).as_deref(),
+            )
+        }
+        Expr::In {
+            expr,
+            list,
+            negated,
+        } => {
+            let collation = collation_of(std::iter::once(&**expr).chain(list));
+            let value = collated(collation, eval(expr, doc));
+            let mut truth = Truth::False;
+            for item in list {
+                truth = truth.or(compare(
+                    BinaryOp::Eq,
+                    value.as_deref(),
+                    collated(collation, eval(item, doc)).as_deref(),
+                ));
+                if truth == Truth::True {
+                    break;
+                }
+            }
+            if *negated {
+                !truth
+            } else {
+                truth
+            }
+        }
+        Expr::Between {
+            expr,
+            low,
+            high,
+            negated,
+        } => {
+            let collation = collation_of([&**expr, &**low, &**high]);
+            let value = collated(collation, eval(expr, doc));
+            let low = collated(collation, eval(low, doc));
+            let high = collated(collation, eval(high, doc));
+            let truth = compare(BinaryOp::Ge, value.as_deref(), low.as_deref()).and(compare(
+                BinaryOp::Le,
+                value.as_deref(),
+                high.as_deref(),
+            ));
+            if *negated {
+                !truth
+            } else {
+                truth
+            }
+        }
+        Expr::IsNull { expr, negated } => {
+            let null = eval(expr, doc).is_none_or(|value| value.is_null());
+            Truth::from(null != *negated)
+        }
+        Expr::Exists { path, negated } => Truth::from(path.get(doc).is_some() != *negated),
+        Expr::Quantified {
+            quantifier,
+            path,
+            predicate,
+        } => {
+            let Some(Value::Array(items)) = path.get(doc) else {
+                return Truth::Unknown;
+            };
+            // the predicate sees the document with the element in place of the array
+            let decided = Truth::from(*quantifier == Quantifier::Any);
+            let mut scope = doc.clone();
+            let mut truth = !decided;
+            for item in items {
+                *path.get_mut(&mut scope).unwrap() = item.clone();
+                let each = test(predicate, &scope);
+                truth = match quantifier {
+                    Quantifier::Any => truth.or(each),
+                    Quantifier::All => truth.and(each),
+                };
+                if truth == decided {
+                    break;
+                }
+            }
+            truth
+        }
+        Expr::InSubquery { .. } => Truth::Unknown,
+        _ => match eval(expr, doc).as_deref() {
+            Some(Value::Bool(b)) => Truth::from(*b),
+            _ => Truth::Unknown,
+        },
+    }
+}
+
+/// Check that every function `expr` calls exists and gets the right number of arguments, and
+/// that it holds no aggregates, subqueries or unbound parameters.
+pub(crate) fn check(expr: &Expr) -> Result<(), QueryError> {
+    bound(expr)?;
+    check_prepared(expr)
+}
+
+/// Fail with the first `$name` parameter in `expr`.
+pub(crate) fn bound(expr: &Expr) -> Result<(), QueryError> {
+    expr.walk(&mut |expr| match expr {
+        Expr::Param(name) => Err(QueryError::MissingParam(name.clone())),
+        _ => Ok(()),
+    })
+}
+
+/// Like [`check`], but leaves parameters to be bound after compiling.
+pub(crate) fn check_prepared(expr: &Expr) -> Result<(), QueryError> {
+    expr.walk(&mut |expr| match expr {
+        Expr::Call { name, args } => func::check(name, args),
+        Expr::Aggregate { func, .. } => Err(QueryError::Invalid(format!(
+            "aggregate `{}` is only allowed in select, having and order by",
+            func.as_str()
+        ))),
+        Expr::Subquery(query) | Expr::InSubquery { query, .. } => Err(QueryError::Invalid(
+            format!("subquery `({query})` can only be used in a query"),
+        )),
+        _ => Ok(()),
+    })
+}
+
+/// A `where` predicate, checked to only use supported constructs.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Filter {
+    expr: Expr,
+}
+
+impl Filter {
+    pub fn new(expr: Expr) -> Result<Self, QueryError> {
+        check(&expr)?;
+        Ok(Filter { expr })
+    }
+
+    /// Like [`new`](Self::new), but `expr` may hold parameters.
+    pub(crate) fn prepared(expr: Expr) -> Result<Self, QueryError> {
+        check_prepared(&expr)?;
+        Ok(Filter { expr })
+    }
+
+    /// The predicate with `f` applied to its expression.
+    pub(crate) fn try_map(
+        &self,
+        f: impl FnOnce(&Expr) -> Result<Expr, QueryError>,
+    ) -> Result<Self, QueryError> {
+        Ok(Filter {
+            expr: f(&self.expr)?,
+        })
+    }
+
+    /// Parse and check a predicate, such as `age >= 18 and exists(email)`.
+    pub fn parse(source: &str) -> Result<Self, QueryError> {
+        Filter::new(parse_expr(source)?)
+    }
+
+    pub fn expr(&self) -> &Expr {
+        &self.expr
+    }
+
+    pub fn test(&self, doc: &Value) -> Truth {
+        test(&self.expr, doc)
+    }
+
+    /// Returns true if the predicate is true for `doc`.
+    pub fn matches(&self, doc: &Value) -> bool {
+        self.test(doc).is_true()
+    }
+
+    /// Every entry of `map` matching the predicate, in map order.
+    pub fn apply<'m, K, M, S>(
+        &'m self,
+        map: &'m MapReadRef<'_, K, Value, M, S>,
+    ) -> impl Iterator<Item = (&'m K, &'m Value)> + 'm
+    where
+        K: Hash + Eq,
+        S: BuildHasher,
+    {
+        map.iter()
+            .map(|(k, v)| (k, v.as_ref()))
+            .filter(|(_, v)| self.matches(v))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::*;
+
+    fn truth(source: &str, doc: Value) -> Truth {
+        Filter::parse(source).unwrap().test(&doc)
+    }
+
+    #[test]
+    fn type_rules() {
+        let doc = json!({"n": 1, "s": "1", "z": null, "b": true, "a": [1, {"x": 2.0}]});
+        for (source, expected) in [
+            ("n = 1.0", Truth::True),
+            ("n = s", Truth::False),
+            ("n != s", Truth::True),
+            ("n < s", Truth::Unknown),
+            ("z = null", Truth::True),
+            ("z < 1", Truth::Unknown),
+            ("nope = 1", Truth::Unknown),
+            ("nope != 1", Truth::Unknown),
+            ("not nope = 1", Truth::Unknown),
+            ("nope = 1 or n = 1", Truth::True),
+            ("nope = 1 and n = 2", Truth::False),
+            ("a[1].x = 2", Truth::True),
+            ("a = [1, 2]", Truth::False),
+            ("b", Truth::True),
+            ("n", Truth::Unknown),
+            ("b > false", Truth::True),
+            ("s > '0'", Truth::True),
+            ("n in (3, '1', 1)", Truth::True),
+            ("n in (3, nope)", Truth::Unknown),
+            ("n not in (3, 4)", Truth::True),
+            ("n in ()", Truth::False),
+            ("n between 0 and 1", Truth::True),
+            ("n not between 2 and nope", Truth::True),
+            ("z is null and nope is null and n is not null", Truth::True),
+            ("exists(z) and missing(nope) and missing(a[5])", Truth::True),
+            (
+                "n + 1 = 2 and n * 2.5 = 2.5 and 7 / 2 = 3.5 and 7 % 4 = 3",
+                Truth::True,
+            ),
+            ("n / 0 = 1", Truth::Unknown),
+            ("s + 1 = 2", Truth::Unknown),
+            ("-n < 0", Truth::True),
+        ] {
+            assert_eq!(truth(source, doc.clone()), expected, "{source}");
+        }
+        assert_eq!(truth("a = [1.0, 2]", json!({"a": [1, 2]})), Truth::True);
+        assert_eq!(
+            Filter::parse("f(a) = 1").unwrap_err(),
+            QueryError::UnknownFunction("f".into())
+        );
+    }
+
+    #[test]
+    fn collations() {
+        let doc = json!({"name": "Ada", "decomposed": "e\u{301}", "composed": "\u{e9}"});
+        for (source, expected) in [
+            ("name = 'ADA'", Truth::False),
+            ("name collate nocase = 'ADA'", Truth::True),
+            ("name = 'ADA' collate nocase", Truth::True),
+            ("name collate binary = 'ADA'", Truth::False),
+            ("decomposed = composed", Truth::False),
+            ("decomposed collate nfc = composed", Truth::True),
+            ("decomposed collate nocase = upper(composed)", Truth::True),
+            ("name collate nocase in ('bob', 'ada')", Truth::True),
+            ("name between 'a' collate nocase and 'b'", Truth::True),
+            ("name between 'a' and 'b'", Truth::False),
+            (
+                "contains(lower(name), 'da') and starts_with(name, 'A')",
+                Truth::True,
+            ),
+            (
+                "regex_match(name, '^a') = false and length(name) = 3",
+                Truth::True,
+            ),
+            ("substring(name, 2) collate nocase = 'DA'", Truth::True),
+            (
+                "timestamp('2024-03-01T13:00:00+01:00') = timestamp('2024-03-01T12:00:00Z')",
+                Truth::True,
+            ),
+            (
+                "timestamp('2024-03-01T09:00:00-05:00') > timestamp('2024-03-01T13:00:00Z')",
+                Truth::True,
+            ),
+            (
+                "'2024-03-01T09:00:00-05:00' > '2024-03-01T13:00:00Z'",
+                Truth::False,
+            ),
+        ] {
+            assert_eq!(truth(source, doc.clone()), expected, "{source}");
+        }
+        assert!(Filter::parse("name collate latin1 = 'x'").is_err());
+        assert!(matches!(
+            Filter::parse("regex_match(name, '(')"),
+            Err(QueryError::Invalid(_))
+        ));
+    }
+
+    #[test]
+    fn quantifiers() {
+        let doc = json!({
+            "tags": ["a", "b"],
+            "none": [],
+            "items": [{"qty": 2, "sku": "x"}, {"qty": 0}],
+            "min": 1,
+            "doc": {"lines": [[1, 2], [3]]},
+        });
+        for (source, expected) in [
+            ("any(tags, tags = 'b')", Truth::True),
+            ("all(tags, tags = 'b')", Truth::False),
+            ("any(tags, tags = 'c')", Truth::False),
+            ("any(none, none = 1)", Truth::False),
+            ("all(none, none = 1)", Truth::True),
+            ("any(items, items.qty >= min)", Truth::True),
+            ("all(items, items.qty >= min)", Truth::False),
+            ("all(items, items.qty >= 0)", Truth::True),
+            ("any(items, items.sku = 'y')", Truth::Unknown),
+            ("all(items, items.sku = 'x')", Truth::Unknown),
+            ("any(min, min = 1)", Truth::Unknown),
+            ("any(nope, nope = 1)", Truth::Unknown),
+            ("any(doc.lines, any(doc.lines, doc.lines = 3))", Truth::True),
+            ("all(doc.lines, array_length(doc.lines) > 1)", Truth::False),
+            ("not any(tags, tags = 'c') and tags[0] = 'a'", Truth::True),
+        ] {
+            assert_eq!(truth(source, doc.clone()), expected, "{source}");
+        }
+    }
+
+    #[test]
+    fn big_integers_compare_exactly() {
+        let doc = json!({"id": 9007199254740993u64});
+        assert_eq!(truth("id = 9007199254740992", doc.clone()), Truth::False);
+        assert_eq!(truth("id > 9007199254740992", doc), Truth::True);
+    }
+}
@@ -0,0 +1,1506 @@
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::hash::BuildHasher;
+use std::time::Instant;
+
+use serde_json::Value;
+
+use super::aggregate::Grouping;
+use super::ast::{Explain, Expr, OrderBy, Query, Select, SelectItem};
+use super::eval::{self, test};
+use super::func;
+use super::join::{self, Documents, JoinPlan, IDS};
+use super::plan::{self, Choice, Counter, Measured, Plan, Profile, Shape};
+use super::subquery::{self, Tables};
+use super::unnest::{self, Unnesting};
+use super::{Budget, Cursor, Filter, Limits, Projection, QueryError, Sort};
+use crate::index::{IndexKey, IndexKind, KeyExpr};
+use crate::path::Path;
+use crate::rwmap::MapReadRef;
+
+/// One result of a query.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Row<'a> {
+    /// key of the source document, if the row comes from a single document.
+    pub id: Option<&'a str>,
+    pub value: Cow<'a, Value>,
+}
+
+impl Row<'_> {
+    pub fn into_value(self) -> Value {
+        self.value.into_owned()
+    }
+}
+
+/// A result row waiting to be sorted: a matching document, or the row of a group.
+struct Candidate<'m> {
+    keys: Vec<Value>,
+    id: Option<&'m str>,
+    /// breaks ties between equal sort keys: the document id, or the key of a group.
+    tie: Cow<'m, str>,
+    doc: Cow<'m, Value>,
+    /// the projected row, if the sort keys needed it.
+    value: Option<Cow<'m, Value>>,
+}
+
+impl<'m> Candidate<'m> {
+    fn new(id: Option<&'m str>, tie: Cow<'m, str>, doc: Cow<'m, Value>) -> Self {
+        Candidate {
+            keys: Vec::new(),
+            id,
+            tie,
+            doc,
+            value: None,
+        }
+    }
+}
+
+/// One page of results, see [`execute_page`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Page<'a> {
+    pub rows: Vec<Row<'a>>,
+    /// where the next page starts, `None` if this is the last page.
+    pub next: Option<Cursor>,
+}
+
+impl Page<'_> {
+    /// The page with rows that own their values, and so have no ids.
+    pub(crate) fn detached(self) -> Page<'static> {
+        let rows = self
+            .rows
+            .into_iter()
+            .map(|row| Row {
+                id: None,
+                value: Cow::Owned(row.into_value()),
+            })
+            .collect();
+        Page {
+            rows,
+            next: self.next,
+        }
+    }
+}
+
+/// Run `query` against the documents visible through `map`.
+///
+/// The collection named by the query is not checked, `map` is taken to hold it, and to be the
+/// collection its `with` queries and subqueries read first. Without `order by`, `limit` or
+/// `offset` rows come in map order, which is arbitrary.
+pub fn execute<'m, M, S>(
+    query: &Query,
+    map: &'m MapReadRef<'_, String, Value, M, S>,
+) -> Result<Vec<Row<'m>>, QueryError>
+where
+    M: Clone + Default + 'static,
+    S: BuildHasher + Clone + Default,
+{
+    Ok(execute_page(query, map, None)?.rows)
+}
+
+/// Run `query` starting after `cursor`, returning at most `limit` rows and the cursor of the
+/// next page.
+///
+/// Pages are always in a total order: the `order by` keys followed by the document id. The
+/// `offset` only applies to the first page and is ignored when a cursor is given.
+///
+/// Queries reading another collection, by a join or a subquery, fail with
+/// [`QueryError::UnknownCollection`], as `map` is the only collection available; run them on a
+/// [`Snapshot`](crate::database::Snapshot) instead.
+///
+/// Rows of a statement with `with` queries have no id.
+pub fn execute_page<'m, M, S>(
+    query: &Query,
+    map: &'m MapReadRef<'_, String, Value, M, S>,
+    cursor: Option<&Cursor>,
+) -> Result<Page<'m>, QueryError>
+where
+    M: Clone + Default + 'static,
+    S: BuildHasher + Clone + Default,
+{
+    execute_page_limited(query, map, cursor, &Limits::new())
+}
+
+/// [`execute_page`], aborting with [`QueryError::Aborted`] once the query exceeds one of
+/// `limits` or is cancelled.
+pub fn execute_page_limited<'m, M, S>(
+    query: &Query,
+    map: &'m MapReadRef<'_, String, Value, M, S>,
+    cursor: Option<&Cursor>,
+    limits: &Limits,
+) -> Result<Page<'m>, QueryError>
+where
+    M: Clone + Default + 'static,
+    S: BuildHasher + Clone + Default,
+{
+    run(query, &alone(query, map), cursor, &Budget::new(limits))
+}
+
+/// Describe how `query` runs on the documents visible through `map`, see
+/// [`Plan`](super::Plan).
+///
+/// With `analyze` the query is run, and the plan also holds the rows each operator produced
+/// and the time it took. An `explain` prefix on the query itself is ignored.
+pub fn explain<M, S>(
+    query: &Query,
+    map: &MapReadRef<'_, String, Value, M, S>,
+    analyze: bool,
+) -> Result<Plan, QueryError>
+where
+    M: Clone + Default + 'static,
+    S: BuildHasher + Clone + Default,
+{
+    explain_in(query, &alone(query, map), analyze, &Budget::unlimited())
+}
+
+/// The collections of a statement run on `map` alone: `map` is the first collection it reads,
+/// and there are no others.
+pub(crate) fn alone<'m, 'g, M, S>(
+    query: &Query,
+    map: Documents<'m, 'g, M, S>,
+) -> impl Fn(&str) -> Option<Documents<'m, 'g, M, S>>
+where
+    S: BuildHasher,
+{
+    let first = subquery::collections(query).into_iter().next();
+    move |name| (first.as_deref() == Some(name)).then_some(map)
+}
+
+/// Run `query` on the collections found by `lookup`, within `budget`.
+///
+/// An `explain` query returns a single row holding its plan as JSON.
+pub(crate) fn run<'m, 'g, M, S>(
+    query: &Query,
+    lookup: &dyn Fn(&str) -> Option<Documents<'m, 'g, M, S>>,
+    cursor: Option<&Cursor>,
+    budget: &Budget,
+) -> Result<Page<'m>, QueryError>
+where
+    M: Clone + Default + 'static,
+    S: BuildHasher + Clone + Default,
+{
+    if let Some(mode) = query.explain {
+        let plan = explain_in(query, lookup, mode == Explain::Analyze, budget)?;
+        let row = Row {
+            id: None,
+            value: Cow::Owned(plan.to_json()),
+        };
+        return Ok(Page {
+            rows: vec![row],
+            next: None,
+        });
+    }
+    if !query.with.is_empty() {
+        let query = func::fix_now_in(query, &func::now());
+        let tables = Tables::new(&query.with, lookup, budget)?;
+        let maps = tables.enter();
+        let lookup = subquery::shadowed(&maps, lookup);
+        let query = Query {
+            with: Vec::new(),
+            ..query
+        };
+        // rows may come from the tables, which only live until here
+        let page = run(&query, &lookup, cursor, budget)?;
+        return Ok(page.detached());
+    }
+    let compiled = Compiled::resolved(query, lookup, budget)?;
+    let physical = compiled.physical(lookup, None, budget)?;
+    compiled.execute(&physical, cursor, None, budget)
+}
+
+/// [`explain`] with the collections `query` reads found by `lookup`. `with` queries and
+/// subqueries run, for the plan to use their results.
+pub(crate) fn explain_in<'m, 'g, M, S>(
+    query: &Query,
+    lookup: &dyn Fn(&str) -> Option<Documents<'m, 'g, M, S>>,
+    analyze: bool,
+    budget: &Budget,
+) -> Result<Plan, QueryError>
+where
+    M: Clone + Default + 'static,
+    S: BuildHasher + Clone + Default,
+{
+    if !query.with.is_empty() {
+        let query = func::fix_now_in(query, &func::now());
+        let tables = Tables::new(&query.with, lookup, budget)?;
+        let maps = tables.enter();
+        let lookup = subquery::shadowed(&maps, lookup);
+        let query = Query {
+            with: Vec::new(),
+            ..query
+        };
+        return explain_in(&query, &lookup, analyze, budget);
+    }
+    let compiled = Compiled::resolved(query, lookup, budget)?;
+    let physical = compiled.physical(lookup, None, budget)?;
+    let profile = analyze.then(|| Profile::new(query.unnest.len(), query.joins.len()));
+    if let Some(profile) = &profile {
+        compiled.execute(&physical, None, Some(profile), budget)?;
+    }
+    Ok(compiled.describe(&physical, profile.as_ref()))
+}
+
+/// A row ordered by the sort of its query, for the heap of the rows a page keeps.
+struct Ranked<'s, 'm> {
+    sort: &'s Sort,
+    row: Candidate<'m>,
+}
+
+impl PartialEq for Ranked<'_, '_> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other).is_eq()
+    }
+}
+
+impl Eq for Ranked<'_, '_> {}
+
+impl PartialOrd for Ranked<'_, '_> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Ranked<'_, '_> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let (a, b) = (&self.row, &other.row);
+        self.sort.cmp((&a.keys, &a.tie), (&b.keys, &b.tie))
+    }
+}
+
+/// Returns true if `row`, read in id order or in the order of an ordered index on the only
+/// sort key, and so every row read after it, sorts after `last`. Keys are compared as the
+/// index orders them, and only booleans, numbers and strings, as documents the index does not
+/// hold sort after them.
+fn past(row: &Candidate, last: &Candidate) -> bool {
+    if row.keys.is_empty() {
+        return row.tie > last.tie;
+    }
+    let key = |row: &Candidate| match row.keys.as_slice() {
+        [key @ (Value::Bool(_) | Value::Number(_) | Value::String(_))] => IndexKey::from_json(key),
+        _ => None,
+    };
+    matches!((key(row), key(last)), (Some(key), Some(last)) if key > last)
+}
+
+/// The `group by`, aggregates and `having` of a query.
+#[derive(Debug, Clone)]
+struct Aggregation {
+    grouping: Grouping,
+    having: Option<Expr>,
+}
+
+/// A checked query split into its operators, which do not depend on the documents it reads.
+#[derive(Debug, Clone)]
+pub(crate) struct Compiled {
+    /// the query as written.
+    source: Query,
+    /// the query with joins bound and `id()` resolved.
+    query: Query,
+    /// rows are copies of their documents holding the document id under [`IDS`], for `id()`.
+    ids: bool,
+    unnesting: Option<Unnesting>,
+    filter: Option<Filter>,
+    aggregation: Option<Aggregation>,
+    projection: Projection,
+    sort: Sort,
+}
+
+/// A compiled query planned against the collections it reads.
+pub(crate) struct Physical<'m, 'g, M, S>
+where
+    S: BuildHasher,
+{
+    map: Documents<'m, 'g, M, S>,
+    access: Choice,
+    joins: Option<JoinPlan<'m, 'g, M, S>>,
+}
+
+impl<M, S> Physical<'_, '_, M, S>
+where
+    S: BuildHasher,
+{
+    /// The shape of the access path, to plan the next run of a prepared query with.
+    pub(crate) fn shape(&self) -> Shape {
+        self.access.shape()
+    }
+}
+
+impl Compiled {
+    pub(crate) fn new(query: &Query) -> Result<Self, QueryError> {
+        for expr in query.exprs() {
+            eval::bound(expr)?;
+        }
+        Self::compile(query, Some(&func::now()))
+    }
+
+    /// Compile `query` keeping its parameters and `now()` calls, for [`bind`](Self::bind) to
+    /// fill in on each run.
+    pub(crate) fn prepared(query: &Query) -> Result<Self, QueryError> {
+        Self::compile(query, None)
+    }
+
+    /// The compiled query with `f` applied to each of its expressions, such as to put values
+    /// in place of parameters. Operators keep what compiling derived from the expressions.
+    pub(crate) fn bind(
+        &self,
+        mut f: impl FnMut(&Expr) -> Result<Expr, QueryError>,
+    ) -> Result<Self, QueryError> {
+        let aggregation = match &self.aggregation {
+            Some(aggregation) => Some(Aggregation {
+                grouping: aggregation.grouping.try_map_exprs(&mut f)?,
+                having: aggregation.having.as_ref().map(&mut f).transpose()?,
+            }),
+            None => None,
+        };
+        Ok(Compiled {
+            source: self.source.clone().try_map_exprs(&mut f)?,
+            query: self.query.clone().try_map_exprs(&mut f)?,
+            ids: self.ids,
+            unnesting: self.unnesting.clone(),
+            filter: self
+                .filter
+                .as_ref()
+                .map(|filter| filter.try_map(&mut f))
+                .transpose()?,
+            aggregation,
+            projection: self.projection.try_map_exprs(&mut f)?,
+            sort: self.sort.try_map_exprs(&mut f)?,
+        })
+    }
+
+    /// Compile `query` with `now()` fixed to `now`, or left in place without.
+    fn compile(query: &Query, now: Option<&Value>) -> Result<Self, QueryError> {
+        let (bound, ids) = if !query.joins.is_empty() {
+            (join::bind(query)?, false)
+        } else {
+            match join::bind_id(query)? {
+                Some(bound) => (bound, true),
+                None => (query.clone(), false),
+            }
+        };
+        let bound = match now {
+            Some(now) => {
+                bound.try_map_exprs(|expr| Ok::<_, QueryError>(func::fix_now(expr, now)))?
+            }
+            None => bound,
+        };
+        let filter = bound.filter.clone().map(Filter::prepared).transpose()?;
+        let unnesting = Unnesting::new(&bound.unnest, !bound.joins.is_empty());
+
+        let (aggregation, projection, sort) = if Grouping::applies(&bound) {
+            if bound.select == Select::All {
+                return Err(QueryError::Invalid(
+                    "`select *` can not be used with group by or aggregates".into(),
+                ));
+            }
+            let mut grouping = Grouping::new(&bound.group_by)?;
+            let projection = Projection::rewritten(&bound.select, |expr| grouping.rewrite(expr))?;
+            let having = bound
+                .having
+                .as_ref()
+                .map(|expr| grouping.rewrite(expr))
+                .transpose()?;
+            let sort = Sort::rewritten(&bound.order_by, &bound.select, |expr| {
+                grouping.rewrite(expr)
+            })?;
+            let aggregation = Aggregation { grouping, having };
+            (Some(aggregation), projection, sort)
+        } else {
+            let checked = |expr: &Expr| {
+                eval::check_prepared(expr)?;
+                Ok(expr.clone())
+            };
+            let mut projection = Projection::rewritten(&bound.select, checked)?;
+            if ids {
+                projection = projection.hiding(IDS);
+            }
+            let sort = Sort::rewritten(&bound.order_by, &bound.select, checked)?;
+            (None, projection, sort)
+        };
+
+        Ok(Compiled {
+            source: query.clone(),
+            query: bound,
+            ids,
+            unnesting,
+            filter,
+            aggregation,
+            projection,
+            sort,
+        })
+    }
+
+    /// Compile `query` after running its subqueries on the collections found by `lookup`.
+    /// Plans still show the subqueries.
+    pub(crate) fn resolved<'m, 'g, M, S>(
+        query: &Query,
+        lookup: &dyn Fn(&str) -> Option<Documents<'m, 'g, M, S>>,
+        budget: &Budget,
+    ) -> Result<Self, QueryError>
+    where
+        M: Clone + Default + 'static,
+        S: BuildHasher + Clone + Default,
+    {
+        if !subquery::nests(query) {
+            return Compiled::new(query);
+        }
+        let query = func::fix_now_in(query, &func::now());
+        let mut compiled = Compiled::new(&subquery::resolve(&query, lookup, budget)?)?;
+        compiled.source = query;
+        Ok(compiled)
+    }
+
+    /// Pick the access path and join strategies over the collections found by `lookup`,
+    /// taking an access path of the shape `hint` if given. Building hash tables for joins
+    /// counts against `budget`.
+    pub(crate) fn physical<'m, 'g, M, S>(
+        &self,
+        lookup: &dyn Fn(&str) -> Option<Documents<'m, 'g, M, S>>,
+        hint: Option<&Shape>,
+        budget: &Budget,
+    ) -> Result<Physical<'m, 'g, M, S>, QueryError>
+    where
+        S: BuildHasher,
+    {
+        let collection = &self.query.from.collection;
+        let map =
+            lookup(collection).ok_or_else(|| QueryError::UnknownCollection(collection.clone()))?;
+        let conjuncts = self
+            .query
+            .filter
+            .as_ref()
+            .map(plan::conjuncts)
+            .unwrap_or_default();
+        // conditions on unnested fields do not hold for the documents themselves
+        let unnested = |expr: &Expr| {
+            self.unnesting
+                .as_ref()
+                .is_some_and(|unnesting| unnesting.touches(expr))
+        };
+        let access = if self.query.joins.is_empty() {
+            let choice = plan::choose(
+                map,
+                &conjuncts,
+                &Path::root().key(IDS),
+                &|expr| (!unnested(expr)).then(|| expr.clone()),
+                hint,
+            );
+            // a page of the first rows in order can stop reading once it is full
+            match self.query.limit {
+                Some(_) if self.unnesting.is_none() && self.aggregation.is_none() => {
+                    plan::in_order(map, choice, &self.sort)
+                }
+                _ => choice,
+            }
+        } else {
+            let from = self.query.from.name();
+            let relative = |expr: &Expr| {
+                let sources = join::sources(expr);
+                let only_from = sources.len() == 1 && sources.contains(from);
+                (only_from && !unnested(expr))
+                    .then(|| join::relative(expr, from))
+                    .flatten()
+            };
+            let id = Path::root().key(IDS).key(from);
+            plan::choose(map, &conjuncts, &id, &relative, hint)
+        };
+        let joins = match self.query.joins.is_empty() {
+            true => None,
+            false => Some(JoinPlan::new(&self.query, lookup, access.estimate, budget)?),
+        };
+        Ok(Physical { map, access, joins })
+    }
+
+    /// Run the query within `budget`, adding what each operator did to `profile`.
+    pub(crate) fn execute<'m, M, S>(
+        &self,
+        physical: &Physical<'m, '_, M, S>,
+        cursor: Option<&Cursor>,
+        profile: Option<&Profile>,
+        budget: &Budget,
+    ) -> Result<Page<'m>, QueryError>
+    where
+        S: BuildHasher,
+    {
+        check_cursor(&self.sort, cursor)?;
+        let start = Instant::now();
+        let access = match cursor {
+            Some(cursor) if physical.access.ordered => {
+                Cow::Owned(physical.access.access.clone().after(cursor))
+            }
+            _ => Cow::Borrowed(&physical.access.access),
+        };
+        let documents = Measured::new(
+            budget.scanning(access.rows(physical.map)),
+            profile.map(|profile| &profile.access),
+        );
+        let owned = |id: &str, doc: &Value| {
+            let mut doc = doc.clone();
+            if let (true, Value::Object(fields)) = (self.ids, &mut doc) {
+                fields.insert(IDS.to_owned(), Value::from(id));
+            }
+            doc
+        };
+        let unnest = profile.map(|profile| profile.unnest.as_slice());
+        let rows: Box<dyn Iterator<Item = Candidate<'m>> + '_> =
+            match (&physical.joins, &self.unnesting) {
+                (None, None) if self.ids => Box::new(documents.map(move |(id, doc)| {
+                    Candidate::new(Some(id), Cow::Borrowed(id), Cow::Owned(owned(id, doc)))
+                })),
+                (None, None) => Box::new(documents.map(|(id, doc)| {
+                    Candidate::new(Some(id), Cow::Borrowed(id), Cow::Borrowed(doc))
+                })),
+                (None, Some(unnesting)) => {
+                    let docs = documents.map(move |(id, doc)| (id, Vec::new(), owned(id, doc)));
+                    Box::new(unnesting.apply(docs, unnest).map(|(id, positions, doc)| {
+                        // rows of the same document differ by the positions of their elements
+                        let tie = Value::from(vec![Value::from(id), Value::from(positions)]);
+                        Candidate::new(Some(id), Cow::Owned(tie.to_string()), Cow::Owned(doc))
+                    }))
+                }
+                (Some(joins), unnesting) => {
+                    let rows = documents.map(|(id, doc)| joins.row(id, doc));
+                    let rows: Box<dyn Iterator<Item = Value>> = match unnesting {
+                        Some(unnesting) => Box::new(
+                            unnesting
+                                .apply(rows.map(|row| ((), Vec::new(), row)), unnest)
+                                .map(|(_, _, row)| row),
+                        ),
+                        None => Box::new(rows),
+                    };
+                    Box::new(
+                        joins
+                            .apply(
+                                rows,
+                                profile.map(|profile| profile.joins.as_slice()),
+                                budget,
+                            )
+                            .map(|(tie, row)| {
+                                Candidate::new(None, Cow::Owned(tie), Cow::Owned(row))
+                            }),
+                    )
+                }
+            };
+        let matching = Measured::new(
+            rows.filter(|row| {
+                self.filter
+                    .as_ref()
+                    .is_none_or(|filter| filter.matches(&row.doc))
+            }),
+            profile.map(|profile| &profile.filter),
+        );
+        let Some(Aggregation { grouping, having }) = &self.aggregation else {
+            let ordered = physical.access.ordered;
+            let page = self.paginate(cursor, matching, ordered, start, profile, budget);
+            budget.check()?;
+            return Ok(page);
+        };
+
+        let groups = grouping.run(matching.map(|row| row.doc));
+        if let Some(profile) = profile {
+            profile.aggregate.add(groups.len() as u64, start.elapsed());
+            profile.having.add(0, start.elapsed());
+        }
+        let groups = Measured::new(
+            groups.into_iter().filter(|group| {
+                having
+                    .as_ref()
+                    .is_none_or(|having| test(having, &group.row).is_true())
+            }),
+            profile.map(|profile| &profile.having),
+        )
+        .map(|group| Candidate::new(None, Cow::Owned(group.key), Cow::Owned(group.row)));
+        let page = self.paginate(cursor, groups, false, start, profile, budget);
+        budget.check()?;
+        Ok(page)
+    }
+
+    /// Returns true if the rows are sorted, which pages need.
+    fn sorted(&self, cursor: Option<&Cursor>) -> bool {
+        !self.sort.is_empty()
+            || self.query.limit.is_some()
+            || self.query.offset.is_some()
+            || cursor.is_some()
+    }
+
+    /// Sort, page and project `rows`, whose production started at `start`, stopping once the
+    /// projected rows exceed `budget`. Rows come `ordered` by the sort key when an ordered
+    /// index reads them, and are no longer read once they sort after a full page.
+    fn paginate<'m>(
+        &self,
+        cursor: Option<&Cursor>,
+        rows: impl Iterator<Item = Candidate<'m>>,
+        ordered: bool,
+        start: Instant,
+        profile: Option<&Profile>,
+        budget: &Budget,
+    ) -> Page<'m> {
+        let (query, sort, projection) = (&self.query, &self.sort, &self.projection);
+        let done = |counter: fn(&Profile) -> &Counter, rows: usize| {
+            if let Some(profile) = profile {
+                counter(profile).add(rows as u64, start.elapsed());
+            }
+        };
+        if !self.sorted(cursor) {
+            let rows: Vec<Row> = rows
+                .map(|row| Row {
+                    id: row.id,
+                    value: project(projection, row.doc),
+                })
+                .take_while(|row| budget.emit(&row.value))
+                .collect();
+            done(|profile| &profile.project, rows.len());
+            return Page { rows, next: None };
+        }
+
+        let rows = rows
+            .map(|mut row| {
+                row.value = sort
+                    .needs_projection()
+                    .then(|| project(projection, row.doc.clone()));
+                row.keys = sort.keys(&row.doc, row.value.as_deref().unwrap_or(&row.doc));
+                row
+            })
+            .filter(|row| {
+                cursor.is_none_or(|cursor| {
+                    sort.cmp((&row.keys, &row.tie), (&cursor.keys, &cursor.id))
+                        .is_gt()
+                })
+            });
+        let cmp = |a: &Candidate, b: &Candidate| sort.cmp((&a.keys, &a.tie), (&b.keys, &b.tie));
+        let offset = match cursor {
+            Some(_) => 0,
+            None => query.offset.unwrap_or(0) as usize,
+        };
+        let end = query
+            .limit
+            .map(|limit| offset.saturating_add(limit as usize));
+        let (mut rows, more) = match end {
+            None => (rows.collect::<Vec<_>>(), false),
+            Some(end) => {
+                // only the first `end` rows are kept, the last of them on top
+                let mut kept = BinaryHeap::new();
+                let mut more = false;
+                for row in rows {
+                    if kept.len() < end {
+                        kept.push(Ranked { sort, row });
+                        continue;
+                    }
+                    more = true;
+                    if ordered
+                        && kept
+                            .peek()
+                            .is_none_or(|last: &Ranked| past(&row, &last.row))
+                    {
+                        break;
+                    }
+                    let row = Ranked { sort, row };
+                    if let Some(mut last) = kept.peek_mut() {
+                        if row < *last {
+                            *last = row;
+                        }
+                    }
+                }
+                let rows = kept.into_iter().map(|ranked| ranked.row).collect();
+                (rows, more)
+            }
+        };
+        rows.sort_unstable_by(cmp);
+        done(|profile| &profile.sort, rows.len());
+
+        let next = rows.last().filter(|_| more).map(|last| Cursor {
+            order: sort.fingerprint(),
+            keys: last.keys.clone(),
+            id: last.tie.clone().into_owned(),
+        });
+        done(|profile| &profile.limit, rows.len().saturating_sub(offset));
+        let rows: Vec<Row> = rows
+            .into_iter()
+            .skip(offset)
+            .map(|row| Row {
+                id: row.id,
+                value: row.value.unwrap_or_else(|| project(projection, row.doc)),
+            })
+            .take_while(|row| budget.emit(&row.value))
+            .collect();
+        done(|profile| &profile.project, rows.len());
+        Page { rows, next }
+    }
+
+    /// The plan tree of the query, with what each operator did if `profile` is given.
+    pub(crate) fn describe<M, S>(
+        &self,
+        physical: &Physical<'_, '_, M, S>,
+        profile: Option<&Profile>,
+    ) -> Plan
+    where
+        S: BuildHasher,
+    {
+        let source = &self.source;
+        let counter = |get: fn(&Profile) -> &Counter| profile.map(get);
+        let access = &physical.access;
+        let mut plan = Plan::leaf(
+            access.access.describe(&source.from.to_string()),
+            access.estimate,
+        )
+        .measured(counter(|profile| &profile.access));
+        let mut rows = access.estimate;
+
+        for (i, clause) in source.unnest.iter().enumerate() {
+            rows = rows.saturating_mul(unnest::FANOUT);
+            plan = plan
+                .above(clause.to_string(), rows)
+                .measured(profile.map(|profile| &profile.unnest[i]));
+        }
+
+        if let Some(joins) = &physical.joins {
+            for (i, ((method, estimate), join)) in
+                joins.describe().into_iter().zip(&source.joins).enumerate()
+            {
+                plan = plan
+                    .above(format!("{join} {method}"), estimate)
+                    .measured(profile.map(|profile| &profile.joins[i]));
+                rows = estimate;
+            }
+        }
+
+        if let (Some(filter), Some(bound)) = (&source.filter, &self.query.filter) {
+            // conjuncts the access path narrowed the documents by are already accounted for
+            let selectivity = plan::conjuncts(bound)
+                .into_iter()
+                .enumerate()
+                .filter(|(i, _)| physical.joins.is_some() || !access.used.contains(i))
+                .map(|(_, conjunct)| plan::selectivity(conjunct))
+                .product::<f64>();
+            rows = (rows as f64 * selectivity).ceil() as u64;
+            plan = plan
+                .above(format!("filter {filter}"), rows)
+                .measured(counter(|profile| &profile.filter));
+        }
+
+        if self.aggregation.is_some() {
+            let (operator, estimate) = match source.group_by.as_slice() {
+                [] => ("aggregate".to_owned(), 1),
+                keys => {
+                    let groups = match keys {
+                        [key] if physical.joins.is_none() && self.unnesting.is_none() => {
+                            distinct(physical.map, key)
+                        }
+                        _ => None,
+                    };
+                    let groups = groups.unwrap_or(rows.div_ceil(10)).clamp(1, rows.max(1));
+                    let keys: Vec<_> = keys.iter().map(Expr::to_string).collect();
+                    (format!("group by {}", keys.join(", ")), groups)
+                }
+            };
+            rows = estimate;
+            plan = plan
+                .above(operator, rows)
+                .measured(counter(|profile| &profile.aggregate));
+            if let Some(having) = &source.having {
+                rows = rows.div_ceil(2);
+                plan = plan
+                    .above(format!("having {having}"), rows)
+                    .measured(counter(|profile| &profile.having));
+            }
+        }
+
+        if self.sorted(None) {
+            let operator = match source.order_by.as_slice() {
+                [] => "sort by id".to_owned(),
+                keys => {
+                    let keys: Vec<_> = keys.iter().map(OrderBy::to_string).collect();
+                    format!("sort by {}", keys.join(", "))
+                }
+            };
+            plan = plan
+                .above(operator, rows)
+                .measured(counter(|profile| &profile.sort));
+            if source.limit.is_some() || source.offset.is_some() {
+                let offset = source.offset.unwrap_or(0);
+                rows = rows
+                    .saturating_sub(offset)
+                    .min(source.limit.unwrap_or(u64::MAX));
+                let mut operator = String::new();
+                if let Some(limit) = source.limit {
+                    operator = format!("limit {limit}");
+                }
+                if let Some(offset) = source.offset {
+                    operator = format!("{operator} offset {offset}")
+                        .trim_start()
+                        .to_owned();
+                }
+                plan = plan
+                    .above(operator, rows)
+                    .measured(counter(|profile| &profile.limit));
+            }
+        }
+
+        let operator = match &source.select {
+            Select::All if physical.joins.is_none() => return plan,
+            Select::All => "select *".to_owned(),
+            Select::Fields(items) => {
+                let items: Vec<_> = items.iter().map(SelectItem::to_string).collect();
+                format!("select {}", items.join(", "))
+            }
+        };
+        plan.above(operator, rows)
+            .measured(counter(|profile| &profile.project))
+    }
+}
+
+/// The number of distinct keys of an index over exactly `key`, if there is one.
+fn distinct<M, S>(map: Documents<'_, '_, M, S>, key: &Expr) -> Option<u64>
+where
+    S: BuildHasher,
+{
+    let Expr::Path(path) = key else {
+        return None;
+    };
+    let def = map.indexes().find(|def| {
+        def.kind != IndexKind::Text
+            && !def.is_partial()
+            && def.keys == [KeyExpr::Path(path.clone())]
+    })?;
+    Some(map.index(&def.name)?.len() as u64)
+}
+
+fn check_cursor(sort: &Sort, cursor: Option<&Cursor>) -> Result<(), QueryError> {
+    if cursor.is_some_and(|cursor| cursor.order != sort.fingerprint()) {
+        return Err(QueryError::InvalidCursor);
+    }
+    Ok(())
+}
+
+/// The projected form of `doc`, owned if `doc` is.
+fn project<'m>(projection: &Projection, doc: Cow<'m, Value>) -> Cow<'m, Value> {
+    match doc {
+        Cow::Borrowed(doc) => projection.apply(doc),
+        Cow::Owned(doc) => Cow::Owned(projection.apply(&doc).into_owned()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use std::time::Duration;
+
+    use super::*;
+    use crate::index::IndexDef;
+    use crate::query::{parse, Abort, CancelToken};
+    use crate::rwmap::RwMap;
+
+    #[test]
+    fn filters_and_projects() {
+        let (mut w, r) = RwMap::default::<String, Value>();
+        w.insert(
+            "1".into(),
+            json!({"name": "a", "age": 30, "blob": [1, 2, 3]}),
+        );
+        w.insert("2".into(), json!({"name": "b", "age": 20, "blob": [4]}));
+        w.publish();
+
+        let map = r.enter().unwrap();
+        let query = parse("from t where age > 25 select name, age / 10 as decades").unwrap();
+        let rows = execute(&query, &map).unwrap();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].id, Some("1"));
+        assert_eq!(
+            rows[0].value,
+            Cow::<Value>::Owned(json!({"name": "a", "decades": 3}))
+        );
+
+        let rows = execute(&parse("from t").unwrap(), &map).unwrap();
+        assert!(rows.iter().all(|row| matches!(row.value, Cow::Borrowed(_))));
+        assert!(execute(&parse("from t where f(x)").unwrap(), &map).is_err());
+    }
+
+    fn ids(rows: &[Row]) -> Vec<String> {
+        rows.iter().map(|row| row.id.unwrap().to_owned()).collect()
+    }
+
+    #[test]
+    fn sorts_and_limits() {
+        let (mut w, r) = RwMap::default::<String, Value>();
+        for (id, team, score) in [
+            ("a", 1, 5),
+            ("b", 2, 7),
+            ("c", 1, 9),
+            ("d", 2, 7),
+            ("e", 1, 1),
+        ] {
+            w.insert(id.into(), json!({ "team": team, "score": score }));
+        }
+        w.publish();
+        let map = r.enter().unwrap();
+        let run = |source: &str| ids(&execute(&parse(source).unwrap(), &map).unwrap());
+
+        assert_eq!(
+            run("from t order by team, score desc"),
+            ["c", "a", "e", "b", "d"]
+        );
+        assert_eq!(
+            run("from t order by score desc limit 2 offset 1"),
+            ["b", "d"]
+        );
+        assert_eq!(run("from t limit 2"), ["a", "b"]);
+        assert_eq!(
+            run("from t select score * -1 as neg order by neg limit 1"),
+            ["c"]
+        );
+    }
+
+    #[test]
+    fn cursors_survive_publishes() {
+        let (mut w, r) = RwMap::default::<String, Value>();
+        for i in 0..10 {
+            w.insert(format!("{i}"), json!({ "n": i % 4 }));
+        }
+        w.publish();
+        let query = parse("from t order by n desc limit 3").unwrap();
+
+        let mut seen = Vec::new();
+        let mut cursor = None;
+        loop {
+            let page = {
+                let map = r.enter().unwrap();
+                let page = execute_page(&query, &map, cursor.as_ref()).unwrap();
+                seen.extend(ids(&page.rows));
+                page.next
+            };
+            let Some(next) = page else {
+                break;
+            };
+            // the cursor travels to the client and back as text
+            cursor = Some(next.to_string().parse::<Cursor>().unwrap());
+            // documents before and after the position change between pages
+            if seen.len() == 3 {
+                w.insert("10".into(), json!({"n": 3}));
+                w.insert("11".into(), json!({"n": 0}));
+                w.remove("9".into());
+                w.publish();
+            }
+        }
+        // "10" sorts before the cursor and "9" was removed before its page was read
+        assert_eq!(seen, ["3", "7", "2", "6", "1", "5", "0", "11", "4", "8"]);
+
+        let map = r.enter().unwrap();
+        let other = parse("from t order by n limit 3").unwrap();
+        assert_eq!(
+            execute_page(&other, &map, cursor.as_ref()),
+            Err(QueryError::InvalidCursor)
+        );
+    }
+
+    #[test]
+    fn groups_and_aggregates() {
+        let (mut w, r) = RwMap::default::<String, Value>();
+        for (id, city, age) in [
+            ("a", "Oslo", 30),
+            ("b", "Bergen", 40),
+            ("c", "Oslo", 20),
+            ("d", "Oslo", 45),
+            ("e", "Tromsø", 50),
+            ("f", "Bergen", 22),
+        ] {
+            w.insert(id.into(), json!({ "address": {"city": city}, "age": age }));
+        }
+        w.publish();
+        let map = r.enter().unwrap();
+        let run = |source: &str| {
+            let rows = execute(&parse(source).unwrap(), &map).unwrap();
+            assert!(rows.iter().all(|row| row.id.is_none()));
+            rows.into_iter().map(Row::into_value).collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            run(
+                "from t where age > 20 group by address.city having count(*) > 1 \
+                 select address.city, count(*) as n, max(age) - min(age) as spread \
+                 order by n desc"
+            ),
+            // groups with equal keys are ordered by their group by values
+            [
+                json!({"address": {"city": "Bergen"}, "n": 2, "spread": 18}),
+                json!({"address": {"city": "Oslo"}, "n": 2, "spread": 15}),
+            ]
+        );
+        assert_eq!(
+            run("from t group by age >= 40 select age >= 40 as old, avg(age) order by old"),
+            [
+                json!({"old": false, "avg(age)": 24.0}),
+                json!({"old": true, "avg(age)": 45.0}),
+            ]
+        );
+        assert_eq!(
+            run("from t where age > 100 select count(*) as n, sum(age) as total"),
+            [json!({"n": 0, "total": null})]
+        );
+
+        // groups page with cursors like documents do
+        let query = parse(
+            "from t group by address.city select address.city order by count(*) desc limit 2",
+        )
+        .unwrap();
+        let page = execute_page(&query, &map, None).unwrap();
+        assert_eq!(page.rows.len(), 2);
+        let rest = execute_page(&query, &map, page.next.as_ref()).unwrap();
+        assert_eq!(
+            rest.rows
+                .into_iter()
+                .map(Row::into_value)
+                .collect::<Vec<_>>(),
+            [json!({"address": {"city": "Tromsø"}})]
+        );
+
+        for invalid in [
+            "from t group by address.city select age",
+            "from t group by age",
+            "from t where count(*) > 1 select age",
+            "from t select count(*), age",
+        ] {
+            assert!(
+                matches!(
+                    execute(&parse(invalid).unwrap(), &map),
+                    Err(QueryError::Invalid(_))
+                ),
+                "{invalid}"
+            );
+        }
+    }
+
+    #[test]
+    fn aborts_past_limits() {
+        let (mut w, r) = RwMap::default::<String, Value>();
+        for i in 0..1000 {
+            w.insert(format!("{i:03}"), json!({ "n": i, "team": i % 4 }));
+        }
+        w.create_index(IndexDef::ordered("by_n", Path::parse("n").unwrap()));
+        w.publish();
+        let map = r.enter().unwrap();
+        let run = |source: &str, limits: &Limits| {
+            execute_page_limited(&parse(source).unwrap(), &map, None, limits)
+                .map(|page| page.rows.len())
+        };
+
+        let limits = Limits::new().max_rows_scanned(100);
+        assert_eq!(run("from t where n < 50", &limits), Ok(50));
+        for source in [
+            "from t where team = 1",
+            "from t group by team select team, count(*)",
+            "from t where n < 10 and n in (from t where team = 2 select n)",
+        ] {
+            assert_eq!(
+                run(source, &limits),
+                Err(QueryError::Aborted(Abort::RowsScanned(100))),
+                "{source}"
+            );
+        }
+
+        let limits = Limits::new().max_result_bytes(100);
+        assert_eq!(run("from t where n < 5 select n", &limits), Ok(5));
+        assert_eq!(
+            run("from t where n < 100 select n order by n", &limits),
+            Err(QueryError::Aborted(Abort::ResultBytes(100)))
+        );
+        // the rows of subqueries are not results
+        assert_eq!(
+            run(
+                "from t where n < 2 and n in (from t select n) select n",
+                &limits
+            ),
+            Ok(2)
+        );
+
+        let token = CancelToken::new();
+        let limits = Limits::new().cancel_on(token.clone());
+        assert_eq!(run("from t", &limits), Ok(1000));
+        token.cancel();
+        assert_eq!(
+            run("from t where id() = '001'", &limits),
+            Err(QueryError::Aborted(Abort::Cancelled))
+        );
+        let limits = Limits::new().timeout(Duration::ZERO);
+        assert_eq!(
+            run("from t", &limits),
+            Err(QueryError::Aborted(Abort::Timeout(Duration::ZERO)))
+        );
+    }
+
+    #[test]
+    fn pages_in_index_order() {
+        let (mut w, r) = RwMap::default::<String, Value>();
+        for i in 0..300 {
+            w.insert(format!("{i:03}"), json!({ "n": (i * 7) % 100 }));
+        }
+        for (id, n) in [
+            ("x1", json!(null)),
+            ("x3", json!({"a": 1})),
+            ("x4", json!("s")),
+            ("x5", json!([1, {"a": 2}])),
+            ("x6", json!([0])),
+            ("x7", json!(true)),
+        ] {
+            w.insert(id.into(), json!({ "n": n }));
+        }
+        w.insert("x2".into(), json!({}));
+        w.create_index(IndexDef::ordered("by_n", Path::parse("n").unwrap()));
+        w.publish();
+        let map = r.enter().unwrap();
+        let query = parse("from t order by n limit 7").unwrap();
+        let plan = explain(&query, &map, true).unwrap();
+        let access = &plan.children[0].children[0];
+        assert_eq!(access.operator, "index order scan t.by_n");
+        // the page and the row after it, which sorts past the page
+        assert_eq!(access.actual_rows, Some(8));
+
+        let mut seen = Vec::new();
+        let mut cursor = None;
+        for page in 0.. {
+            // pages of numbers read about their own rows, and the rest the documents the
+            // index does not order
+            let limits = match page {
+                0..40 => Limits::new().max_rows_scanned(16),
+                _ => Limits::new(),
+            };
+            let page = execute_page_limited(&query, &map, cursor.as_ref(), &limits).unwrap();
+            seen.extend(ids(&page.rows));
+            cursor = page.next;
+            if cursor.is_none() {
+                break;
+            }
+        }
+        let all = execute(&parse("from t order by n").unwrap(), &map).unwrap();
+        assert_eq!(seen, ids(&all));
+        assert_eq!(seen[..2], ["x7", "000"]);
+        assert_eq!(seen[seen.len() - 6..], ["x4", "x6", "x5", "x3", "x1", "x2"]);
+    }
+
+    #[test]
+    fn pages_in_index_order_across_numbers_and_nulls() {
+        let (mut w, r) = RwMap::default::<String, Value>();
+        for i in 0..200 {
+            let k = i / 4;
+            let n = match i % 4 {
+                0 => json!(k),
+                1 => json!(k as f64),
+                2 => json!(k as f64 + 0.5),
+                _ => json!(-(k as f64) - 0.25),
+            };
+            w.insert(format!("{i:03}"), json!({ "n": n }));
+        }
+        for i in 0..10 {
+            w.insert(format!("m{i}"), json!({}));
+            w.insert(format!("n{i}"), json!({ "n": null }));
+        }
+        w.create_index(IndexDef::ordered("by_n", Path::parse("n").unwrap()));
+        w.publish();
+        let map = r.enter().unwrap();
+        let query = parse("from t order by n limit 7").unwrap();
+
+        let mut seen = Vec::new();
+        let mut cursor = None;
+        // a page reads the rows equal to the cursor, its own, the one after it and the
+        // documents without a number, never the whole collection
+        let limits = Limits::new().max_rows_scanned(30);
+        loop {
+            let page = execute_page_limited(&query, &map, cursor.as_ref(), &limits).unwrap();
+            seen.extend(ids(&page.rows));
+            cursor = page.next;
+            if cursor.is_none() {
+                break;
+            }
+        }
+        let all = execute(&parse("from t order by n").unwrap(), &map).unwrap();
+        assert_eq!(seen, ids(&all));
+        assert_eq!(seen[..2], ["199", "195"]);
+        // 48 and 48.0 tie, so they come in id order
+        assert_eq!(
+            seen[seen.len() - 26..seen.len() - 20],
+            ["192", "193", "194", "196", "197", "198"]
+        );
+        let nulls: Vec<_> = (0..10)
+            .map(|i| format!("m{i}"))
+            .chain((0..10).map(|i| format!("n{i}")))
+            .collect();
+        assert_eq!(seen[seen.len() - 20..], nulls);
+    }
+
+    #[test]
+    fn explains_plans() {
+        let (mut w, r) = RwMap::default::<String, Value>();
+        for i in 0..20 {
+            w.insert(format!("{i:02}"), json!({ "n": i, "team": i % 4 }));
+        }
+        w.create_index(crate::index::IndexDef::ordered(
+            "by_n",
+            Path::parse("n").unwrap(),
+        ));
+        w.publish();
+        let map = r.enter().unwrap();
+        let run = |source: &str| ids(&execute(&parse(source).unwrap(), &map).unwrap());
+
+        assert_eq!(run("from t where id() in ('03', '05', 'x')"), ["03", "05"]);
+        assert_eq!(run("from t where n >= 17 and team = 1"), ["17"]);
+        assert_eq!(
+            execute(
+                &parse("from t where id() = '01' select id() as id").unwrap(),
+                &map
+            )
+            .unwrap()[0]
+                .value,
+            Cow::<Value>::Owned(json!({"id": "01"}))
+        );
+        assert_eq!(
+            execute(&parse("from t where id() = '01'").unwrap(), &map).unwrap()[0].value,
+            Cow::<Value>::Owned(json!({"n": 1, "team": 1}))
+        );
+        assert!(matches!(
+            execute(&parse("from t where id(t) = '01'").unwrap(), &map),
+            Err(QueryError::Invalid(_))
+        ));
+
+        let query =
+            parse("from t where n >= 10 and team = 1 select n order by n desc limit 2").unwrap();
+        let plan = explain(&query, &map, false).unwrap();
+        assert_eq!(
+            plan.to_string(),
+            "select n (rows=1)\n\
+             -> limit 2 (rows=1)\n   \
+                -> sort by n desc (rows=1)\n      \
+                   -> filter n >= 10 and team = 1 (rows=1)\n         \
+                      -> index range scan t.by_n [10, *] (rows=10)"
+        );
+        assert!(plan.actual_rows.is_none());
+
+        let plan = explain(&query, &map, true).unwrap();
+        let actual = |plan: &Plan| plan.actual_rows.unwrap();
+        let filter = &plan.children[0].children[0].children[0];
+        assert_eq!(actual(&plan), 2);
+        assert_eq!(actual(filter), 2);
+        assert_eq!(actual(&filter.children[0]), 10);
+        assert!(filter.elapsed.unwrap() <= plan.elapsed.unwrap());
+
+        let rows = execute(&parse(&format!("explain analyze {query}")).unwrap(), &map).unwrap();
+        assert_eq!(rows[0].value["children"][0]["actual_rows"], json!(2));
+        let plan = explain(
+            &parse("from t group by team select team, count(*) as n").unwrap(),
+            &map,
+            false,
+        )
+        .unwrap();
+        assert_eq!(plan.operator, "select team, count(*) as n");
+        assert_eq!(plan.children[0].operator, "group by team");
+        assert_eq!(plan.children[0].children[0].operator, "scan t");
+    }
+
+    #[test]
+    fn string_functions_use_expression_indexes() {
+        let (mut w, r) = RwMap::default::<String, Value>();
+        w.create_index(IndexDef::hash(
+            "by_email",
+            KeyExpr::parse("lower(trim(email))").unwrap(),
+        ));
+        w.create_index(IndexDef::ordered("by_name", Path::parse("name").unwrap()));
+        for (id, email, name) in [
+            ("1", " Ada@X ", "ada"),
+            ("2", "bob@x", "Bob"),
+            ("3", "cy@x", "\u{c9}mile"),
+        ] {
+            w.insert(id.into(), json!({"email": email, "name": name}));
+        }
+        w.publish();
+        let map = r.enter().unwrap();
+        let access = |source: &str| {
+            let plan = explain(&parse(source).unwrap(), &map, false).unwrap();
+            let mut plan = &plan;
+            while let Some(child) = plan.children.first() {
+                plan = child;
+            }
+            plan.operator.clone()
+        };
+        let run = |source: &str| ids(&execute(&parse(source).unwrap(), &map).unwrap());
+
+        let source = "from t where lower(trim(email)) = 'ada@x'";
+        assert_eq!(access(source), "index lookup t.by_email (\"ada@x\")");
+        assert_eq!(run(source), ["1"]);
+        let source = "from t where name = 'BOB' collate nocase";
+        assert_eq!(access(source), "scan t");
+        assert_eq!(run(source), ["2"]);
+        assert_eq!(run("from t order by name collate nocase"), ["1", "2", "3"]);
+        assert_eq!(run("from t order by name"), ["2", "1", "3"]);
+    }
+
+    #[test]
+    fn groups_by_time_buckets() {
+        let (mut w, r) = RwMap::default::<String, Value>();
+        for (id, at, n) in [
+            ("1", json!("2024-03-01T10:05:00+01:00"), 1),
+            ("2", json!(1709287800000i64), 2),
+            ("3", json!("2024-03-01T09:31:00Z"), 4),
+            ("4", json!("2024-03-02T00:30:00+02:00"), 8),
+            ("5", json!("not a time"), 16),
+        ] {
+            w.insert(id.into(), json!({"at": at, "n": n}));
+        }
+        w.publish();
+        let map = r.enter().unwrap();
+        let values = |source: &str| -> Vec<Value> {
+            execute(&parse(source).unwrap(), &map)
+                .unwrap()
+                .into_iter()
+                .map(Row::into_value)
+                .collect()
+        };
+
+        assert_eq!(
+            values(
+                "from t group by time_bucket('30 min', at) \
+                 having time_bucket('30 min', at) is not null \
+                 select time_bucket('30 min', at) as bucket, sum(n) as n order by bucket"
+            ),
+            [
+                json!({"bucket": "2024-03-01T09:00:00.000Z", "n": 1}),
+                json!({"bucket": "2024-03-01T09:30:00.000Z", "n": 4}),
+                json!({"bucket": "2024-03-01T10:00:00.000Z", "n": 2}),
+                json!({"bucket": "2024-03-01T22:30:00.000Z", "n": 8}),
+            ]
+        );
+        assert_eq!(
+            values(
+                "from t where exists(at) and timestamp(at) is not null \
+                 group by date_trunc('day', at, 'Asia/Tokyo') \
+                 select date_trunc('day', at, 'Asia/Tokyo') as day, sum(n) as n order by day"
+            ),
+            [
+                json!({"day": "2024-02-29T15:00:00.000Z", "n": 7}),
+                json!({"day": "2024-03-01T15:00:00.000Z", "n": 8}),
+            ]
+        );
+        let sorted = |source: &str| ids(&execute(&parse(source).unwrap(), &map).unwrap());
+        assert_eq!(
+            sorted("from t where timestamp(at) is not null order by timestamp(at)"),
+            ["1", "3", "2", "4"]
+        );
+        assert_eq!(
+            sorted(
+                "from t where timestamp(at) between '2024-03-01T09:30:00Z' \
+                 and date_add('2024-03-01T09:30:00Z', 'PT1H') order by id()"
+            ),
+            ["2", "3"]
+        );
+
+        // every row sees the same `now()`, which is after the documents were written
+        let rows = values("from t where timestamp(at) < now() select now() as a, now() as b");
+        assert_eq!(rows.len(), 4);
+        assert!(rows
+            .iter()
+            .all(|row| row["a"] == rows[0]["a"] && row["b"] == row["a"]));
+    }
+
+    #[test]
+    fn compares_times_with_literals() {
+        let (mut w, r) = RwMap::default::<String, Value>();
+        for (id, at) in [
+            ("1", json!("2024-03-01T09:30:00Z")),
+            ("2", json!("2024-03-01T10:30:00+01:00")),
+            ("3", json!(1709289000000i64)),
+            ("4", json!("2024-03-01T09:29:59.999Z")),
+            ("5", json!("2024-03-02")),
+        ] {
+            w.insert(id.into(), json!({ "at": at }));
+        }
+        w.publish();
+        let map = r.enter().unwrap();
+        let sorted = |filter: &str| {
+            let query = parse(&format!("from t where {filter} order by id()")).unwrap();
+            ids(&execute(&query, &map).unwrap())
+        };
+
+        assert_eq!(sorted("timestamp(at) = '2024-03-01T09:30:00Z'"), ["1", "2"]);
+        assert_eq!(
+            sorted("timestamp(at) >= '2024-03-01T11:30+02:00'"),
+            ["1", "2", "3", "5"]
+        );
+        assert_eq!(sorted("'2024-03-01T09:30:00.000Z' > timestamp(at)"), ["4"]);
+        // both bounds hold a document
+        assert_eq!(
+            sorted("timestamp(at) between '2024-03-01T09:30:00+00:00' and 1709289000000"),
+            ["1", "2", "3"]
+        );
+        assert_eq!(
+            sorted("timestamp(at) not between '2024-03-01T09:30:00Z' and '2024-03-01T10:00Z'"),
+            ["3", "4", "5"]
+        );
+        assert_eq!(
+            sorted("timestamp(at) in ('2024-03-02', '2024-03-01T11:30:00+01:00', 'x')"),
+            ["3", "5"]
+        );
+        assert_eq!(sorted("date_trunc('day', at) = '2024-03-02'"), ["5"]);
+    }
+
+    #[test]
+    fn unnests_arrays_into_rows() {
+        let (mut w, r) = RwMap::default::<String, Value>();
+        w.create_index(IndexDef::hash("by_tags", Path::parse("tags").unwrap()));
+        w.insert("1".into(), json!({"tags": ["a", "b"], "n": 1}));
+        w.insert("2".into(), json!({"tags": ["b"], "n": 2}));
+        w.insert("3".into(), json!({"tags": [], "n": 3}));
+        w.insert("4".into(), json!({"n": 4}));
+        w.publish();
+        let map = r.enter().unwrap();
+        let values = |source: &str| -> Vec<Value> {
+            execute(&parse(source).unwrap(), &map)
+                .unwrap()
+                .into_iter()
+                .map(Row::into_value)
+                .collect()
+        };
+
+        assert_eq!(
+            values("from t unnest tags select id() as id, tags order by id, tags"),
+            [
+                json!({"id": "1", "tags": "a"}),
+                json!({"id": "1", "tags": "b"}),
+                json!({"id": "2", "tags": "b"}),
+            ]
+        );
+        assert_eq!(
+            values(
+                "from t unnest tags as tag group by tag \
+                 select tag, count(*) as docs, sum(n) as n order by tag"
+            ),
+            [
+                json!({"tag": "a", "docs": 1, "n": 1}),
+                json!({"tag": "b", "docs": 2, "n": 3}),
+            ]
+        );
+        // `tags = 'b'` holds for the rows, not the documents, so the index is not used
+        let query = parse("from t unnest tags where tags = 'b'").unwrap();
+        let mut found = ids(&execute(&query, &map).unwrap());
+        found.sort();
+        assert_eq!(found, ["1", "2"]);
+        assert_eq!(
+            explain(&query, &map, false).unwrap().to_string(),
+            "filter tags = \"b\" (rows=2)\n-> unnest tags (rows=16)\n   -> scan t (rows=4)"
+        );
+        assert_eq!(
+            values("from t where any(tags, tags = 'a') or array_contains(tags, 'x') select n"),
+            [json!({"n": 1})]
+        );
+
+        // rows of one document page like any others
+        let query = parse("from t unnest tags as tag order by n limit 1").unwrap();
+        let first = execute_page(&query, &map, None).unwrap();
+        let second = execute_page(&query, &map, first.next.as_ref()).unwrap();
+        let third = execute_page(&query, &map, second.next.as_ref()).unwrap();
+        let tags: Vec<_> = [first, second, third]
+            .into_iter()
+            .flat_map(|page| page.rows)
+            .map(|row| row.value["tag"].clone())
+            .collect();
+        assert_eq!(tags.len(), 3);
+        assert_eq!(tags[2], json!("b"));
+    }
+}
@@ -0,0 +1,780 @@
+//! Built-in scalar functions callable from expressions.
+//!
+//! `lower`, `upper`, `trim` and `length` compute the same values as the index
+//! [`KeyExpr`](crate::index::KeyExpr)s of the same name, so conditions on them can use an
+//! expression index.
+//!
+//! The temporal functions work on the timestamps, durations and time zones described in
+//! [`temporal`](super::temporal):
+//!
+//! * `now()` is the time the statement started, the same for all of its rows.
+//! * `timestamp(x[, zone])` is the canonical form of a timestamp, and `epoch_millis(x[, zone])`
+//!   its milliseconds since the epoch.
+//! * `date_trunc(unit, x[, zone])` rounds down to the start of its `millisecond`, `second`,
+//!   `minute`, `hour`, `day`, `week` (from Monday), `month`, `quarter` or `year` in `zone`.
+//! * `date_add(x, duration[, zone])` moves a timestamp by a duration, and `date_diff(a, b)` is
+//!   the milliseconds from `b` to `a`.
+//! * `duration(d)` is a duration in milliseconds.
+//! * `time_bucket(width, x[, origin])` is the start of the `width` long bucket holding `x`,
+//!   counting buckets from `origin` or else the epoch: `group by time_bucket('15 min', at)`.
+use std::borrow::Cow;
+use std::ops::RangeInclusive;
+use std::sync::{Mutex, OnceLock};
+
+use chrono::{DateTime, Utc};
+use hashbrown::HashMap;
+use regex::Regex;
+use serde_json::Value;
+
+use super::ast::{BinaryOp, Cte, Expr, Query};
+use super::eval::{canonical, json_eq};
+use super::temporal::{self, Span, Unit, Zone};
+use super::QueryError;
+
+/// An evaluated argument, `None` when it is missing.
+pub(crate) type Arg<'a> = Option<Cow<'a, Value>>;
+
+struct Function {
+    name: &'static str,
+    arity: RangeInclusive<usize>,
+    call: for<'a> fn(Vec<Arg<'a>>) -> Arg<'a>,
+}
+
+const FUNCTIONS: &[Function] = &[
+    Function {
+        name: "coalesce",
+        arity: 1..=usize::MAX,
+        call: coalesce,
+    },
+    Function {
+        name: "lower",
+        arity: 1..=1,
+        call: |args| map_string(args, str::to_lowercase),
+    },
+    Function {
+        name: "upper",
+        arity: 1..=1,
+        call: |args| map_string(args, str::to_uppercase),
+    },
+    Function {
+        name: "trim",
+        arity: 1..=1,
+        call: |args| map_string(args, |s| s.trim().to_owned()),
+    },
+    Function {
+        name: "length",
+        arity: 1..=1,
+        call: length,
+    },
+    Function {
+        name: "contains",
+        arity: 2..=2,
+        call: |args| test_strings(args, |s, part| s.contains(part)),
+    },
+    Function {
+        name: "starts_with",
+        arity: 2..=2,
+        call: |args| test_strings(args, |s, prefix| s.starts_with(prefix)),
+    },
+    Function {
+        name: "ends_with",
+        arity: 2..=2,
+        call: |args| test_strings(args, |s, suffix| s.ends_with(suffix)),
+    },
+    Function {
+        name: "substring",
+        arity: 2..=3,
+        call: substring,
+    },
+    Function {
+        name: "regex_match",
+        arity: 2..=2,
+        call: regex_match,
+    },
+    Function {
+        name: "array_contains",
+        arity: 2..=2,
+        call: array_contains,
+    },
+    Function {
+        name: "array_length",
+        arity: 1..=1,
+        call: array_length,
+    },
+    Function {
+        name: "array_concat",
+        arity: 1..=usize::MAX,
+        call: array_concat,
+    },
+    // what decorrelated subqueries become, see `subquery`; `#` keeps it from being written
+    Function {
+        name: "#lookup",
+        arity: 2..=3,
+        call: table_lookup,
+    },
+    Function {
+        name: "now",
+        arity: 0..=0,
+        call: |_| Some(Cow::Owned(now())),
+    },
+    Function {
+        name: "timestamp",
+        arity: 1..=2,
+        call: |args| temporal::format(time(&args, 0, 1)?).map(Cow::Owned),
+    },
+    Function {
+        name: "epoch_millis",
+        arity: 1..=2,
+        call: |args| {
+            Some(Cow::Owned(Value::from(
+                time(&args, 0, 1)?.timestamp_millis(),
+            )))
+        },
+    },
+    Function {
+        name: "date_trunc",
+        arity: 2..=3,
+        call: date_trunc,
+    },
+    Function {
+        name: "date_add",
+        arity: 2..=3,
+        call: date_add,
+    },
+    Function {
+        name: "date_diff",
+        arity: 2..=2,
+        call: |args| {
+            let diff = time(&args, 0, 2)?.signed_duration_since(time(&args, 1, 2)?);
+            Some(Cow::Owned(Value::from(diff.num_milliseconds())))
+        },
+    },
+    Function {
+        name: "duration",
+        arity: 1..=1,
+        call: |args| {
+            Some(Cow::Owned(Value::from(
+                Span::parse(args[0].as_deref()?)?.fixed()?,
+            )))
+        },
+    },
+    Function {
+        name: "time_bucket",
+        arity: 2..=3,
+        call: time_bucket,
+    },
+];
+
+/// What a constant argument of a temporal function must be, checked before the query runs.
+struct ConstantArg {
+    function: &'static str,
+    position: usize,
+    what: &'static str,
+    valid: fn(&Value) -> bool,
+}
+
+const TEMPORAL_ARGS: &[ConstantArg] = &[
+    ConstantArg {
+        function: "timestamp",
+        position: 1,
+        what: "time zone",
+        valid: |v| zone(v).is_some(),
+    },
+    ConstantArg {
+        function: "epoch_millis",
+        position: 1,
+        what: "time zone",
+        valid: |v| zone(v).is_some(),
+    },
+    ConstantArg {
+        function: "date_trunc",
+        position: 0,
+        what: "unit",
+        valid: |v| unit(v).is_some(),
+    },
+    ConstantArg {
+        function: "date_trunc",
+        position: 2,
+        what: "time zone",
+        valid: |v| zone(v).is_some(),
+    },
+    ConstantArg {
+        function: "date_add",
+        position: 1,
+        what: "duration",
+        valid: |v| Span::parse(v).is_some(),
+    },
+    ConstantArg {
+        function: "date_add",
+        position: 2,
+        what: "time zone",
+        valid: |v| zone(v).is_some(),
+    },
+    ConstantArg {
+        function: "duration",
+        position: 0,
+        what: "duration",
+        valid: |v| width(v).is_some(),
+    },
+    ConstantArg {
+        function: "time_bucket",
+        position: 0,
+        what: "bucket width",
+        valid: |v| width(v).is_some(),
+    },
+];
+
+fn lookup(name: &str) -> Option<&'static Function> {
+    FUNCTIONS.iter().find(|function| function.name == name)
+}
+
+/// Check that `name` is a known function taking `args`, that a constant pattern given to
+/// `regex_match` compiles, and that constant units, zones and durations are valid.
+pub(crate) fn check(name: &str, args: &[Expr]) -> Result<(), QueryError> {
+    let function = lookup(name).ok_or_else(|| QueryError::UnknownFunction(name.to_owned()))?;
+    if !function.arity.contains(&args.len()) {
+        return Err(QueryError::Arity {
+            function: function.name.to_owned(),
+            min: *function.arity.start(),
+            max: *function.arity.end(),
+            found: args.len(),
+        });
+    }
+    if let ("regex_match", [_, Expr::Literal(Value::String(pattern))]) = (name, args) {
+        regex(pattern).map_err(|err| {
+            QueryError::Invalid(format!("invalid pattern in `regex_match`: {err}"))
+        })?;
+    }
+    for arg in TEMPORAL_ARGS.iter().filter(|arg| arg.function == name) {
+        if let Some(Expr::Literal(value)) = args.get(arg.position) {
+            if !(arg.valid)(value) {
+                return Err(QueryError::Invalid(format!(
+                    "invalid {} {value} in `{name}`",
+                    arg.what
+                )));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The current time, as `now()` returns it.
+pub(crate) fn now() -> Value {
+    temporal::format(Utc::now()).expect("the current year is between 0 and 9999")
+}
+
+/// `expr` with every `now()` replaced by `now`, so that all rows of a statement see the same
+/// time, and times compared with timestamps in canonical form, see [`canonical_operands`].
+/// Subqueries get the same time.
+pub(crate) fn fix_now(expr: &Expr, now: &Value) -> Expr {
+    match expr {
+        Expr::Call { name, args } if name == "now" && args.is_empty() => Expr::Literal(now.clone()),
+        Expr::Subquery(query) => Expr::Subquery(Box::new(fix_now_in(query, now))),
+        Expr::InSubquery {
+            expr,
+            query,
+            negated,
+        } => Expr::InSubquery {
+            expr: Box::new(fix_now(expr, now)),
+            query: Box::new(fix_now_in(query, now)),
+            negated: *negated,
+        },
+        _ => {
+            let fixed = canonical_operands(expr)
+                .try_map_children(|child| Ok::<_, std::convert::Infallible>(fix_now(&child, now)));
+            match fixed {
+                Ok(expr) => expr,
+                Err(never) => match never {},
+            }
+        }
+    }
+}
+
+/// Functions returning canonical timestamps.
+const TIMESTAMPS: &[&str] = &["date_add", "date_trunc", "now", "time_bucket", "timestamp"];
+
+/// `expr` with the literal times it compares with a timestamp in canonical form, so that
+/// `timestamp(at) >= '2024-03-01'` or `date_trunc('day', at) = 1709251200000` compare instants
+/// rather than strings. Literals that are not times are left as they are.
+fn canonical_operands(expr: &Expr) -> Expr {
+    let timestamp = |expr: &Expr| matches!(expr, Expr::Call { name, .. } if TIMESTAMPS.contains(&name.as_str()));
+    let canonical = |operand: &Expr| -> Box<Expr> {
+        let time = match operand {
+            Expr::Literal(value) => temporal::parse(value, None).and_then(temporal::format),
+            _ => None,
+        };
+        Box::new(time.map_or_else(|| operand.clone(), Expr::Literal))
+    };
+    match expr {
+        Expr::Binary { left, op, right }
+            if matches!(
+                op,
+                BinaryOp::Eq
+                    | BinaryOp::Ne
+                    | BinaryOp::Lt
+                    | BinaryOp::Le
+                    | BinaryOp::Gt
+                    | BinaryOp::Ge
+            ) && (timestamp(left) || timestamp(right)) =>
+        {
+            Expr::Binary {
+                left: canonical(left),
+                op: *op,
+                right: canonical(right),
+            }
+        }
+        Expr::In {
+            expr: operand,
+            list,
+            negated,
+        } if timestamp(operand) => Expr::In {
+            expr: operand.clone(),
+            list: list.iter().map(|item| *canonical(item)).collect(),
+            negated: *negated,
+        },
+        Expr::Between {
+            expr: operand,
+            low,
+            high,
+            negated,
+        } if timestamp(operand) => Expr::Between {
+            expr: operand.clone(),
+            low: canonical(low),
+            high: canonical(high),
+            negated: *negated,
+        },
+        _ => expr.clone(),
+    }
+}
+
+/// [`fix_now`] over every expression of `query` and its `with` queries.
+pub(crate) fn fix_now_in(query: &Query, now: &Value) -> Query {
+    let mut fixed = query
+        .clone()
+        .try_map_exprs(|expr| Ok::<_, std::convert::Infallible>(fix_now(expr, now)))
+        .unwrap_or_else(|never| match never {});
+    fixed.with = query
+        .with
+        .iter()
+        .map(|cte| Cte {
+            name: cte.name.clone(),
+            query: fix_now_in(&cte.query, now),
+        })
+        .collect();
+    fixed
+}
+
+/// Call a function previously accepted by [`check`]; unknown names return missing.
+pub(crate) fn call<'a>(name: &str, args: Vec<Arg<'a>>) -> Arg<'a> {
+    (lookup(name)?.call)(args)
+}
+
+/// The most compiled patterns kept for `regex_match`.
+const PATTERNS: usize = 256;
+
+/// `pattern` compiled, from a cache shared by all queries. The cache is emptied when full.
+pub(crate) fn regex(pattern: &str) -> Result<Regex, regex::Error> {
+    static CACHE: OnceLock<Mutex<HashMap<String, Regex>>> = OnceLock::new();
+    let mut cache = CACHE.get_or_init(Mutex::default).lock().unwrap();
+    if let Some(regex) = cache.get(pattern) {
+        return Ok(regex.clone());
+    }
+    let regex = Regex::new(pattern)?;
+    if cache.len() >= PATTERNS {
+        cache.clear();
+    }
+    cache.insert(pattern.to_owned(), regex.clone());
+    Ok(regex)
+}
+
+/// the first argument that is neither missing nor `null`.
+fn coalesce(args: Vec<Arg<'_>>) -> Arg<'_> {
+    args.into_iter().flatten().find(|value| !value.is_null())
+}
+
+/// `f` of a string argument, missing for anything else.
+fn map_string(args: Vec<Arg<'_>>, f: fn(&str) -> String) -> Arg<'_> {
+    match args[0].as_deref()? {
+        Value::String(s) => Some(Cow::Owned(Value::String(f(s)))),
+        _ => None,
+    }
+}
+
+/// `f` of two string arguments as a boolean, missing unless both are strings.
+fn test_strings(args: Vec<Arg<'_>>, f: impl Fn(&str, &str) -> bool) -> Arg<'_> {
+    match (args[0].as_deref()?, args[1].as_deref()?) {
+        (Value::String(s), Value::String(other)) => Some(Cow::Owned(Value::Bool(f(s, other)))),
+        _ => None,
+    }
+}
+
+/// whether a string matches a pattern, missing if the pattern does not compile.
+fn regex_match(args: Vec<Arg<'_>>) -> Arg<'_> {
+    match (args[0].as_deref()?, args[1].as_deref()?) {
+        (Value::String(s), Value::String(pattern)) => {
+            let matched = regex(pattern).ok()?.is_match(s);
+            Some(Cow::Owned(Value::Bool(matched)))
+        }
+        _ => None,
+    }
+}
+
+/// characters in a string or items in an array.
+fn length(args: Vec<Arg<'_>>) -> Arg<'_> {
+    let length = match args[0].as_deref()? {
+        Value::String(s) => s.chars().count(),
+        Value::Array(items) => items.len(),
+        _ => return None,
+    };
+    Some(Cow::Owned(Value::from(length)))
+}
+
+/// whether an array has an item equal to a value, missing unless given an array and a value.
+fn array_contains(args: Vec<Arg<'_>>) -> Arg<'_> {
+    let (Value::Array(items), value) = (args[0].as_deref()?, args[1].as_deref()?) else {
+        return None;
+    };
+    let found = items.iter().any(|item| json_eq(item, value));
+    Some(Cow::Owned(Value::Bool(found)))
+}
+
+/// `#lookup(table, key[, default])`: the value an object holds under the canonical form of
+/// `key`, or else `default`.
+fn table_lookup(mut args: Vec<Arg<'_>>) -> Arg<'_> {
+    let default = if args.len() == 3 { args.pop()? } else { None };
+    let key = args.pop()?;
+    let found = match (args.pop()??, key) {
+        (Cow::Borrowed(table), Some(key)) => table.get(canonical(&key)).map(Cow::Borrowed),
+        (Cow::Owned(table), Some(key)) => table.get(canonical(&key)).cloned().map(Cow::Owned),
+        (_, None) => None,
+    };
+    found.or(default)
+}
+
+/// items in an array, missing for anything else.
+fn array_length(args: Vec<Arg<'_>>) -> Arg<'_> {
+    match args[0].as_deref()? {
+        Value::Array(items) => Some(Cow::Owned(Value::from(items.len()))),
+        _ => None,
+    }
+}
+
+/// the items of every argument in turn, missing unless all are arrays.
+fn array_concat(args: Vec<Arg<'_>>) -> Arg<'_> {
+    let mut items = Vec::new();
+    for arg in args {
+        match arg?.into_owned() {
+            Value::Array(more) => items.extend(more),
+            _ => return None,
+        }
+    }
+    Some(Cow::Owned(Value::Array(items)))
+}
+
+fn zone(value: &Value) -> Option<Zone> {
+    Zone::parse(value.as_str()?)
+}
+
+fn unit(value: &Value) -> Option<Unit> {
+    Unit::lookup(value.as_str()?)
+}
+
+/// a duration of a fixed, positive length in milliseconds.
+fn width(value: &Value) -> Option<i64> {
+    Span::parse(value)?.fixed().filter(|width| *width > 0)
+}
+
+/// The instant of argument `at`, read in the zone of argument `zone_at` if there is one.
+/// `None` if either is missing or invalid.
+fn time(args: &[Arg<'_>], at: usize, zone_at: usize) -> Option<DateTime<Utc>> {
+    let zone = match args.get(zone_at) {
+        Some(arg) => Some(zone(arg.as_deref()?)?),
+        None => None,
+    };
+    temporal::parse(args[at].as_deref()?, zone)
+}
+
+/// The zone of argument `at`, UTC if there is none.
+fn zone_or_utc(args: &[Arg<'_>], at: usize) -> Option<Zone> {
+    match args.get(at) {
+        Some(arg) => zone(arg.as_deref()?),
+        None => Zone::parse("UTC"),
+    }
+}
+
+/// `date_trunc(unit, x[, zone])`
+fn date_trunc(args: Vec<Arg<'_>>) -> Arg<'_> {
+    let unit = unit(args[0].as_deref()?)?;
+    let zone = zone_or_utc(&args, 2)?;
+    let truncated = unit.truncate(time(&args, 1, 2)?, zone)?;
+    temporal::format(truncated).map(Cow::Owned)
+}
+
+/// `date_add(x, duration[, zone])`
+fn date_add(args: Vec<Arg<'_>>) -> Arg<'_> {
+    let span = Span::parse(args[1].as_deref()?)?;
+    let zone = zone_or_utc(&args, 2)?;
+    let moved = span.add_to(time(&args, 0, 2)?, zone)?;
+    temporal::format(moved).map(Cow::Owned)
+}
+
+/// `time_bucket(width, x[, origin])`
+fn time_bucket(args: Vec<Arg<'_>>) -> Arg<'_> {
+    let width = width(args[0].as_deref()?)?;
+    let origin = match args.get(2) {
+        Some(origin) => temporal::parse(origin.as_deref()?, None)?,
+        None => DateTime::UNIX_EPOCH,
+    };
+    let start = temporal::bucket(width, temporal::parse(args[1].as_deref()?, None)?, origin)?;
+    temporal::format(start).map(Cow::Owned)
+}
+
+/// `substring(s, start[, length])`: the characters of `s` from position `start`, counting
+/// from 1. Positions before the first character count toward `length`, as in SQL.
+fn substring(args: Vec<Arg<'_>>) -> Arg<'_> {
+    let Value::String(s) = args[0].as_deref()? else {
+        return None;
+    };
+    let start = args[1].as_deref()?.as_i64()?;
+    let end = match args.get(2) {
+        Some(length) => Some(start.checked_add(length.as_deref()?.as_i64().filter(|n| *n >= 0)?)?),
+        None => None,
+    };
+    let skip = usize::try_from(start.max(1) - 1).ok()?;
+    let take = match end {
+        Some(end) => usize::try_from((end - start.max(1)).max(0)).ok()?,
+        None => usize::MAX,
+    };
+    let part: String = s.chars().skip(skip).take(take).collect();
+    Some(Cow::Owned(Value::String(part)))
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::*;
+
+    fn run(name: &str, args: &[Value]) -> Option<Value> {
+        call(
+            name,
+            args.iter().map(|arg| Some(Cow::Borrowed(arg))).collect(),
+        )
+        .map(Cow::into_owned)
+    }
+
+    #[test]
+    fn string_functions() {
+        for (name, args, expected) in [
+            ("lower", vec![json!("ÀbC")], Some(json!("àbc"))),
+            ("upper", vec![json!("straße")], Some(json!("STRASSE"))),
+            ("trim", vec![json!("  a b \n")], Some(json!("a b"))),
+            ("lower", vec![json!(1)], None),
+            ("length", vec![json!("né")], Some(json!(2))),
+            ("length", vec![json!([1, 2, 3])], Some(json!(3))),
+            (
+                "contains",
+                vec![json!("haystack"), json!("st")],
+                Some(json!(true)),
+            ),
+            (
+                "starts_with",
+                vec![json!("haystack"), json!("st")],
+                Some(json!(false)),
+            ),
+            (
+                "ends_with",
+                vec![json!("haystack"), json!("ack")],
+                Some(json!(true)),
+            ),
+            ("contains", vec![json!(["st"]), json!("st")], None),
+            (
+                "substring",
+                vec![json!("héllo"), json!(2)],
+                Some(json!("éllo")),
+            ),
+            (
+                "substring",
+                vec![json!("héllo"), json!(2), json!(3)],
+                Some(json!("éll")),
+            ),
+            (
+                "substring",
+                vec![json!("hello"), json!(0), json!(2)],
+                Some(json!("h")),
+            ),
+            ("substring", vec![json!("hello"), json!(9)], Some(json!(""))),
+            ("substring", vec![json!("hello"), json!(1), json!(-1)], None),
+            (
+                "regex_match",
+                vec![json!("a-12"), json!(r"^\w-\d+$")],
+                Some(json!(true)),
+            ),
+            ("regex_match", vec![json!("a-12"), json!("(")], None),
+        ] {
+            assert_eq!(run(name, &args), expected, "{name}({args:?})");
+        }
+    }
+
+    #[test]
+    fn array_functions() {
+        for (name, args, expected) in [
+            (
+                "array_contains",
+                vec![json!([1, "a", {"b": 2}]), json!(1.0)],
+                Some(json!(true)),
+            ),
+            (
+                "array_contains",
+                vec![json!([1, "a", {"b": 2}]), json!({"b": 2})],
+                Some(json!(true)),
+            ),
+            (
+                "array_contains",
+                vec![json!([1, "a"]), json!("1")],
+                Some(json!(false)),
+            ),
+            ("array_contains", vec![json!("abc"), json!("a")], None),
+            ("array_length", vec![json!([1, [2, 3]])], Some(json!(2))),
+            ("array_length", vec![json!([])], Some(json!(0))),
+            ("array_length", vec![json!("abc")], None),
+            (
+                "array_concat",
+                vec![json!([1]), json!([]), json!([[2], 3])],
+                Some(json!([1, [2], 3])),
+            ),
+            ("array_concat", vec![json!([1]), json!(2)], None),
+        ] {
+            assert_eq!(run(name, &args), expected, "{name}({args:?})");
+        }
+    }
+
+    #[test]
+    fn temporal_functions() {
+        for (name, args, expected) in [
+            (
+                "timestamp",
+                vec![json!("2024-03-01T13:00:00+01:00")],
+                Some(json!("2024-03-01T12:00:00.000Z")),
+            ),
+            (
+                "timestamp",
+                vec![json!("2024-03-01 13:00"), json!("Europe/Oslo")],
+                None,
+            ),
+            (
+                "timestamp",
+                vec![json!("2024-03-01 13:00:00"), json!("Europe/Oslo")],
+                Some(json!("2024-03-01T12:00:00.000Z")),
+            ),
+            ("timestamp", vec![json!("soon")], None),
+            ("timestamp", vec![json!(0), json!("Nowhere")], None),
+            (
+                "epoch_millis",
+                vec![json!("1970-01-01T00:00:01.5Z")],
+                Some(json!(1500)),
+            ),
+            (
+                "date_trunc",
+                vec![json!("month"), json!(1709294400000i64)],
+                Some(json!("2024-03-01T00:00:00.000Z")),
+            ),
+            (
+                "date_trunc",
+                vec![json!("day"), json!("2024-03-01T23:30:00Z"), json!("+02:00")],
+                Some(json!("2024-03-01T22:00:00.000Z")),
+            ),
+            ("date_trunc", vec![json!("decade"), json!(0)], None),
+            (
+                "date_add",
+                vec![json!("2024-01-31"), json!("1 month")],
+                Some(json!("2024-02-29T00:00:00.000Z")),
+            ),
+            (
+                "date_add",
+                vec![json!("2024-03-01T00:00:00Z"), json!(-1)],
+                Some(json!("2024-02-29T23:59:59.999Z")),
+            ),
+            (
+                "date_diff",
+                vec![
+                    json!("2024-03-01T01:00:00+01:00"),
+                    json!("2024-03-01T00:00:00Z"),
+                ],
+                Some(json!(0)),
+            ),
+            (
+                "date_diff",
+                vec![json!("2024-03-02"), json!("2024-03-01T12:00:00Z")],
+                Some(json!(43_200_000)),
+            ),
+            ("duration", vec![json!("PT1M")], Some(json!(60_000))),
+            ("duration", vec![json!("P1Y")], None),
+            (
+                "time_bucket",
+                vec![json!("15 minutes"), json!("2024-03-01T12:44:59Z")],
+                Some(json!("2024-03-01T12:30:00.000Z")),
+            ),
+            (
+                "time_bucket",
+                vec![
+                    json!("1 day"),
+                    json!("2024-03-01T05:00:00Z"),
+                    json!("2024-01-01T06:00:00Z"),
+                ],
+                Some(json!("2024-02-29T06:00:00.000Z")),
+            ),
+            ("time_bucket", vec![json!(0), json!("2024-03-01")], None),
+        ] {
+            assert_eq!(run(name, &args), expected, "{name}({args:?})");
+        }
+        let now = run("now", &[]).unwrap();
+        assert_eq!(run("timestamp", std::slice::from_ref(&now)), Some(now));
+    }
+
+    #[test]
+    fn checks_temporal_constants() {
+        let invalid =
+            |name: &str, args: Vec<Expr>| matches!(check(name, &args), Err(QueryError::Invalid(_)));
+        let at = Expr::Path(crate::path::Path::parse("at").unwrap());
+        assert!(invalid(
+            "date_trunc",
+            vec![Expr::literal("decade"), at.clone()]
+        ));
+        assert!(invalid(
+            "date_trunc",
+            vec![Expr::literal("day"), at.clone(), Expr::literal("Mars/Base")]
+        ));
+        assert!(invalid("date_add", vec![at.clone(), Expr::literal("soon")]));
+        assert!(invalid(
+            "time_bucket",
+            vec![Expr::literal("1 month"), at.clone()]
+        ));
+        assert!(invalid("timestamp", vec![at.clone(), Expr::literal(2)]));
+        assert!(check(
+            "date_add",
+            &[at.clone(), Expr::literal("P1M"), Expr::literal("UTC")]
+        )
+        .is_ok());
+        assert!(check("date_trunc", &[at.clone(), at]).is_ok());
+    }
+
+    #[test]
+    fn checks_patterns() {
+        let args = [Expr::literal("x"), Expr::literal("[a-")];
+        assert!(matches!(
+            check("regex_match", &args),
+            Err(QueryError::Invalid(_))
+        ));
+        assert!(check("regex_match", &[Expr::literal("x"), Expr::literal("a+")]).is_ok());
+        assert_eq!(
+            check("trim", &args),
+            Err(QueryError::Arity {
+                function: "trim".into(),
+                min: 1,
+                max: 1,
+                found: 2
+            })
+        );
+    }
+}
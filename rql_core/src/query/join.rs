@@ -0,0 +1,608 @@
+//! Joins between collections.
+//!
+//! In a query with joins, a row is an object binding each source name, the alias or else the
+//! collection name, to a document: `from orders as o join customers as c on ...` produces rows
+//! like `{"o": {...}, "c": {...}}`, and every path in the query starts with one of the names.
+//! `id(c)` is the id of the document bound to `c`. The name of an `unnest ... as i` binds like a
+//! source too, with `id(i)` the position of the element in its array.
+//!
+//! Each join finds the documents to try against its `on` condition in the cheapest way the
+//! condition allows:
+//!
+//! 1. `id(c) = <expr>` gets the document by id.
+//! 2. `c.<path> = <expr>` looks the value up in a hash or ordered index over `<path>`, as long
+//!    as fewer rows are expected to reach the join than the collection has documents.
+//! 3. any other `<expr over c> = <expr>`, or the above with more rows, probes a hash table built
+//!    over the collection once.
+//! 4. otherwise every document is tried.
+//!
+//! Here `<expr>` only uses sources bound before the join. Candidates are always checked against
+//! the full condition, so the choice never changes the result.
+use std::hash::BuildHasher;
+use std::time::{Duration, Instant};
+
+use hashbrown::{HashMap, HashSet};
+use serde_json::{Map, Value};
+
+use super::ast::{BinaryOp, Collation, Expr, JoinKind, Query, Select, SelectItem};
+use super::eval::{canonical, check, collation_of, eval, test};
+use super::plan::{conjuncts, selectivity, Counter, Measured};
+use super::{Budget, QueryError};
+use crate::index::{IndexKey, IndexKind, KeyExpr};
+use crate::path::{Path, Segment};
+use crate::rwmap::MapReadRef;
+
+/// Key of the object holding the document ids of a joined row.
+pub(crate) const IDS: &str = "#id";
+
+/// Check that every path of a query with joins starts with a source name, and rewrite
+/// `id(name)` and `select *` to read joined rows.
+pub(crate) fn bind(query: &Query) -> Result<Query, QueryError> {
+    fn add<'q>(names: &mut Vec<&'q str>, name: &'q str) -> Result<(), QueryError> {
+        if names.contains(&name) {
+            return Err(QueryError::Invalid(format!(
+                "`{name}` names two sources, give one an alias"
+            )));
+        }
+        names.push(name);
+        Ok(())
+    }
+    let mut names = vec![query.from.name()];
+    for unnest in &query.unnest {
+        bind_expr(&Expr::Path(unnest.path.clone()), &names)?;
+        if let Some(alias) = &unnest.alias {
+            add(&mut names, alias)?;
+        }
+    }
+    for join in &query.joins {
+        add(&mut names, join.source.name())?;
+    }
+    let bind = |expr: &Expr| bind_expr(expr, &names);
+    let mut bound = query.clone();
+    for join in &mut bound.joins {
+        join.on = bind(&join.on)?;
+    }
+    bound.filter = query.filter.as_ref().map(bind).transpose()?;
+    bound.group_by = query.group_by.iter().map(bind).collect::<Result<_, _>>()?;
+    bound.having = query.having.as_ref().map(bind).transpose()?;
+    let aliases: Vec<&str> = match &query.select {
+        Select::All => {
+            bound.select = Select::Fields(
+                names
+                    .iter()
+                    .map(|name| SelectItem {
+                        expr: Expr::Path(Path::root().key(*name)),
+                        alias: None,
+                    })
+                    .collect(),
+            );
+            Vec::new()
+        }
+        Select::Fields(items) => {
+            bound.select = Select::Fields(
+                items
+                    .iter()
+                    .map(|item| {
+                        Ok(SelectItem {
+                            expr: bind(&item.expr)?,
+                            alias: item.alias.clone(),
+                        })
+                    })
+                    .collect::<Result<_, QueryError>>()?,
+            );
+            items
+                .iter()
+                .filter_map(|item| item.alias.as_deref())
+                .collect()
+        }
+    };
+    for order in &mut bound.order_by {
+        let alias = matches!(&order.expr, Expr::Path(path)
+            if matches!(path.segments(), [Segment::Key(key)] if aliases.contains(&key.as_str())));
+        if !alias {
+            order.expr = bind(&order.expr)?;
+        }
+    }
+    Ok(bound)
+}
+
+fn bind_expr(expr: &Expr, names: &[&str]) -> Result<Expr, QueryError> {
+    match expr {
+        Expr::Call { name, args } if name == "id" => match args.as_slice() {
+            [Expr::Path(path)] => match path.segments() {
+                [Segment::Key(key)] if names.contains(&key.as_str()) => {
+                    Ok(Expr::Path(Path::root().key(IDS).key(key.as_str())))
+                }
+                _ => Err(QueryError::Invalid(format!(
+                    "`{path}` in `{expr}` is not a source of the query"
+                ))),
+            },
+            _ => Err(QueryError::Invalid(format!(
+                "`id` takes the name of a source, found `{expr}`"
+            ))),
+        },
+        Expr::Quantified { path, .. } => {
+            bind_expr(&Expr::Path(path.clone()), names)?;
+            expr.clone()
+                .try_map_children(|child| bind_expr(&child, names))
+        }
+        Expr::Path(path) | Expr::Exists { path, .. } => match path.segments().first() {
+            Some(Segment::Key(key)) if names.contains(&key.as_str()) => Ok(expr.clone()),
+            _ => Err(QueryError::Invalid(format!(
+                "`{expr}` must start with one of {}",
+                names
+                    .iter()
+                    .map(|name| format!("`{name}`"))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ))),
+        },
+        _ => expr
+            .clone()
+            .try_map_children(|child| bind_expr(&child, names)),
+    }
+}
+
+/// Rewrite `id()` in a query without joins to read the document id, which its rows then
+/// carry under [`IDS`]. Returns `None` if the query never calls `id()`.
+pub(crate) fn bind_id(query: &Query) -> Result<Option<Query>, QueryError> {
+    let mut used = false;
+    let bound = query
+        .clone()
+        .try_map_exprs(|expr| bind_id_expr(expr, &mut used))?;
+    Ok(used.then_some(bound))
+}
+
+/// `expr` with `id()` replaced by the document id path, setting `used` if it had any.
+pub(crate) fn bind_id_expr(expr: &Expr, used: &mut bool) -> Result<Expr, QueryError> {
+    match expr {
+        Expr::Call { name, args } if name == "id" => {
+            if !args.is_empty() {
+                return Err(QueryError::Invalid(format!(
+                    "`id` takes no arguments in a query without joins, found `{expr}`"
+                )));
+            }
+            *used = true;
+            Ok(Expr::Path(Path::root().key(IDS)))
+        }
+        _ => expr
+            .clone()
+            .try_map_children(|child| bind_id_expr(&child, used)),
+    }
+}
+
+/// The source names `expr` reads from.
+pub(crate) fn sources(expr: &Expr) -> HashSet<String> {
+    let mut names = HashSet::new();
+    let _ = expr.walk(&mut |expr| {
+        if let Expr::Path(path) | Expr::Exists { path, .. } | Expr::Quantified { path, .. } = expr {
+            match path.segments() {
+                [Segment::Key(ids), Segment::Key(name), ..] if ids == IDS => {
+                    names.insert(name.clone());
+                }
+                [Segment::Key(name), ..] => {
+                    names.insert(name.clone());
+                }
+                _ => {}
+            }
+        }
+        Ok::<_, ()>(())
+    });
+    names
+}
+
+/// `expr` with the leading `name` stripped from its paths, to evaluate on bare documents.
+/// `None` if it reads the document id.
+pub(crate) fn relative(expr: &Expr, name: &str) -> Option<Expr> {
+    match expr {
+        Expr::Path(path) => match path.segments() {
+            [Segment::Key(first), rest @ ..] if first == name => {
+                Some(Expr::Path(Path::from_segments(rest.to_vec())))
+            }
+            _ => None,
+        },
+        Expr::Exists { .. } | Expr::Quantified { .. } => None,
+        _ => expr
+            .clone()
+            .try_map_children(|child| relative(&child, name).ok_or(()))
+            .ok(),
+    }
+}
+
+/// How a join finds the documents to try against its condition.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Strategy {
+    /// get the document whose id is the value of `probe`.
+    PrimaryKey { probe: Expr },
+    /// look the value of `probe` up in the secondary index called `index`.
+    Index { index: String, probe: Expr },
+    /// probe a hash table over `key`, evaluated on each document of the collection.
+    Hash { key: Expr, probe: Expr },
+    /// try every document.
+    Scan,
+}
+
+/// The documents of one collection as a join input.
+pub(crate) type Documents<'m, 'g, M, S> = &'m MapReadRef<'g, String, Value, M, S>;
+
+struct Step<'m, 'g, M, S>
+where
+    S: BuildHasher,
+{
+    kind: JoinKind,
+    name: String,
+    map: Documents<'m, 'g, M, S>,
+    on: Expr,
+    strategy: Strategy,
+    /// the hash table of a [`Strategy::Hash`], by canonical key.
+    table: HashMap<String, Vec<(&'m str, &'m Value)>>,
+    /// the estimated number of rows after the join.
+    estimate: u64,
+    /// the time it took to build `table`.
+    build: Duration,
+}
+
+impl<'m, 'g, M, S> Step<'m, 'g, M, S>
+where
+    S: BuildHasher,
+{
+    /// The documents that may match `row`.
+    fn candidates(&self, row: &Value) -> Vec<(&'m str, &'m Value)> {
+        let all = || {
+            self.map
+                .iter()
+                .map(|(id, doc)| (id.as_str(), doc.as_ref()))
+                .collect()
+        };
+        match &self.strategy {
+            Strategy::PrimaryKey { probe } => match eval(probe, row).as_deref() {
+                Some(Value::String(id)) => self
+                    .map
+                    .get_key_value(id.as_str())
+                    .map(|(id, doc)| (id.as_str(), doc.as_ref()))
+                    .into_iter()
+                    .collect(),
+                _ => Vec::new(),
+            },
+            Strategy::Index { index, probe } => {
+                let Some(value) = eval(probe, row) else {
+                    return Vec::new();
+                };
+                match (IndexKey::from_json(&value), self.map.index(index)) {
+                    (Some(key), Some(index)) => index
+                        .get(&key)
+                        .map(|(id, doc)| (id.as_str(), doc.as_ref()))
+                        .collect(),
+                    // objects are never indexed
+                    _ => all(),
+                }
+            }
+            Strategy::Hash { probe, .. } => match eval(probe, row) {
+                Some(value) => self
+                    .table
+                    .get(&canonical(&value))
+                    .cloned()
+                    .unwrap_or_default(),
+                None => Vec::new(),
+            },
+            Strategy::Scan => all(),
+        }
+    }
+
+    /// The rows made by joining `row` with each matching document, counting the documents
+    /// tried against `budget`.
+    fn join(&self, mut row: Value, budget: &Budget) -> Vec<Value> {
+        let mut joined = Vec::new();
+        for (id, doc) in budget.scanning(self.candidates(&row).into_iter()) {
+            let fields = row.as_object_mut().unwrap();
+            fields[IDS]
+                .as_object_mut()
+                .unwrap()
+                .insert(self.name.clone(), Value::from(id));
+            fields.insert(self.name.clone(), doc.clone());
+            if test(&self.on, &row).is_true() {
+                joined.push(row.clone());
+            }
+        }
+        if joined.is_empty() && self.kind == JoinKind::Left {
+            let fields = row.as_object_mut().unwrap();
+            fields[IDS].as_object_mut().unwrap().remove(&self.name);
+            fields.remove(&self.name);
+            joined.push(row);
+        }
+        joined
+    }
+}
+
+/// The joins of a query, planned against the collections they read.
+pub(crate) struct JoinPlan<'m, 'g, M, S>
+where
+    S: BuildHasher,
+{
+    from: String,
+    steps: Vec<Step<'m, 'g, M, S>>,
+}
+
+impl<'m, 'g, M, S> JoinPlan<'m, 'g, M, S>
+where
+    S: BuildHasher,
+{
+    /// Plan the joins of a [bound](bind) query, finding joined collections with `lookup`.
+    /// `rows` is the estimated number of documents read from the `from` collection.
+    pub(crate) fn new(
+        query: &Query,
+        lookup: &dyn Fn(&str) -> Option<Documents<'m, 'g, M, S>>,
+        mut rows: u64,
+        budget: &Budget,
+    ) -> Result<Self, QueryError> {
+        let mut bound = vec![query.from.name().to_owned()];
+        bound.extend(
+            query
+                .unnest
+                .iter()
+                .filter_map(|unnest| unnest.alias.clone()),
+        );
+        let mut steps = Vec::new();
+        for join in &query.joins {
+            check(&join.on)?;
+            let map = lookup(&join.source.collection)
+                .ok_or_else(|| QueryError::UnknownCollection(join.source.collection.clone()))?;
+            let name = join.source.name().to_owned();
+            let strategy = plan(&join.on, &name, &bound, map, rows);
+            let start = Instant::now();
+            let mut table: HashMap<String, Vec<(&'m str, &'m Value)>> = HashMap::new();
+            if let Strategy::Hash { key, .. } = &strategy {
+                for (id, doc) in budget.scanning(map.iter()) {
+                    if let Some(value) = eval(key, doc.as_ref()) {
+                        table
+                            .entry(canonical(&value))
+                            .or_default()
+                            .push((id.as_str(), doc.as_ref()));
+                    }
+                }
+            }
+            let build = start.elapsed();
+            budget.check()?;
+
+            let documents = map.len() as f64;
+            let per_row = match &strategy {
+                Strategy::PrimaryKey { .. } => 1.0,
+                Strategy::Index { index, .. } => map
+                    .index(index)
+                    .map_or(1.0, |index| documents / index.len().max(1) as f64),
+                Strategy::Hash { .. } => documents / table.len().max(1) as f64,
+                Strategy::Scan => conjuncts(&join.on)
+                    .into_iter()
+                    .map(selectivity)
+                    .fold(documents, |rows, selectivity| rows * selectivity),
+            };
+            let mut estimate = (rows as f64 * per_row).ceil() as u64;
+            if join.kind == JoinKind::Left {
+                estimate = estimate.max(rows);
+            }
+            rows = estimate;
+
+            bound.push(name.clone());
+            steps.push(Step {
+                kind: join.kind,
+                name,
+                map,
+                on: join.on.clone(),
+                strategy,
+                table,
+                estimate,
+                build,
+            });
+        }
+        Ok(JoinPlan {
+            from: query.from.name().to_owned(),
+            steps,
+        })
+    }
+
+    /// The strategy of each join, in order.
+    pub(crate) fn strategies(&self) -> impl Iterator<Item = &Strategy> {
+        self.steps.iter().map(|step| &step.strategy)
+    }
+
+    /// How each join finds its documents and how many rows it is expected to produce, for
+    /// `explain`.
+    pub(crate) fn describe(&self) -> Vec<(String, u64)> {
+        self.steps
+            .iter()
+            .map(|step| {
+                let method = match &step.strategy {
+                    Strategy::PrimaryKey { .. } => "by id".to_owned(),
+                    Strategy::Index { index, .. } => format!("using index {index}"),
+                    Strategy::Hash { .. } => "using hash table".to_owned(),
+                    Strategy::Scan => "by nested loop".to_owned(),
+                };
+                (method, step.estimate)
+            })
+            .collect()
+    }
+
+    /// The row binding a document read from the `from` collection, before any join.
+    pub(crate) fn row(&self, id: &str, doc: &Value) -> Value {
+        let mut ids = Map::new();
+        ids.insert(self.from.clone(), Value::from(id));
+        let mut base = Map::new();
+        base.insert(IDS.to_owned(), Value::Object(ids));
+        base.insert(self.from.clone(), doc.clone());
+        Value::Object(base)
+    }
+
+    /// The joined rows of the [`row`](Self::row)s of the `from` collection, each with a text
+    /// that identifies it among all rows.
+    ///
+    /// With `counters`, the rows and time of each join are added to its counter.
+    pub(crate) fn apply<'a>(
+        &'a self,
+        rows: impl Iterator<Item = Value> + 'a,
+        counters: Option<&'a [Counter]>,
+        budget: &'a Budget,
+    ) -> Box<dyn Iterator<Item = (String, Value)> + 'a> {
+        let mut rows: Box<dyn Iterator<Item = Value> + 'a> = Box::new(rows);
+        for (i, step) in self.steps.iter().enumerate() {
+            let counter = counters.map(|counters| &counters[i]);
+            if let Some(counter) = counter {
+                counter.add(0, step.build);
+            }
+            rows = Box::new(Measured::new(
+                rows.flat_map(|row| step.join(row, budget)),
+                counter,
+            ));
+        }
+        Box::new(rows.map(|row| (row[IDS].to_string(), row)))
+    }
+}
+
+/// Pick the strategy for joining `name` on `on`, with the sources in `bound` already joined
+/// into an estimated `rows` rows.
+///
+/// Looking each row up in an index costs about two steps per row, while a hash table costs a
+/// step per document of the collection to build and one per row to probe, so an index is
+/// only used while there are fewer rows than documents.
+fn plan<M, S>(
+    on: &Expr,
+    name: &str,
+    bound: &[String],
+    map: Documents<'_, '_, M, S>,
+    rows: u64,
+) -> Strategy
+where
+    S: BuildHasher,
+{
+    let mut equalities = Vec::new();
+    for conjunct in conjuncts(on) {
+        let Expr::Binary {
+            left,
+            op: BinaryOp::Eq,
+            right,
+        } = conjunct
+        else {
+            continue;
+        };
+        let collation = collation_of([&**left, &**right]);
+        for (inner, outer) in [(left, right), (right, left)] {
+            let inner_sources = sources(inner);
+            let joined = inner_sources.len() == 1 && inner_sources.contains(name);
+            if joined && sources(outer).iter().all(|source| bound.contains(source)) {
+                equalities.push((&**inner, &**outer, collation));
+            }
+        }
+    }
+    let id = Path::root().key(IDS).key(name);
+    // only a hash table can hold the collation keys of a collated equality
+    let exact = |collation: &Option<Collation>| collation.is_none_or(|c| c == Collation::Binary);
+    if let Some((_, probe, _)) = equalities.iter().find(|(inner, _, collation)| {
+        exact(collation) && matches!(inner, Expr::Path(path) if *path == id)
+    }) {
+        return Strategy::PrimaryKey {
+            probe: (*probe).clone(),
+        };
+    }
+    let lookups = rows <= map.len() as u64;
+    for (inner, probe, _) in equalities
+        .iter()
+        .filter(|(_, _, collation)| lookups && exact(collation))
+    {
+        let Some(Expr::Path(path)) = relative(inner, name) else {
+            continue;
+        };
+        let index = map.indexes().find(|def| {
+            def.kind != IndexKind::Text
+                && !def.is_partial()
+                && def.keys == [KeyExpr::Path(path.clone())]
+        });
+        if let Some(def) = index {
+            return Strategy::Index {
+                index: def.name.clone(),
+                probe: (*probe).clone(),
+            };
+        }
+    }
+    for (inner, probe, collation) in &equalities {
+        if let Some(key) = relative(inner, name) {
+            let collate = |expr: Expr| match collation {
+                Some(collation) => Expr::Collate {
+                    expr: Box::new(expr),
+                    collation: *collation,
+                },
+                None => expr,
+            };
+            return Strategy::Hash {
+                key: collate(key),
+                probe: collate((*probe).clone()),
+            };
+        }
+    }
+    Strategy::Scan
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::index::IndexDef;
+    use crate::query::parse;
+    use crate::rwmap::RwMap;
+
+    #[test]
+    fn binds_names() {
+        let query = parse(
+            "from orders as o join customers as c on id(c) = o.customer \
+             where exists(c.email) select id(o) as n order by n",
+        )
+        .unwrap();
+        let bound = bind(&query).unwrap();
+        assert_eq!(bound.joins[0].on.to_string(), "`#id`.c = o.customer");
+        assert_eq!(
+            bind(&parse("from orders as o join c on o.x = c.y").unwrap())
+                .unwrap()
+                .select,
+            parse("from t select o, c").unwrap().select
+        );
+        for invalid in [
+            "from o join c on o.x = y",
+            "from o join c on id(d) = o.x",
+            "from o join c on id(o.x) = c.y",
+            "from o join o on o.x = o.y",
+            "from o join c on o.x = c.y select total",
+        ] {
+            assert!(
+                matches!(bind(&parse(invalid).unwrap()), Err(QueryError::Invalid(_))),
+                "{invalid}"
+            );
+        }
+    }
+
+    #[test]
+    fn picks_strategies() {
+        let (mut w, r) = RwMap::default::<String, Value>();
+        w.create_index(IndexDef::hash("by_email", Path::parse("email").unwrap()));
+        w.publish();
+        let map = r.enter().unwrap();
+        let strategy_for = |on: &str, rows| {
+            let query = bind(&parse(&format!("from o join c on {on}")).unwrap()).unwrap();
+            let plan = JoinPlan::new(&query, &|_| Some(&map), rows, &Budget::unlimited()).unwrap();
+            let strategy = plan.strategies().next().unwrap().clone();
+            match strategy {
+                Strategy::PrimaryKey { .. } => "pk",
+                Strategy::Index { .. } => "index",
+                Strategy::Hash { .. } => "hash",
+                Strategy::Scan => "scan",
+            }
+        };
+        let strategy = |on: &str| strategy_for(on, 0);
+        assert_eq!(strategy("o.x > 1 and id(c) = o.customer"), "pk");
+        assert_eq!(strategy("o.email = c.email"), "index");
+        assert_eq!(strategy("c.name = o.name and c.email = 'x'"), "index");
+        assert_eq!(strategy("coalesce(c.name) = o.name or true"), "scan");
+        assert_eq!(strategy("c.name || '' = o.name"), "hash");
+        assert_eq!(strategy("c.name = c.nick"), "scan");
+        assert_eq!(strategy("o.a < c.b"), "scan");
+        // more rows than documents are cheaper to join through a hash table
+        assert_eq!(strategy_for("o.email = c.email", 1), "hash");
+        assert_eq!(strategy_for("id(c) = o.customer", 1), "pk");
+    }
+}
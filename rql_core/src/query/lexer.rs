@@ -0,0 +1,481 @@
+use std::fmt;
+use std::ops::Range;
+
+use super::SyntaxError;
+
+/// Reserved words. They are matched case-insensitively and can only be used as field names
+/// when quoted with backticks or after a `.`.
+///
+/// Words only meaningful in one spot, like `first` in `nulls first`, are not reserved and are
+/// matched as identifiers by the parser.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Keyword {
+    And,
+    As,
+    Asc,
+    Between,
+    By,
+    Case,
+    Desc,
+    Distinct,
+    Else,
+    End,
+    Exists,
+    False,
+    From,
+    Group,
+    Having,
+    In,
+    Is,
+    Join,
+    Limit,
+    Missing,
+    Not,
+    Null,
+    Offset,
+    On,
+    Or,
+    Order,
+    Select,
+    Then,
+    True,
+    When,
+    Where,
+}
+
+impl Keyword {
+    const ALL: &'static [(&'static str, Keyword)] = &[
+        ("and", Keyword::And),
+        ("as", Keyword::As),
+        ("asc", Keyword::Asc),
+        ("between", Keyword::Between),
+        ("by", Keyword::By),
+        ("case", Keyword::Case),
+        ("desc", Keyword::Desc),
+        ("distinct", Keyword::Distinct),
+        ("else", Keyword::Else),
+        ("end", Keyword::End),
+        ("exists", Keyword::Exists),
+        ("false", Keyword::False),
+        ("from", Keyword::From),
+        ("group", Keyword::Group),
+        ("having", Keyword::Having),
+        ("in", Keyword::In),
+        ("is", Keyword::Is),
+        ("join", Keyword::Join),
+        ("limit", Keyword::Limit),
+        ("missing", Keyword::Missing),
+        ("not", Keyword::Not),
+        ("null", Keyword::Null),
+        ("offset", Keyword::Offset),
+        ("on", Keyword::On),
+        ("or", Keyword::Or),
+        ("order", Keyword::Order),
+        ("select", Keyword::Select),
+        ("then", Keyword::Then),
+        ("true", Keyword::True),
+        ("when", Keyword::When),
+        ("where", Keyword::Where),
+    ];
+
+    pub(crate) fn lookup(word: &str) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(word))
+            .map(|(_, keyword)| *keyword)
+    }
+
+    pub(crate) fn as_str(&self) -> &'static str {
+        Self::ALL
+            .iter()
+            .find(|(_, keyword)| keyword == self)
+            .map(|(name, _)| *name)
+            .unwrap()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Token {
+    Ident(String),
+    /// a backtick quoted identifier.
+    Quoted(String),
+    Keyword(Keyword),
+    String(String),
+    Number(serde_json::Number),
+    /// a `$name` placeholder.
+    Param(String),
+    /// `$` in front of the `[` starting a path, as in `$[0].a`.
+    Root,
+    Dot,
+    Comma,
+    LParen,
+    RParen,
+    LBracket,
+    RBracket,
+    LBrace,
+    RBrace,
+    Colon,
+    Star,
+    Plus,
+    Minus,
+    Slash,
+    Percent,
+    Concat,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Semicolon,
+    Eof,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let symbol = match self {
+            Token::Ident(name) => return write!(f, "`{name}`"),
+            Token::Quoted(name) => return write!(f, "`{name}`"),
+            Token::Keyword(keyword) => return write!(f, "`{}`", keyword.as_str()),
+            Token::String(s) => return write!(f, "string {}", serde_json::Value::from(s.as_str())),
+            Token::Number(n) => return write!(f, "number `{n}`"),
+            Token::Param(name) => return write!(f, "`${name}`"),
+            Token::Root => "$",
+            Token::Eof => return f.write_str("end of input"),
+            Token::Dot => ".",
+            Token::Comma => ",",
+            Token::LParen => "(",
+            Token::RParen => ")",
+            Token::LBracket => "[",
+            Token::RBracket => "]",
+            Token::LBrace => "{",
+            Token::RBrace => "}",
+            Token::Colon => ":",
+            Token::Star => "*",
+            Token::Plus => "+",
+            Token::Minus => "-",
+            Token::Slash => "/",
+            Token::Percent => "%",
+            Token::Concat => "||",
+            Token::Eq => "=",
+            Token::Ne => "!=",
+            Token::Lt => "<",
+            Token::Le => "<=",
+            Token::Gt => ">",
+            Token::Ge => ">=",
+            Token::Semicolon => ";",
+        };
+        write!(f, "`{symbol}`")
+    }
+}
+
+/// A token with the byte range of source text it was read from.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Spanned {
+    pub(crate) token: Token,
+    pub(crate) span: Range<usize>,
+}
+
+/// Split `source` into tokens, ending with [`Token::Eof`]. `--` starts a comment running to
+/// the end of the line.
+pub(crate) fn tokenize(source: &str) -> Result<Vec<Spanned>, SyntaxError> {
+    let mut lexer = Lexer { source, pos: 0 };
+    let mut tokens = Vec::new();
+    loop {
+        let spanned = lexer.next()?;
+        let eof = spanned.token == Token::Eof;
+        tokens.push(spanned);
+        if eof {
+            return Ok(tokens);
+        }
+    }
+}
+
+struct Lexer<'a> {
+    source: &'a str,
+    pos: usize,
+}
+
+impl<'a> Lexer<'a> {
+    fn peek(&self) -> Option<char> {
+        self.source[self.pos..].chars().next()
+    }
+
+    fn peek_second(&self) -> Option<char> {
+        self.source[self.pos..].chars().nth(1)
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += c.len_utf8();
+        Some(c)
+    }
+
+    fn eat_while(&mut self, f: impl Fn(char) -> bool) -> &'a str {
+        let start = self.pos;
+        while self.peek().is_some_and(&f) {
+            self.bump();
+        }
+        &self.source[start..self.pos]
+    }
+
+    fn error(&self, span: Range<usize>, message: impl Into<String>) -> SyntaxError {
+        SyntaxError::new(self.source, span, message)
+    }
+
+    fn skip_trivia(&mut self) {
+        loop {
+            self.eat_while(char::is_whitespace);
+            if self.source[self.pos..].starts_with("--") {
+                self.eat_while(|c| c != '\n');
+            } else {
+                return;
+            }
+        }
+    }
+
+    fn next(&mut self) -> Result<Spanned, SyntaxError> {
+        self.skip_trivia();
+        let start = self.pos;
+        let Some(c) = self.bump() else {
+            return Ok(Spanned {
+                token: Token::Eof,
+                span: start..start,
+            });
+        };
+        let token = match c {
+            '.' if self.peek().is_some_and(|c| c.is_ascii_digit()) => {
+                self.pos = start;
+                self.number()?
+            }
+            '.' => Token::Dot,
+            ',' => Token::Comma,
+            '(' => Token::LParen,
+            ')' => Token::RParen,
+            '[' => Token::LBracket,
+            ']' => Token::RBracket,
+            '{' => Token::LBrace,
+            '}' => Token::RBrace,
+            ':' => Token::Colon,
+            '*' => Token::Star,
+            '+' => Token::Plus,
+            '-' => Token::Minus,
+            '/' => Token::Slash,
+            '%' => Token::Percent,
+            ';' => Token::Semicolon,
+            '=' => {
+                if self.peek() == Some('=') {
+                    self.bump();
+                }
+                Token::Eq
+            }
+            '|' if self.peek() == Some('|') => {
+                self.bump();
+                Token::Concat
+            }
+            '!' if self.peek() == Some('=') => {
+                self.bump();
+                Token::Ne
+            }
+            '<' => match self.peek() {
+                Some('=') => {
+                    self.bump();
+                    Token::Le
+                }
+                Some('>') => {
+                    self.bump();
+                    Token::Ne
+                }
+                _ => Token::Lt,
+            },
+            '>' if self.peek() == Some('=') => {
+                self.bump();
+                Token::Ge
+            }
+            '>' => Token::Gt,
+            '"' | '\'' => Token::String(self.string(start, c)?),
+            '`' => {
+                let name = self.eat_while(|c| c != '`');
+                if self.bump().is_none() {
+                    return Err(self.error(start..self.pos, "unterminated quoted identifier"));
+                }
+                if name.is_empty() {
+                    return Err(self.error(start..self.pos, "empty quoted identifier"));
+                }
+                Token::Quoted(name.to_owned())
+            }
+            '$' => {
+                let name = self.eat_while(|c| c.is_alphanumeric() || c == '_');
+                if name.is_empty() && self.peek() == Some('[') {
+                    Token::Root
+                } else if name.is_empty() {
+                    return Err(self.error(start..self.pos, "expected parameter name after `$`"));
+                } else {
+                    Token::Param(name.to_owned())
+                }
+            }
+            c if c.is_ascii_digit() => {
+                self.pos = start;
+                self.number()?
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                self.pos = start;
+                let word = self.eat_while(|c| c.is_alphanumeric() || c == '_');
+                match Keyword::lookup(word) {
+                    Some(keyword) => Token::Keyword(keyword),
+                    None => Token::Ident(word.to_owned()),
+                }
+            }
+            c => return Err(self.error(start..self.pos, format!("unexpected character `{c}`"))),
+        };
+        Ok(Spanned {
+            token,
+            span: start..self.pos,
+        })
+    }
+
+    fn number(&mut self) -> Result<Token, SyntaxError> {
+        let start = self.pos;
+        self.eat_while(|c| c.is_ascii_digit());
+        let mut float = false;
+        if self.peek() == Some('.') && self.peek_second().is_some_and(|c| c.is_ascii_digit()) {
+            float = true;
+            self.bump();
+            self.eat_while(|c| c.is_ascii_digit());
+        }
+        if matches!(self.peek(), Some('e' | 'E')) {
+            float = true;
+            self.bump();
+            if matches!(self.peek(), Some('+' | '-')) {
+                self.bump();
+            }
+            if self.eat_while(|c| c.is_ascii_digit()).is_empty() {
+                return Err(self.error(start..self.pos, "missing exponent in number"));
+            }
+        }
+        if self.peek().is_some_and(|c| c.is_alphanumeric() || c == '_') {
+            self.eat_while(|c| c.is_alphanumeric() || c == '_');
+            return Err(self.error(start..self.pos, "invalid number"));
+        }
+        let text = &self.source[start..self.pos];
+        let number = if float {
+            None
+        } else {
+            text.parse::<u64>().ok().map(serde_json::Number::from)
+        };
+        let number = number
+            .or_else(|| {
+                text.parse::<f64>()
+                    .ok()
+                    .and_then(serde_json::Number::from_f64)
+            })
+            .ok_or_else(|| self.error(start..self.pos, "number out of range"))?;
+        Ok(Token::Number(number))
+    }
+
+    /// The rest of a string literal opened by `quote` at `start`.
+    fn string(&mut self, start: usize, quote: char) -> Result<String, SyntaxError> {
+        let mut s = String::new();
+        loop {
+            let escape_start = self.pos;
+            match self.bump() {
+                None => return Err(self.error(start..self.pos, "unterminated string")),
+                Some(c) if c == quote => return Ok(s),
+                Some('\\') => {
+                    let c = match self.bump() {
+                        Some('n') => '\n',
+                        Some('t') => '\t',
+                        Some('r') => '\r',
+                        Some('0') => '\0',
+                        Some('b') => '\u{8}',
+                        Some('f') => '\u{c}',
+                        Some('/') => '/',
+                        Some(c @ ('\\' | '"' | '\'')) => c,
+                        Some('u') => self.unicode_escape(escape_start)?,
+                        _ => {
+                            return Err(
+                                self.error(escape_start..self.pos, "invalid escape sequence")
+                            )
+                        }
+                    };
+                    s.push(c);
+                }
+                Some(c) => s.push(c),
+            }
+        }
+    }
+
+    /// `\u{...}` or `\uXXXX` after the `\u`.
+    fn unicode_escape(&mut self, start: usize) -> Result<char, SyntaxError> {
+        let digits = if self.peek() == Some('{') {
+            self.bump();
+            let digits = self.eat_while(|c| c.is_ascii_hexdigit());
+            if self.bump() != Some('}') {
+                return Err(self.error(start..self.pos, "expected `}` in unicode escape"));
+            }
+            digits
+        } else {
+            let digits_start = self.pos;
+            for _ in 0..4 {
+                if self.peek().is_some_and(|c| c.is_ascii_hexdigit()) {
+                    self.bump();
+                }
+            }
+            &self.source[digits_start..self.pos]
+        };
+        u32::from_str_radix(digits, 16)
+            .ok()
+            .and_then(char::from_u32)
+            .ok_or_else(|| self.error(start..self.pos, "invalid unicode escape"))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn tokens(source: &str) -> Vec<Token> {
+        tokenize(source)
+            .unwrap()
+            .into_iter()
+            .map(|spanned| spanned.token)
+            .collect()
+    }
+
+    #[test]
+    fn tokens_and_spans() {
+        assert_eq!(
+            tokens("FROM users -- comment\nwhere a.b[0] >= 1.5e1 and `x y` <> 'it\\'s'"),
+            vec![
+                Token::Keyword(Keyword::From),
+                Token::Ident("users".into()),
+                Token::Keyword(Keyword::Where),
+                Token::Ident("a".into()),
+                Token::Dot,
+                Token::Ident("b".into()),
+                Token::LBracket,
+                Token::Number(0.into()),
+                Token::RBracket,
+                Token::Ge,
+                Token::Number(serde_json::Number::from_f64(15.0).unwrap()),
+                Token::Keyword(Keyword::And),
+                Token::Quoted("x y".into()),
+                Token::Ne,
+                Token::String("it's".into()),
+                Token::Eof,
+            ]
+        );
+        let spans: Vec<_> = tokenize("ä <= \"\\u{e9}\"")
+            .unwrap()
+            .into_iter()
+            .map(|spanned| spanned.span)
+            .collect();
+        assert_eq!(spans, vec![0..2, 3..5, 6..14, 14..14]);
+
+        let err = tokenize("from x\nwhere name = \"abc").unwrap_err();
+        assert_eq!(err.message, "unterminated string");
+        assert_eq!((err.start.line, err.start.column), (2, 14));
+        assert_eq!(tokenize("a ? b").unwrap_err().start.column, 3);
+        assert!(tokenize("12abc").is_err());
+    }
+}
@@ -0,0 +1,303 @@
+//! Limits on what one query may use, and cancelling it.
+//!
+//! A query holds its read guards until it returns, and a guard blocks every publish of its
+//! collection, so a runaway query stalls the writers. A query run with [`Limits`] stops reading
+//! as soon as it exceeds one of them or its [`CancelToken`] is cancelled, and fails with
+//! [`QueryError::Aborted`], leaving the caller free to drop its guards.
+use std::cell::Cell;
+use std::fmt;
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use serde_json::Value;
+
+use super::QueryError;
+
+/// Documents read between two looks at the clock and the cancel token.
+const POLL: u64 = 64;
+
+/// What a query may use before it is aborted. Everything is unlimited by default.
+///
+/// ```
+/// # use std::time::Duration;
+/// # use rql_core::query::{CancelToken, Limits};
+/// let token = CancelToken::new();
+/// let limits = Limits::new()
+///     .timeout(Duration::from_millis(50))
+///     .max_rows_scanned(100_000)
+///     .cancel_on(token.clone());
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Limits {
+    timeout: Option<Duration>,
+    rows_scanned: Option<u64>,
+    result_bytes: Option<u64>,
+    cancel: Option<CancelToken>,
+}
+
+impl Limits {
+    pub const fn new() -> Self {
+        Limits {
+            timeout: None,
+            rows_scanned: None,
+            result_bytes: None,
+            cancel: None,
+        }
+    }
+
+    /// Abort the query once it has run for `timeout`, measured from when it starts.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Abort the query once it has read more than `rows` documents, counting every document
+    /// read from a collection: by its access path, by its joins and by its subqueries and
+    /// `with` queries.
+    pub fn max_rows_scanned(mut self, rows: u64) -> Self {
+        self.rows_scanned = Some(rows);
+        self
+    }
+
+    /// Abort the query once its result rows take more than `bytes` bytes as JSON.
+    pub fn max_result_bytes(mut self, bytes: u64) -> Self {
+        self.result_bytes = Some(bytes);
+        self
+    }
+
+    /// Abort the query once `token` is cancelled.
+    pub fn cancel_on(mut self, token: CancelToken) -> Self {
+        self.cancel = Some(token);
+        self
+    }
+}
+
+/// Cancels the queries whose [`Limits`] hold a clone of it, from any thread.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Make every query run with this token, or a clone of it, abort. Queries started later
+    /// abort right away.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Why a query was aborted, see [`QueryError::Aborted`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Abort {
+    /// its [`CancelToken`] was cancelled.
+    Cancelled,
+    /// it ran for longer than this.
+    Timeout(Duration),
+    /// it read more than this many documents.
+    RowsScanned(u64),
+    /// its result took more than this many bytes.
+    ResultBytes(u64),
+}
+
+impl fmt::Display for Abort {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Abort::Cancelled => f.write_str("the query was cancelled"),
+            Abort::Timeout(timeout) => write!(f, "the query ran for longer than {timeout:?}"),
+            Abort::RowsScanned(rows) => write!(f, "the query read more than {rows} documents"),
+            Abort::ResultBytes(bytes) => write!(f, "the query result took more than {bytes} bytes"),
+        }
+    }
+}
+
+/// What one run of a statement has used of its [`Limits`].
+///
+/// Operators stop reading once [`scan`](Self::scan) or [`emit`](Self::emit) return false, and
+/// [`check`](Self::check) turns the reason into an error.
+pub(crate) struct Budget<'l> {
+    limits: &'l Limits,
+    deadline: Option<Instant>,
+    scanned: Cell<u64>,
+    bytes: Cell<u64>,
+    /// the number of subqueries and `with` queries being run, whose rows are not results.
+    nested: Cell<u32>,
+    aborted: Cell<Option<Abort>>,
+}
+
+impl<'l> Budget<'l> {
+    /// Start spending `limits`.
+    pub(crate) fn new(limits: &'l Limits) -> Self {
+        Budget {
+            limits,
+            deadline: limits.timeout.map(|timeout| Instant::now() + timeout),
+            scanned: Cell::new(0),
+            bytes: Cell::new(0),
+            nested: Cell::new(0),
+            aborted: Cell::new(None),
+        }
+    }
+
+    /// A budget without limits.
+    pub(crate) fn unlimited() -> Budget<'static> {
+        static NONE: Limits = Limits::new();
+        Budget::new(&NONE)
+    }
+
+    /// Count a document read from a collection. Returns false if the query must stop.
+    pub(crate) fn scan(&self) -> bool {
+        if self.aborted.get().is_some() {
+            return false;
+        }
+        let scanned = self.scanned.get() + 1;
+        self.scanned.set(scanned);
+        if let Some(rows) = self.limits.rows_scanned.filter(|&rows| scanned > rows) {
+            return self.abort(Abort::RowsScanned(rows));
+        }
+        !scanned.is_multiple_of(POLL) || self.poll()
+    }
+
+    /// `iter`, counting each item as a document read and ending once the query must stop.
+    pub(crate) fn scanning<'b, I>(&'b self, iter: I) -> impl Iterator<Item = I::Item> + 'b
+    where
+        I: Iterator + 'b,
+    {
+        iter.take_while(move |_| self.scan())
+    }
+
+    /// Count `row` as a result of the query, unless it comes from a subquery. Returns false if
+    /// the query must stop.
+    pub(crate) fn emit(&self, row: &Value) -> bool {
+        if self.aborted.get().is_some() {
+            return false;
+        }
+        let Some(limit) = self.limits.result_bytes else {
+            return true;
+        };
+        if self.nested.get() > 0 {
+            return true;
+        }
+        let bytes = self.bytes.get() + encoded_len(row);
+        self.bytes.set(bytes);
+        bytes <= limit || self.abort(Abort::ResultBytes(limit))
+    }
+
+    /// Run a subquery or `with` query, whose rows are not results.
+    pub(crate) fn nested<T>(&self, run: impl FnOnce() -> T) -> T {
+        self.nested.set(self.nested.get() + 1);
+        let result = run();
+        self.nested.set(self.nested.get() - 1);
+        result
+    }
+
+    /// Fails if the query has been aborted, or must be now.
+    pub(crate) fn check(&self) -> Result<(), QueryError> {
+        if self.aborted.get().is_none() {
+            self.poll();
+        }
+        match self.aborted.get() {
+            Some(abort) => Err(QueryError::Aborted(abort)),
+            None => Ok(()),
+        }
+    }
+
+    /// Look at the cancel token and the clock. Returns false if the query must stop.
+    fn poll(&self) -> bool {
+        if self
+            .limits
+            .cancel
+            .as_ref()
+            .is_some_and(CancelToken::is_cancelled)
+        {
+            return self.abort(Abort::Cancelled);
+        }
+        match (self.deadline, self.limits.timeout) {
+            (Some(deadline), Some(timeout)) if Instant::now() >= deadline => {
+                self.abort(Abort::Timeout(timeout))
+            }
+            _ => true,
+        }
+    }
+
+    fn abort(&self, abort: Abort) -> bool {
+        self.aborted.set(Some(abort));
+        false
+    }
+}
+
+/// The length of `value` written as JSON.
+fn encoded_len(value: &Value) -> u64 {
+    struct Count(u64);
+
+    impl io::Write for Count {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0 += buf.len() as u64;
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let mut count = Count(0);
+    serde_json::to_writer(&mut count, value).expect("counting does not fail");
+    count.0
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn stops_at_the_first_limit_hit() {
+        let limits = Limits::new().max_rows_scanned(100);
+        let budget = Budget::new(&limits);
+        assert_eq!(budget.scanning(0..1000).count(), 100);
+        assert_eq!(
+            budget.check(),
+            Err(QueryError::Aborted(Abort::RowsScanned(100)))
+        );
+        assert!(!budget.emit(&json!(1)));
+
+        let limits = Limits::new().max_result_bytes(10);
+        let budget = Budget::new(&limits);
+        assert!(budget.nested(|| budget.emit(&json!("a long string"))));
+        assert!(budget.emit(&json!([1, 2])));
+        assert!(!budget.emit(&json!({"a": 1})));
+        assert_eq!(
+            budget.check(),
+            Err(QueryError::Aborted(Abort::ResultBytes(10)))
+        );
+        assert_eq!(encoded_len(&json!({"a": [1, "x"]})), 13);
+    }
+
+    #[test]
+    fn polls_the_token_and_the_clock() {
+        let token = CancelToken::new();
+        let limits = Limits::new().cancel_on(token.clone());
+        let budget = Budget::new(&limits);
+        assert_eq!(budget.scanning(0..1000).count(), 1000);
+        assert_eq!(budget.check(), Ok(()));
+        token.clone().cancel();
+        assert!(budget.scanning(0..1000).count() < POLL as usize);
+        assert_eq!(budget.check(), Err(QueryError::Aborted(Abort::Cancelled)));
+
+        let limits = Limits::new().timeout(Duration::ZERO);
+        let budget = Budget::new(&limits);
+        assert_eq!(budget.scanning(0..1000).count(), POLL as usize - 1);
+        assert_eq!(
+            budget.check(),
+            Err(QueryError::Aborted(Abort::Timeout(Duration::ZERO)))
+        );
+    }
+}
@@ -0,0 +1,306 @@
+//! Queries that keep their result up to date.
+//!
+//! A [`LiveQuery`] starts with the full result of its query, then each
+//! [`refresh`](LiveQuery::refresh) returns the rows that were added, changed or removed by
+//! the publishes since the last one. The collections it reads are [watched](Changes), so a
+//! refresh after no writes costs nothing, and a query that maps each document to its own rows
+//! is only evaluated on the documents that were written: its filter gains
+//! `id() in (<written ids>)`, which reads them by id. That is any query on one collection
+//! without joins, aggregates, subqueries, `with` queries, `limit` or `offset`.
+//!
+//! Any other query runs again in full when a collection it reads was written, and its new
+//! result is compared with the old one. Rows are matched by document id, by their `group by`
+//! keys for aggregates, and otherwise by their value, in which case a changed row shows up as
+//! removed and added.
+//!
+//! Rows depending on `now()` are only evaluated again when their document is written.
+use hashbrown::HashMap;
+
+use super::aggregate::Grouping;
+use super::ast::{Expr, Query, Select, SelectItem};
+use super::builder::id;
+use super::eval::canonical;
+use super::stream::{owned_rows, OwnedRow};
+use super::subquery;
+use super::{execute_page, QueryError};
+use crate::collection::{Changes, Reader};
+
+/// The field holding the key of a row of a query that runs in full.
+const KEY: &str = "#key";
+
+/// What a refresh changed in the result of a [`LiveQuery`].
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Diff {
+    pub added: Vec<OwnedRow>,
+    /// the new form of rows that were in the result before.
+    pub changed: Vec<OwnedRow>,
+    /// the rows as they were before being removed.
+    pub removed: Vec<OwnedRow>,
+}
+
+impl Diff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.changed.is_empty() && self.removed.is_empty()
+    }
+}
+
+/// Runs a query on the current epoch.
+pub(crate) type Run<'h> = Box<dyn Fn(&Query) -> Result<Vec<OwnedRow>, QueryError> + 'h>;
+
+/// A query whose result is updated as the collections it reads change, see the
+/// [module docs](self).
+pub struct LiveQuery<'h> {
+    /// the query, selecting the key of each row under [`KEY`] if it is not `incremental`.
+    query: Query,
+    incremental: bool,
+    run: Run<'h>,
+    changes: Vec<Changes>,
+    /// the rows of the result by key, in result order for rows sharing a key.
+    rows: HashMap<String, Vec<OwnedRow>>,
+    /// a refresh failed after taking the written ids.
+    stale: bool,
+}
+
+impl<'h> LiveQuery<'h> {
+    /// Start keeping the result of `query` on the collection `reader` reads, returning the
+    /// live query and the current result. `changes` must come from
+    /// [`Collection::watch`](crate::collection::Collection::watch) on the same collection,
+    /// called before this.
+    pub fn start(
+        query: &Query,
+        reader: &'h Reader,
+        changes: Changes,
+    ) -> Result<(Self, Vec<OwnedRow>), QueryError> {
+        let run = move |query: &Query| match reader.enter() {
+            Some(map) => Ok(owned_rows(execute_page(query, &map, None)?)),
+            None => Ok(Vec::new()),
+        };
+        Self::new(query, Box::new(run), vec![changes])
+    }
+
+    /// Start keeping the result of `query`, which may read several collections, running it
+    /// with `run`. `watch` starts watching a collection by name.
+    pub(crate) fn with_run(
+        query: &Query,
+        run: Run<'h>,
+        watch: impl Fn(&str) -> Option<Changes>,
+    ) -> Result<(Self, Vec<OwnedRow>), QueryError> {
+        let changes = subquery::collections(query)
+            .iter()
+            .filter_map(|name| watch(name))
+            .collect();
+        Self::new(query, run, changes)
+    }
+
+    /// Start keeping the result of `query`, running it with `run` whenever one of `changes`
+    /// has ids.
+    fn new(
+        query: &Query,
+        run: Run<'h>,
+        changes: Vec<Changes>,
+    ) -> Result<(Self, Vec<OwnedRow>), QueryError> {
+        let incremental = incremental(query);
+        let mut live = LiveQuery {
+            query: if incremental {
+                query.clone()
+            } else {
+                keyed(query)
+            },
+            incremental,
+            run,
+            changes,
+            rows: HashMap::new(),
+            stale: false,
+        };
+        let mut initial = Vec::new();
+        for row in (live.run)(&live.query)? {
+            let (key, row) = split(row);
+            live.rows.entry(key).or_default().push(row.clone());
+            initial.push(row);
+        }
+        Ok((live, initial))
+    }
+
+    /// The rows added, changed and removed by the publishes since the start or the last
+    /// refresh, each in the order of their keys.
+    pub fn refresh(&mut self) -> Result<Diff, QueryError> {
+        let mut written: Vec<String> = self.changes.iter().flat_map(Changes::take).collect();
+        if written.is_empty() && !self.stale {
+            return Ok(Diff::default());
+        }
+        written.sort();
+        // the ids written before a failed refresh are gone, so it runs the query in full
+        let full = self.stale || !self.incremental;
+        self.stale = true;
+        let (mut keys, rows) = if full {
+            let rows = (self.run)(&self.query)?;
+            (self.rows.keys().cloned().collect(), rows)
+        } else {
+            let ids = written.iter().map(String::as_str);
+            let rows = (self.run)(&self.query.clone().filter(id().in_list(ids)))?;
+            (written, rows)
+        };
+        self.stale = false;
+
+        let mut found: HashMap<String, Vec<OwnedRow>> = HashMap::new();
+        for row in rows {
+            let (key, row) = split(row);
+            if !self.rows.contains_key(&key) && !found.contains_key(&key) {
+                keys.push(key.clone());
+            }
+            found.entry(key).or_default().push(row);
+        }
+        keys.sort();
+        keys.dedup();
+
+        let mut diff = Diff::default();
+        for key in keys {
+            let old = self.rows.remove(&key).unwrap_or_default();
+            let new = found.remove(&key).unwrap_or_default();
+            let mut old_rows = old.into_iter();
+            for row in &new {
+                match old_rows.next() {
+                    Some(old) if old == *row => {}
+                    Some(_) => diff.changed.push(row.clone()),
+                    None => diff.added.push(row.clone()),
+                }
+            }
+            diff.removed.extend(old_rows);
+            if !new.is_empty() {
+                self.rows.insert(key, new);
+            }
+        }
+        Ok(diff)
+    }
+}
+
+/// Returns true if the rows of `query` only depend on their own document, so they can be
+/// found again for just the documents that were written.
+fn incremental(query: &Query) -> bool {
+    query.joins.is_empty()
+        && !Grouping::applies(query)
+        && !subquery::nests(query)
+        && query.limit.is_none()
+        && query.offset.is_none()
+        && query.explain.is_none()
+}
+
+/// `query`, also selecting the `group by` keys of its rows under [`KEY`] if it has
+/// aggregates.
+fn keyed(query: &Query) -> Query {
+    let mut query = query.clone();
+    if let (true, Select::Fields(items)) = (Grouping::applies(&query), &mut query.select) {
+        items.push(SelectItem {
+            expr: Expr::Array(query.group_by.clone()),
+            alias: Some(KEY.to_owned()),
+        });
+    }
+    query
+}
+
+/// The key of `row`, and the row without the key it may hold.
+fn split(mut row: OwnedRow) -> (String, OwnedRow) {
+    if let Some(key) = row
+        .value
+        .as_object_mut()
+        .and_then(|fields| fields.remove(KEY))
+    {
+        return (canonical(&key), row);
+    }
+    let key = match &row.id {
+        Some(id) => id.clone(),
+        None => canonical(&row.value),
+    };
+    (key, row)
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::RefCell;
+
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::collection::Collection;
+    use crate::query::parse;
+
+    fn row(id: &str, value: Value) -> OwnedRow {
+        OwnedRow {
+            id: Some(id.into()),
+            value,
+        }
+    }
+
+    #[test]
+    fn diffs_the_written_documents() {
+        let (mut t, reader) = Collection::new("t");
+        for (id, n) in [("a1", 1), ("a2", 5), ("a3", 8)] {
+            t.insert(id.into(), json!({ "n": n })).unwrap();
+        }
+        t.publish();
+
+        let ran = RefCell::new(Vec::new());
+        let run = |query: &Query| {
+            ran.borrow_mut().push(query.clone());
+            let map = reader.enter().unwrap();
+            Ok(owned_rows(execute_page(query, &map, None)?))
+        };
+        let query = parse("from t where n > 2 select n order by n").unwrap();
+        let (mut live, rows) = LiveQuery::new(&query, Box::new(run), vec![t.watch()]).unwrap();
+        assert_eq!(
+            rows,
+            [row("a2", json!({"n": 5})), row("a3", json!({"n": 8}))]
+        );
+        assert_eq!(live.refresh().unwrap(), Diff::default());
+        assert_eq!(ran.borrow().len(), 1);
+
+        t.update("a1".into(), json!({"n": 3})).unwrap();
+        t.update("a2".into(), json!({"n": 6})).unwrap();
+        t.update("a3".into(), json!({"n": 0})).unwrap();
+        t.insert("a4".into(), json!({"n": 1})).unwrap();
+        t.publish();
+        assert_eq!(
+            live.refresh().unwrap(),
+            Diff {
+                added: vec![row("a1", json!({"n": 3}))],
+                changed: vec![row("a2", json!({"n": 6}))],
+                removed: vec![row("a3", json!({"n": 8}))],
+            }
+        );
+        let by_id = query.filter(id().in_list(["a1", "a2", "a3", "a4"]));
+        assert_eq!(ran.borrow()[1], by_id);
+    }
+
+    #[test]
+    fn reruns_grouped_queries() {
+        let (mut t, reader) = Collection::new("t");
+        for i in 0..4 {
+            t.insert(format!("{i}"), json!({ "odd": i % 2 == 1 }))
+                .unwrap();
+        }
+        t.publish();
+
+        let query = parse("from t group by odd select odd, count(*) as n order by odd").unwrap();
+        let (mut live, rows) = LiveQuery::start(&query, &reader, t.watch()).unwrap();
+        let values: Vec<_> = rows.into_iter().map(|row| row.value).collect();
+        assert_eq!(
+            values,
+            [json!({"odd": false, "n": 2}), json!({"odd": true, "n": 2})]
+        );
+
+        t.remove("1".into()).remove("3".into());
+        t.insert("4".into(), json!({"odd": false})).unwrap();
+        t.publish();
+        let diff = live.refresh().unwrap();
+        let values =
+            |rows: Vec<OwnedRow>| -> Vec<Value> { rows.into_iter().map(|row| row.value).collect() };
+        assert!(diff.added.is_empty());
+        assert_eq!(values(diff.changed), [json!({"odd": false, "n": 3})]);
+        assert_eq!(values(diff.removed), [json!({"odd": true, "n": 2})]);
+
+        t.insert("5".into(), json!({"odd": true})).unwrap();
+        t.publish();
+        let diff = live.refresh().unwrap();
+        assert_eq!(values(diff.added), [json!({"odd": true, "n": 1})]);
+    }
+}
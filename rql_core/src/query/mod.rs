@@ -0,0 +1,165 @@
+//! RQL, the query language over collections.
+//!
+//! A read query names a collection and optionally filters, projects, sorts and pages its
+//! documents:
+//!
+//! ```text
+//! from users
+//! where age >= 18 and address.city in ("Oslo", "Bergen")
+//! select name, address.city as city
+//! order by age desc nulls last
+//! limit 10 offset 20
+//! ```
+//!
+//! Keywords are case-insensitive. Fields are written as paths (`a.b[0]`, or `$[0].a` when the
+//! row is an array); names that are not plain identifiers, or that clash with a keyword, are
+//! quoted with backticks (`` `first name` ``).
+//! Strings use single or double quotes and `--` starts a comment.
+//!
+//! Aggregates fold the matching documents into one row per group:
+//!
+//! ```text
+//! from orders
+//! where status = "paid"
+//! group by customer
+//! having sum(total) > 100
+//! select customer, count(*) as orders, sum(total) as spent
+//! order by spent desc
+//! ```
+//!
+//! Queries run on a [`Snapshot`](crate::database::Snapshot) can join other collections, binding
+//! each document to its source name:
+//!
+//! ```text
+//! from orders as o
+//! join customers as c on id(c) = o.customer
+//! left join notes as n on n.email = c.email
+//! select id(o) as order, c.name, n.text
+//! ```
+//!
+//! `unnest` turns each element of an array into its own row, before joins and the `where`
+//! clause, so elements can be joined on and grouped by; `any` and `all` test the elements of
+//! an array without unnesting it:
+//!
+//! ```text
+//! from orders as o
+//! unnest o.items as i
+//! join products as p on id(p) = i.product
+//! where any(o.tags, o.tags = "gift")
+//! group by p.name
+//! select p.name, sum(i.quantity) as sold
+//! ```
+//!
+//! Times stored as ISO-8601 strings or epoch milliseconds compare correctly, across time zones,
+//! once normalized with `timestamp(x)`; `date_trunc`, `date_add`, `time_bucket` and `now()` work
+//! on the same canonical UTC form:
+//!
+//! ```text
+//! from events
+//! where timestamp(at) >= date_add(now(), "-7 days")
+//! group by date_trunc("day", at, "Europe/Oslo")
+//! select date_trunc("day", at, "Europe/Oslo") as day, count(*) as events
+//! ```
+//!
+//! A parenthesized query is a subquery: `(from ...)` stands for the one value it selects and
+//! `x in (from ...)` tests `x` against all of them. A subquery may read the sources of the
+//! query right around it; `with` names queries that the rest of the statement reads like
+//! collections. A statement run on a snapshot reads one epoch throughout:
+//!
+//! ```text
+//! with counts as (from orders group by customer select customer, count(*) as n)
+//! from users as u
+//! where (from counts where customer = id(u) select n) > (from counts select avg(n))
+//! ```
+//!
+//! Without joins, `id()` is the id of the document. The `where` clause picks how documents are
+//! read: by id, through an index or by a full scan. Prefixing a query with `explain` returns
+//! that plan instead of the rows, and `explain analyze` runs the query and adds the rows and
+//! time of each step, see [`explain`] and [`Plan`].
+//!
+//! Queries can also be built in Rust with the [`builder`], which yields the same [`Query`] as
+//! parsing their text.
+//!
+//! A query run with [`Limits`] is aborted once it runs too long, reads too many documents,
+//! returns too many bytes or is cancelled through a [`CancelToken`], see
+//! [`execute_page_limited`].
+//!
+//! A [`RowStream`] yields the rows of a query as they are read, either a chunk at a time
+//! between publishes or copied from one snapshot.
+//!
+//! A [`LiveQuery`] keeps the result of a query up to date, returning the rows each publish
+//! added, changed or removed as a [`Diff`]. Queries whose rows each come from one document
+//! are only evaluated on the documents that were written.
+//!
+//! MongoDB filter and update documents translate into the same syntax tree, see [`mongo`], and
+//! so do SQL `select` statements, see [`parse_sql`].
+//!
+//! Values can be left as `$name` parameters and bound when a [`Prepared`] query runs; bound
+//! values are never parsed, so they cannot change the shape of the query.
+//!
+//! Write statements change one collection and are published as a whole, see [`mutate`]:
+//!
+//! ```text
+//! insert into users values ("u1", {name: "Ann", age: 31}), ("u2", {name: "Bo"})
+//! update users set age = age + 1, address.city = "Oslo" where id() = "u1" returning
+//! delete from users where missing(age)
+//! ```
+mod aggregate;
+pub mod ast;
+pub mod builder;
+mod cursor;
+mod error;
+pub mod eval;
+mod exec;
+mod func;
+mod join;
+mod lexer;
+mod limits;
+mod live;
+pub mod mongo;
+mod mutate;
+mod parser;
+mod plan;
+mod prepare;
+mod project;
+mod sort;
+mod sql;
+mod stream;
+mod subquery;
+mod temporal;
+mod unnest;
+
+pub use ast::{Mutation, Query};
+pub use cursor::Cursor;
+pub use error::{Position, QueryError, SyntaxError};
+pub use eval::{Filter, Truth};
+pub use exec::{execute, execute_page, execute_page_limited, explain, Page, Row};
+pub(crate) use exec::{explain_in, run};
+pub(crate) use limits::Budget;
+pub use limits::{Abort, CancelToken, Limits};
+pub use live::{Diff, LiveQuery};
+pub use mutate::{mutate, Affected};
+pub use parser::{parse, parse_expr, parse_mutation};
+pub use plan::Plan;
+pub use prepare::{Param, Params, PlanCache, Prepared};
+pub use project::Projection;
+pub use sort::{sort_cmp, Sort};
+pub use sql::parse_sql;
+pub(crate) use stream::owned_rows;
+pub use stream::{Mode, OwnedRow, RowStream};
+
+impl std::str::FromStr for Query {
+    type Err = SyntaxError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse(s)
+    }
+}
+
+impl std::str::FromStr for Mutation {
+    type Err = SyntaxError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_mutation(s)
+    }
+}
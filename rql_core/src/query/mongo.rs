@@ -0,0 +1,621 @@
+//! MongoDB filter and update documents, translated into RQL.
+//!
+//! A filter document becomes a `where` expression and an update document the assignments of
+//! an `update` statement, so they run on the same planner and evaluator as RQL text:
+//!
+//! ```
+//! use rql_core::query::{mongo, parse_expr};
+//! use serde_json::json;
+//!
+//! let filter = mongo::filter(&json!({"age": {"$gt": 30}, "_id": {"$in": ["u1", "u2"]}}));
+//! let expected = "id() in ('u1', 'u2') and (age > 30 or any(age, age > 30))";
+//! assert_eq!(filter.unwrap(), Some(parse_expr(expected).unwrap()));
+//! ```
+//!
+//! Filters support `$eq`, `$ne`, `$gt`, `$gte`, `$lt`, `$lte`, `$in`, `$nin`, `$exists`,
+//! `$regex` with `$options`, `$not`, `$all`, `$size` and `$elemMatch` on fields, and `$and`,
+//! `$or` and `$nor` on filters. Updates support `$set`, `$unset`, `$inc` and `$push` with
+//! `$each`. Other operators fail with [`QueryError::Unsupported`], and malformed documents with
+//! [`QueryError::Invalid`].
+//!
+//! The translation keeps MongoDB's meaning where RQL differs:
+//!
+//! * A condition on a field that holds an array also holds if it holds for one of its
+//!   elements, so `{"tags": "a"}` matches `{"tags": ["a", "b"]}`. That takes an `any` next to
+//!   the comparison, which no index can answer. A [`Translator`] given the collection's
+//!   [`Schema`] leaves it out for fields the schema declares without the `array` type.
+//! * `{"a": null}` matches documents where `a` is null or missing, and `$ne`, `$nin`, `$not`
+//!   and `$nor` match documents the condition they negate does not match, including those
+//!   where it is unknown because the field is missing.
+//! * Dotted field names are paths, and segments made of digits are array indexes. Arrays
+//!   along a path are not looked into, so `{"items.qty": 1}` does not match
+//!   `{"items": [{"qty": 1}]}`; `{"items": {"$elemMatch": {"qty": 1}}}` does.
+//! * `_id` is the document id, [`id()`](super::builder::id).
+//! * `$inc` and `$push` leave a field that is not a number or an array unchanged, where
+//!   MongoDB would fail.
+use serde_json::{Map, Value};
+
+use super::ast::{BinaryOp, Expr, Mutation, Quantifier, Query, Update};
+use super::builder::{call, case, id};
+use super::QueryError;
+use crate::path::{Path, Segment};
+use crate::schema::{JsonType, Schema};
+
+/// Translates MongoDB documents, optionally knowing the schema of the collection they are
+/// for.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Translator<'s> {
+    schema: Option<&'s Schema>,
+    /// compare fields as a whole, never with their elements.
+    whole: bool,
+}
+
+/// The filter document `doc` as a `where` expression, `None` for `{}`, see the
+/// [module docs](self).
+pub fn filter(doc: &Value) -> Result<Option<Expr>, QueryError> {
+    Translator::new().filter(doc)
+}
+
+/// The update document `doc` as the assignments of an `update` statement.
+pub fn update(doc: &Value) -> Result<Vec<(Path, Expr)>, QueryError> {
+    Translator::new().update(doc)
+}
+
+impl<'s> Translator<'s> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Trust `schema` to tell which fields never hold arrays.
+    pub fn schema(mut self, schema: &'s Schema) -> Self {
+        self.schema = Some(schema);
+        self
+    }
+
+    /// `find(filter)`: the query returning the documents of `collection` that match.
+    pub fn find(&self, collection: &str, filter: &Value) -> Result<Query, QueryError> {
+        let mut query = Query::new(collection);
+        query.filter = self.filter(filter)?;
+        Ok(query)
+    }
+
+    /// `updateMany(filter, update)`: the statement applying `update` to the documents of
+    /// `collection` that match `filter`.
+    pub fn update_many(
+        &self,
+        collection: &str,
+        filter: &Value,
+        update: &Value,
+    ) -> Result<Mutation, QueryError> {
+        Ok(Mutation::Update(Update {
+            collection: collection.to_owned(),
+            set: self.update(update)?,
+            filter: self.filter(filter)?,
+            returning: false,
+        }))
+    }
+
+    /// The filter document `doc` as a `where` expression, `None` for `{}`.
+    pub fn filter(&self, doc: &Value) -> Result<Option<Expr>, QueryError> {
+        self.document(doc, &Path::root())
+    }
+
+    /// The update document `doc` as the assignments of an `update` statement, in the order
+    /// the document holds its operators and fields.
+    pub fn update(&self, doc: &Value) -> Result<Vec<(Path, Expr)>, QueryError> {
+        let mut set = Vec::new();
+        for (op, fields) in object(doc, "an update")? {
+            for (name, value) in object(fields, op)? {
+                let path = path(name)?;
+                let field = Expr::path(path.clone());
+                let expr = match op.as_str() {
+                    "$set" => Expr::literal(value.clone()),
+                    // a missing value removes the field
+                    "$unset" => case([(Expr::literal(false), Expr::literal(Value::Null))], None),
+                    "$inc" if value.is_number() => {
+                        let sum = Expr::binary(
+                            call("coalesce", [field.clone(), Expr::literal(0)]),
+                            BinaryOp::Add,
+                            Expr::literal(value.clone()),
+                        );
+                        call("coalesce", [sum, field])
+                    }
+                    "$inc" => return Err(invalid(format!("`$inc` of `{name}` is not a number"))),
+                    "$push" => {
+                        let items = match value {
+                            Value::Object(each) if each.keys().any(|key| key.starts_with('$')) => {
+                                match (each.get("$each"), each.len()) {
+                                    (Some(Value::Array(items)), 1) => items.clone(),
+                                    _ => {
+                                        return Err(invalid(format!(
+                                        "`$push` of `{name}` supports only `$each` with an array"
+                                    )))
+                                    }
+                                }
+                            }
+                            value => vec![value.clone()],
+                        };
+                        let pushed = call(
+                            "array_concat",
+                            [
+                                call("coalesce", [field.clone(), Expr::Array(Vec::new())]),
+                                Expr::literal(items),
+                            ],
+                        );
+                        call("coalesce", [pushed, field])
+                    }
+                    _ => return Err(unsupported(op, "in an update")),
+                };
+                if set.iter().any(|(set, _)| *set == path) {
+                    return Err(invalid(format!("the update changes `{name}` twice")));
+                }
+                set.push((path, expr));
+            }
+        }
+        Ok(set)
+    }
+
+    /// The conditions of the filter document `doc`, joined with `and`. Field names are
+    /// relative to `base`.
+    fn document(&self, doc: &Value, base: &Path) -> Result<Option<Expr>, QueryError> {
+        let mut conditions = Vec::new();
+        for (key, value) in object(doc, "a filter")? {
+            conditions.push(match key.as_str() {
+                "$and" | "$or" | "$nor" => {
+                    let Some(docs) = value.as_array().filter(|docs| !docs.is_empty()) else {
+                        return Err(invalid(format!(
+                            "`{key}` takes a non-empty array of filters"
+                        )));
+                    };
+                    let mut each = Vec::new();
+                    for doc in docs {
+                        each.push(self.document(doc, base)?.unwrap_or(Expr::literal(true)));
+                    }
+                    match key.as_str() {
+                        "$and" => join(each, BinaryOp::And),
+                        "$or" => join(each, BinaryOp::Or),
+                        _ => negate(join(each, BinaryOp::Or)),
+                    }
+                }
+                key if key.starts_with('$') => return Err(unsupported(key, "in a filter")),
+                name => self.field(name, base, value)?,
+            });
+        }
+        Ok((!conditions.is_empty()).then(|| join(conditions, BinaryOp::And)))
+    }
+
+    /// The condition `{name: value}`.
+    fn field(&self, name: &str, base: &Path, value: &Value) -> Result<Expr, QueryError> {
+        if name == "_id" && base.is_root() {
+            let Some(ops) = operators(value) else {
+                return Ok(id().eq(value.clone()));
+            };
+            let mut conditions = Vec::new();
+            for (op, value) in ops {
+                conditions.push(match (op.as_str(), value) {
+                    ("$eq", value) => id().eq(value.clone()),
+                    ("$ne", value) => id().ne(value.clone()),
+                    ("$in", Value::Array(ids)) => id().in_list(ids.iter().cloned()),
+                    ("$nin", Value::Array(ids)) => id().not_in(ids.iter().cloned()),
+                    _ => return Err(unsupported(op, "on `_id`")),
+                });
+            }
+            return Ok(join(conditions, BinaryOp::And));
+        }
+        let mut path = base.clone();
+        for segment in path_of(name)?.segments() {
+            path.push(segment.clone());
+        }
+        match operators(value) {
+            Some(ops) => self.operators(name, &path, ops),
+            None => Ok(self.equals(&path, value)),
+        }
+    }
+
+    /// The conditions of the operators in `ops` on the field at `path`, joined with `and`.
+    fn operators(
+        &self,
+        name: &str,
+        path: &Path,
+        ops: &Map<String, Value>,
+    ) -> Result<Expr, QueryError> {
+        let field = || Expr::path(path.clone());
+        let mut conditions = Vec::new();
+        for (op, value) in ops {
+            let compared = |op| {
+                self.elements(
+                    path,
+                    Expr::binary(field(), op, Expr::literal(value.clone())),
+                )
+            };
+            conditions.push(match op.as_str() {
+                "$eq" => self.equals(path, value),
+                "$ne" => negate(self.equals(path, value)),
+                "$gt" => compared(BinaryOp::Gt),
+                "$gte" => compared(BinaryOp::Ge),
+                "$lt" => compared(BinaryOp::Lt),
+                "$lte" => compared(BinaryOp::Le),
+                "$in" | "$nin" => {
+                    let Value::Array(values) = value else {
+                        return Err(invalid(format!("`{op}` on `{name}` takes an array")));
+                    };
+                    let any = self.one_of(path, values);
+                    if op == "$in" {
+                        any
+                    } else {
+                        negate(any)
+                    }
+                }
+                "$all" => {
+                    let Some(values) = value.as_array().filter(|values| !values.is_empty()) else {
+                        return Err(invalid(format!(
+                            "`$all` on `{name}` takes a non-empty array"
+                        )));
+                    };
+                    let each = values
+                        .iter()
+                        .map(|value| self.equals(path, value))
+                        .collect();
+                    join(each, BinaryOp::And)
+                }
+                "$exists" => match value {
+                    Value::Bool(exists) => Expr::Exists {
+                        path: path.clone(),
+                        negated: !exists,
+                    },
+                    _ => return Err(invalid(format!("`$exists` on `{name}` takes a boolean"))),
+                },
+                "$size" => match value.as_u64() {
+                    Some(size) => call("array_length", [field()]).eq(size),
+                    None => return Err(invalid(format!("`$size` on `{name}` takes a count"))),
+                },
+                "$regex" => {
+                    let options = ops.get("$options").map(Value::as_str);
+                    let pattern = match (value, options) {
+                        (Value::String(pattern), None) => pattern.clone(),
+                        (Value::String(pattern), Some(Some(flags)))
+                            if flags.chars().all(|flag| "imsx".contains(flag)) =>
+                        {
+                            if flags.is_empty() {
+                                pattern.clone()
+                            } else {
+                                format!("(?{flags}){pattern}")
+                            }
+                        }
+                        _ => {
+                            return Err(invalid(format!(
+                                "`$regex` on `{name}` takes a pattern and options among `imsx`"
+                            )))
+                        }
+                    };
+                    self.elements(path, call("regex_match", [field(), Expr::literal(pattern)]))
+                }
+                "$options" if ops.contains_key("$regex") => continue,
+                "$not" => match operators(value) {
+                    Some(ops) => negate(self.operators(name, path, ops)?),
+                    None => return Err(invalid(format!("`$not` on `{name}` takes operators"))),
+                },
+                "$elemMatch" => {
+                    // the element stands in for the field, as in `any`
+                    let predicate = match operators(value) {
+                        Some(ops) => Translator {
+                            whole: true,
+                            ..*self
+                        }
+                        .operators(name, path, ops)?,
+                        None => self.document(value, path)?.unwrap_or(Expr::literal(true)),
+                    };
+                    Expr::Quantified {
+                        quantifier: Quantifier::Any,
+                        path: path.clone(),
+                        predicate: Box::new(predicate),
+                    }
+                }
+                op => return Err(unsupported(op, &format!("on `{name}`"))),
+            });
+        }
+        Ok(join(conditions, BinaryOp::And))
+    }
+
+    /// `{path: value}`: equal to `value`, or holding it if `path` is an array. A null value
+    /// also matches a missing field.
+    fn equals(&self, path: &Path, value: &Value) -> Expr {
+        if value.is_null() {
+            return Expr::path(path.clone()).is_null();
+        }
+        self.elements(path, Expr::path(path.clone()).eq(value.clone()))
+    }
+
+    /// `{path: {$in: values}}`
+    fn one_of(&self, path: &Path, values: &[Value]) -> Expr {
+        let (nulls, values): (Vec<_>, Vec<_>) = values.iter().cloned().partition(Value::is_null);
+        let field = Expr::path(path.clone());
+        let mut any = self.elements(path, field.clone().in_list(values));
+        if !nulls.is_empty() {
+            any = field.is_null().or(any);
+        }
+        any
+    }
+
+    /// `condition` on the field at `path`, or on one of its elements if it may be an array.
+    fn elements(&self, path: &Path, condition: Expr) -> Expr {
+        let never_array = self
+            .schema
+            .and_then(|schema| schema.types_at(path))
+            .is_some_and(|types| !types.contains(&JsonType::Array));
+        if self.whole || never_array {
+            return condition;
+        }
+        let each = Expr::Quantified {
+            quantifier: Quantifier::Any,
+            path: path.clone(),
+            predicate: Box::new(condition.clone()),
+        };
+        condition.or(each)
+    }
+}
+
+/// The fields of `doc`, which must be an object.
+fn object<'v>(doc: &'v Value, what: &str) -> Result<&'v Map<String, Value>, QueryError> {
+    doc.as_object()
+        .ok_or_else(|| invalid(format!("{what} must be an object, not `{doc}`")))
+}
+
+/// The operators of a field condition, `None` if it is a value to compare with.
+fn operators(value: &Value) -> Option<&Map<String, Value>> {
+    value
+        .as_object()
+        .filter(|ops| !ops.is_empty() && ops.keys().all(|key| key.starts_with('$')))
+}
+
+/// The path of a dotted field name.
+fn path_of(name: &str) -> Result<Path, QueryError> {
+    if name.is_empty() || name.split('.').any(str::is_empty) {
+        return Err(invalid(format!("`{name}` is not a field name")));
+    }
+    let segments = name
+        .split('.')
+        .map(|segment| match segment.parse() {
+            Ok(index) if segment.bytes().all(|b| b.is_ascii_digit()) => Segment::Index(index),
+            _ => Segment::Key(segment.to_owned()),
+        })
+        .collect();
+    Ok(Path::from_segments(segments))
+}
+
+/// The path of a field an update writes.
+fn path(name: &str) -> Result<Path, QueryError> {
+    if name == "_id" {
+        return Err(invalid("an update can not change `_id`".to_owned()));
+    }
+    path_of(name)
+}
+
+/// `conditions` joined with `op`.
+fn join(conditions: Vec<Expr>, op: BinaryOp) -> Expr {
+    conditions
+        .into_iter()
+        .reduce(|left, right| Expr::binary(left, op, right))
+        .expect("at least one condition")
+}
+
+/// True where `condition` is not, including where it is unknown.
+fn negate(condition: Expr) -> Expr {
+    case(
+        [(condition, Expr::literal(false))],
+        Some(Expr::literal(true)),
+    )
+}
+
+fn invalid(message: String) -> QueryError {
+    QueryError::Invalid(message)
+}
+
+fn unsupported(op: &str, place: &str) -> QueryError {
+    QueryError::Unsupported(format!("`{op}` {place}"))
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::*;
+    use crate::collection::Collection;
+    use crate::query::{execute, mutate, parse, parse_expr};
+
+    fn translated(doc: Value) -> String {
+        filter(&doc).unwrap().unwrap().to_string()
+    }
+
+    #[test]
+    fn translates_filters() {
+        for (doc, expected) in [
+            (json!({"a": 1}), "a = 1 or any(a, a = 1)"),
+            (
+                json!({"a.b": {"$gte": 2}}),
+                "a.b >= 2 or any(a.b, a.b >= 2)",
+            ),
+            (json!({"a": null}), "a is null"),
+            (
+                json!({"a": {"$in": [1, null]}}),
+                "a is null or (a in (1) or any(a, a in (1)))",
+            ),
+            (
+                json!({"a": {"$ne": "x"}}),
+                r#"case when a = "x" or any(a, a = "x") then false else true end"#,
+            ),
+            (
+                json!({"$or": [{"a": {"$exists": false}}, {"b.0": {"$size": 2}}]}),
+                "missing(a) or array_length(b[0]) = 2",
+            ),
+            (
+                json!({"name": {"$regex": "^a", "$options": "i"}}),
+                r#"regex_match(name, "(?i)^a") or any(name, regex_match(name, "(?i)^a"))"#,
+            ),
+            (
+                json!({"items": {"$elemMatch": {"qty": {"$gt": 1}, "sku": "x"}}}),
+                "any(items, (items.qty > 1 or any(items.qty, items.qty > 1)) \
+                 and (items.sku = \"x\" or any(items.sku, items.sku = \"x\")))",
+            ),
+            (
+                json!({"_id": {"$nin": ["a"]}, "n": {"$not": {"$lt": 3}}}),
+                "id() not in (\"a\") and case when n < 3 or any(n, n < 3) \
+                 then false else true end",
+            ),
+        ] {
+            assert_eq!(translated(doc.clone()), expected, "{doc}");
+            assert_eq!(
+                filter(&doc).unwrap().unwrap(),
+                parse_expr(expected).unwrap(),
+                "{doc}"
+            );
+        }
+        assert_eq!(filter(&json!({})).unwrap(), None);
+
+        for (doc, message) in [
+            (json!([]), "a filter must be an object"),
+            (
+                json!({"$where": "x"}),
+                "`$where` in a filter is not supported",
+            ),
+            (
+                json!({"a": {"$near": 1}}),
+                "`$near` on `a` is not supported",
+            ),
+            (json!({"a": {"$in": 1}}), "`$in` on `a` takes an array"),
+            (json!({"$or": []}), "`$or` takes a non-empty array"),
+            (json!({"a..b": 1}), "`a..b` is not a field name"),
+            (
+                json!({"a": {"$regex": "x", "$options": "g"}}),
+                "options among `imsx`",
+            ),
+        ] {
+            match filter(&doc) {
+                Err(err) => assert!(err.to_string().contains(message), "{err}"),
+                other => panic!("{doc}: {other:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn matches_like_mongo() {
+        let (mut users, reader) = Collection::new("users");
+        for (id, doc) in [
+            ("u1", json!({"age": 31, "tags": ["a", "b"]})),
+            ("u2", json!({"age": 25, "tags": "a"})),
+            ("u3", json!({"age": null, "tags": []})),
+            ("u4", json!({"tags": ["c"]})),
+        ] {
+            users.insert(id.into(), doc).unwrap();
+        }
+        users.publish();
+        let map = reader.enter().unwrap();
+        let ids = |doc: Value| -> Vec<String> {
+            let mut query = Translator::new().find("users", &doc).unwrap();
+            query.order_by = parse("from users order by id()").unwrap().order_by;
+            execute(&query, &map)
+                .unwrap()
+                .into_iter()
+                .map(|row| row.id.unwrap().to_owned())
+                .collect()
+        };
+        assert_eq!(ids(json!({"tags": "a"})), ["u1", "u2"]);
+        assert_eq!(ids(json!({"tags": {"$in": ["b", "c"]}})), ["u1", "u4"]);
+        assert_eq!(ids(json!({"age": {"$gt": 30}})), ["u1"]);
+        assert_eq!(ids(json!({"age": {"$ne": 25}})), ["u1", "u3", "u4"]);
+        assert_eq!(ids(json!({"age": null})), ["u3", "u4"]);
+        assert_eq!(ids(json!({"tags": {"$all": ["a", "b"]}})), ["u1"]);
+        assert_eq!(
+            ids(json!({"$nor": [{"tags": "a"}, {"age": null}]})),
+            Vec::<String>::new()
+        );
+
+        let schema =
+            Schema::compile(&json!({"properties": {"age": {"type": ["integer", "null"]}}}))
+                .unwrap();
+        let typed = Translator::new().schema(&schema);
+        let expected = parse_expr(r#"age > 30 and (tags = "a" or any(tags, tags = "a"))"#).unwrap();
+        assert_eq!(
+            typed
+                .filter(&json!({"age": {"$gt": 30}, "tags": "a"}))
+                .unwrap(),
+            Some(expected)
+        );
+    }
+
+    #[test]
+    fn translates_updates() {
+        let set = update(&json!({
+            "$set": {"name": "Ann", "address.city": "Oslo"},
+            "$unset": {"nick": ""},
+            "$inc": {"visits": 1},
+            "$push": {"tags": {"$each": ["x", "y"]}},
+        }))
+        .unwrap();
+        let text: Vec<_> = set
+            .iter()
+            .map(|(path, expr)| format!("{path} = {expr}"))
+            .collect();
+        assert_eq!(
+            text,
+            [
+                "visits = coalesce(coalesce(visits, 0) + 1, visits)",
+                r#"tags = coalesce(array_concat(coalesce(tags, []), ["x","y"]), tags)"#,
+                r#"address.city = "Oslo""#,
+                r#"name = "Ann""#,
+                "nick = case when false then null end",
+            ]
+        );
+
+        let (mut users, reader) = Collection::new("users");
+        users
+            .insert(
+                "u1".into(),
+                json!({"nick": "a", "visits": 2, "tags": ["w"]}),
+            )
+            .unwrap();
+        users
+            .insert("u2".into(), json!({"visits": "many"}))
+            .unwrap();
+        users.publish();
+        let statement = Translator::new()
+            .update_many(
+                "users",
+                &json!({}),
+                &json!({"$unset": {"nick": 1}, "$inc": {"visits": 1}, "$push": {"tags": "z"}}),
+            )
+            .unwrap();
+        mutate(&statement, &mut users).unwrap();
+        let map = reader.enter().unwrap();
+        let docs: Vec<_> = execute(&parse("from users order by id()").unwrap(), &map)
+            .unwrap()
+            .into_iter()
+            .map(|row| row.into_value())
+            .collect();
+        assert_eq!(
+            docs,
+            [
+                json!({"visits": 3, "tags": ["w", "z"]}),
+                json!({"visits": "many", "tags": ["z"]}),
+            ]
+        );
+
+        for (doc, message) in [
+            (
+                json!({"$rename": {"a": "b"}}),
+                "`$rename` in an update is not supported",
+            ),
+            (json!({"$inc": {"a": "1"}}), "`$inc` of `a` is not a number"),
+            (json!({"$set": {"_id": "x"}}), "can not change `_id`"),
+            (
+                json!({"$set": {"a": 1}, "$inc": {"a": 1}}),
+                "changes `a` twice",
+            ),
+        ] {
+            match update(&doc) {
+                Err(err) => assert!(err.to_string().contains(message), "{err}"),
+                other => panic!("{doc}: {other:?}"),
+            }
+        }
+    }
+}
@@ -0,0 +1,428 @@
+//! Write statements, compiled to a batch of writes that is published at once.
+use std::borrow::Cow;
+
+use hashbrown::HashMap;
+use serde_json::{Map, Value};
+
+use super::ast::{Delete, Expr, Insert, Mutation, Query, RqlPath, Update};
+use super::eval::{check, eval};
+use super::exec::alone;
+use super::func;
+use super::join::{bind_id_expr, IDS};
+use super::plan::constant;
+use super::{run, Budget, QueryError};
+use crate::collection::Collection;
+use crate::path::Segment;
+
+/// What a [`Mutation`] did.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Affected {
+    /// documents stored under an id that had none.
+    pub inserted: u64,
+    /// documents replaced, including matched documents an `update` left unchanged.
+    pub updated: u64,
+    pub deleted: u64,
+    /// with `returning`, every written document by id, or the removed one for a delete.
+    pub documents: Option<Vec<(String, Value)>>,
+}
+
+impl Affected {
+    /// The number of documents written.
+    pub fn count(&self) -> u64 {
+        self.inserted + self.updated + self.deleted
+    }
+}
+
+/// One write of a batch: store `doc` under `id`, or remove it when `None`.
+struct Write {
+    id: String,
+    doc: Option<Value>,
+    /// fail if `id` already exists.
+    create: bool,
+}
+
+/// Apply `mutation` to `collection` in a single publish.
+///
+/// Pending writes of the collection are published first, and the statement is compiled into a
+/// batch of writes against what readers see then; `where` clauses pick documents through the
+/// same access paths as queries. If the collection refuses any write of the batch, the ones
+/// before it are undone and nothing of the statement becomes visible.
+///
+/// The collection named by the statement is not checked, `collection` is taken to be it.
+pub fn mutate(mutation: &Mutation, collection: &mut Collection) -> Result<Affected, QueryError> {
+    if collection.has_pending() {
+        collection.publish();
+    }
+    let mutation = &at_now(mutation);
+    let batch = match mutation {
+        Mutation::Insert(insert) => inserts(insert)?,
+        Mutation::Update(update) => updates(update, collection)?,
+        Mutation::Delete(delete) => deletes(delete, collection)?,
+    };
+
+    let mut affected = Affected {
+        documents: mutation.returning().then(Vec::new),
+        ..Affected::default()
+    };
+    // the document each id had before the statement wrote it, to undo a failed batch
+    let mut written: HashMap<String, Option<Value>> = HashMap::new();
+    let mut undo: Vec<(String, Option<Value>)> = Vec::new();
+    for Write { id, doc, create } in batch {
+        let old = match written.get(&id) {
+            Some(doc) => doc.clone(),
+            None => collection.get(&id).map(|doc| doc.clone()),
+        };
+        let result = match &doc {
+            Some(doc) if create => collection.create(id.clone(), doc.clone()).map(drop),
+            Some(doc) => collection.insert(id.clone(), doc.clone()).map(drop),
+            None => {
+                collection.remove(id.clone());
+                Ok(())
+            }
+        };
+        if let Err(err) = result {
+            for (id, doc) in undo.into_iter().rev() {
+                collection.restore(id, doc);
+            }
+            collection.publish();
+            return Err(err.into());
+        }
+        match (&old, &doc) {
+            (_, None) => affected.deleted += 1,
+            (None, Some(_)) => affected.inserted += 1,
+            (Some(_), Some(_)) => affected.updated += 1,
+        }
+        if let Some(documents) = &mut affected.documents {
+            if let Some(returned) = doc.clone().or_else(|| old.clone()) {
+                documents.push((id.clone(), returned));
+            }
+        }
+        undo.push((id.clone(), old));
+        written.insert(id, doc);
+    }
+    collection.publish();
+    Ok(affected)
+}
+
+/// `mutation` with every `now()` fixed to the time the statement started.
+fn at_now(mutation: &Mutation) -> Mutation {
+    let now = func::now();
+    let fix = |expr: &Expr| func::fix_now(expr, &now);
+    let mut mutation = mutation.clone();
+    match &mut mutation {
+        Mutation::Insert(insert) => {
+            for (id, doc) in &mut insert.rows {
+                (*id, *doc) = (fix(id), fix(doc));
+            }
+        }
+        Mutation::Update(update) => {
+            for (_, value) in &mut update.set {
+                *value = fix(value);
+            }
+            update.filter = update.filter.as_ref().map(fix);
+        }
+        Mutation::Delete(delete) => delete.filter = delete.filter.as_ref().map(fix),
+    }
+    mutation
+}
+
+fn inserts(insert: &Insert) -> Result<Vec<Write>, QueryError> {
+    insert
+        .rows
+        .iter()
+        .map(|(id, doc)| {
+            let id = match value(id)? {
+                Value::String(id) => id,
+                other => {
+                    return Err(QueryError::Invalid(format!(
+                        "document ids are strings, found `{other}`"
+                    )))
+                }
+            };
+            Ok(Write {
+                id,
+                doc: Some(value(doc)?),
+                create: !insert.upsert,
+            })
+        })
+        .collect()
+}
+
+/// The value of an expression that reads no document.
+fn value(expr: &Expr) -> Result<Value, QueryError> {
+    check(expr)?;
+    constant(expr).ok_or_else(|| QueryError::Invalid(format!("`{expr}` is not a constant value")))
+}
+
+fn updates(update: &Update, collection: &Collection) -> Result<Vec<Write>, QueryError> {
+    let mut ids = false;
+    let set = update
+        .set
+        .iter()
+        .map(|(path, value)| {
+            let value = bind_id_expr(value, &mut ids)?;
+            check(&value)?;
+            Ok((path, value))
+        })
+        .collect::<Result<Vec<_>, QueryError>>()?;
+    matching(&update.collection, &update.filter, collection, |id, old| {
+        let scope = match old {
+            Value::Object(fields) if ids => {
+                let mut fields = fields.clone();
+                fields.insert(IDS.to_owned(), Value::String(id.to_owned()));
+                Cow::Owned(Value::Object(fields))
+            }
+            _ => Cow::Borrowed(old),
+        };
+        let values: Vec<_> = set
+            .iter()
+            .map(|(_, value)| eval(value, &scope).map(Cow::into_owned))
+            .collect();
+        let mut doc = old.clone();
+        for ((path, _), value) in set.iter().zip(values) {
+            if !assign(&mut doc, path.segments(), value) {
+                return Err(QueryError::Invalid(format!(
+                    "can not set `{}` of document `{id}`, a parent is not an object or array",
+                    RqlPath(path)
+                )));
+            }
+        }
+        Ok(Write {
+            id: id.to_owned(),
+            doc: Some(doc),
+            create: false,
+        })
+    })
+}
+
+fn deletes(delete: &Delete, collection: &Collection) -> Result<Vec<Write>, QueryError> {
+    matching(&delete.collection, &delete.filter, collection, |id, _| {
+        Ok(Write {
+            id: id.to_owned(),
+            doc: None,
+            create: false,
+        })
+    })
+}
+
+/// A write for every document of `collection` matching `filter`, found like a query finds
+/// them.
+fn matching(
+    name: &str,
+    filter: &Option<Expr>,
+    collection: &Collection,
+    mut write: impl FnMut(&str, &Value) -> Result<Write, QueryError>,
+) -> Result<Vec<Write>, QueryError> {
+    let Some(map) = collection.enter() else {
+        return Ok(Vec::new());
+    };
+    let mut query = Query::new(name);
+    query.filter = filter.clone();
+    let rows = run(&query, &alone(&query, &map), None, &Budget::unlimited())?.rows;
+    rows.iter()
+        .map(|row| write(row.id.expect("rows of a plain query have ids"), &row.value))
+        .collect()
+}
+
+/// Set the value at `path` in `doc`, creating missing objects on the way, or remove it when
+/// `value` is missing. Returns false if a parent is neither an object nor an array.
+fn assign(doc: &mut Value, path: &[Segment], value: Option<Value>) -> bool {
+    let Some((last, parents)) = path.split_last() else {
+        return false;
+    };
+    let mut target = doc;
+    for segment in parents {
+        target = match (segment, target) {
+            (Segment::Key(key), Value::Object(fields)) => {
+                if value.is_none() && !fields.contains_key(key) {
+                    return true;
+                }
+                fields
+                    .entry(key.clone())
+                    .or_insert_with(|| Value::Object(Map::new()))
+            }
+            (Segment::Index(i), Value::Array(items)) if *i < items.len() => &mut items[*i],
+            // there is nothing to remove
+            _ if value.is_none() => return true,
+            _ => return false,
+        };
+    }
+    match (last, target, value) {
+        (Segment::Key(key), Value::Object(fields), Some(value)) => {
+            fields.insert(key.clone(), value);
+        }
+        (Segment::Key(key), Value::Object(fields), None) => {
+            fields.remove(key);
+        }
+        (Segment::Index(i), Value::Array(items), Some(value)) if *i < items.len() => {
+            items[*i] = value;
+        }
+        (Segment::Index(i), Value::Array(items), Some(value)) if *i == items.len() => {
+            items.push(value);
+        }
+        (Segment::Index(i), Value::Array(items), None) if *i < items.len() => {
+            items.remove(*i);
+        }
+        (_, _, None) => {}
+        _ => return false,
+    }
+    true
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::*;
+    use crate::collection::{CollectionError, ExistingDocuments};
+    use crate::index::IndexDef;
+    use crate::path::Path;
+    use crate::query::parse_mutation;
+    use crate::schema::Schema;
+
+    fn users() -> Collection {
+        let (mut users, _) = Collection::new("users");
+        users
+            .create_index(IndexDef::hash("by_email", Path::parse("email").unwrap()).unique())
+            .unwrap();
+        users.publish();
+        users
+    }
+
+    fn apply(users: &mut Collection, source: &str) -> Result<Affected, QueryError> {
+        mutate(&parse_mutation(source).unwrap(), users)
+    }
+
+    fn doc(users: &Collection, id: &str) -> Option<Value> {
+        users.get(id).map(|doc| doc.clone())
+    }
+
+    #[test]
+    fn inserts_updates_and_deletes() {
+        let mut users = users();
+        let affected = apply(
+            &mut users,
+            "insert into users values ('a', {email: 'a@x', age: 30}), \
+             ('b', {email: 'b@x', age: 20, tags: ['x']}) returning",
+        )
+        .unwrap();
+        assert_eq!(affected.inserted, 2);
+        assert_eq!(affected.documents.unwrap()[1].0, "b");
+        assert!(!users.has_pending());
+
+        let affected = apply(
+            &mut users,
+            "update users set age = age + 1, name.first = 'B' || id(), tags[1] = 'y', email = nope \
+             where age < 25 returning",
+        )
+        .unwrap();
+        assert_eq!((affected.updated, affected.count()), (1, 1));
+        let updated = json!({"age": 21, "name": {"first": "Bb"}, "tags": ["x", "y"]});
+        assert_eq!(
+            affected.documents,
+            Some(vec![("b".into(), updated.clone())])
+        );
+        assert_eq!(doc(&users, "b"), Some(updated));
+
+        let affected = apply(
+            &mut users,
+            "upsert into users values ('b', {email: 'b@x'}), ('c', {email: 'c@x'})",
+        )
+        .unwrap();
+        assert_eq!((affected.inserted, affected.updated), (1, 1));
+        assert_eq!(affected.documents, None);
+
+        let affected = apply(&mut users, "delete from users where email != 'a@x'").unwrap();
+        assert_eq!(affected.deleted, 2);
+        assert_eq!(users.len(), 1);
+        assert_eq!(apply(&mut users, "delete from users").unwrap().deleted, 1);
+    }
+
+    #[test]
+    fn failed_statements_write_nothing() {
+        let mut users = users();
+        apply(&mut users, "insert into users values ('a', {email: 'a@x'})").unwrap();
+        let err = apply(
+            &mut users,
+            "insert into users values ('b', {email: 'b@x'}), ('a', {email: 'c@x'})",
+        )
+        .unwrap_err();
+        assert_eq!(
+            err,
+            QueryError::Write(CollectionError::Exists { id: "a".into() })
+        );
+
+        users.insert("b".into(), json!({"email": "b@x"})).unwrap();
+        let err = apply(
+            &mut users,
+            "upsert into users values ('c', {email: 'c@x'}), ('b', {email: 'b2@x'}), ('d', {email: 'a@x'})",
+        )
+        .unwrap_err();
+        assert!(matches!(
+            err,
+            QueryError::Write(CollectionError::UniqueViolation { .. })
+        ));
+        assert_eq!(users.len(), 2);
+        assert_eq!(doc(&users, "b"), Some(json!({"email": "b@x"})));
+        assert!(!users.has_pending());
+
+        for (source, message) in [
+            (
+                "insert into users values (1, {})",
+                "document ids are strings, found `1`",
+            ),
+            (
+                "insert into users values ('x', email)",
+                "`email` is not a constant value",
+            ),
+            (
+                "update users set email.domain = 'x'",
+                "can not set `email.domain` of document",
+            ),
+        ] {
+            match apply(&mut users, source) {
+                Err(QueryError::Invalid(found)) => assert!(found.starts_with(message), "{found}"),
+                other => panic!("{source}: {other:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn undoes_writes_the_schema_no_longer_accepts() {
+        let (mut users, _) = Collection::new("users");
+        users
+            .create_index(IndexDef::hash("by_k", Path::parse("k").unwrap()).unique())
+            .unwrap();
+        users.insert("a".into(), json!({"v": "s", "k": 1})).unwrap();
+        users.insert("b".into(), json!({"v": "s", "k": 2})).unwrap();
+        let schema = Schema::compile(&json!({"properties": {"v": {"type": "integer"}}})).unwrap();
+        users.set_schema(schema, ExistingDocuments::Skip).unwrap();
+        users.publish();
+
+        let err = apply(&mut users, "update users set v = 1, k = 9").unwrap_err();
+        assert!(matches!(
+            err,
+            QueryError::Write(CollectionError::UniqueViolation { .. })
+        ));
+        assert_eq!(doc(&users, "a"), Some(json!({"v": "s", "k": 1})));
+        assert_eq!(doc(&users, "b"), Some(json!({"v": "s", "k": 2})));
+        assert!(!users.has_pending());
+        // the restored keys are held again
+        apply(&mut users, "update users set v = 1 where k = 1").unwrap();
+        assert!(apply(&mut users, "update users set v = 2, k = 2 where k = 1").is_err());
+    }
+
+    #[test]
+    fn assigns_paths() {
+        let path = |p: &str| Path::parse(p).unwrap();
+        let mut doc = json!({"a": {"b": 1}, "c": [1, 2], "n": 1});
+        assert!(assign(&mut doc, path("a.d.e").segments(), Some(json!(2))));
+        assert!(assign(&mut doc, path("c[2]").segments(), Some(json!(3))));
+        assert!(assign(&mut doc, path("c[0]").segments(), None));
+        assert!(assign(&mut doc, path("a.b").segments(), None));
+        assert!(assign(&mut doc, path("x.y").segments(), None));
+        assert!(!assign(&mut doc, path("n.m").segments(), Some(json!(1))));
+        assert!(!assign(&mut doc, path("c[5]").segments(), Some(json!(1))));
+        assert_eq!(doc, json!({"a": {"d": {"e": 2}}, "c": [2, 3], "n": 1}));
+    }
+}
@@ -0,0 +1,1051 @@
+use std::ops::Range;
+
+use serde_json::Value;
+
+use super::ast::*;
+use super::lexer::{tokenize, Keyword, Spanned, Token};
+use super::{sql, SyntaxError};
+use crate::path::{Path, Segment};
+
+/// Parse a complete read query.
+pub fn parse(source: &str) -> Result<Query, SyntaxError> {
+    let mut parser = Parser::new(source)?;
+    let query = parser.query()?;
+    parser.finish()?;
+    Ok(query)
+}
+
+/// Parse a standalone expression, such as the body of a `where` clause.
+pub fn parse_expr(source: &str) -> Result<Expr, SyntaxError> {
+    let mut parser = Parser::new(source)?;
+    let expr = parser.expr()?;
+    parser.finish()?;
+    Ok(expr)
+}
+
+/// Parse an `insert`, `upsert`, `update` or `delete` statement.
+pub fn parse_mutation(source: &str) -> Result<Mutation, SyntaxError> {
+    let mut parser = Parser::new(source)?;
+    let mutation = parser.mutation()?;
+    parser.finish()?;
+    Ok(mutation)
+}
+
+/// Parse a field path such as `a.b[0]`. Unlike in an expression, the first name may be a
+/// keyword.
+pub(crate) fn parse_path(source: &str) -> Result<Path, SyntaxError> {
+    let mut parser = Parser::new(source)?;
+    if let Token::Keyword(_) = parser.peek() {
+        let word = source[parser.span()].to_owned();
+        parser.tokens[0].token = Token::Ident(word);
+    }
+    let path = parser.path()?;
+    parser.finish()?;
+    Ok(path)
+}
+
+/// The deepest expressions may nest, so that parsing and evaluating them can not overflow the
+/// stack.
+const MAX_DEPTH: usize = 64;
+
+pub(crate) struct Parser<'a> {
+    source: &'a str,
+    tokens: Vec<Spanned>,
+    pos: usize,
+    /// how many expressions are being parsed inside one another.
+    depth: usize,
+    /// parsing SQL, see [`sql`](super::sql).
+    sql: bool,
+}
+
+impl<'a> Parser<'a> {
+    pub(crate) fn new(source: &'a str) -> Result<Self, SyntaxError> {
+        Ok(Parser {
+            source,
+            tokens: tokenize(source)?,
+            pos: 0,
+            depth: 0,
+            sql: false,
+        })
+    }
+
+    /// A parser of SQL `tokens`, whose expressions may use `like` and may not hold subqueries.
+    pub(crate) fn sql(source: &'a str, tokens: Vec<Spanned>) -> Self {
+        Parser {
+            source,
+            tokens,
+            pos: 0,
+            depth: 0,
+            sql: true,
+        }
+    }
+
+    pub(crate) fn peek(&self) -> &Token {
+        &self.tokens[self.pos].token
+    }
+
+    fn peek_second(&self) -> &Token {
+        self.peek_nth(1)
+    }
+
+    /// The token `n` places after the next one.
+    pub(crate) fn peek_nth(&self, n: usize) -> &Token {
+        let next = (self.pos + n).min(self.tokens.len() - 1);
+        &self.tokens[next].token
+    }
+
+    pub(crate) fn span(&self) -> Range<usize> {
+        self.tokens[self.pos].span.clone()
+    }
+
+    pub(crate) fn bump(&mut self) -> Token {
+        let token = self.tokens[self.pos].token.clone();
+        if token != Token::Eof {
+            self.pos += 1;
+        }
+        token
+    }
+
+    pub(crate) fn eat(&mut self, token: &Token) -> bool {
+        let found = self.peek() == token;
+        if found {
+            self.bump();
+        }
+        found
+    }
+
+    pub(crate) fn eat_keyword(&mut self, keyword: Keyword) -> bool {
+        self.eat(&Token::Keyword(keyword))
+    }
+
+    /// Eat an unreserved word such as `nulls`.
+    pub(crate) fn eat_word(&mut self, word: &str) -> bool {
+        let found = matches!(self.peek(), Token::Ident(name) if name.eq_ignore_ascii_case(word));
+        if found {
+            self.bump();
+        }
+        found
+    }
+
+    pub(crate) fn error_at(&self, span: Range<usize>, message: impl Into<String>) -> SyntaxError {
+        SyntaxError::new(self.source, span, message)
+    }
+
+    /// An error pointing at the next token.
+    pub(crate) fn expected(&self, what: &str) -> SyntaxError {
+        self.error_at(
+            self.span(),
+            format!("expected {what}, found {}", self.peek()),
+        )
+    }
+
+    /// An error pointing at the next token, which starts `what`.
+    pub(crate) fn unsupported(&self, what: &str) -> SyntaxError {
+        self.error_at(self.span(), format!("{what} is not supported"))
+    }
+
+    pub(crate) fn expect(&mut self, token: Token) -> Result<(), SyntaxError> {
+        if self.eat(&token) {
+            Ok(())
+        } else {
+            Err(self.expected(&token.to_string()))
+        }
+    }
+
+    pub(crate) fn expect_keyword(&mut self, keyword: Keyword) -> Result<(), SyntaxError> {
+        self.expect(Token::Keyword(keyword))
+    }
+
+    /// Allow a trailing `;`, then require the end of input.
+    pub(crate) fn finish(&mut self) -> Result<(), SyntaxError> {
+        self.eat(&Token::Semicolon);
+        match self.peek() {
+            Token::Eof => Ok(()),
+            _ => Err(self.expected("end of query")),
+        }
+    }
+
+    /// An identifier, plain or backtick quoted.
+    pub(crate) fn name(&mut self, what: &str) -> Result<String, SyntaxError> {
+        match self.peek() {
+            Token::Ident(_) | Token::Quoted(_) => match self.bump() {
+                Token::Ident(name) | Token::Quoted(name) => Ok(name),
+                _ => unreachable!(),
+            },
+            _ => Err(self.expected(what)),
+        }
+    }
+
+    pub(crate) fn unsigned(&mut self, what: &str) -> Result<u64, SyntaxError> {
+        match self.peek() {
+            Token::Number(n) if n.as_u64().is_some() => {
+                let n = n.as_u64().unwrap();
+                self.bump();
+                Ok(n)
+            }
+            _ => Err(self.expected(what)),
+        }
+    }
+
+    pub(crate) fn query(&mut self) -> Result<Query, SyntaxError> {
+        let explain = if self.eat_word("explain") {
+            Some(if self.eat_word("analyze") {
+                Explain::Analyze
+            } else {
+                Explain::Plan
+            })
+        } else {
+            None
+        };
+        let mut with: Vec<Cte> = Vec::new();
+        if self.eat_word("with") {
+            loop {
+                let span = self.span();
+                let name = self.name("query name")?;
+                if with.iter().any(|cte| cte.name == name) {
+                    return Err(self.error_at(span, format!("`{name}` names two queries")));
+                }
+                self.expect_keyword(Keyword::As)?;
+                self.expect(Token::LParen)?;
+                let query = self.subquery()?;
+                self.expect(Token::RParen)?;
+                with.push(Cte { name, query });
+                if !self.eat(&Token::Comma) {
+                    break;
+                }
+            }
+        }
+        let mut query = self.subquery()?;
+        query.explain = explain;
+        query.with = with;
+        Ok(query)
+    }
+
+    /// A query without `explain` or `with`, as nested in another.
+    fn subquery(&mut self) -> Result<Query, SyntaxError> {
+        self.expect_keyword(Keyword::From)?;
+        let mut query = Query::new("");
+        query.from = self.source()?;
+        while self.eat_word("unnest") {
+            let path = self.path()?;
+            let alias = if self.eat_keyword(Keyword::As) {
+                Some(self.name("alias")?)
+            } else {
+                None
+            };
+            query.unnest.push(Unnest { path, alias });
+        }
+        while let Some(kind) = self.join_kind()? {
+            let source = self.source()?;
+            self.expect_keyword(Keyword::On)?;
+            let on = self.expr()?;
+            query.joins.push(Join { kind, source, on });
+        }
+        if self.eat_keyword(Keyword::Where) {
+            query.filter = Some(self.expr()?);
+        }
+        if self.eat_keyword(Keyword::Group) {
+            self.expect_keyword(Keyword::By)?;
+            query.group_by = self.comma_separated(Self::expr)?;
+        }
+        if self.eat_keyword(Keyword::Having) {
+            query.having = Some(self.expr()?);
+        }
+        if self.eat_keyword(Keyword::Select) {
+            query.select = self.select()?;
+        }
+        if self.eat_keyword(Keyword::Order) {
+            self.expect_keyword(Keyword::By)?;
+            query.order_by = self.comma_separated(Self::order_by)?;
+        }
+        if self.eat_keyword(Keyword::Limit) {
+            query.limit = Some(self.unsigned("row count")?);
+        }
+        if self.eat_keyword(Keyword::Offset) {
+            query.offset = Some(self.unsigned("row count")?);
+        }
+        Ok(query)
+    }
+
+    /// A write statement. Its words are not reserved, so they stay usable as field names.
+    pub(crate) fn mutation(&mut self) -> Result<Mutation, SyntaxError> {
+        let upsert = self.eat_word("upsert");
+        let mutation = if upsert || self.eat_word("insert") {
+            self.expect_word("into")?;
+            let collection = self.name("collection name")?;
+            self.expect_word("values")?;
+            let rows = self.comma_separated(|parser| {
+                parser.expect(Token::LParen)?;
+                let id = parser.expr()?;
+                parser.expect(Token::Comma)?;
+                let doc = parser.expr()?;
+                parser.expect(Token::RParen)?;
+                Ok((id, doc))
+            })?;
+            Mutation::Insert(Insert {
+                collection,
+                upsert,
+                rows,
+                returning: false,
+            })
+        } else if self.eat_word("update") {
+            let collection = self.name("collection name")?;
+            self.expect_word("set")?;
+            let set = self.comma_separated(|parser| {
+                let path = parser.path()?;
+                parser.expect(Token::Eq)?;
+                Ok((path, parser.expr()?))
+            })?;
+            Mutation::Update(Update {
+                collection,
+                set,
+                filter: self.filter()?,
+                returning: false,
+            })
+        } else if self.eat_word("delete") {
+            self.expect_keyword(Keyword::From)?;
+            let collection = self.name("collection name")?;
+            Mutation::Delete(Delete {
+                collection,
+                filter: self.filter()?,
+                returning: false,
+            })
+        } else {
+            return Err(self.expected("`insert`, `upsert`, `update` or `delete`"));
+        };
+        let returning = self.eat_word("returning");
+        Ok(match mutation {
+            Mutation::Insert(insert) => Mutation::Insert(Insert {
+                returning,
+                ..insert
+            }),
+            Mutation::Update(update) => Mutation::Update(Update {
+                returning,
+                ..update
+            }),
+            Mutation::Delete(delete) => Mutation::Delete(Delete {
+                returning,
+                ..delete
+            }),
+        })
+    }
+
+    fn expect_word(&mut self, word: &str) -> Result<(), SyntaxError> {
+        if self.eat_word(word) {
+            Ok(())
+        } else {
+            Err(self.expected(&format!("`{word}`")))
+        }
+    }
+
+    /// An optional `where` clause.
+    fn filter(&mut self) -> Result<Option<Expr>, SyntaxError> {
+        if self.eat_keyword(Keyword::Where) {
+            Ok(Some(self.expr()?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// `collection [as alias]`.
+    fn source(&mut self) -> Result<Source, SyntaxError> {
+        let collection = self.name("collection name")?;
+        let alias = if self.eat_keyword(Keyword::As) {
+            Some(self.name("alias")?)
+        } else {
+            None
+        };
+        Ok(Source { collection, alias })
+    }
+
+    /// `join`, `inner join`, `left join` or `left outer join`. Only `join` is reserved.
+    pub(crate) fn join_kind(&mut self) -> Result<Option<JoinKind>, SyntaxError> {
+        let kind = if self.eat_word("left") {
+            self.eat_word("outer");
+            JoinKind::Left
+        } else if self.eat_word("inner") || self.peek() == &Token::Keyword(Keyword::Join) {
+            JoinKind::Inner
+        } else {
+            return Ok(None);
+        };
+        self.expect_keyword(Keyword::Join)?;
+        Ok(Some(kind))
+    }
+
+    pub(crate) fn comma_separated<T>(
+        &mut self,
+        mut item: impl FnMut(&mut Self) -> Result<T, SyntaxError>,
+    ) -> Result<Vec<T>, SyntaxError> {
+        let mut items = vec![item(self)?];
+        while self.eat(&Token::Comma) {
+            items.push(item(self)?);
+        }
+        Ok(items)
+    }
+
+    fn select(&mut self) -> Result<Select, SyntaxError> {
+        if self.eat(&Token::Star) {
+            return Ok(Select::All);
+        }
+        let items = self.comma_separated(|parser| {
+            let expr = parser.expr()?;
+            let alias = if parser.eat_keyword(Keyword::As) {
+                Some(parser.name("alias")?)
+            } else {
+                None
+            };
+            Ok(SelectItem { expr, alias })
+        })?;
+        Ok(Select::Fields(items))
+    }
+
+    pub(crate) fn order_by(&mut self) -> Result<OrderBy, SyntaxError> {
+        let expr = self.expr()?;
+        let descending = if self.eat_keyword(Keyword::Desc) {
+            true
+        } else {
+            self.eat_keyword(Keyword::Asc);
+            false
+        };
+        let nulls = if self.eat_word("nulls") {
+            if self.eat_word("first") {
+                Some(Nulls::First)
+            } else if self.eat_word("last") {
+                Some(Nulls::Last)
+            } else {
+                return Err(self.expected("`first` or `last`"));
+            }
+        } else {
+            None
+        };
+        Ok(OrderBy {
+            expr,
+            descending,
+            nulls,
+        })
+    }
+
+    pub(crate) fn expr(&mut self) -> Result<Expr, SyntaxError> {
+        self.expr_with(0)
+    }
+
+    /// Parse an expression whose operators bind at least as tight as `min`, failing past
+    /// [`MAX_DEPTH`] nested expressions.
+    fn expr_with(&mut self, min: u8) -> Result<Expr, SyntaxError> {
+        if self.depth == MAX_DEPTH {
+            return Err(self.error_at(self.span(), "expression nested too deeply"));
+        }
+        self.depth += 1;
+        let expr = self.climb(min);
+        self.depth -= 1;
+        expr
+    }
+
+    /// Precedence climbing, for [`expr_with`](Self::expr_with).
+    fn climb(&mut self, min: u8) -> Result<Expr, SyntaxError> {
+        let mut left = if self.peek() == &Token::Keyword(Keyword::Not) {
+            if min > NOT_PRECEDENCE {
+                return Err(self.error_at(
+                    self.span(),
+                    "`not` needs parentheses here, as in `(not ...)`",
+                ));
+            }
+            self.bump();
+            !self.expr_with(NOT_PRECEDENCE)?
+        } else {
+            self.prefix()?
+        };
+        loop {
+            if let Some(op) = self.binary_op() {
+                if op.precedence() < min {
+                    break;
+                }
+                self.bump();
+                let right = self.expr_with(op.precedence() + 1)?;
+                left = Expr::binary(left, op, right);
+                continue;
+            }
+            if COMPARISON_PRECEDENCE < min {
+                break;
+            }
+            let negated = self.peek() == &Token::Keyword(Keyword::Not)
+                && (matches!(
+                    self.peek_second(),
+                    Token::Keyword(Keyword::In | Keyword::Between)
+                ) || self.like_at(1).is_some());
+            if negated {
+                self.bump();
+            }
+            let operand = COMPARISON_PRECEDENCE + 1;
+            if let Some(insensitive) = self.like_at(0) {
+                self.bump();
+                let span = self.span();
+                let pattern = match self.expr_with(operand)? {
+                    Expr::Literal(Value::String(pattern)) => pattern,
+                    _ => {
+                        return Err(self.error_at(
+                            span.start..self.span().start,
+                            "`like` with a pattern that is not a string is not supported",
+                        ))
+                    }
+                };
+                if matches!(self.peek(), Token::Ident(word) if word.eq_ignore_ascii_case("escape"))
+                {
+                    return Err(self.unsupported("`escape`"));
+                }
+                let like = sql::like(left, &pattern, insensitive);
+                left = if negated { !like } else { like };
+                continue;
+            }
+            left = match self.peek() {
+                Token::Keyword(Keyword::In) => {
+                    self.bump();
+                    self.expect(Token::LParen)?;
+                    if !self.sql && self.peek() == &Token::Keyword(Keyword::From) {
+                        let query = self.subquery()?;
+                        self.expect(Token::RParen)?;
+                        left = Expr::InSubquery {
+                            expr: Box::new(left),
+                            query: Box::new(query),
+                            negated,
+                        };
+                        continue;
+                    }
+                    let list = if self.peek() == &Token::RParen {
+                        Vec::new()
+                    } else {
+                        self.comma_separated(Self::expr)?
+                    };
+                    self.expect(Token::RParen)?;
+                    Expr::In {
+                        expr: Box::new(left),
+                        list,
+                        negated,
+                    }
+                }
+                Token::Keyword(Keyword::Between) => {
+                    self.bump();
+                    let low = self.expr_with(operand)?;
+                    self.expect_keyword(Keyword::And)?;
+                    let high = self.expr_with(operand)?;
+                    Expr::Between {
+                        expr: Box::new(left),
+                        low: Box::new(low),
+                        high: Box::new(high),
+                        negated,
+                    }
+                }
+                Token::Keyword(Keyword::Is) => {
+                    self.bump();
+                    let negated = self.eat_keyword(Keyword::Not);
+                    self.expect_keyword(Keyword::Null)?;
+                    Expr::IsNull {
+                        expr: Box::new(left),
+                        negated,
+                    }
+                }
+                _ => break,
+            };
+        }
+        Ok(left)
+    }
+
+    /// Whether the token `n` places ahead is SQL `like`, or `ilike` if it returns true.
+    fn like_at(&self, n: usize) -> Option<bool> {
+        match self.peek_nth(n) {
+            Token::Ident(word) if self.sql && word.eq_ignore_ascii_case("like") => Some(false),
+            Token::Ident(word) if self.sql && word.eq_ignore_ascii_case("ilike") => Some(true),
+            _ => None,
+        }
+    }
+
+    fn binary_op(&self) -> Option<BinaryOp> {
+        Some(match self.peek() {
+            Token::Keyword(Keyword::Or) => BinaryOp::Or,
+            Token::Keyword(Keyword::And) => BinaryOp::And,
+            Token::Eq => BinaryOp::Eq,
+            Token::Ne => BinaryOp::Ne,
+            Token::Lt => BinaryOp::Lt,
+            Token::Le => BinaryOp::Le,
+            Token::Gt => BinaryOp::Gt,
+            Token::Ge => BinaryOp::Ge,
+            Token::Plus => BinaryOp::Add,
+            Token::Minus => BinaryOp::Sub,
+            Token::Star => BinaryOp::Mul,
+            Token::Slash => BinaryOp::Div,
+            Token::Percent => BinaryOp::Rem,
+            Token::Concat => BinaryOp::Concat,
+            _ => return None,
+        })
+    }
+
+    fn prefix(&mut self) -> Result<Expr, SyntaxError> {
+        if !self.eat(&Token::Minus) {
+            return self.postfix();
+        }
+        let expr = self.expr_with(PREFIX_PRECEDENCE)?;
+        // fold negative number literals
+        if let Expr::Literal(Value::Number(n)) = &expr {
+            let negated = if let Some(i) = n.as_i64().filter(|i| *i != 0) {
+                Some(Value::from(-i))
+            } else {
+                n.as_f64()
+                    .and_then(|f| serde_json::Number::from_f64(-f))
+                    .map(Value::Number)
+            };
+            if let Some(value) = negated {
+                return Ok(Expr::Literal(value));
+            }
+        }
+        Ok(Expr::Unary {
+            op: UnaryOp::Neg,
+            expr: Box::new(expr),
+        })
+    }
+
+    /// A primary expression followed by any number of `collate <name>`.
+    fn postfix(&mut self) -> Result<Expr, SyntaxError> {
+        let mut expr = self.primary()?;
+        if self.sql
+            && matches!(self.peek(), Token::Ident(word) if word.eq_ignore_ascii_case("over"))
+        {
+            return Err(self.unsupported("a window function"));
+        }
+        while self.eat_word("collate") {
+            let collation = match self.peek() {
+                Token::Ident(name) => Collation::lookup(name),
+                _ => None,
+            }
+            .ok_or_else(|| self.expected("`binary`, `nfc` or `nocase`"))?;
+            self.bump();
+            expr = Expr::Collate {
+                expr: Box::new(expr),
+                collation,
+            };
+        }
+        Ok(expr)
+    }
+
+    fn primary(&mut self) -> Result<Expr, SyntaxError> {
+        let expr = match self.peek().clone() {
+            Token::Number(n) => {
+                self.bump();
+                Expr::Literal(Value::Number(n))
+            }
+            Token::String(s) => {
+                self.bump();
+                Expr::Literal(Value::String(s))
+            }
+            Token::Param(name) => {
+                self.bump();
+                Expr::Param(name)
+            }
+            Token::Keyword(Keyword::True) => {
+                self.bump();
+                Expr::Literal(Value::Bool(true))
+            }
+            Token::Keyword(Keyword::False) => {
+                self.bump();
+                Expr::Literal(Value::Bool(false))
+            }
+            Token::Keyword(Keyword::Null) => {
+                self.bump();
+                Expr::Literal(Value::Null)
+            }
+            Token::Keyword(keyword @ (Keyword::Exists | Keyword::Missing)) => {
+                self.bump();
+                self.expect(Token::LParen)?;
+                let path = self.path()?;
+                self.expect(Token::RParen)?;
+                Expr::Exists {
+                    path,
+                    negated: keyword == Keyword::Missing,
+                }
+            }
+            Token::Keyword(Keyword::Case) => {
+                self.bump();
+                let mut branches = Vec::new();
+                while self.eat_keyword(Keyword::When) {
+                    let when = self.expr()?;
+                    self.expect_keyword(Keyword::Then)?;
+                    branches.push((when, self.expr()?));
+                }
+                if branches.is_empty() {
+                    return Err(self.expected("`when`"));
+                }
+                let otherwise = if self.eat_keyword(Keyword::Else) {
+                    Some(Box::new(self.expr()?))
+                } else {
+                    None
+                };
+                self.expect_keyword(Keyword::End)?;
+                Expr::Case {
+                    branches,
+                    otherwise,
+                }
+            }
+            Token::LBracket => {
+                self.bump();
+                let items = if self.peek() == &Token::RBracket {
+                    Vec::new()
+                } else {
+                    self.comma_separated(Self::expr)?
+                };
+                self.expect(Token::RBracket)?;
+                Expr::Array(items)
+            }
+            Token::LBrace => {
+                self.bump();
+                let fields = if self.peek() == &Token::RBrace {
+                    Vec::new()
+                } else {
+                    self.comma_separated(|parser| {
+                        let key = match parser.peek().clone() {
+                            Token::Ident(key) | Token::Quoted(key) | Token::String(key) => key,
+                            Token::Keyword(keyword) => keyword.as_str().to_owned(),
+                            _ => return Err(parser.expected("field name")),
+                        };
+                        parser.bump();
+                        parser.expect(Token::Colon)?;
+                        Ok((key, parser.expr()?))
+                    })?
+                };
+                self.expect(Token::RBrace)?;
+                Expr::Object(fields)
+            }
+            Token::Keyword(Keyword::Select) if self.sql => {
+                return Err(self.unsupported("a subquery"));
+            }
+            Token::LParen if !self.sql && self.peek_second() == &Token::Keyword(Keyword::From) => {
+                self.bump();
+                let query = self.subquery()?;
+                self.expect(Token::RParen)?;
+                Expr::Subquery(Box::new(query))
+            }
+            Token::LParen => {
+                self.bump();
+                let expr = self.expr()?;
+                self.expect(Token::RParen)?;
+                expr
+            }
+            Token::Ident(name) if self.peek_second() == &Token::LParen => {
+                if let Some(func) = AggregateFunc::lookup(&name) {
+                    return self.aggregate(func);
+                }
+                if self.sql && name.eq_ignore_ascii_case("cast") {
+                    return Err(self.unsupported("`cast`"));
+                }
+                if let Some(quantifier) = Quantifier::lookup(&name) {
+                    self.bump();
+                    self.bump();
+                    let path = self.path()?;
+                    self.expect(Token::Comma)?;
+                    let predicate = Box::new(self.expr()?);
+                    self.expect(Token::RParen)?;
+                    return Ok(Expr::Quantified {
+                        quantifier,
+                        path,
+                        predicate,
+                    });
+                }
+                self.bump();
+                self.bump();
+                let args = if self.peek() == &Token::RParen {
+                    Vec::new()
+                } else {
+                    self.comma_separated(Self::expr)?
+                };
+                self.expect(Token::RParen)?;
+                Expr::Call {
+                    name: name.to_lowercase(),
+                    args,
+                }
+            }
+            Token::Ident(_) | Token::Quoted(_) | Token::Root => Expr::Path(self.path()?),
+            _ => return Err(self.expected("expression")),
+        };
+        Ok(expr)
+    }
+
+    /// `func(*)`, `func(expr)` or `func(distinct expr)`, with the name already peeked.
+    fn aggregate(&mut self, func: AggregateFunc) -> Result<Expr, SyntaxError> {
+        self.bump();
+        self.bump();
+        let star = func == AggregateFunc::Count && self.eat(&Token::Star);
+        let distinct = !star && self.eat_keyword(Keyword::Distinct);
+        let arg = if star {
+            None
+        } else {
+            Some(Box::new(self.expr()?))
+        };
+        self.expect(Token::RParen)?;
+        Ok(Expr::Aggregate {
+            func,
+            arg,
+            distinct,
+        })
+    }
+
+    /// `name`, followed by any number of `.name`, `[index]` or `["key"]`.
+    /// A path starting with an index or quoted key is written after `$`, as in `$[0].a`.
+    fn path(&mut self) -> Result<Path, SyntaxError> {
+        let mut segments = if self.eat(&Token::Root) {
+            Vec::new()
+        } else {
+            vec![Segment::Key(self.name("field name")?)]
+        };
+        loop {
+            if self.eat(&Token::Dot) {
+                let key = match self.peek().clone() {
+                    Token::Ident(name) | Token::Quoted(name) => name,
+                    Token::Keyword(keyword) => keyword.as_str().to_owned(),
+                    _ => return Err(self.expected("field name")),
+                };
+                self.bump();
+                segments.push(Segment::Key(key));
+            } else if self.eat(&Token::LBracket) {
+                let segment = match self.peek().clone() {
+                    Token::String(key) => {
+                        self.bump();
+                        Segment::Key(key)
+                    }
+                    _ => Segment::Index(self.unsigned("array index or quoted key")? as usize),
+                };
+                self.expect(Token::RBracket)?;
+                segments.push(segment);
+            } else {
+                return Ok(Path::from_segments(segments));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::*;
+
+    fn path(p: &str) -> Expr {
+        Expr::Path(Path::parse(p).unwrap())
+    }
+
+    #[test]
+    fn parses_queries() {
+        let query = parse(
+            "from users as u
+             where age >= 18 and not (status = 'banned' or missing(email))
+             select name, address.city as city, age * 2 + 1 as score
+             order by age desc nulls last, name
+             limit 10 offset 20;",
+        )
+        .unwrap();
+        assert_eq!(query.from.collection, "users");
+        assert_eq!(query.from.alias.as_deref(), Some("u"));
+        assert_eq!(
+            query.filter,
+            Some(Expr::binary(
+                Expr::binary(path("age"), BinaryOp::Ge, Expr::literal(18)),
+                BinaryOp::And,
+                !Expr::binary(
+                    Expr::binary(path("status"), BinaryOp::Eq, Expr::literal("banned")),
+                    BinaryOp::Or,
+                    Expr::Exists {
+                        path: Path::parse("email").unwrap(),
+                        negated: true
+                    }
+                )
+            ))
+        );
+        let Select::Fields(items) = &query.select else {
+            panic!("expected fields");
+        };
+        assert_eq!(items[1].expr, path("address.city"));
+        assert_eq!(items[1].alias.as_deref(), Some("city"));
+        assert_eq!(
+            items[2].expr,
+            Expr::binary(
+                Expr::binary(path("age"), BinaryOp::Mul, Expr::literal(2)),
+                BinaryOp::Add,
+                Expr::literal(1)
+            )
+        );
+        assert_eq!(query.order_by.len(), 2);
+        assert!(query.order_by[0].descending);
+        assert_eq!(query.order_by[0].nulls, Some(Nulls::Last));
+        assert_eq!((query.limit, query.offset), (Some(10), Some(20)));
+
+        assert_eq!(parse("from users").unwrap(), Query::new("users"));
+        assert_eq!(parse("FROM users SELECT *").unwrap(), Query::new("users"));
+    }
+
+    #[test]
+    fn parses_expressions() {
+        assert_eq!(
+            parse_expr("tags[0] not in (1, -2.5, 'x')").unwrap(),
+            Expr::In {
+                expr: Box::new(path("tags[0]")),
+                list: vec![Expr::literal(1), Expr::literal(-2.5), Expr::literal("x")],
+                negated: true,
+            }
+        );
+        assert_eq!(
+            parse_expr("a between 1 and 2 and b is not null").unwrap(),
+            Expr::binary(
+                Expr::Between {
+                    expr: Box::new(path("a")),
+                    low: Box::new(Expr::literal(1)),
+                    high: Box::new(Expr::literal(2)),
+                    negated: false,
+                },
+                BinaryOp::And,
+                Expr::IsNull {
+                    expr: Box::new(path("b")),
+                    negated: true
+                }
+            )
+        );
+        assert_eq!(
+            parse_expr("Lower(`first name`) = a.order[\"x y\"]").unwrap(),
+            Expr::binary(
+                Expr::Call {
+                    name: "lower".into(),
+                    args: vec![path(r#"["first name"]"#)]
+                },
+                BinaryOp::Eq,
+                path(r#"a.order["x y"]"#)
+            )
+        );
+        assert_eq!(parse_expr("- - 1").unwrap(), Expr::literal(1));
+        assert_eq!(
+            parse_expr("[1, null]").unwrap(),
+            Expr::Array(vec![Expr::literal(1), Expr::literal(json!(null))])
+        );
+    }
+
+    #[test]
+    fn display_round_trips() {
+        for source in [
+            "from users",
+            "from `my users` as u where (a or b) and not c select a.b[2] as `select`, -x order by a desc nulls first, b limit 1 offset 2",
+            "from t where a - (b - c) = (a = b) and x not between -1 and 1 + 2",
+            "from t where (not a) = b or c in () or d is null or exists(e.`f g`)",
+            "from t where a * (-1) > 1 - -1 and -(-a) < 0 and f(a, [1, 'x\"y'], 2.5e-3)",
+            "from t select a || 'x' || (b + 1) as c, case when a then 1 when not b then 2 else 3 end",
+            "from orders as o join customers as c on id(c) = o.customer left join `line items` on `line items`.order = id(o) and `line items`.n > 1 where o.total > 0",
+            "from t where x group by a.b, lower(c) having count(*) > 1 select a.b, COUNT(distinct d) + sum(e) as n, array_agg(f) order by max(g)",
+            "explain from explain where a > 1",
+            "from t where a = $a and b in ($b1, $_2) select $a + 1 as n",
+            "EXPLAIN ANALYZE from t join u on id(u) = t.u limit 2",
+            "from t select {a: 1, `b c`: [x], `select`: {}} as o",
+            "from t unnest a.b unnest `c d` as e where any(a.b, a.b.x > 1) and not all(e, e in (1, 2))",
+            "from o as o unnest o.items as i join p on id(p) = i.p select i, p",
+            "from t where any(a, all(b, b = a))",
+            "from t where a collate nocase = -b collate nfc and (-c) collate binary collate nocase > 'x' order by d collate NoCase desc",
+            "with a as (from t where x > 1), `b c` as (from a select x) from `b c` where x in (from a select x limit 1) and (from u select max(y)) not in (from v select y)",
+            "explain with w as (from t) from t as t where (from w where w.x = t.x select count(*)) > 1 select ((from u select y) + 1) as z",
+            "from t unnest $[0] as e where $[1].a[2] > 1 and exists($[0]) select $[0] as first",
+        ] {
+            let query = parse(source).unwrap();
+            let printed = query.to_string();
+            assert_eq!(parse(&printed).unwrap(), query, "{printed}");
+        }
+        let query = Query {
+            filter: Some(Expr::Binary {
+                left: Box::new(Expr::Path(Path::root().index(0).key("a"))),
+                op: BinaryOp::Gt,
+                right: Box::new(Expr::Literal(json!(1))),
+            }),
+            ..Query::new("t")
+        };
+        assert_eq!(query.to_string(), "from t where $[0].a > 1");
+        assert_eq!(parse(&query.to_string()).unwrap(), query);
+    }
+
+    #[test]
+    fn parses_mutations() {
+        for source in [
+            "insert into users values (\"u1\", {name: \"Ann\", tags: [\"a\"]}), (\"u2\", {}) returning",
+            "upsert into `my users` values (\"u\" || \"1\", {\"first name\": 1})",
+            "update users set age = age + 1, a.b[0] = null, `set` = id() where age > 1 returning",
+            "delete from users",
+            "delete from users where missing(email) returning",
+        ] {
+            let mutation = parse_mutation(source).unwrap();
+            let printed = mutation.to_string();
+            assert_eq!(parse_mutation(&printed).unwrap(), mutation, "{printed}");
+        }
+        let Mutation::Update(update) = parse_mutation("UPDATE t SET a.b = 1").unwrap() else {
+            panic!("expected an update");
+        };
+        assert_eq!(
+            update.set,
+            vec![(Path::parse("a.b").unwrap(), Expr::literal(1))]
+        );
+        assert_eq!(
+            parse_mutation("insert into t values ('a')")
+                .unwrap_err()
+                .message,
+            "expected `,`, found `)`"
+        );
+        assert_eq!(
+            parse_mutation("from t").unwrap_err().message,
+            "expected `insert`, `upsert`, `update` or `delete`, found `from`"
+        );
+        assert_eq!(
+            parse_mutation("update t where a = 1").unwrap_err().message,
+            "expected `set`, found `where`"
+        );
+    }
+
+    #[test]
+    fn syntax_errors() {
+        let source = "from users\nwhere age > and name = 'x'";
+        let err = parse(source).unwrap_err();
+        assert_eq!(err.message, "expected expression, found `and`");
+        assert_eq!((err.start.line, err.start.column), (2, 13));
+        assert_eq!(
+            err.render(source),
+            "error: expected expression, found `and`\n --> 2:13\n  |\n2 | where age > and name = 'x'\n  |             ^^^"
+        );
+
+        let err = parse("from users where a = 1 limit").unwrap_err();
+        assert_eq!(err.message, "expected row count, found end of input");
+        assert_eq!(err.start.column, 29);
+        assert!(err
+            .render("from users where a = 1 limit")
+            .ends_with("    ^"));
+
+        let err = parse("from users where a.").unwrap_err();
+        assert_eq!(err.message, "expected field name, found end of input");
+        assert_eq!(err.start.column, 20);
+
+        let err = parse("from users order age").unwrap_err();
+        assert_eq!(err.message, "expected `by`, found `age`");
+        let err = parse("from users select a b").unwrap_err();
+        assert_eq!(err.message, "expected end of query, found `b`");
+        let err = parse("where a = 1").unwrap_err();
+        assert_eq!(err.message, "expected `from`, found `where`");
+        assert!(parse("from t where a = not b").is_err());
+        let err = parse("with a as (from t), a as (from u) from a").unwrap_err();
+        assert_eq!(err.message, "`a` names two queries");
+        let err = parse("from t where x in (from u select y").unwrap_err();
+        assert_eq!(err.message, "expected `)`, found end of input");
+
+        for source in [
+            format!(
+                "from t where {}a{}",
+                "(".repeat(100_000),
+                ")".repeat(100_000)
+            ),
+            format!("from t where {}a", "not ".repeat(100_000)),
+            format!("from t where {}1", "- ".repeat(100_000)),
+            format!("from t where a in {}", "(from t where a in ".repeat(10_000)),
+        ] {
+            let err = parse(&source).unwrap_err();
+            assert_eq!(err.message, "expression nested too deeply");
+        }
+        let nested = format!("from t where {}a{}", "(".repeat(60), ")".repeat(60));
+        assert!(parse(&nested).is_ok());
+    }
+}
@@ -0,0 +1,927 @@
+//! Choosing how a query reads its documents, and describing the choice for `explain`.
+//!
+//! The `where` clause is split into its `and`-ed conjuncts, and each conjunct comparing the
+//! document id or an indexed expression with constants becomes a candidate access path:
+//!
+//! * `id() = 'a'` or `id() in (...)` gets documents by id,
+//! * `email = 'x'` or `email in (...)` looks keys up in a hash or ordered index, or a composite
+//!   index when every component is compared with `=`,
+//! * `a = 1` over the leading components of an ordered composite index scans that prefix,
+//! * `<`, `<=`, `>`, `>=` and `between` scan a range of an ordered index.
+//!
+//! Every candidate is costed with statistics read from the index itself, counting the keys it
+//! would visit but never more than the best candidate so far, and the cheapest one wins over a
+//! full scan. A query sorted by an expression an ordered index holds, with a `limit`, reads
+//! that index in key order instead of scanning, so it can stop once its page is full. Partial
+//! and text indexes are not used. The whole `where` clause is still checked
+//! on every document read, so an access path only decides how many documents are looked at.
+use std::cell::Cell;
+use std::fmt;
+use std::hash::BuildHasher;
+use std::ops::Bound;
+use std::time::Duration;
+
+use serde_json::{json, Value};
+
+use super::ast::{BinaryOp, Collation, Expr};
+use super::eval::{collation_of, eval};
+use super::join::Documents;
+use super::{Cursor, Sort};
+use crate::index::{IndexDef, IndexKey, IndexKind, KeyExpr, Number};
+use crate::path::Path;
+
+/// How the documents of the `from` collection are found.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Access {
+    Scan,
+    PrimaryKey(Vec<String>),
+    IndexEq {
+        index: String,
+        keys: Vec<IndexKey>,
+    },
+    IndexPrefix {
+        index: String,
+        prefix: Vec<IndexKey>,
+    },
+    IndexRange {
+        index: String,
+        low: IndexKey,
+        high: IndexKey,
+    },
+    /// every key of an ordered index from `from` on, or past `null` without, then the
+    /// documents it holds `null` or no key for. From `null` only those are read.
+    IndexOrder {
+        index: String,
+        from: Option<IndexKey>,
+    },
+    /// every document in id order, after `after` if given.
+    IdOrder {
+        after: Option<String>,
+    },
+}
+
+impl Access {
+    /// The documents the access path reads, with their ids.
+    pub(crate) fn rows<'m, M, S>(
+        &self,
+        map: Documents<'m, '_, M, S>,
+    ) -> Box<dyn Iterator<Item = (&'m str, &'m Value)> + 'm>
+    where
+        S: BuildHasher,
+    {
+        let index = |name: &str| map.index(name).expect("planned indexes exist");
+        match self.clone() {
+            Access::Scan => Box::new(map.iter().map(|(id, doc)| (id.as_str(), doc.as_ref()))),
+            Access::PrimaryKey(ids) => Box::new(
+                ids.into_iter()
+                    .filter_map(move |id| map.get_key_value(&id))
+                    .map(|(id, doc)| (id.as_str(), doc.as_ref())),
+            ),
+            Access::IndexEq { index: name, keys } => Box::new(
+                index(&name)
+                    .get_many(keys)
+                    .map(|(id, doc)| (id.as_str(), doc.as_ref())),
+            ),
+            Access::IndexPrefix {
+                index: name,
+                prefix,
+            } => Box::new(
+                index(&name)
+                    .prefix(&prefix)
+                    .into_iter()
+                    .flatten()
+                    .map(|(id, doc)| (id.as_str(), doc.as_ref())),
+            ),
+            Access::IndexRange { low, high, .. } if low > high => Box::new(std::iter::empty()),
+            Access::IndexRange {
+                index: name,
+                low,
+                high,
+            } => Box::new(
+                index(&name)
+                    .range(low..=high)
+                    .into_iter()
+                    .flatten()
+                    .map(|(id, doc)| (id.as_str(), doc.as_ref())),
+            ),
+            Access::IndexOrder { index: name, from } => {
+                let index = index(&name);
+                let keyed = match from {
+                    Some(IndexKey::Null) => None,
+                    from => {
+                        let start = from.map_or(Bound::Excluded(IndexKey::Null), Bound::Included);
+                        index.range((start, Bound::Unbounded))
+                    }
+                };
+                let rest = index
+                    .get(&IndexKey::Null)
+                    .chain(index.unkeyed().into_iter().flatten());
+                Box::new(
+                    keyed
+                        .into_iter()
+                        .flatten()
+                        .chain(rest)
+                        .map(|(id, doc)| (id.as_str(), doc.as_ref())),
+                )
+            }
+            Access::IdOrder { after } => {
+                let Some(order) = map.key_order() else {
+                    // a map that keeps no key order is scanned and sorted instead
+                    let mut rows: Vec<_> = map
+                        .iter()
+                        .map(|(id, doc)| (id.as_str(), doc.as_ref()))
+                        .filter(|(id, _)| after.as_deref().is_none_or(|after| *id > after))
+                        .collect();
+                    rows.sort_unstable_by_key(|(id, _)| *id);
+                    return Box::new(rows.into_iter());
+                };
+                let start =
+                    after.map_or(Bound::Unbounded, |id| Bound::Excluded(IndexKey::String(id)));
+                Box::new(
+                    order
+                        .range((start, Bound::Unbounded))
+                        .into_iter()
+                        .flatten()
+                        .map(|(id, doc)| (id.as_str(), doc.as_ref())),
+                )
+            }
+        }
+    }
+
+    /// The access path of an [ordered](Choice::ordered) choice starting at the documents
+    /// that sort with the last row before `cursor`. A `null` sort key starts past every other
+    /// key of the index, and arrays and objects start nowhere later.
+    pub(crate) fn after(self, cursor: &Cursor) -> Access {
+        if let Access::IdOrder { .. } = self {
+            return Access::IdOrder {
+                after: Some(cursor.id.clone()),
+            };
+        }
+        let key = match cursor.keys.first() {
+            Some(key @ (Value::Null | Value::Bool(_) | Value::Number(_) | Value::String(_))) => {
+                IndexKey::from_json(key)
+            }
+            _ => None,
+        };
+        let Some(key) = key else {
+            return self;
+        };
+        match self {
+            Access::IndexRange { index, low, high } => Access::IndexRange {
+                index,
+                low: low.max(key),
+                high,
+            },
+            Access::IndexOrder { index, .. } => Access::IndexOrder {
+                index,
+                from: Some(key),
+            },
+            access => access,
+        }
+    }
+
+    /// Orders access paths from the simplest, to choose among equal estimates.
+    fn rank(&self) -> (u8, &str) {
+        match self {
+            Access::PrimaryKey(_) => (0, ""),
+            Access::IndexEq { index, .. } => (1, index),
+            Access::IndexPrefix { index, .. } => (2, index),
+            Access::IndexRange { index, .. } => (3, index),
+            Access::Scan => (4, ""),
+            Access::IndexOrder { index, .. } => (5, index),
+            Access::IdOrder { .. } => (6, ""),
+        }
+    }
+
+    /// Describe the access path for `explain`, reading `collection`.
+    pub(crate) fn describe(&self, collection: &str) -> String {
+        let list = |keys: &[IndexKey]| {
+            keys.iter()
+                .map(IndexKey::to_string)
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        match self {
+            Access::Scan => format!("scan {collection}"),
+            Access::PrimaryKey(ids) => format!(
+                "get {collection} by id ({})",
+                ids.iter()
+                    .map(|id| Value::from(id.as_str()).to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            Access::IndexEq { index, keys } => {
+                format!("index lookup {collection}.{index} ({})", list(keys))
+            }
+            Access::IndexPrefix { index, prefix } => {
+                format!("index prefix scan {collection}.{index} ({})", list(prefix))
+            }
+            Access::IndexRange { index, low, high } => {
+                // the bounds a one-sided comparison gets print as `*`
+                let bound = |key: &IndexKey| match key {
+                    IndexKey::Number(n) if n.get().is_infinite() => "*".to_owned(),
+                    IndexKey::Array(items) if items.is_empty() => "*".to_owned(),
+                    key => key.to_string(),
+                };
+                format!(
+                    "index range scan {collection}.{index} [{}, {}]",
+                    bound(low),
+                    bound(high)
+                )
+            }
+            Access::IndexOrder { index, .. } => {
+                format!("index order scan {collection}.{index}")
+            }
+            Access::IdOrder { .. } => format!("id order scan {collection}"),
+        }
+    }
+}
+
+/// The access path picked for a query.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Choice {
+    pub(crate) access: Access,
+    /// the number of documents it is expected to read.
+    pub(crate) estimate: u64,
+    /// the conjuncts of the `where` clause it already narrows the documents to.
+    pub(crate) used: Vec<usize>,
+    /// it reads the documents in the order of the query's sort key, see [`in_order`].
+    pub(crate) ordered: bool,
+}
+
+impl Choice {
+    pub(crate) fn shape(&self) -> Shape {
+        let (rank, index) = self.access.rank();
+        Shape {
+            rank,
+            index: index.to_owned(),
+            estimate: self.estimate,
+        }
+    }
+}
+
+/// The kind of access path a query was given, without the values it looks up. A
+/// [prepared](super::Prepared) query keeps it to skip costing when it runs again with other
+/// parameter values.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Shape {
+    rank: u8,
+    index: String,
+    estimate: u64,
+}
+
+/// A conjunct comparing an expression over the document with constants.
+enum Restriction {
+    /// `expr = value` or `expr in (values)`.
+    Eq(Vec<Value>),
+    /// `expr >= low and expr <= high`, with either side open.
+    Range(Option<Value>, Option<Value>),
+}
+
+/// The value of `expr` if it reads nothing from the document.
+pub(crate) fn constant(expr: &Expr) -> Option<Value> {
+    let reads = expr
+        .walk(&mut |expr| match expr {
+            Expr::Path(_)
+            | Expr::Exists { .. }
+            | Expr::Quantified { .. }
+            | Expr::Aggregate { .. } => Err(()),
+            _ => Ok(()),
+        })
+        .is_err();
+    if reads {
+        return None;
+    }
+    eval(expr, &Value::Null).map(|value| value.into_owned())
+}
+
+/// `conjunct` as a restriction on the expression it compares.
+fn restriction(conjunct: &Expr) -> Option<(&Expr, Restriction)> {
+    // indexes hold raw values, which a collation does not compare by
+    if collation_of(conjunct.children()).is_some_and(|c| c != Collation::Binary) {
+        return None;
+    }
+    match conjunct {
+        Expr::Binary { left, op, right } => {
+            let (expr, op, value) = match (constant(left), constant(right)) {
+                (None, Some(value)) => (&**left, *op, value),
+                (Some(value), None) => {
+                    let flipped = match op {
+                        BinaryOp::Lt => BinaryOp::Gt,
+                        BinaryOp::Le => BinaryOp::Ge,
+                        BinaryOp::Gt => BinaryOp::Lt,
+                        BinaryOp::Ge => BinaryOp::Le,
+                        op => *op,
+                    };
+                    (&**right, flipped, value)
+                }
+                _ => return None,
+            };
+            let restriction = match op {
+                BinaryOp::Eq => Restriction::Eq(vec![value]),
+                BinaryOp::Lt | BinaryOp::Le => Restriction::Range(None, Some(value)),
+                BinaryOp::Gt | BinaryOp::Ge => Restriction::Range(Some(value), None),
+                _ => return None,
+            };
+            Some((expr, restriction))
+        }
+        Expr::In {
+            expr,
+            list,
+            negated: false,
+        } => {
+            let values = list.iter().map(constant).collect::<Option<_>>()?;
+            Some((&**expr, Restriction::Eq(values)))
+        }
+        Expr::Between {
+            expr,
+            low,
+            high,
+            negated: false,
+        } => Some((
+            &**expr,
+            Restriction::Range(Some(constant(low)?), Some(constant(high)?)),
+        )),
+        _ => None,
+    }
+}
+
+/// A rough fraction of rows a conjunct keeps, for estimates.
+pub(crate) fn selectivity(conjunct: &Expr) -> f64 {
+    match restriction(conjunct) {
+        Some((_, Restriction::Eq(values))) => (0.1 * values.len() as f64).min(1.0),
+        Some((_, Restriction::Range(..))) => 0.3,
+        None => 0.5,
+    }
+}
+
+/// The conjuncts of an `and` chain.
+pub(crate) fn conjuncts(expr: &Expr) -> Vec<&Expr> {
+    match expr {
+        Expr::Binary {
+            left,
+            op: BinaryOp::And,
+            right,
+        } => {
+            let mut all = conjuncts(left);
+            all.extend(conjuncts(right));
+            all
+        }
+        _ => vec![expr],
+    }
+}
+
+/// The RQL form of an index key expression.
+fn key_expr(key: &KeyExpr) -> Expr {
+    let call = |name: &str, inner: &KeyExpr| Expr::Call {
+        name: name.to_owned(),
+        args: vec![key_expr(inner)],
+    };
+    match key {
+        KeyExpr::Path(path) => Expr::Path(path.clone()),
+        KeyExpr::Lower(inner) => call("lower", inner),
+        KeyExpr::Upper(inner) => call("upper", inner),
+        KeyExpr::Trim(inner) => call("trim", inner),
+        KeyExpr::Length(inner) => call("length", inner),
+    }
+}
+
+/// The smallest and largest key of the type of `value`, which is where a comparison with it
+/// can be true. `None` for types `<` does not order.
+fn type_bounds(value: &Value) -> Option<(IndexKey, IndexKey)> {
+    Some(match value {
+        Value::Bool(_) => (IndexKey::Bool(false), IndexKey::Bool(true)),
+        Value::Number(_) => (
+            IndexKey::Number(Number::new(f64::NEG_INFINITY)),
+            IndexKey::Number(Number::new(f64::INFINITY)),
+        ),
+        // the largest string sorts before every array
+        Value::String(_) => (IndexKey::from(""), IndexKey::Array(Vec::new())),
+        _ => return None,
+    })
+}
+
+/// Pick the access path for a `where` clause split into `conjuncts`.
+///
+/// `relative` turns an expression over a row into one over the bare document, or returns
+/// `None` if it reads anything else; the document id is the path `id`.
+///
+/// With a `hint`, nothing is costed: the first access path of that shape is taken, or a scan
+/// if there is none, for instance because its index was dropped.
+pub(crate) fn choose<M, S>(
+    map: Documents<'_, '_, M, S>,
+    conjuncts: &[&Expr],
+    id: &Path,
+    relative: &dyn Fn(&Expr) -> Option<Expr>,
+    hint: Option<&Shape>,
+) -> Choice
+where
+    S: BuildHasher,
+{
+    let mut restrictions = Vec::new();
+    for (i, conjunct) in conjuncts.iter().enumerate() {
+        if let Some((expr, restriction)) = restriction(conjunct) {
+            if matches!(expr, Expr::Path(path) if path == id) {
+                restrictions.push((i, None, restriction));
+            } else if let Some(expr) = relative(expr) {
+                restrictions.push((i, Some(expr), restriction));
+            }
+        }
+    }
+
+    let mut best = Choice {
+        access: Access::Scan,
+        estimate: hint.map_or(map.len() as u64, |shape| shape.estimate),
+        used: Vec::new(),
+        ordered: false,
+    };
+    let consider = |best: &mut Choice, mut choice: Choice| match hint {
+        Some(shape) => {
+            if best.access == Access::Scan
+                && choice.access.rank() == (shape.rank, shape.index.as_str())
+            {
+                choice.estimate = shape.estimate;
+                *best = choice;
+            }
+        }
+        // equal estimates go to the simplest access path, then to the first index by name
+        None => {
+            if (choice.estimate, choice.access.rank()) < (best.estimate, best.access.rank()) {
+                *best = choice;
+            }
+        }
+    };
+    let cap = |best: &Choice| match hint {
+        Some(_) => 0,
+        None => best.estimate as usize + 1,
+    };
+
+    for (i, expr, restriction) in &restrictions {
+        if let (None, Restriction::Eq(values)) = (expr, restriction) {
+            let mut ids: Vec<String> = values
+                .iter()
+                .filter_map(|value| value.as_str().map(str::to_owned))
+                .collect();
+            ids.sort();
+            ids.dedup();
+            let estimate = ids
+                .iter()
+                .filter(|id| map.contains_key(id.as_str()))
+                .count();
+            consider(
+                &mut best,
+                Choice {
+                    access: Access::PrimaryKey(ids),
+                    estimate: estimate as u64,
+                    used: vec![*i],
+                    ordered: false,
+                },
+            );
+        }
+    }
+
+    let usable = |def: &&IndexDef| def.kind != IndexKind::Text && !def.is_partial();
+    for def in map.indexes().filter(usable) {
+        let Some(index) = map.index(&def.name) else {
+            continue;
+        };
+        let ordered = index.is_ordered();
+        let keys: Vec<Expr> = def.keys.iter().map(key_expr).collect();
+        let on = |key: &Expr| {
+            restrictions
+                .iter()
+                .filter(|(_, expr, _)| expr.as_ref() == Some(key))
+                .collect::<Vec<_>>()
+        };
+
+        if let [key] = keys.as_slice() {
+            let (mut low, mut high, mut used) = (None, None, Vec::new());
+            for (i, _, restriction) in on(key) {
+                match restriction {
+                    Restriction::Eq(values) => {
+                        let Some(mut keys) = values
+                            .iter()
+                            .map(IndexKey::from_json)
+                            .collect::<Option<Vec<_>>>()
+                        else {
+                            continue;
+                        };
+                        keys.sort();
+                        keys.dedup();
+                        let estimate = keys.iter().map(|key| index.count(key) as u64).sum();
+                        consider(
+                            &mut best,
+                            Choice {
+                                access: Access::IndexEq {
+                                    index: def.name.clone(),
+                                    keys,
+                                },
+                                estimate,
+                                used: vec![*i],
+                                ordered: false,
+                            },
+                        );
+                    }
+                    Restriction::Range(from, to) if ordered => {
+                        let Some((min, max)) = from.as_ref().or(to.as_ref()).and_then(type_bounds)
+                        else {
+                            continue;
+                        };
+                        let from = from.as_ref().map_or(Some(min), IndexKey::from_json);
+                        let to = to.as_ref().map_or(Some(max), IndexKey::from_json);
+                        let (Some(from), Some(to)) = (from, to) else {
+                            continue;
+                        };
+                        // several ranges over the same key intersect
+                        low = low.max(Some(from));
+                        high = Some(high.map_or(to.clone(), |high: IndexKey| high.min(to)));
+                        used.push(*i);
+                    }
+                    Restriction::Range(..) => {}
+                }
+            }
+            if let (Some(low), Some(high)) = (low, high) {
+                let estimate = if low > high {
+                    0
+                } else {
+                    let cap = cap(&best);
+                    index
+                        .range(low.clone()..=high.clone())
+                        .map_or(0, |rows| rows.take(cap).count())
+                };
+                consider(
+                    &mut best,
+                    Choice {
+                        access: Access::IndexRange {
+                            index: def.name.clone(),
+                            low,
+                            high,
+                        },
+                        estimate: estimate as u64,
+                        used,
+                        ordered: false,
+                    },
+                );
+            }
+            continue;
+        }
+
+        // a composite index, usable with `=` on its leading components
+        let mut prefix = Vec::new();
+        let mut used = Vec::new();
+        for key in &keys {
+            let found = on(key)
+                .into_iter()
+                .find_map(|(i, _, restriction)| match restriction {
+                    Restriction::Eq(values) if values.len() == 1 => {
+                        Some((*i, IndexKey::from_json(&values[0])?))
+                    }
+                    _ => None,
+                });
+            let Some((i, component)) = found else {
+                break;
+            };
+            prefix.push(component);
+            used.push(i);
+        }
+        if prefix.len() == keys.len() {
+            let key = IndexKey::Array(prefix);
+            consider(
+                &mut best,
+                Choice {
+                    estimate: index.count(&key) as u64,
+                    access: Access::IndexEq {
+                        index: def.name.clone(),
+                        keys: vec![key],
+                    },
+                    used,
+                    ordered: false,
+                },
+            );
+        } else if !prefix.is_empty() && ordered {
+            let cap = cap(&best);
+            let estimate = index
+                .prefix(&prefix)
+                .map_or(0, |rows| rows.take(cap).count());
+            consider(
+                &mut best,
+                Choice {
+                    access: Access::IndexPrefix {
+                        index: def.name.clone(),
+                        prefix,
+                    },
+                    estimate: estimate as u64,
+                    used,
+                    ordered: false,
+                },
+            );
+        }
+    }
+    best
+}
+
+/// `choice` reading the documents in the order of `sort` where it can. Rows sorted by id
+/// alone are read in the key order of the collection instead of a scan, if it keeps one.
+/// Rows sorted by one key ascending use an ordered index on it: the range of that index the
+/// choice already reads, or the whole index instead of a scan. Documents the index holds no
+/// key other than `null` for are read after it, as they sort after all of its other keys.
+pub(crate) fn in_order<M, S>(
+    map: Documents<'_, '_, M, S>,
+    mut choice: Choice,
+    sort: &Sort,
+) -> Choice
+where
+    S: BuildHasher,
+{
+    if sort.is_empty() {
+        if choice.access == Access::Scan && map.key_order().is_some() {
+            choice.access = Access::IdOrder { after: None };
+            choice.ordered = true;
+        }
+        return choice;
+    }
+    let Some(key) = sort.index_order() else {
+        return choice;
+    };
+    let on_key = |def: &IndexDef| {
+        def.kind == IndexKind::Ordered
+            && !def.is_partial()
+            && matches!(def.keys.as_slice(), [only] if key_expr(only) == *key)
+    };
+    match &choice.access {
+        Access::IndexRange { index, .. } => {
+            choice.ordered = map.indexes().any(|def| &def.name == index && on_key(def));
+        }
+        Access::Scan => {
+            if let Some(def) = map.indexes().find(|def| on_key(def)) {
+                choice.access = Access::IndexOrder {
+                    index: def.name.clone(),
+                    from: None,
+                };
+                choice.ordered = true;
+            }
+        }
+        _ => {}
+    }
+    choice
+}
+
+/// Rows and time spent in one operator of a running query, for `explain analyze`.
+#[derive(Debug, Default)]
+pub(crate) struct Counter {
+    rows: Cell<u64>,
+    time: Cell<Duration>,
+}
+
+impl Counter {
+    pub(crate) fn add(&self, rows: u64, time: Duration) {
+        self.rows.set(self.rows.get() + rows);
+        self.time.set(self.time.get() + time);
+    }
+}
+
+/// The counters of every operator of a query.
+#[derive(Debug, Default)]
+pub(crate) struct Profile {
+    pub(crate) access: Counter,
+    pub(crate) unnest: Vec<Counter>,
+    pub(crate) joins: Vec<Counter>,
+    pub(crate) filter: Counter,
+    pub(crate) aggregate: Counter,
+    pub(crate) having: Counter,
+    pub(crate) sort: Counter,
+    pub(crate) limit: Counter,
+    pub(crate) project: Counter,
+}
+
+impl Profile {
+    pub(crate) fn new(unnest: usize, joins: usize) -> Self {
+        Profile {
+            unnest: (0..unnest).map(|_| Counter::default()).collect(),
+            joins: (0..joins).map(|_| Counter::default()).collect(),
+            ..Profile::default()
+        }
+    }
+}
+
+/// An iterator that counts its items and the time spent producing them, including the time
+/// spent in the iterators it pulls from.
+pub(crate) struct Measured<'c, I> {
+    iter: I,
+    counter: Option<&'c Counter>,
+}
+
+impl<'c, I> Measured<'c, I> {
+    pub(crate) fn new(iter: I, counter: Option<&'c Counter>) -> Self {
+        Measured { iter, counter }
+    }
+}
+
+impl<I: Iterator> Iterator for Measured<'_, I> {
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let Some(counter) = self.counter else {
+            return self.iter.next();
+        };
+        let start = std::time::Instant::now();
+        let item = self.iter.next();
+        counter.add(item.is_some() as u64, start.elapsed());
+        item
+    }
+}
+
+/// One operator of a query plan, as returned by [`explain`](super::explain).
+///
+/// Rows flow from the children up to their parent. Times include the time spent in the
+/// children.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Plan {
+    pub operator: String,
+    pub estimated_rows: u64,
+    /// rows produced, only known after `explain analyze`.
+    pub actual_rows: Option<u64>,
+    /// time spent producing the rows, only known after `explain analyze`.
+    pub elapsed: Option<Duration>,
+    pub children: Vec<Plan>,
+}
+
+impl Plan {
+    pub(crate) fn leaf(operator: impl Into<String>, estimated_rows: u64) -> Self {
+        Plan {
+            operator: operator.into(),
+            estimated_rows,
+            actual_rows: None,
+            elapsed: None,
+            children: Vec::new(),
+        }
+    }
+
+    /// A new operator reading from this one.
+    pub(crate) fn above(self, operator: impl Into<String>, estimated_rows: u64) -> Self {
+        Plan {
+            children: vec![self],
+            ..Plan::leaf(operator, estimated_rows)
+        }
+    }
+
+    /// Fill in the actual rows and time from `counter`.
+    pub(crate) fn measured(mut self, counter: Option<&Counter>) -> Self {
+        if let Some(counter) = counter {
+            self.actual_rows = Some(counter.rows.get());
+            self.elapsed = Some(counter.time.get());
+        }
+        self
+    }
+
+    pub fn to_json(&self) -> Value {
+        let mut plan = json!({
+            "operator": self.operator,
+            "estimated_rows": self.estimated_rows,
+        });
+        if let Some(rows) = self.actual_rows {
+            plan["actual_rows"] = json!(rows);
+        }
+        if let Some(elapsed) = self.elapsed {
+            plan["time_ms"] = json!(elapsed.as_secs_f64() * 1000.0);
+        }
+        if !self.children.is_empty() {
+            plan["children"] = self.children.iter().map(Plan::to_json).collect();
+        }
+        plan
+    }
+
+    fn write(&self, f: &mut fmt::Formatter<'_>, depth: usize) -> fmt::Result {
+        if depth > 0 {
+            write!(f, "\n{}-> ", "   ".repeat(depth - 1))?;
+        }
+        write!(f, "{} (rows={})", self.operator, self.estimated_rows)?;
+        if let (Some(rows), Some(elapsed)) = (self.actual_rows, self.elapsed) {
+            write!(
+                f,
+                " (actual rows={rows} time={:.3}ms)",
+                elapsed.as_secs_f64() * 1000.0
+            )?;
+        }
+        for child in &self.children {
+            child.write(f, depth + 1)?;
+        }
+        Ok(())
+    }
+}
+
+/// The plan as an indented tree, one operator per line:
+///
+/// ```
+/// # use rql_core::collection::Collection;
+/// # use rql_core::index::IndexDef;
+/// # use rql_core::path::Path;
+/// # use rql_core::query::{explain, parse};
+/// # use serde_json::json;
+/// let (mut users, reader) = Collection::new("users");
+/// for (id, age) in [("u1", 25), ("u2", 35), ("u3", 41)] {
+///     users.insert(id.into(), json!({"name": id, "age": age})).unwrap();
+/// }
+/// users.create_index(IndexDef::ordered("by_age", Path::parse("age").unwrap())).unwrap();
+/// users.publish();
+///
+/// let query = parse("from users where age > 30 and name != 'x' select name order by name");
+/// let plan = explain(&query.unwrap(), &reader.enter().unwrap(), false).unwrap();
+/// assert_eq!(
+///     plan.to_string(),
+///     "select name (rows=1)
+/// -> sort by name (rows=1)
+///    -> filter age > 30 and name != \"x\" (rows=1)
+///       -> index range scan users.by_age [30, *] (rows=2)"
+/// );
+/// ```
+impl fmt::Display for Plan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.write(f, 0)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::*;
+    use crate::query::parse_expr;
+    use crate::rwmap::RwMap;
+
+    #[test]
+    fn chooses_cheapest_access() {
+        let (mut w, r) = RwMap::default::<String, Value>();
+        for i in 0..100 {
+            let doc =
+                json!({"age": i % 50, "team": i % 2, "name": format!("n{i}"), "kind": "user"});
+            w.insert(format!("{i}"), doc);
+        }
+        let path = |p: &str| Path::parse(p).unwrap();
+        w.create_index(IndexDef::ordered("by_age", path("age")));
+        w.create_index(IndexDef::hash("by_team", path("team")));
+        w.create_index(IndexDef::hash("by_kind", path("kind")));
+        w.create_index(IndexDef::ordered("by_team_age", path("team")).and(path("age")));
+        w.publish();
+        let map = r.enter().unwrap();
+        let id = Path::root().key("#id");
+        let choose = |filter: &str| {
+            let filter = parse_expr(filter).unwrap();
+            let conjuncts = conjuncts(&filter);
+            let choice = choose(&map, &conjuncts, &id, &|expr| Some(expr.clone()), None);
+            (choice.access.describe("t"), choice.estimate)
+        };
+
+        assert_eq!(choose("name = 'x'"), ("scan t".into(), 100));
+        assert_eq!(
+            choose("`#id` in ('1', '2', 'nope') and team = 1"),
+            ("get t by id (\"1\", \"2\", \"nope\")".into(), 2)
+        );
+        assert_eq!(
+            choose("age in (3, 4.0, 3) and kind = 'user'"),
+            ("index lookup t.by_age (3, 4)".into(), 4)
+        );
+        assert_eq!(
+            choose("age >= 45 and 48 > age"),
+            ("index range scan t.by_age [45, 48]".into(), 8)
+        );
+        assert_eq!(
+            choose("age between 'a' and 'z'"),
+            ("index range scan t.by_age [\"a\", \"z\"]".into(), 0)
+        );
+        assert_eq!(
+            choose("team = 0 and age = 7"),
+            ("index lookup t.by_team_age ([0,7])".into(), 0)
+        );
+        assert_eq!(choose("age > 5 and age < 'a'").1, 0);
+        // the composite prefix visits as many documents as the plain index, which came first
+        assert_eq!(
+            choose("team = 0 and kind = 'user'"),
+            ("index lookup t.by_team (0)".into(), 50)
+        );
+        assert_eq!(choose("not (age = 3) or kind = 'user'").0, "scan t");
+    }
+
+    #[test]
+    fn reads_ids_in_order_with_or_without_key_order() {
+        for key_order in [true, false] {
+            let (mut w, r) = RwMap::default::<String, Value>();
+            if key_order {
+                w.order_keys();
+            }
+            for id in ["c", "a", "d", "b"] {
+                w.insert(id.into(), json!({}));
+            }
+            w.publish();
+            let map = r.enter().unwrap();
+            let ids = |after: Option<&str>| {
+                let access = Access::IdOrder {
+                    after: after.map(str::to_owned),
+                };
+                access.rows(&map).map(|(id, _)| id).collect::<Vec<_>>()
+            };
+            assert_eq!(ids(None), ["a", "b", "c", "d"]);
+            assert_eq!(ids(Some("b")), ["c", "d"]);
+        }
+    }
+}
@@ -0,0 +1,842 @@
+//! Prepared queries with `$name` parameters.
+//!
+//! A query is parsed and compiled once by [`Prepared::new`], then run any number of times
+//! with [`Params`], which only puts their values into the compiled query. Values are placed
+//! into it as JSON values and never spliced into query text, so whatever a value holds it can
+//! only ever be compared as a value.
+//!
+//! Each parameter gets the types it may hold from where it is used: a number for arithmetic,
+//! a boolean for `and`, `or` and `not`, a string for `id()`, the type of the literal it is
+//! compared with, or the type the collection's schema declares for the path it is compared
+//! with. A parameter ordered against nothing of known type, as in `age > $min` without a
+//! schema, takes any scalar, and one used nowhere that tells its type accepts any value.
+//!
+//! The first run also picks the access path, which later runs reuse without costing. A
+//! [`PlanCache`] keeps prepared queries by their text and prepares them again once an index or
+//! schema of a collection they read changes.
+use std::convert::Infallible;
+use std::hash::BuildHasher;
+use std::sync::{Arc, Mutex};
+
+use hashbrown::HashMap;
+use serde_json::Value;
+
+use super::ast::{BinaryOp, Expr, Query, UnaryOp, Unnest};
+use super::exec::{self, Compiled};
+use super::join::{Documents, IDS};
+use super::plan::Shape;
+use super::subquery;
+use super::{eval, func};
+use super::{parse, Budget, Cursor, Limits, Page, QueryError, Row};
+use crate::path::{Path, Segment};
+use crate::rwmap::MapReadRef;
+use crate::schema::JsonType;
+
+/// Values for the `$name` parameters of a [`Prepared`] query.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Params(HashMap<String, Value>);
+
+impl Params {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set `$name` to `value`.
+    pub fn set(mut self, name: impl Into<String>, value: impl Into<Value>) -> Self {
+        self.0.insert(name.into(), value.into());
+        self
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        self.0.get(name)
+    }
+}
+
+impl<K: Into<String>, V: Into<Value>> FromIterator<(K, V)> for Params {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        Params(
+            iter.into_iter()
+                .map(|(name, value)| (name.into(), value.into()))
+                .collect(),
+        )
+    }
+}
+
+/// A parameter of a prepared query.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Param {
+    pub name: String,
+    /// the types its value may have, `None` for any.
+    pub types: Option<Vec<JsonType>>,
+}
+
+/// A query parsed and checked once, to run many times with different [`Params`].
+#[derive(Debug)]
+pub struct Prepared {
+    query: Query,
+    params: Vec<Param>,
+    /// the query compiled with its parameters in place, unless it explains or nests queries.
+    compiled: Option<Compiled>,
+    /// the access path the first run picked.
+    shape: Mutex<Option<Shape>>,
+}
+
+impl Prepared {
+    /// Check `query` and find the types of its parameters from how they are used.
+    pub fn new(query: Query) -> Result<Self, QueryError> {
+        Self::with_schemas(query, &|_, _| None)
+    }
+
+    /// Like [`new`](Self::new), also typing parameters compared with a path by the types
+    /// `schema` returns for that path in a collection.
+    pub(crate) fn with_schemas(
+        query: Query,
+        schema: &dyn Fn(&str, &Path) -> Option<Vec<JsonType>>,
+    ) -> Result<Self, QueryError> {
+        let mut params = Vec::new();
+        let mut inference = Inference {
+            query: &query,
+            schema,
+            params: &mut params,
+            shadowed: query.unnest.iter().map(Unnest::target).collect(),
+        };
+        // list the parameters in source order before typing them; those of `with` queries
+        // and subqueries keep any type
+        each_param(&query, &mut |name| inference.require(name, None))?;
+        query
+            .clone()
+            .try_map_exprs(|expr| inference.visit(expr).map(|()| expr.clone()))?;
+
+        // the access path of a query over subquery results depends on what they return, so
+        // such queries are compiled on each run; checking them with every parameter set to
+        // null finds the errors that do not depend on their values
+        let compiled = if query.explain.is_some() || subquery::nests(&query) {
+            let nulls = Params(
+                params
+                    .iter()
+                    .map(|param| (param.name.clone(), Value::Null))
+                    .collect(),
+            );
+            let checked = substitute(&query, &nulls);
+            for cte in &checked.with {
+                Compiled::new(&subquery::stubbed(&cte.query))?;
+            }
+            Compiled::new(&subquery::stubbed(&checked))?;
+            None
+        } else {
+            Some(Compiled::prepared(&query)?)
+        };
+        Ok(Prepared {
+            query,
+            params,
+            compiled,
+            shape: Mutex::new(None),
+        })
+    }
+
+    /// Parse and prepare `source`.
+    pub fn parse(source: &str) -> Result<Self, QueryError> {
+        Self::new(parse(source)?)
+    }
+
+    /// The query as written, with its parameters.
+    pub fn query(&self) -> &Query {
+        &self.query
+    }
+
+    /// The parameters in order of first use.
+    pub fn params(&self) -> &[Param] {
+        &self.params
+    }
+
+    /// The query with `params` in place of its parameters, checking that each parameter gets
+    /// exactly one value of a type it allows.
+    pub fn bind(&self, params: &Params) -> Result<Query, QueryError> {
+        self.check(params)?;
+        Ok(substitute(&self.query, params))
+    }
+
+    /// Check that each parameter gets exactly one value of a type it allows.
+    fn check(&self, params: &Params) -> Result<(), QueryError> {
+        if let Some(name) = params
+            .0
+            .keys()
+            .find(|name| !self.params.iter().any(|param| &param.name == *name))
+        {
+            return Err(QueryError::UnknownParam(name.clone()));
+        }
+        for param in &self.params {
+            let value = params
+                .get(&param.name)
+                .ok_or_else(|| QueryError::MissingParam(param.name.clone()))?;
+            if let Some(types) = &param.types {
+                if !types.iter().any(|ty| ty.matches(value)) {
+                    return Err(QueryError::ParamType {
+                        name: param.name.clone(),
+                        expected: types.clone(),
+                        found: JsonType::of(value),
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Run the query with `params` against the documents visible through `map`, see
+    /// [`execute`](super::execute).
+    pub fn execute<'m, M, S>(
+        &self,
+        map: &'m MapReadRef<'_, String, Value, M, S>,
+        params: &Params,
+    ) -> Result<Vec<Row<'m>>, QueryError>
+    where
+        M: Clone + Default + 'static,
+        S: BuildHasher + Clone + Default,
+    {
+        Ok(self.execute_page(map, params, None)?.rows)
+    }
+
+    /// Run the query with `params` starting after `cursor`, see
+    /// [`execute_page`](super::execute_page).
+    pub fn execute_page<'m, M, S>(
+        &self,
+        map: &'m MapReadRef<'_, String, Value, M, S>,
+        params: &Params,
+        cursor: Option<&Cursor>,
+    ) -> Result<Page<'m>, QueryError>
+    where
+        M: Clone + Default + 'static,
+        S: BuildHasher + Clone + Default,
+    {
+        self.execute_page_limited(map, params, cursor, &Limits::new())
+    }
+
+    /// [`execute_page`](Self::execute_page) within `limits`, see
+    /// [`execute_page_limited`](super::execute_page_limited).
+    pub fn execute_page_limited<'m, M, S>(
+        &self,
+        map: &'m MapReadRef<'_, String, Value, M, S>,
+        params: &Params,
+        cursor: Option<&Cursor>,
+        limits: &Limits,
+    ) -> Result<Page<'m>, QueryError>
+    where
+        M: Clone + Default + 'static,
+        S: BuildHasher + Clone + Default,
+    {
+        let lookup = exec::alone(&self.query, map);
+        self.run(&lookup, params, cursor, &Budget::new(limits))
+    }
+
+    /// Run the query with `params` on the collections found by `lookup`, within `budget`.
+    pub(crate) fn run<'m, 'g, M, S>(
+        &self,
+        lookup: &dyn Fn(&str) -> Option<Documents<'m, 'g, M, S>>,
+        params: &Params,
+        cursor: Option<&Cursor>,
+        budget: &Budget,
+    ) -> Result<Page<'m>, QueryError>
+    where
+        M: Clone + Default + 'static,
+        S: BuildHasher + Clone + Default,
+    {
+        let Some(compiled) = &self.compiled else {
+            return exec::run(&self.bind(params)?, lookup, cursor, budget);
+        };
+        self.check(params)?;
+        let now = func::now();
+        let compiled = compiled.bind(|expr| bind_expr(expr, params, &now))?;
+        let hint = self.shape.lock().unwrap().clone();
+        let physical = compiled.physical(lookup, hint.as_ref(), budget)?;
+        if hint.is_none() {
+            *self.shape.lock().unwrap() = Some(physical.shape());
+        }
+        compiled.execute(&physical, cursor, None, budget)
+    }
+}
+
+/// `query` with every parameter replaced by its value in `params`, also in its `with` queries
+/// and subqueries; parameters without a value are left in place.
+fn substitute(query: &Query, params: &Params) -> Query {
+    let bound = query
+        .clone()
+        .try_map_exprs(|expr| Ok::<_, Infallible>(substitute_expr(expr, params)));
+    let mut bound = match bound {
+        Ok(query) => query,
+        Err(never) => match never {},
+    };
+    for cte in &mut bound.with {
+        cte.query = substitute(&cte.query, params);
+    }
+    bound
+}
+
+/// `expr` of a compiled query with the values of `params` in place of its parameters and
+/// `now()` fixed to `now`, checking the calls that get a parameter value as argument.
+fn bind_expr(expr: &Expr, params: &Params, now: &Value) -> Result<Expr, QueryError> {
+    let bound = func::fix_now(&substitute_expr(expr, params), now);
+    if eval::bound(expr).is_err() {
+        bound.walk(&mut |expr| match expr {
+            Expr::Call { name, args } => func::check(name, args),
+            _ => Ok(()),
+        })?;
+    }
+    Ok(bound)
+}
+
+fn substitute_expr(expr: &Expr, params: &Params) -> Expr {
+    match expr {
+        Expr::Param(name) => {
+            if let Some(value) = params.get(name) {
+                return Expr::Literal(value.clone());
+            }
+        }
+        Expr::Subquery(query) => return Expr::Subquery(Box::new(substitute(query, params))),
+        Expr::InSubquery {
+            expr,
+            query,
+            negated,
+        } => {
+            return Expr::InSubquery {
+                expr: Box::new(substitute_expr(expr, params)),
+                query: Box::new(substitute(query, params)),
+                negated: *negated,
+            }
+        }
+        _ => {}
+    }
+    let bound = expr
+        .clone()
+        .try_map_children(|child| Ok::<_, Infallible>(substitute_expr(&child, params)));
+    match bound {
+        Ok(expr) => expr,
+        Err(never) => match never {},
+    }
+}
+
+/// Call `f` with each parameter of `query`, its `with` queries and subqueries included.
+fn each_param(
+    query: &Query,
+    f: &mut impl FnMut(&str) -> Result<(), QueryError>,
+) -> Result<(), QueryError> {
+    for cte in &query.with {
+        each_param(&cte.query, f)?;
+    }
+    for expr in query.exprs() {
+        expr.walk(&mut |expr| match expr {
+            Expr::Param(name) => f(name),
+            Expr::Subquery(query) | Expr::InSubquery { query, .. } => each_param(query, f),
+            _ => Ok(()),
+        })?;
+    }
+    Ok(())
+}
+
+/// The types an ordering comparison holds for.
+const SCALARS: &[JsonType] = &[
+    JsonType::Null,
+    JsonType::Boolean,
+    JsonType::Number,
+    JsonType::String,
+];
+
+/// Finds the types of the parameters of a query.
+struct Inference<'a> {
+    query: &'a Query,
+    schema: &'a dyn Fn(&str, &Path) -> Option<Vec<JsonType>>,
+    params: &'a mut Vec<Param>,
+    /// paths that do not hold what the schema says: unnested arrays, and the array of a
+    /// quantifier within its predicate.
+    shadowed: Vec<Path>,
+}
+
+impl Inference<'_> {
+    fn visit(&mut self, expr: &Expr) -> Result<(), QueryError> {
+        match expr {
+            Expr::Unary { op, expr: operand } => {
+                let ty = match op {
+                    UnaryOp::Neg => JsonType::Number,
+                    UnaryOp::Not => JsonType::Boolean,
+                };
+                self.operand(operand, vec![ty])?;
+            }
+            Expr::Binary { left, op, right } => match op {
+                BinaryOp::And | BinaryOp::Or => {
+                    self.operand(left, vec![JsonType::Boolean])?;
+                    self.operand(right, vec![JsonType::Boolean])?;
+                }
+                BinaryOp::Add | BinaryOp::Sub | BinaryOp::Mul | BinaryOp::Div | BinaryOp::Rem => {
+                    self.operand(left, vec![JsonType::Number])?;
+                    self.operand(right, vec![JsonType::Number])?;
+                }
+                BinaryOp::Eq | BinaryOp::Ne => {
+                    self.compared(left, &[right])?;
+                    self.compared(right, &[left])?;
+                }
+                BinaryOp::Lt | BinaryOp::Le | BinaryOp::Gt | BinaryOp::Ge => {
+                    self.ordered(left, &[right])?;
+                    self.ordered(right, &[left])?;
+                }
+                _ => {}
+            },
+            Expr::In { expr, list, .. } => {
+                self.compared(expr, &list.iter().collect::<Vec<_>>())?;
+                for item in list {
+                    self.compared(item, &[expr])?;
+                }
+            }
+            Expr::Between {
+                expr, low, high, ..
+            } => {
+                self.ordered(expr, &[low, high])?;
+                self.ordered(low, &[expr])?;
+                self.ordered(high, &[expr])?;
+            }
+            Expr::Quantified {
+                path, predicate, ..
+            } => {
+                self.shadowed.push(path.clone());
+                let visited = self.visit(predicate);
+                self.shadowed.pop();
+                return visited;
+            }
+            _ => {}
+        }
+        for child in expr.children() {
+            self.visit(child)?;
+        }
+        Ok(())
+    }
+
+    /// Record that `expr`, if it is a parameter, must have one of `types`.
+    fn operand(&mut self, expr: &Expr, types: Vec<JsonType>) -> Result<(), QueryError> {
+        match expr {
+            Expr::Param(name) => self.require(name, Some(types)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Type `expr`, if it is a parameter, by what it is compared with.
+    fn compared(&mut self, expr: &Expr, others: &[&Expr]) -> Result<(), QueryError> {
+        for other in others {
+            if let Some(types) = self.type_of(other) {
+                self.operand(expr, types)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Like [`compared`](Self::compared) for an ordering comparison, which only holds for
+    /// scalars when nothing else tells the type.
+    fn ordered(&mut self, expr: &Expr, others: &[&Expr]) -> Result<(), QueryError> {
+        if others.iter().any(|other| self.type_of(other).is_some()) {
+            return self.compared(expr, others);
+        }
+        self.operand(expr, SCALARS.to_vec())
+    }
+
+    /// The types `expr` can have, if they are known before running the query.
+    fn type_of(&self, expr: &Expr) -> Option<Vec<JsonType>> {
+        let widen = |ty: JsonType| match ty {
+            // any number compares with an integer
+            JsonType::Integer => JsonType::Number,
+            ty => ty,
+        };
+        match expr {
+            Expr::Literal(value) => Some(vec![widen(JsonType::of(value))]),
+            Expr::Call { name, args } if name == "id" && args.len() <= 1 => {
+                Some(vec![JsonType::String])
+            }
+            Expr::Path(path)
+                if self
+                    .shadowed
+                    .iter()
+                    .any(|shadowed| shadowed.is_prefix_of(path) || path.is_prefix_of(shadowed)) =>
+            {
+                None
+            }
+            Expr::Path(path) => {
+                let (collection, path) = self.locate(path)?;
+                let mut types: Vec<_> = (self.schema)(collection, &path)?
+                    .into_iter()
+                    .map(widen)
+                    .collect();
+                types.dedup();
+                Some(types)
+            }
+            _ => None,
+        }
+    }
+
+    /// The collection a path reads and the path within its documents.
+    fn locate<'q>(&'q self, path: &Path) -> Option<(&'q str, Path)> {
+        let query = self.query;
+        if query.joins.is_empty() {
+            return match path.segments().first() {
+                Some(Segment::Key(key)) if key == IDS => None,
+                _ => Some((&query.from.collection, path.clone())),
+            };
+        }
+        let [Segment::Key(name), rest @ ..] = path.segments() else {
+            return None;
+        };
+        let source = std::iter::once(&query.from)
+            .chain(query.joins.iter().map(|join| &join.source))
+            .find(|source| source.name() == name)?;
+        Some((&source.collection, Path::from_segments(rest.to_vec())))
+    }
+
+    /// Add `types` to what parameter `name` may hold.
+    fn require(&mut self, name: &str, types: Option<Vec<JsonType>>) -> Result<(), QueryError> {
+        let Some(param) = self.params.iter_mut().find(|param| param.name == name) else {
+            self.params.push(Param {
+                name: name.to_owned(),
+                types,
+            });
+            return Ok(());
+        };
+        let Some(types) = types else {
+            return Ok(());
+        };
+        let narrowed = match param.types.take() {
+            None => types,
+            Some(known) => known.into_iter().filter(|ty| types.contains(ty)).collect(),
+        };
+        if narrowed.is_empty() {
+            return Err(QueryError::Invalid(format!(
+                "parameter `${name}` is used as values of different types"
+            )));
+        }
+        param.types = Some(narrowed);
+        Ok(())
+    }
+}
+
+/// The catalog versions a cached query was prepared at, by collection, `None` for collections
+/// that did not exist yet.
+type Versions = Vec<(String, Option<u64>)>;
+
+struct Entry {
+    prepared: Arc<Prepared>,
+    versions: Versions,
+    /// the tick of the last use.
+    used: u64,
+}
+
+#[derive(Default)]
+struct CacheState {
+    capacity: usize,
+    tick: u64,
+    entries: HashMap<String, Entry>,
+}
+
+/// A least recently used cache of [`Prepared`] queries by their text, see
+/// [`DatabaseReader::prepare`](crate::database::DatabaseReader::prepare).
+///
+/// Clones share the same entries.
+#[derive(Clone)]
+pub struct PlanCache {
+    state: Arc<Mutex<CacheState>>,
+}
+
+impl PlanCache {
+    /// A cache holding at most `capacity` queries.
+    pub fn new(capacity: usize) -> Self {
+        PlanCache {
+            state: Arc::new(Mutex::new(CacheState {
+                capacity,
+                ..CacheState::default()
+            })),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.state.lock().unwrap().entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn clear(&self) {
+        self.state.lock().unwrap().entries.clear();
+    }
+
+    /// Change how many queries the cache holds, dropping the least recently used ones if it
+    /// holds more.
+    pub fn resize(&self, capacity: usize) {
+        let mut state = self.state.lock().unwrap();
+        state.capacity = capacity;
+        state.evict();
+    }
+
+    /// The prepared form of `source`, made with `prepare` unless it is cached and every
+    /// collection it reads still has the catalog `version` it was prepared at, or still does not
+    /// exist.
+    pub(crate) fn get_or_prepare(
+        &self,
+        source: &str,
+        version: &dyn Fn(&str) -> Option<u64>,
+        prepare: impl FnOnce(Query) -> Result<Prepared, QueryError>,
+    ) -> Result<Arc<Prepared>, QueryError> {
+        let current = |versions: &Versions| {
+            versions
+                .iter()
+                .all(|(collection, at)| version(collection) == *at)
+        };
+        {
+            let mut state = self.state.lock().unwrap();
+            state.tick += 1;
+            let tick = state.tick;
+            match state.entries.get_mut(source) {
+                Some(entry) if current(&entry.versions) => {
+                    entry.used = tick;
+                    return Ok(entry.prepared.clone());
+                }
+                Some(_) => {
+                    state.entries.remove(source);
+                }
+                None => {}
+            }
+        }
+
+        let query = parse(source)?;
+        let versions: Versions = subquery::collections(&query)
+            .into_iter()
+            .map(|collection| {
+                let at = version(&collection);
+                (collection, at)
+            })
+            .collect();
+        let prepared = Arc::new(prepare(query)?);
+        let mut state = self.state.lock().unwrap();
+        if state.capacity > 0 {
+            let used = state.tick;
+            let entry = Entry {
+                prepared: prepared.clone(),
+                versions,
+                used,
+            };
+            state.entries.insert(source.to_owned(), entry);
+            state.evict();
+        }
+        Ok(prepared)
+    }
+}
+
+impl CacheState {
+    fn evict(&mut self) {
+        while self.entries.len() > self.capacity {
+            let oldest = self
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.used)
+                .map(|(source, _)| source.clone())
+                .unwrap();
+            self.entries.remove(&oldest);
+        }
+    }
+}
+
+impl Default for PlanCache {
+    /// A cache of 256 queries.
+    fn default() -> Self {
+        PlanCache::new(256)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::*;
+    use crate::index::IndexDef;
+    use crate::rwmap::RwMap;
+
+    #[test]
+    fn infers_and_checks_types() {
+        let prepared = Prepared::parse(
+            "from t where age > $min and (name = $name or $flag) and id() in ($a, $b) \
+             select age * $k as scaled, $any as echo",
+        )
+        .unwrap();
+        let types: Vec<_> = prepared
+            .params()
+            .iter()
+            .map(|param| (param.name.as_str(), param.types.clone()))
+            .collect();
+        assert_eq!(
+            types,
+            [
+                ("min", Some(SCALARS.to_vec())),
+                ("name", None),
+                ("flag", Some(vec![JsonType::Boolean])),
+                ("a", Some(vec![JsonType::String])),
+                ("b", Some(vec![JsonType::String])),
+                ("k", Some(vec![JsonType::Number])),
+                ("any", None),
+            ]
+        );
+
+        let params = Params::new()
+            .set("min", 1)
+            .set("name", "x")
+            .set("flag", false)
+            .set("a", "1")
+            .set("b", "2")
+            .set("k", 2.5)
+            .set("any", json!({"o": 1}));
+        assert!(prepared.bind(&params).is_ok());
+        assert_eq!(
+            prepared.bind(&params.clone().set("k", "2")),
+            Err(QueryError::ParamType {
+                name: "k".into(),
+                expected: vec![JsonType::Number],
+                found: JsonType::String,
+            })
+        );
+        assert_eq!(
+            prepared.bind(&params.clone().set("extra", 1)),
+            Err(QueryError::UnknownParam("extra".into()))
+        );
+        assert_eq!(
+            prepared.bind(&params.clone().set("min", json!([1]))),
+            Err(QueryError::ParamType {
+                name: "min".into(),
+                expected: SCALARS.to_vec(),
+                found: JsonType::Array,
+            })
+        );
+        let mut missing = params.clone();
+        missing.0.remove("b");
+        assert_eq!(
+            prepared.bind(&missing),
+            Err(QueryError::MissingParam("b".into()))
+        );
+
+        let schema = |collection: &str, path: &Path| {
+            (collection == "t" && path.to_string() == "age").then(|| vec![JsonType::Integer])
+        };
+        let typed =
+            Prepared::with_schemas(parse("from t where $min <= age").unwrap(), &schema).unwrap();
+        assert_eq!(typed.params()[0].types, Some(vec![JsonType::Number]));
+
+        assert!(matches!(
+            Prepared::parse("from t where $x = 1 and $x = 'a'"),
+            Err(QueryError::Invalid(_))
+        ));
+        assert!(matches!(
+            Prepared::parse("from t where nope($x)"),
+            Err(QueryError::UnknownFunction(_))
+        ));
+    }
+
+    #[test]
+    fn values_never_become_syntax() {
+        let (mut w, r) = RwMap::default::<String, Value>();
+        w.insert("1".into(), json!({"name": "a"}));
+        w.insert("2".into(), json!({"name": "b' or 'x' = 'x"}));
+        w.publish();
+        let map = r.enter().unwrap();
+        let prepared = Prepared::parse("from t where name = $name").unwrap();
+        let run = |name: &str| {
+            prepared
+                .execute(&map, &Params::new().set("name", name))
+                .unwrap()
+                .iter()
+                .map(|row| row.id.unwrap().to_owned())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(run("a"), ["1"]);
+        assert_eq!(run("a' or 'x' = 'x"), Vec::<String>::new());
+        assert_eq!(run("b' or 'x' = 'x"), ["2"]);
+    }
+
+    #[test]
+    fn binds_values_into_the_compiled_query() {
+        let (mut w, r) = RwMap::default::<String, Value>();
+        for (id, name, n) in [("1", "ann", 1), ("2", "bob", 2), ("3", "amy", 3)] {
+            w.insert(id.into(), json!({"name": name, "n": n}));
+        }
+        w.publish();
+        let map = r.enter().unwrap();
+        let prepared = Prepared::parse(
+            "from t where n >= $low and regex_match(name, $pattern) \
+             select name, n * $k as m order by m desc",
+        )
+        .unwrap();
+        let run = |low: i64, pattern: &str, k: i64| {
+            let params = Params::new()
+                .set("low", low)
+                .set("pattern", pattern)
+                .set("k", k);
+            prepared
+                .execute(&map, &params)
+                .map(|rows| rows.into_iter().map(Row::into_value).collect::<Vec<_>>())
+        };
+        assert_eq!(
+            run(1, "^a", 10).unwrap(),
+            [
+                json!({"name": "amy", "m": 30}),
+                json!({"name": "ann", "m": 10})
+            ]
+        );
+        assert_eq!(run(2, "b", -1).unwrap(), [json!({"name": "bob", "m": -2})]);
+        // a value can make a call invalid where its parameter was not
+        assert!(matches!(run(1, "(", 1), Err(QueryError::Invalid(_))));
+    }
+
+    #[test]
+    fn reuses_the_first_access_path() {
+        let (mut w, r) = RwMap::default::<String, Value>();
+        for i in 0..10 {
+            w.insert(format!("{i}"), json!({"n": i}));
+        }
+        w.create_index(IndexDef::ordered("by_n", Path::parse("n").unwrap()));
+        w.publish();
+        let prepared = Prepared::parse("from t where n >= $low order by n").unwrap();
+        let run = |low: i64| {
+            let map = r.enter().unwrap();
+            let rows = prepared
+                .execute(&map, &Params::new().set("low", low))
+                .unwrap();
+            rows.len()
+        };
+        assert_eq!(run(8), 2);
+        let shape = prepared.shape.lock().unwrap().clone().unwrap();
+        // a range this wide would be scanned, but the cached plan keeps the index
+        assert_eq!(run(0), 10);
+        assert_eq!(prepared.shape.lock().unwrap().as_ref(), Some(&shape));
+
+        // without the index the plan falls back to a scan
+        w.drop_index("by_n");
+        w.publish();
+        assert_eq!(run(5), 5);
+    }
+
+    #[test]
+    fn evicts_and_invalidates() {
+        let cache = PlanCache::new(2);
+        let version = Mutex::new(0);
+        let get = |source: &str| {
+            cache
+                .get_or_prepare(source, &|_| Some(*version.lock().unwrap()), Prepared::new)
+                .unwrap()
+        };
+        let a = get("from a");
+        assert!(Arc::ptr_eq(&a, &get("from a")));
+        get("from b");
+        get("from a");
+        get("from c");
+        // `b` was the least recently used
+        assert_eq!(cache.len(), 2);
+        assert!(Arc::ptr_eq(&a, &get("from a")));
+
+        *version.lock().unwrap() += 1;
+        assert!(!Arc::ptr_eq(&a, &get("from a")));
+        assert!(matches!(
+            cache.get_or_prepare("from", &|_| None, Prepared::new),
+            Err(QueryError::Syntax(_))
+        ));
+    }
+}
@@ -0,0 +1,209 @@
+use std::borrow::Cow;
+
+use serde_json::{Map, Value};
+
+use super::ast::{Expr, Select, SelectItem};
+use super::eval::{check, eval};
+use super::QueryError;
+use crate::path::Segment;
+
+/// Where a `select` item is written in the result object.
+#[derive(Debug, Clone, PartialEq)]
+enum Target {
+    /// nested keys, for a path of plain keys without an alias.
+    Nested(Vec<String>),
+    Key(String),
+}
+
+/// A compiled `select` clause.
+///
+/// Each item is written to a new object under its alias. Without an alias, a path of plain
+/// keys such as `address.city` keeps its nesting, `{"address": {"city": ...}}`, and any other
+/// expression is named by its RQL text. Items that evaluate to missing are left out. Only the
+/// selected values are cloned out of the source document.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Projection {
+    items: Option<Vec<(Expr, Target)>>,
+    /// a key `select *` leaves out of the documents it passes through.
+    hidden: Option<String>,
+}
+
+impl Projection {
+    pub fn new(select: &Select) -> Result<Self, QueryError> {
+        Self::rewritten(select, |expr| {
+            check(expr)?;
+            Ok(expr.clone())
+        })
+    }
+
+    /// The projection with `f` applied to the expression of each item.
+    pub(crate) fn try_map_exprs(
+        &self,
+        f: &mut impl FnMut(&Expr) -> Result<Expr, QueryError>,
+    ) -> Result<Self, QueryError> {
+        let items = self
+            .items
+            .as_ref()
+            .map(|items| {
+                items
+                    .iter()
+                    .map(|(expr, target)| Ok((f(expr)?, target.clone())))
+                    .collect::<Result<_, QueryError>>()
+            })
+            .transpose()?;
+        Ok(Projection {
+            items,
+            hidden: self.hidden.clone(),
+        })
+    }
+
+    /// Like [`new`](Self::new), but each item evaluates `rewrite` of its expression while
+    /// keeping the name of the original.
+    pub(crate) fn rewritten(
+        select: &Select,
+        mut rewrite: impl FnMut(&Expr) -> Result<Expr, QueryError>,
+    ) -> Result<Self, QueryError> {
+        let Select::Fields(items) = select else {
+            return Ok(Projection {
+                items: None,
+                hidden: None,
+            });
+        };
+        let items = items
+            .iter()
+            .map(|SelectItem { expr, alias }| {
+                let target = match (alias, expr) {
+                    (Some(alias), _) => Target::Key(alias.clone()),
+                    (None, Expr::Path(path)) => {
+                        let keys: Option<Vec<_>> = path
+                            .segments()
+                            .iter()
+                            .map(|segment| match segment {
+                                Segment::Key(key) => Some(key.clone()),
+                                Segment::Index(_) => None,
+                            })
+                            .collect();
+                        keys.map_or_else(|| Target::Key(expr.to_string()), Target::Nested)
+                    }
+                    (None, expr) => Target::Key(expr.to_string()),
+                };
+                Ok((rewrite(expr)?, target))
+            })
+            .collect::<Result<_, QueryError>>()?;
+        Ok(Projection {
+            items: Some(items),
+            hidden: None,
+        })
+    }
+
+    /// Make `select *` leave out `key`, which the query added to its documents.
+    pub(crate) fn hiding(mut self, key: &str) -> Self {
+        self.hidden = Some(key.to_owned());
+        self
+    }
+
+    /// Returns true for `select *`, which passes documents through unchanged.
+    pub fn is_identity(&self) -> bool {
+        self.items.is_none()
+    }
+
+    /// The projected form of `doc`, borrowing it for `select *`.
+    pub fn apply<'a>(&self, doc: &'a Value) -> Cow<'a, Value> {
+        let Some(items) = &self.items else {
+            return match (&self.hidden, doc) {
+                (Some(key), Value::Object(fields)) if fields.contains_key(key) => {
+                    let mut fields = fields.clone();
+                    fields.remove(key);
+                    Cow::Owned(Value::Object(fields))
+                }
+                _ => Cow::Borrowed(doc),
+            };
+        };
+        let mut out = Map::new();
+        for (expr, target) in items {
+            let Some(value) = eval(expr, doc) else {
+                continue;
+            };
+            match target {
+                Target::Key(key) => {
+                    out.insert(key.clone(), value.into_owned());
+                }
+                Target::Nested(keys) => insert_nested(&mut out, keys, value.into_owned()),
+            }
+        }
+        Cow::Owned(Value::Object(out))
+    }
+}
+
+/// Insert `value` at `keys`, creating intermediate objects and replacing anything else in
+/// the way.
+fn insert_nested(out: &mut Map<String, Value>, keys: &[String], value: Value) {
+    let (last, parents) = keys
+        .split_last()
+        .expect("paths in a select are never empty");
+    let mut map = out;
+    for key in parents {
+        let slot = map
+            .entry(key.clone())
+            .or_insert_with(|| Value::Object(Map::new()));
+        if !slot.is_object() {
+            *slot = Value::Object(Map::new());
+        }
+        map = slot.as_object_mut().unwrap();
+    }
+    map.insert(last.clone(), value);
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::*;
+    use crate::query::parse;
+
+    fn project(select: &str, doc: Value) -> Value {
+        let query = parse(&format!("from t select {select}")).unwrap();
+        Projection::new(&query.select)
+            .unwrap()
+            .apply(&doc)
+            .into_owned()
+    }
+
+    #[test]
+    fn builds_new_objects() {
+        let doc = json!({
+            "first": "Ada",
+            "last": "Lovelace",
+            "born": 1815,
+            "address": {"city": "London", "zip": "W1", "lines": ["a", "b"]},
+            "bio": "a long text that should not be copied",
+        });
+        assert_eq!(
+            project(
+                "address.city, address.zip, first || ' ' || last as name, born + 36 as died, \
+                 address.lines[1], nope, case when born < 1800 then 'old' when born < 1900 \
+                 then 'victorian' else 'modern' end as era, coalesce(nick, first) as nick",
+                doc.clone()
+            ),
+            json!({
+                "address": {"city": "London", "zip": "W1"},
+                "name": "Ada Lovelace",
+                "died": 1851,
+                "address.lines[1]": "b",
+                "era": "victorian",
+                "nick": "Ada",
+            })
+        );
+        assert_eq!(
+            project("born * 2, born > 1800 as modern, 'n' || born", doc.clone()),
+            // unaliased expressions are named by their RQL text
+            json!({"born * 2": 3630, "modern": true, "\"n\" || born": "n1815"})
+        );
+        let query = parse("from t").unwrap();
+        let projection = Projection::new(&query.select).unwrap();
+        assert!(matches!(projection.apply(&doc), Cow::Borrowed(_)));
+
+        let query = parse("from t select coalesce()").unwrap();
+        assert!(Projection::new(&query.select).is_err());
+    }
+}
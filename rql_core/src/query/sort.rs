@@ -0,0 +1,264 @@
+use std::cmp::Ordering;
+
+use serde_json::Value;
+
+use super::ast::{Expr, Nulls, OrderBy, Select};
+use super::eval::{check, cmp_numbers, eval};
+use super::QueryError;
+use crate::path::Segment;
+
+/// The position of a value's type in the sort order.
+fn type_rank(value: &Value) -> u8 {
+    match value {
+        Value::Null => 0,
+        Value::Bool(_) => 1,
+        Value::Number(_) => 2,
+        Value::String(_) => 3,
+        Value::Array(_) => 4,
+        Value::Object(_) => 5,
+    }
+}
+
+/// A total order over JSON values used by `order by`.
+///
+/// Values of different types order as `booleans < numbers < strings < arrays < objects`.
+/// Numbers compare by value, strings by code point, arrays element by element and objects as
+/// their sorted list of entries. Null placement is decided by [`Sort`], not here.
+pub fn sort_cmp(a: &Value, b: &Value) -> Ordering {
+    match (a, b) {
+        (Value::Bool(a), Value::Bool(b)) => a.cmp(b),
+        (Value::Number(a), Value::Number(b)) => cmp_numbers(a, b),
+        (Value::String(a), Value::String(b)) => a.cmp(b),
+        (Value::Array(a), Value::Array(b)) => a
+            .iter()
+            .zip(b)
+            .map(|(a, b)| sort_cmp(a, b))
+            .find(|o| o.is_ne())
+            .unwrap_or_else(|| a.len().cmp(&b.len())),
+        (Value::Object(a), Value::Object(b)) => a
+            .iter()
+            .zip(b)
+            .map(|((ka, va), (kb, vb))| ka.cmp(kb).then_with(|| sort_cmp(va, vb)))
+            .find(|o| o.is_ne())
+            .unwrap_or_else(|| a.len().cmp(&b.len())),
+        _ => type_rank(a).cmp(&type_rank(b)),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Key {
+    expr: Expr,
+    /// evaluate against the projected row, as the key names a `select` alias.
+    projected: bool,
+    descending: bool,
+    nulls_first: bool,
+}
+
+/// A compiled `order by` clause.
+///
+/// `null` and missing values sort after every other value, so they come last in ascending
+/// and first in descending order, unless `nulls first` or `nulls last` says otherwise. Rows
+/// with equal keys are ordered by document id, which makes the order total.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sort {
+    keys: Vec<Key>,
+}
+
+impl Sort {
+    pub fn new(order_by: &[OrderBy], select: &Select) -> Result<Self, QueryError> {
+        Self::rewritten(order_by, select, |expr| {
+            check(expr)?;
+            Ok(expr.clone())
+        })
+    }
+
+    /// The sort with `f` applied to the expression of each key.
+    pub(crate) fn try_map_exprs(
+        &self,
+        f: &mut impl FnMut(&Expr) -> Result<Expr, QueryError>,
+    ) -> Result<Self, QueryError> {
+        let keys = self
+            .keys
+            .iter()
+            .map(|key| {
+                Ok(Key {
+                    expr: f(&key.expr)?,
+                    ..key.clone()
+                })
+            })
+            .collect::<Result<_, QueryError>>()?;
+        Ok(Sort { keys })
+    }
+
+    /// Like [`new`](Self::new), but keys that are not `select` aliases are replaced by
+    /// `rewrite` of themselves.
+    pub(crate) fn rewritten(
+        order_by: &[OrderBy],
+        select: &Select,
+        mut rewrite: impl FnMut(&Expr) -> Result<Expr, QueryError>,
+    ) -> Result<Self, QueryError> {
+        let aliases: Vec<&str> = match select {
+            Select::All => Vec::new(),
+            Select::Fields(items) => items
+                .iter()
+                .filter_map(|item| item.alias.as_deref())
+                .collect(),
+        };
+        let keys = order_by
+            .iter()
+            .map(|order| {
+                let projected = match &order.expr {
+                    Expr::Path(path) => match path.segments() {
+                        [Segment::Key(name)] => aliases.contains(&name.as_str()),
+                        _ => false,
+                    },
+                    _ => false,
+                };
+                Ok(Key {
+                    expr: if projected {
+                        order.expr.clone()
+                    } else {
+                        rewrite(&order.expr)?
+                    },
+                    projected,
+                    descending: order.descending,
+                    nulls_first: match order.nulls {
+                        Some(Nulls::First) => true,
+                        Some(Nulls::Last) => false,
+                        None => order.descending,
+                    },
+                })
+            })
+            .collect::<Result<_, QueryError>>()?;
+        Ok(Sort { keys })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// Returns true if some key names a `select` alias, so rows must be projected before they
+    /// can be sorted.
+    pub fn needs_projection(&self) -> bool {
+        self.keys.iter().any(|key| key.projected)
+    }
+
+    /// The expression of the only key if rows sort by it ascending with nulls last, the order
+    /// an ordered index on it reads them in.
+    pub(crate) fn index_order(&self) -> Option<&Expr> {
+        match self.keys.as_slice() {
+            [key] if !key.projected && !key.descending && !key.nulls_first => Some(&key.expr),
+            _ => None,
+        }
+    }
+
+    /// A text uniquely describing the order, used to tie cursors to it.
+    pub(crate) fn fingerprint(&self) -> String {
+        self.keys
+            .iter()
+            .map(|key| {
+                format!(
+                    "{}{} {} {}",
+                    if key.projected { "=" } else { "" },
+                    key.expr,
+                    if key.descending { "desc" } else { "asc" },
+                    if key.nulls_first { "first" } else { "last" }
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+
+    /// The sort keys of a row, with `null` standing in for missing values.
+    pub fn keys(&self, doc: &Value, projected: &Value) -> Vec<Value> {
+        self.keys
+            .iter()
+            .map(|key| {
+                let source = if key.projected { projected } else { doc };
+                eval(&key.expr, source).map_or(Value::Null, |value| value.into_owned())
+            })
+            .collect()
+    }
+
+    /// Compare two rows by their [keys](Self::keys) and then by id.
+    pub fn cmp(&self, a: (&[Value], &str), b: (&[Value], &str)) -> Ordering {
+        for ((key, a), b) in self.keys.iter().zip(a.0).zip(b.0) {
+            let ordering = match (a.is_null(), b.is_null()) {
+                (true, true) => Ordering::Equal,
+                (true, false) if key.nulls_first => Ordering::Less,
+                (true, false) => Ordering::Greater,
+                (false, true) if key.nulls_first => Ordering::Greater,
+                (false, true) => Ordering::Less,
+                (false, false) if key.descending => sort_cmp(b, a),
+                (false, false) => sort_cmp(a, b),
+            };
+            if ordering.is_ne() {
+                return ordering;
+            }
+        }
+        a.1.cmp(b.1)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::*;
+    use crate::query::parse;
+
+    #[test]
+    fn orders_mixed_values() {
+        let mut values = vec![
+            json!("b"),
+            json!(null),
+            json!(2),
+            json!([1, 2]),
+            json!(1.5),
+            json!(false),
+            json!({"a": 1}),
+            json!([1]),
+            json!("a"),
+        ];
+        values.sort_by(sort_cmp);
+        assert_eq!(
+            values,
+            vec![
+                json!(null),
+                json!(false),
+                json!(1.5),
+                json!(2),
+                json!("a"),
+                json!("b"),
+                json!([1]),
+                json!([1, 2]),
+                json!({"a": 1}),
+            ]
+        );
+    }
+
+    #[test]
+    fn null_placement_and_ties() {
+        let docs = [
+            ("a", json!({"n": 2})),
+            ("b", json!({})),
+            ("c", json!({"n": 1})),
+            ("d", json!({"n": null})),
+            ("e", json!({"n": 1})),
+        ];
+        let order = |clause: &str| {
+            let query = parse(&format!("from t order by {clause}")).unwrap();
+            let sort = Sort::new(&query.order_by, &query.select).unwrap();
+            let mut rows: Vec<_> = docs
+                .iter()
+                .map(|(id, doc)| (sort.keys(doc, doc), *id))
+                .collect();
+            rows.sort_by(|a, b| sort.cmp((&a.0, a.1), (&b.0, b.1)));
+            rows.into_iter().map(|(_, id)| id).collect::<String>()
+        };
+        assert_eq!(order("n"), "ceabd");
+        assert_eq!(order("n desc"), "bdace");
+        assert_eq!(order("n nulls first"), "bdcea");
+        assert_eq!(order("n desc nulls last"), "acebd");
+    }
+}
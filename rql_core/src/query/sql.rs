@@ -0,0 +1,506 @@
+//! SQL `select` statements, lowered into RQL.
+//!
+//! A practical subset of SQL parses into the same [`Query`] as the RQL it stands for, so it
+//! runs on the same planner and evaluator. Tables are collections and dotted column names are
+//! paths into their documents:
+//!
+//! ```
+//! use rql_core::query::{parse, parse_sql};
+//!
+//! let query = parse_sql(
+//!     "SELECT c.name, count(*) AS orders
+//!      FROM orders o JOIN customers c ON id(c) = o.customer
+//!      WHERE o.status = 'paid' AND c.address.city LIKE 'Os%'
+//!      GROUP BY c.name HAVING sum(o.total) > 100
+//!      ORDER BY orders DESC LIMIT 10",
+//! );
+//! let expected = parse(
+//!     "from orders as o join customers as c on id(c) = o.customer
+//!      where o.status = 'paid' and regex_match(c.address.city, '(?s)^Os.*$')
+//!      group by c.name having sum(o.total) > 100
+//!      select c.name, count(*) as orders
+//!      order by orders desc limit 10",
+//! );
+//! assert_eq!(query.unwrap(), expected.unwrap());
+//! ```
+//!
+//! Statements have the clauses `select [distinct]`, `from`, `[inner | left [outer]] join ...
+//! on`, `where`, `group by`, `having`, `order by`, `limit` and `offset`, in that order.
+//! Strings take single quotes, doubled to escape one, and double quotes name columns and
+//! tables. Aliases may leave out `as`. Expressions are otherwise those of RQL, with its
+//! functions, and `[not] like` and `ilike`, which become `regex_match`.
+//!
+//! Without joins a row is its document, so a column qualified with the table, as in
+//! `users.name` or `u.name`, is read without it, and `id(u)` becomes `id()`. With joins,
+//! columns must be qualified, as in RQL. `select distinct` becomes a `group by` of the
+//! selected columns.
+//!
+//! Subqueries, set operations such as `union`, `with`, window functions, `cast`, right, full,
+//! cross and comma joins and statements other than `select` fail with a [`SyntaxError`]
+//! saying they are not supported, pointing at where they start.
+use std::convert::Infallible;
+use std::ops::Range;
+
+use super::ast::{Expr, Join, Query, Select, SelectItem, Source};
+use super::lexer::{tokenize, Keyword, Spanned, Token};
+use super::parser::Parser;
+use super::SyntaxError;
+use crate::path::{Path, Segment};
+
+/// Words that may follow a column or a table without being its alias.
+const CLAUSE_WORDS: &[&str] = &[
+    "cross",
+    "except",
+    "fetch",
+    "full",
+    "inner",
+    "intersect",
+    "left",
+    "natural",
+    "right",
+    "union",
+    "using",
+    "window",
+];
+
+/// Statements other than `select`.
+const STATEMENTS: &[&str] = &[
+    "alter", "create", "delete", "drop", "insert", "merge", "update", "values", "with",
+];
+
+/// Parse a SQL `select` statement into the RQL query it stands for.
+pub fn parse_sql(source: &str) -> Result<Query, SyntaxError> {
+    let mut parser = Parser::sql(source, tokens(source)?);
+    let query = parser.statement()?;
+    parser.finish()?;
+    Ok(query)
+}
+
+/// The RQL tokens of `source`, with adjacent strings joined by their quote and double quoted
+/// strings turned into names.
+fn tokens(source: &str) -> Result<Vec<Spanned>, SyntaxError> {
+    let quote = |span: &Range<usize>| source[span.clone()].chars().next();
+    let mut tokens: Vec<Spanned> = Vec::new();
+    for spanned in tokenize(source)? {
+        if let (Some(last), Token::String(next)) = (tokens.last_mut(), &spanned.token) {
+            if let Token::String(string) = &mut last.token {
+                if last.span.end == spanned.span.start && quote(&last.span) == quote(&spanned.span)
+                {
+                    string.extend(quote(&spanned.span));
+                    string.push_str(next);
+                    last.span.end = spanned.span.end;
+                    continue;
+                }
+            }
+        }
+        tokens.push(spanned);
+    }
+    for spanned in &mut tokens {
+        if let (Token::String(name), Some('"')) = (&mut spanned.token, quote(&spanned.span)) {
+            spanned.token = Token::Quoted(std::mem::take(name));
+        }
+    }
+    Ok(tokens)
+}
+
+/// `expr like pattern`, or `ilike` if `insensitive`, as a regular expression match.
+pub(crate) fn like(expr: Expr, pattern: &str, insensitive: bool) -> Expr {
+    let mut regex = String::from(if insensitive { "(?is)^" } else { "(?s)^" });
+    for c in pattern.chars() {
+        match c {
+            '%' => regex.push_str(".*"),
+            '_' => regex.push('.'),
+            c => regex.push_str(&regex::escape(c.encode_utf8(&mut [0; 4]))),
+        }
+    }
+    regex.push('$');
+    Expr::Call {
+        name: "regex_match".to_owned(),
+        args: vec![expr, Expr::literal(regex)],
+    }
+}
+
+/// The columns of a `select`, before the tables are known.
+enum Columns {
+    /// `*`, or `t.*` with the span of `t`.
+    All(Option<(String, Range<usize>)>),
+    Items(Vec<SelectItem>),
+}
+
+impl Parser<'_> {
+    /// A `select` statement.
+    fn statement(&mut self) -> Result<Query, SyntaxError> {
+        if !self.eat_keyword(Keyword::Select) {
+            return Err(match self.peek() {
+                Token::Ident(word) if STATEMENTS.contains(&word.to_lowercase().as_str()) => {
+                    self.unsupported(&format!("`{}`", word.to_lowercase()))
+                }
+                _ => self.expected("`select`"),
+            });
+        }
+        let distinct = self.span();
+        let distinct = self.eat_keyword(Keyword::Distinct).then_some(distinct);
+        if self.is_word("top") && matches!(self.peek_nth(1), Token::Number(_)) {
+            return Err(self.unsupported("`top`"));
+        }
+        let columns = self.columns()?;
+        self.expect_keyword(Keyword::From)?;
+        let mut query = Query::new("");
+        query.from = self.table()?;
+        loop {
+            if self.peek() == &Token::Comma {
+                return Err(self.error_at(
+                    self.span(),
+                    "a comma between tables is not supported, join them with `join ... on`",
+                ));
+            }
+            for (word, what) in [
+                ("right", "a right join"),
+                ("full", "a full join"),
+                ("cross", "a cross join"),
+                ("natural", "a natural join"),
+            ] {
+                if self.is_word(word) {
+                    return Err(self.unsupported(what));
+                }
+            }
+            let Some(kind) = self.join_kind()? else {
+                break;
+            };
+            let source = self.table()?;
+            if self.is_word("using") {
+                return Err(self.unsupported("`using`"));
+            }
+            self.expect_keyword(Keyword::On)?;
+            let on = self.expr()?;
+            query.joins.push(Join { kind, source, on });
+        }
+        if self.eat_keyword(Keyword::Where) {
+            query.filter = Some(self.expr()?);
+        }
+        if self.eat_keyword(Keyword::Group) {
+            self.expect_keyword(Keyword::By)?;
+            query.group_by = self.comma_separated(Self::expr)?;
+        }
+        if self.eat_keyword(Keyword::Having) {
+            query.having = Some(self.expr()?);
+        }
+        if self.eat_keyword(Keyword::Order) {
+            self.expect_keyword(Keyword::By)?;
+            query.order_by = self.comma_separated(Self::order_by)?;
+        }
+        if self.eat_keyword(Keyword::Limit) {
+            query.limit = Some(self.unsigned("row count")?);
+        }
+        if self.eat_keyword(Keyword::Offset) {
+            query.offset = Some(self.unsigned("row count")?);
+            let _ = self.eat_word("rows") || self.eat_word("row");
+        }
+        for word in ["fetch", "union", "intersect", "except"] {
+            if self.is_word(word) {
+                return Err(self.unsupported(&format!("`{word}`")));
+            }
+        }
+
+        query.select = match columns {
+            Columns::All(None) => Select::All,
+            Columns::All(Some((table, _)))
+                if query.joins.is_empty() && table == query.from.name() =>
+            {
+                Select::All
+            }
+            Columns::All(Some((table, span))) if query.joins.is_empty() => {
+                return Err(self.error_at(span, format!("`{table}` is not a table of the query")))
+            }
+            Columns::All(Some((table, span))) => {
+                return Err(self.error_at(
+                    span,
+                    format!("`{table}.*` is not supported in a query with joins"),
+                ))
+            }
+            Columns::Items(items) => Select::Fields(items),
+        };
+        if let Some(span) = distinct {
+            query.group_by = match &query.select {
+                Select::All => {
+                    return Err(self.error_at(span, "`distinct` without columns is not supported"))
+                }
+                Select::Fields(items)
+                    if !query.group_by.is_empty()
+                        || query.having.is_some()
+                        || items.iter().any(|item| item.expr.has_aggregate()) =>
+                {
+                    return Err(self.error_at(
+                        span,
+                        "`distinct` with `group by` or aggregates is not supported",
+                    ))
+                }
+                Select::Fields(items) => items.iter().map(|item| item.expr.clone()).collect(),
+            };
+        }
+        if query.joins.is_empty() {
+            let table = query.from.name().to_owned();
+            query = query
+                .try_map_exprs(|expr| Ok::<_, Infallible>(unqualify(expr, &table)))
+                .unwrap_or_else(|never| match never {});
+        }
+        Ok(query)
+    }
+
+    /// The columns of a `select`: `*`, `t.*` or expressions with optional aliases.
+    fn columns(&mut self) -> Result<Columns, SyntaxError> {
+        let star = |parser: &Self| {
+            parser.peek() == &Token::Star
+                || matches!(parser.peek(), Token::Ident(_) | Token::Quoted(_))
+                    && parser.peek_nth(1) == &Token::Dot
+                    && parser.peek_nth(2) == &Token::Star
+        };
+        if star(self) {
+            let columns = if self.eat(&Token::Star) {
+                Columns::All(None)
+            } else {
+                let span = self.span();
+                let table = self.name("table name")?;
+                self.bump();
+                self.bump();
+                Columns::All(Some((table, span)))
+            };
+            if self.peek() == &Token::Comma {
+                return Err(self.unsupported("a column next to `*`"));
+            }
+            return Ok(columns);
+        }
+        let items = self.comma_separated(|parser| {
+            if star(parser) {
+                return Err(parser.unsupported("`*` next to other columns"));
+            }
+            let expr = parser.expr()?;
+            let alias = parser.alias()?;
+            Ok(SelectItem { expr, alias })
+        })?;
+        Ok(Columns::Items(items))
+    }
+
+    /// `table [[as] alias]`.
+    fn table(&mut self) -> Result<Source, SyntaxError> {
+        let collection = self.name("table name")?;
+        let alias = self.alias()?;
+        Ok(Source { collection, alias })
+    }
+
+    /// An alias, with or without `as`.
+    fn alias(&mut self) -> Result<Option<String>, SyntaxError> {
+        if self.eat_keyword(Keyword::As) {
+            return Ok(Some(self.name("alias")?));
+        }
+        match self.peek() {
+            Token::Ident(word) if CLAUSE_WORDS.contains(&word.to_lowercase().as_str()) => Ok(None),
+            Token::Ident(_) | Token::Quoted(_) => Ok(Some(self.name("alias")?)),
+            _ => Ok(None),
+        }
+    }
+
+    /// Whether the next token is the unreserved `word`.
+    fn is_word(&self, word: &str) -> bool {
+        matches!(self.peek(), Token::Ident(name) if name.eq_ignore_ascii_case(word))
+    }
+}
+
+/// `expr` without `table.` in front of its paths, and with `id(table)` as `id()`.
+fn unqualify(expr: &Expr, table: &str) -> Expr {
+    let strip = |path: &Path| match path.segments() {
+        [Segment::Key(key), rest @ ..] if key == table && !rest.is_empty() => {
+            Path::from_segments(rest.to_vec())
+        }
+        _ => path.clone(),
+    };
+    match expr {
+        Expr::Path(path) => Expr::Path(strip(path)),
+        Expr::Exists { path, negated } => Expr::Exists {
+            path: strip(path),
+            negated: *negated,
+        },
+        Expr::Call { name, args } if name == "id" => match args.as_slice() {
+            [Expr::Path(path)] if matches!(path.segments(), [Segment::Key(key)] if key == table) => {
+                Expr::Call {
+                    name: name.clone(),
+                    args: Vec::new(),
+                }
+            }
+            _ => expr.clone(),
+        },
+        Expr::Quantified {
+            quantifier,
+            path,
+            predicate,
+        } => Expr::Quantified {
+            quantifier: *quantifier,
+            path: strip(path),
+            predicate: Box::new(unqualify(predicate, table)),
+        },
+        _ => expr
+            .clone()
+            .try_map_children(|child| Ok::<_, Infallible>(unqualify(&child, table)))
+            .unwrap_or_else(|never| match never {}),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::collection::Collection;
+    use crate::query::{execute, parse};
+
+    fn lowered(sql: &str, rql: &str) {
+        assert_eq!(parse_sql(sql).unwrap(), parse(rql).unwrap(), "{sql}");
+    }
+
+    #[test]
+    fn lowers_select_statements() {
+        lowered("select * from users", "from users");
+        lowered(
+            "SELECT u.name, u.address.city AS city, id(u) id FROM users u \
+             WHERE u.age >= 18 AND \"u\".\"first name\" <> 'O''Brien' \
+             ORDER BY city DESC NULLS LAST, u.name LIMIT 10 OFFSET 20 ROWS;",
+            "from users as u where age >= 18 and `first name` != \"O'Brien\" \
+             select name, address.city as city, id() as id \
+             order by city desc nulls last, name limit 10 offset 20",
+        );
+        lowered(
+            "select users.* from users where users.tags is not null and users = 1",
+            "from users where tags is not null and users = 1",
+        );
+        lowered(
+            "select distinct lower(name) as name, city from users",
+            "from users group by lower(name), city select lower(name) as name, city",
+        );
+        lowered(
+            "select status, count(distinct customer) from orders group by status \
+             having count(*) between 2 and 10",
+            "from orders group by status having count(*) between 2 and 10 \
+             select status, count(distinct customer)",
+        );
+        lowered(
+            "select o.total, c.name from orders as o \
+             left outer join customers c on id(c) = o.customer \
+             inner join notes n on n.email = c.email \
+             where o.total not in (1, 2) and c.name not like 'a_%' \
+             and c.name ilike '%.B'",
+            "from orders as o left join customers as c on id(c) = o.customer \
+             join notes as n on n.email = c.email \
+             where o.total not in (1, 2) and not regex_match(c.name, '(?s)^a..*$') \
+             and regex_match(c.name, '(?is)^.*\\\\.B$') \
+             select o.total, c.name",
+        );
+    }
+
+    #[test]
+    fn rejects_unsupported_sql() {
+        for (sql, message, at) in [
+            ("delete from t", "`delete` is not supported", "delete"),
+            (
+                "select a from t union select a from u",
+                "`union` is not supported",
+                "union",
+            ),
+            (
+                "select a from (select a from t)",
+                "expected table name, found `(`",
+                "(",
+            ),
+            (
+                "select a from t where a in (select b from u)",
+                "a subquery is not supported",
+                "select b",
+            ),
+            (
+                "select rank() over (order by a) from t",
+                "a window function is not supported",
+                "over",
+            ),
+            (
+                "select cast(a as int) from t",
+                "`cast` is not supported",
+                "cast",
+            ),
+            (
+                "select * from t, u",
+                "a comma between tables is not supported, join them with `join ... on`",
+                ", u",
+            ),
+            (
+                "select * from t right join u on t.a = u.a",
+                "a right join is not supported",
+                "right",
+            ),
+            (
+                "select t.* from t join u on t.a = u.a",
+                "`t.*` is not supported in a query with joins",
+                "t.*",
+            ),
+            (
+                "select distinct count(*) from t",
+                "`distinct` with `group by` or aggregates is not supported",
+                "distinct",
+            ),
+            (
+                "select a from t where a like b",
+                "`like` with a pattern that is not a string is not supported",
+                "b",
+            ),
+            (
+                "select a from t limit 1 fetch next 1 rows only",
+                "`fetch` is not supported",
+                "fetch",
+            ),
+        ] {
+            let error = parse_sql(sql).unwrap_err();
+            assert_eq!(error.message, message, "{sql}");
+            assert!(
+                sql[error.start.offset..].starts_with(at),
+                "{sql}: {error:?}"
+            );
+        }
+        let deep = format!(
+            "select a from t where {}a{}",
+            "(".repeat(100_000),
+            ")".repeat(100_000)
+        );
+        assert_eq!(
+            parse_sql(&deep).unwrap_err().message,
+            "expression nested too deeply"
+        );
+    }
+
+    #[test]
+    fn runs_lowered_queries() {
+        let (mut users, reader) = Collection::new("users");
+        for (id, name, city) in [
+            ("u1", "Ann", "Oslo"),
+            ("u2", "Bo", "Bergen"),
+            ("u3", "anna", "Oslo"),
+        ] {
+            let doc = json!({"name": name, "address": {"city": city}});
+            users.insert(id.into(), doc).unwrap();
+        }
+        users.publish();
+        let map = reader.enter().unwrap();
+        let rows = |sql: &str| -> Vec<Value> {
+            execute(&parse_sql(sql).unwrap(), &map)
+                .unwrap()
+                .into_iter()
+                .map(|row| row.into_value())
+                .collect()
+        };
+        assert_eq!(
+            rows("select id(u) as id from users u where u.name ilike 'ann%' order by u.name"),
+            [json!({"id": "u1"}), json!({"id": "u3"})]
+        );
+        assert_eq!(
+            rows("select distinct address.city as city from users order by city"),
+            [json!({"city": "Bergen"}), json!({"city": "Oslo"})]
+        );
+    }
+}
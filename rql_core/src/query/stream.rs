@@ -0,0 +1,268 @@
+//! Reading the rows of a query a few at a time.
+//!
+//! A [`RowStream`] yields owned rows as an [`Iterator`], and only holds a read guard while it
+//! computes rows, never while the caller consumes them. It works in one of two [`Mode`]s:
+//!
+//! * [`Mode::Chunks`] runs the query once per chunk, entering the collections anew each time
+//!   and resuming after the last row of the previous chunk like [`execute_page`] does. A
+//!   guard is only held for one chunk, so writers can publish between chunks. Each chunk sees
+//!   the epoch that was current when it started, and rows come in the total order of the query:
+//!   its `order by` keys, then the document id. A document that does not change is returned
+//!   exactly once. A document written between chunks is returned, possibly again, only if its
+//!   new sort keys come after the last row returned, and a document removed before its chunk
+//!   is not returned at all. Groups, subqueries and `with` queries are computed anew for
+//!   each chunk, at that chunk's epoch. A chunk resumes reading where the previous one stopped
+//!   when the rows are sorted by id alone and the map [keeps its ids in
+//!   order](crate::rwmap::WriteHandle::order_keys), as collections made with
+//!   [`Collection::with_key_order`](crate::collection::Collection::with_key_order) do, or by
+//!   one key ascending that an ordered index holds. Other queries read what their access path
+//!   finds for every chunk, so one that scans its collection scans it once per chunk.
+//! * [`Mode::Snapshot`] runs the query once, copies its rows out of the map and releases the
+//!   guard before the first row is yielded. Every row comes from the same epoch, at the cost of
+//!   holding the whole result in memory and the guard for the whole run of the query.
+use std::hash::BuildHasher;
+
+use serde_json::Value;
+
+use super::ast::Query;
+use super::{execute_page, Cursor, Page, QueryError};
+use crate::rwmap::ReadHandle;
+
+/// A result row that owns its id and value, see [`Row`](super::Row).
+#[derive(Debug, Clone, PartialEq)]
+pub struct OwnedRow {
+    pub id: Option<String>,
+    pub value: Value,
+}
+
+/// How a [`RowStream`] reads the collections, see the [module docs](self).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// run the query for at most this many rows at a time, entering the collections anew for
+    /// each chunk.
+    Chunks(usize),
+    /// run the query once on one epoch, and yield its copied rows.
+    Snapshot,
+}
+
+/// Runs a query for one chunk of rows, starting after a cursor.
+pub(crate) type Fetch<'h> =
+    Box<dyn FnMut(&Query, Option<&Cursor>) -> Result<Chunk, QueryError> + 'h>;
+
+/// The rows of one run of the query and the cursor of the next.
+pub(crate) struct Chunk {
+    rows: Vec<OwnedRow>,
+    next: Option<Cursor>,
+}
+
+impl From<Page<'_>> for Chunk {
+    fn from(mut page: Page<'_>) -> Self {
+        let next = page.next.take();
+        Chunk {
+            rows: owned_rows(page),
+            next,
+        }
+    }
+}
+
+/// The rows of `page`, copied out of the map.
+pub(crate) fn owned_rows(page: Page<'_>) -> Vec<OwnedRow> {
+    page.rows
+        .into_iter()
+        .map(|row| OwnedRow {
+            id: row.id.map(str::to_owned),
+            value: row.value.into_owned(),
+        })
+        .collect()
+}
+
+/// The rows of a query, computed a chunk at a time as they are consumed.
+///
+/// Yields an error at most once, as its last item.
+pub struct RowStream<'h> {
+    query: Query,
+    mode: Mode,
+    fetch: Fetch<'h>,
+    rows: std::vec::IntoIter<OwnedRow>,
+    cursor: Option<Cursor>,
+    /// rows the query's `limit` still allows.
+    remaining: Option<u64>,
+    done: bool,
+}
+
+impl<'h> RowStream<'h> {
+    /// Stream `query` over the collection `handle` reads, see [`execute_page`] for which
+    /// queries run on a single collection. A destroyed map has no rows.
+    pub fn new<M, S>(query: &Query, handle: &'h ReadHandle<String, Value, M, S>, mode: Mode) -> Self
+    where
+        M: Clone + Default + 'static,
+        S: BuildHasher + Clone + Default,
+    {
+        Self::with_fetch(
+            query,
+            mode,
+            Box::new(move |query, cursor| match handle.enter() {
+                Some(map) => Ok(execute_page(query, &map, cursor)?.into()),
+                None => Ok(Chunk {
+                    rows: Vec::new(),
+                    next: None,
+                }),
+            }),
+        )
+    }
+
+    /// Stream `query`, getting each chunk from `fetch`.
+    pub(crate) fn with_fetch(query: &Query, mode: Mode, fetch: Fetch<'h>) -> Self {
+        RowStream {
+            query: query.clone(),
+            mode,
+            fetch,
+            rows: Vec::new().into_iter(),
+            cursor: None,
+            remaining: query.limit,
+            done: false,
+        }
+    }
+
+    /// Compute the next chunk of rows.
+    fn fetch(&mut self) -> Result<(), QueryError> {
+        let mut query = self.query.clone();
+        let chunked = match self.mode {
+            // the plan of an explain query is one row
+            Mode::Chunks(size) if query.explain.is_none() => {
+                let size = size.max(1) as u64;
+                query.limit = Some(self.remaining.map_or(size, |remaining| remaining.min(size)));
+                true
+            }
+            _ => false,
+        };
+        let chunk = (self.fetch)(&query, self.cursor.as_ref())?;
+        if let Some(remaining) = &mut self.remaining {
+            *remaining = remaining.saturating_sub(chunk.rows.len() as u64);
+        }
+        self.cursor = chunk.next;
+        self.done = !chunked || self.cursor.is_none() || self.remaining == Some(0);
+        self.rows = chunk.rows.into_iter();
+        Ok(())
+    }
+}
+
+impl Iterator for RowStream<'_> {
+    type Item = Result<OwnedRow, QueryError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(row) = self.rows.next() {
+                return Some(Ok(row));
+            }
+            if self.done {
+                return None;
+            }
+            if let Err(err) = self.fetch() {
+                self.done = true;
+                return Some(Err(err));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::*;
+    use crate::index::IndexDef;
+    use crate::path::Path;
+    use crate::query::{execute_page_limited, parse, Limits};
+    use crate::rwmap::RwMap;
+
+    #[test]
+    fn streams_in_chunks_between_publishes() {
+        let (mut w, r) = RwMap::default::<String, Value>();
+        for i in 0..10 {
+            w.insert(format!("{i}"), json!({ "n": i }));
+        }
+        w.publish();
+
+        let query = parse("from t where n >= 2 select n order by n desc limit 6").unwrap();
+        let mut stream = RowStream::new(&query, &r, Mode::Chunks(4));
+        let mut seen = Vec::new();
+        for _ in 0..4 {
+            seen.push(stream.next().unwrap().unwrap().value["n"].clone());
+        }
+        // no guard is held between chunks, so the writer can publish
+        w.insert("10".into(), json!({ "n": 10 }));
+        w.insert("6".into(), json!({ "n": 6.5 }));
+        w.remove("5".into());
+        w.publish();
+        seen.extend(stream.map(|row| row.unwrap().value["n"].clone()));
+        assert_eq!(
+            seen,
+            [json!(9), json!(8), json!(7), json!(6), json!(4), json!(3)]
+        );
+
+        let query = parse("from t where n < 3").unwrap();
+        let ids: Vec<_> = RowStream::new(&query, &r, Mode::Chunks(1))
+            .map(|row| row.unwrap().id.unwrap())
+            .collect();
+        assert_eq!(ids, ["0", "1", "2"]);
+    }
+
+    #[test]
+    fn resumes_chunks_where_the_last_stopped() {
+        let (mut w, r) = RwMap::default::<String, Value>();
+        for i in 0..100 {
+            w.insert(format!("{i:03}"), json!({ "n": 99 - i }));
+        }
+        w.order_keys();
+        w.create_index(IndexDef::ordered("by_n", Path::parse("n").unwrap()));
+        w.publish();
+        // each chunk reads its own rows, the one after them and in index order the last of the
+        // chunk before, which may share its key with the first
+        let limits = Limits::new().max_rows_scanned(6);
+        let stream = |source: &str| {
+            let fetch: Fetch = Box::new(|query, cursor| {
+                let map = r.enter().unwrap();
+                Ok(execute_page_limited(query, &map, cursor, &limits)?.into())
+            });
+            RowStream::with_fetch(&parse(source).unwrap(), Mode::Chunks(4), fetch)
+                .map(|row| row.unwrap().value["n"].as_u64().unwrap())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(stream("from t"), (0..100).rev().collect::<Vec<_>>());
+        assert_eq!(stream("from t order by n"), (0..100).collect::<Vec<_>>());
+        assert_eq!(
+            stream("from t order by n limit 10"),
+            (0..10).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn streams_a_snapshot() {
+        let (mut w, r) = RwMap::default::<String, Value>();
+        for i in 0..5 {
+            w.insert(format!("{i}"), json!({ "n": i, "odd": i % 2 == 1 }));
+        }
+        w.publish();
+
+        let query = parse("from t group by odd select odd, count(*) as n order by odd").unwrap();
+        let mut stream = RowStream::new(&query, &r, Mode::Snapshot);
+        let first = stream.next().unwrap().unwrap();
+        w.insert("5".into(), json!({ "n": 5, "odd": true }));
+        w.publish();
+        assert_eq!(first.value, json!({"odd": false, "n": 3}));
+        assert_eq!(
+            stream.next().unwrap().unwrap().value,
+            json!({"odd": true, "n": 2})
+        );
+        assert!(stream.next().is_none());
+
+        let query = parse("from t where f(n)").unwrap();
+        let mut stream = RowStream::new(&query, &r, Mode::Chunks(2));
+        assert!(matches!(
+            stream.next(),
+            Some(Err(QueryError::UnknownFunction(_)))
+        ));
+        assert!(stream.next().is_none());
+    }
+}
@@ -0,0 +1,941 @@
+//! Subqueries and `with` queries.
+//!
+//! A statement first runs its `with` queries in order, each into a temporary collection that
+//! later `with` queries, subqueries and the main query read by name, in place of any
+//! collection of the same name. Then every subquery is replaced by what it returns before the
+//! query holding it is compiled:
+//!
+//! * a subquery that only reads its own sources runs once: `(from ...)` becomes the value it
+//!   selects and `x in (from ...)` a list of values, which picks an index like a written-out
+//!   list.
+//! * a correlated subquery reads a source of the query around it, through `id(name)` or a path
+//!   starting with `name`. If it only does so in `<inner> = <outer>` conditions of its `where`
+//!   clause, and has no `group by` or `having`, it is decorrelated: it runs once without those
+//!   conditions, grouped by `<inner>`, and each row of the outer query looks its `<outer>` key
+//!   up in the results.
+//! * any other correlated subquery runs once for each distinct combination of the outer
+//!   values it reads, found by scanning the `from` source of the query around it. Such a
+//!   subquery may only read that source.
+//!
+//! A subquery can only refer to the query right around it. Every collection is read through
+//! the same lookup, so a statement run on a [`Snapshot`](crate::database::Snapshot) sees one
+//! epoch throughout, however often its subqueries run.
+use std::convert::Infallible;
+use std::hash::BuildHasher;
+
+use hashbrown::{HashMap, HashSet};
+use serde_json::{Map, Value};
+
+use super::aggregate::Grouping;
+use super::ast::{BinaryOp, Cte, Expr, Query, Select, SelectItem};
+use super::eval::{canonical, collation_of, eval};
+use super::exec::{self, Row};
+use super::join::{Documents, IDS};
+use super::plan::conjuncts;
+use super::{Budget, QueryError};
+use crate::path::{Path, Segment};
+use crate::rwmap::{MapReadRef, ReadHandle, RwMap, WriteHandle};
+
+/// The stored collections `query` reads, its `with` queries and subqueries included, in order
+/// of first use.
+pub(crate) fn collections(query: &Query) -> Vec<String> {
+    fn visit(query: &Query, scope: &mut Vec<String>, found: &mut Vec<String>) {
+        let depth = scope.len();
+        for cte in &query.with {
+            visit(&cte.query, scope, found);
+            scope.push(cte.name.clone());
+        }
+        let sources =
+            std::iter::once(&query.from).chain(query.joins.iter().map(|join| &join.source));
+        for source in sources {
+            if !scope.contains(&source.collection) && !found.contains(&source.collection) {
+                found.push(source.collection.clone());
+            }
+        }
+        for subquery in subqueries(query) {
+            visit(subquery, scope, found);
+        }
+        scope.truncate(depth);
+    }
+    let mut found = Vec::new();
+    visit(query, &mut Vec::new(), &mut found);
+    found
+}
+
+/// Returns true if `query` has `with` queries or subqueries.
+pub(crate) fn nests(query: &Query) -> bool {
+    !query.with.is_empty() || !subqueries(query).is_empty()
+}
+
+/// The subqueries in the expressions of `query`, not looking into them.
+fn subqueries(query: &Query) -> Vec<&Query> {
+    fn visit<'q>(expr: &'q Expr, found: &mut Vec<&'q Query>) {
+        if let Expr::Subquery(query) | Expr::InSubquery { query, .. } = expr {
+            found.push(query);
+        }
+        for child in expr.children() {
+            visit(child, found);
+        }
+    }
+    let mut found = Vec::new();
+    for expr in query.exprs() {
+        visit(expr, &mut found);
+    }
+    found
+}
+
+/// The rows of the `with` queries of a statement, each in a map of its own.
+pub(crate) struct Tables<M, S>
+where
+    M: Clone + 'static,
+    S: BuildHasher + Clone,
+{
+    tables: Vec<Table<M, S>>,
+}
+
+struct Table<M, S>
+where
+    M: Clone + 'static,
+    S: BuildHasher + Clone,
+{
+    name: String,
+    read: ReadHandle<String, Value, M, S>,
+    /// kept for the map to live as long as `read`.
+    _write: WriteHandle<String, Value, M, S>,
+}
+
+impl<M, S> Tables<M, S>
+where
+    M: Clone + Default + 'static,
+    S: BuildHasher + Clone + Default,
+{
+    /// Run each of `with` in turn within `budget`, finding collections with `lookup` and the
+    /// rows of the queries before it.
+    ///
+    /// A row keeps the id of its document if every row has a distinct one, and is numbered
+    /// otherwise.
+    pub(crate) fn new<'m, 'g>(
+        with: &[Cte],
+        lookup: &dyn Fn(&str) -> Option<Documents<'m, 'g, M, S>>,
+        budget: &Budget,
+    ) -> Result<Self, QueryError> {
+        let mut tables = Tables { tables: Vec::new() };
+        for cte in with {
+            let rows: Vec<(Option<String>, Value)> = {
+                let maps = tables.enter();
+                let lookup = shadowed(&maps, lookup);
+                let page = budget.nested(|| exec::run(&cte.query, &lookup, None, budget))?;
+                page.rows
+                    .into_iter()
+                    .map(|row| (row.id.map(str::to_owned), row.into_value()))
+                    .collect()
+            };
+            let mut ids = HashSet::new();
+            let keep = rows
+                .iter()
+                .all(|(id, _)| id.as_ref().is_some_and(|id| ids.insert(id.clone())));
+            let (mut write, read) = RwMap::maybe_with_meta_and_hasher(None, None);
+            for (i, (id, row)) in rows.into_iter().enumerate() {
+                let id = match id {
+                    Some(id) if keep => id,
+                    _ => i.to_string(),
+                };
+                write.insert(id, row);
+            }
+            write.publish();
+            tables.tables.push(Table {
+                name: cte.name.clone(),
+                read,
+                _write: write,
+            });
+        }
+        Ok(tables)
+    }
+
+    /// The maps of the tables by name, to look up with [`shadowed`].
+    pub(crate) fn enter(&self) -> Vec<Entered<'_, M, S>> {
+        self.tables
+            .iter()
+            .map(|table| {
+                let map = table.read.enter().expect("tables are published");
+                (table.name.as_str(), map)
+            })
+            .collect()
+    }
+}
+
+/// The map of a table and its name.
+pub(crate) type Entered<'a, M, S> = (&'a str, MapReadRef<'a, String, Value, M, S>);
+
+/// Finds collections by name.
+pub(crate) type Lookup<'a, M, S> = Box<dyn Fn(&str) -> Option<Documents<'a, 'a, M, S>> + 'a>;
+
+/// `lookup`, with the maps of `tables` in place of collections of the same name.
+pub(crate) fn shadowed<'a, 'm: 'a, 'g: 'a, M, S>(
+    tables: &'a [Entered<'a, M, S>],
+    lookup: &'a dyn Fn(&str) -> Option<Documents<'m, 'g, M, S>>,
+) -> Lookup<'a, M, S>
+where
+    S: BuildHasher,
+{
+    Box::new(
+        move |name| match tables.iter().find(|(table, _)| *table == name) {
+            Some((_, map)) => Some(map),
+            None => lookup(name).map(|map| map as Documents<'a, 'a, M, S>),
+        },
+    )
+}
+
+/// `query` with each of its subqueries replaced by what it returns, running them on the
+/// collections found by `lookup` within `budget`.
+pub(crate) fn resolve<'m, 'g, M, S>(
+    query: &Query,
+    lookup: &dyn Fn(&str) -> Option<Documents<'m, 'g, M, S>>,
+    budget: &Budget,
+) -> Result<Query, QueryError>
+where
+    M: Clone + Default + 'static,
+    S: BuildHasher + Clone + Default,
+{
+    let outer = Outer::new(query);
+    let resolver = Resolver {
+        outer,
+        lookup,
+        budget,
+    };
+    query.clone().try_map_exprs(|expr| resolver.expr(expr))
+}
+
+/// `query` with each subquery replaced by a constant used the same way, to check the rest of
+/// it before the subqueries can run.
+pub(crate) fn stubbed(query: &Query) -> Query {
+    fn stub(expr: &Expr) -> Expr {
+        match expr {
+            Expr::Subquery(_) => Expr::Literal(Value::Null),
+            Expr::InSubquery { expr, negated, .. } => Expr::In {
+                expr: Box::new(stub(expr)),
+                list: Vec::new(),
+                negated: *negated,
+            },
+            _ => expr
+                .clone()
+                .try_map_children(|child| Ok::<_, Infallible>(stub(&child)))
+                .unwrap_or_else(|never| match never {}),
+        }
+    }
+    query
+        .clone()
+        .try_map_exprs(|expr| Ok::<_, Infallible>(stub(expr)))
+        .unwrap_or_else(|never| match never {})
+}
+
+/// An expression standing for a missing value, which the syntax has no literal for.
+fn missing() -> Expr {
+    Expr::Case {
+        branches: Vec::new(),
+        otherwise: None,
+    }
+}
+
+/// The query around a subquery.
+struct Outer<'q> {
+    query: &'q Query,
+    /// the source names a subquery refers to it by.
+    names: Vec<&'q str>,
+    joined: bool,
+}
+
+impl<'q> Outer<'q> {
+    fn new(query: &'q Query) -> Self {
+        Outer {
+            query,
+            names: names(query),
+            joined: !query.joins.is_empty(),
+        }
+    }
+
+    /// `expr`, which only reads the sources of the query, as that query reads it: without
+    /// joins its paths lose the source name and `id(name)` becomes `id()`.
+    fn form(&self, expr: &Expr) -> Expr {
+        if self.joined {
+            return expr.clone();
+        }
+        let strip = |path: &Path| Path::from_segments(path.segments()[1..].to_vec());
+        match expr {
+            Expr::Call { name, .. } if name == "id" => Expr::Call {
+                name: name.clone(),
+                args: Vec::new(),
+            },
+            Expr::Path(path) => Expr::Path(strip(path)),
+            Expr::Exists { path, negated } => Expr::Exists {
+                path: strip(path),
+                negated: *negated,
+            },
+            Expr::Quantified {
+                quantifier,
+                path,
+                predicate,
+            } => Expr::Quantified {
+                quantifier: *quantifier,
+                path: strip(path),
+                predicate: Box::new(self.form(predicate)),
+            },
+            _ => expr
+                .clone()
+                .try_map_children(|child| Ok::<_, Infallible>(self.form(&child)))
+                .unwrap_or_else(|never| match never {}),
+        }
+    }
+}
+
+/// The names the sources of `query` are bound to: without joins only the `from` source.
+fn names(query: &Query) -> Vec<&str> {
+    let mut names = vec![query.from.name()];
+    if !query.joins.is_empty() {
+        names.extend(
+            query
+                .unnest
+                .iter()
+                .filter_map(|unnest| unnest.alias.as_deref()),
+        );
+        names.extend(query.joins.iter().map(|join| join.source.name()));
+    }
+    names
+}
+
+/// What a subquery reads from the query around it.
+struct Reads<'a> {
+    outer: &'a [&'a str],
+    /// names of the subquery, which hide the same names of the outer query.
+    inner: Vec<&'a str>,
+}
+
+impl Reads<'_> {
+    fn names(&self, name: &str) -> bool {
+        self.outer.contains(&name) && !self.inner.contains(&name)
+    }
+
+    /// The source name a path of the subquery refers to the outer query by, if it does.
+    fn path(&self, path: &Path) -> bool {
+        matches!(path.segments().first(), Some(Segment::Key(key)) if self.names(key))
+    }
+
+    /// The largest parts of `expr` reading the outer query, adding them to `found`. Returns
+    /// true if `expr` also reads the subquery's own rows.
+    fn collect(&self, expr: &Expr, found: &mut Vec<Expr>) -> Result<bool, QueryError> {
+        match expr {
+            Expr::Call { name, args } if name == "id" => match args.as_slice() {
+                [Expr::Path(path)] if self.path(path) => {
+                    found.push(expr.clone());
+                    Ok(false)
+                }
+                _ => Ok(true),
+            },
+            Expr::Path(path) | Expr::Exists { path, .. } if self.path(path) => {
+                found.push(expr.clone());
+                Ok(false)
+            }
+            Expr::Quantified {
+                path, predicate, ..
+            } if self.path(path) => {
+                if self.collect(predicate, &mut Vec::new())? {
+                    return Err(QueryError::Invalid(format!(
+                        "`{expr}` mixes the outer query with the subquery"
+                    )));
+                }
+                found.push(expr.clone());
+                Ok(false)
+            }
+            Expr::Path(_)
+            | Expr::Exists { .. }
+            | Expr::Quantified { .. }
+            | Expr::Aggregate { .. } => Ok(true),
+            Expr::Subquery(query) | Expr::InSubquery { query, .. } => {
+                let mut inner = self.inner.clone();
+                inner.extend(names(query));
+                let nested = Reads {
+                    outer: self.outer,
+                    inner,
+                };
+                if !nested.all(query)?.is_empty() {
+                    return Err(QueryError::Invalid(format!(
+                        "subquery `({query})` can only refer to the query right around it"
+                    )));
+                }
+                match expr {
+                    Expr::InSubquery { expr, .. } => self.collect(expr, found),
+                    _ => Ok(true),
+                }
+            }
+            _ => {
+                let mut own = false;
+                for child in expr.children() {
+                    own |= self.collect(child, found)?;
+                }
+                Ok(own)
+            }
+        }
+    }
+
+    /// Everything `query` reads from the outer query.
+    fn all(&self, query: &Query) -> Result<Vec<Expr>, QueryError> {
+        let mut found = Vec::new();
+        for expr in query.exprs() {
+            self.collect(expr, &mut found)?;
+        }
+        Ok(found)
+    }
+}
+
+/// How a subquery is used.
+enum Use {
+    /// for the one value it selects.
+    Scalar,
+    /// `<expr> [not] in (<query>)`.
+    In(Expr, bool),
+}
+
+struct Resolver<'a, 'q, 'm, 'g, M, S>
+where
+    S: BuildHasher,
+{
+    outer: Outer<'q>,
+    lookup: &'a dyn Fn(&str) -> Option<Documents<'m, 'g, M, S>>,
+    budget: &'a Budget<'a>,
+}
+
+impl<M, S> Resolver<'_, '_, '_, '_, M, S>
+where
+    M: Clone + Default + 'static,
+    S: BuildHasher + Clone + Default,
+{
+    fn expr(&self, expr: &Expr) -> Result<Expr, QueryError> {
+        match expr {
+            Expr::Subquery(query) => self.subquery(query, Use::Scalar),
+            Expr::InSubquery {
+                expr,
+                query,
+                negated,
+            } => self.subquery(query, Use::In(self.expr(expr)?, *negated)),
+            _ => expr.clone().try_map_children(|child| self.expr(&child)),
+        }
+    }
+
+    fn subquery(&self, query: &Query, usage: Use) -> Result<Expr, QueryError> {
+        match &query.select {
+            Select::Fields(items) if items.len() == 1 => {}
+            _ => {
+                return Err(QueryError::Invalid(format!(
+                    "subquery `({query})` must select exactly one value"
+                )))
+            }
+        }
+        let reads = Reads {
+            outer: &self.outer.names,
+            inner: names(query),
+        };
+        let outer = reads.all(query)?;
+        if outer.is_empty() {
+            let values = self.values(query)?;
+            return Ok(match usage {
+                Use::Scalar => match scalar(query, values)? {
+                    Some(value) => Expr::Literal(value),
+                    None => missing(),
+                },
+                Use::In(expr, negated) => Expr::In {
+                    expr: Box::new(expr),
+                    list: values.into_iter().flatten().map(Expr::Literal).collect(),
+                    negated,
+                },
+            });
+        }
+        let (keys, table, default) = match self.decorrelated(query, &reads, &usage)? {
+            Some(decorrelated) => decorrelated,
+            None => self.memoized(query, outer, &usage)?,
+        };
+        let key = Expr::Object(
+            keys.into_iter()
+                .enumerate()
+                .map(|(i, key)| (format!("#k{i}"), self.outer.form(&key)))
+                .collect(),
+        );
+        let mut args = vec![Expr::Literal(Value::Object(table)), key];
+        args.extend(default.map(Expr::Literal));
+        let lookup = Expr::Call {
+            name: "#lookup".to_owned(),
+            args,
+        };
+        Ok(match usage {
+            Use::Scalar => lookup,
+            Use::In(expr, negated) => {
+                let contains = Expr::Call {
+                    name: "array_contains".to_owned(),
+                    args: vec![lookup, expr],
+                };
+                if negated {
+                    !contains
+                } else {
+                    contains
+                }
+            }
+        })
+    }
+
+    /// Run a subquery, whose rows are not results of the statement.
+    fn run(&self, query: &Query) -> Result<exec::Page<'static>, QueryError> {
+        let page = self
+            .budget
+            .nested(|| exec::run(query, self.lookup, None, self.budget))?;
+        Ok(page.detached())
+    }
+
+    /// The value each row of `query` selects, missing for rows without one.
+    fn values(&self, query: &Query) -> Result<Vec<Option<Value>>, QueryError> {
+        let rows = self.run(query)?.rows;
+        Ok(rows.into_iter().map(value).collect())
+    }
+
+    /// Run `query` once without its `<inner> = <outer>` conditions, grouped by `<inner>`.
+    /// Returns the `<outer>` keys, the result for each key and the result for keys without
+    /// rows, or `None` if the subquery reads the outer query any other way.
+    fn decorrelated(
+        &self,
+        query: &Query,
+        reads: &Reads,
+        usage: &Use,
+    ) -> Result<Option<Decorrelated>, QueryError> {
+        let aggregate = Grouping::applies(query);
+        let limited = query.limit.is_some() || query.offset.is_some();
+        if !query.group_by.is_empty() || query.having.is_some() || (aggregate && limited) {
+            return Ok(None);
+        }
+        let Some(filter) = &query.filter else {
+            return Ok(None);
+        };
+        let mut rest = Vec::new();
+        let mut keys: Vec<(Expr, Expr)> = Vec::new();
+        for conjunct in conjuncts(filter) {
+            let mut found = Vec::new();
+            reads.collect(conjunct, &mut found)?;
+            if found.is_empty() {
+                rest.push(conjunct.clone());
+                continue;
+            }
+            let Expr::Binary {
+                left,
+                op: BinaryOp::Eq,
+                right,
+            } = conjunct
+            else {
+                return Ok(None);
+            };
+            let side = |expr: &Expr| {
+                let mut found = Vec::new();
+                let own = reads.collect(expr, &mut found)?;
+                Ok::<_, QueryError>((own, found.is_empty()))
+            };
+            let (inner, outer) = match (side(left)?, side(right)?) {
+                ((_, true), (false, false)) => (left, right),
+                ((false, false), (_, true)) => (right, left),
+                _ => return Ok(None),
+            };
+            // a collated comparison matches keys by their collation key
+            let (mut inner, mut outer) = (inner.as_ref().clone(), outer.as_ref().clone());
+            if let Some(collation) = collation_of([&inner, &outer]) {
+                for side in [&mut inner, &mut outer] {
+                    if !matches!(side, Expr::Collate { .. }) {
+                        *side = Expr::Collate {
+                            expr: Box::new(side.clone()),
+                            collation,
+                        };
+                    }
+                }
+            }
+            keys.push((inner, outer));
+        }
+        let mut elsewhere = query.clone();
+        elsewhere.filter = None;
+        if !reads.all(&elsewhere)?.is_empty() {
+            return Ok(None);
+        }
+
+        let mut grouped = query.clone();
+        grouped.filter = rest
+            .into_iter()
+            .reduce(|all, conjunct| Expr::binary(all, BinaryOp::And, conjunct));
+        if let Select::Fields(items) = &mut grouped.select {
+            items.extend(keys.iter().enumerate().map(|(i, (inner, _))| SelectItem {
+                expr: inner.clone(),
+                alias: Some(format!("#k{i}")),
+            }));
+        }
+        if aggregate {
+            grouped.group_by = keys.iter().map(|(inner, _)| inner.clone()).collect();
+            grouped.order_by.clear();
+        } else if limited {
+            // sorted like the subquery, to page each key's rows the same way
+            grouped.limit = Some(u64::MAX);
+            grouped.offset = None;
+        }
+        let mut rows: HashMap<String, Vec<Option<Value>>> = HashMap::new();
+        let mut order = Vec::new();
+        'rows: for row in self.run(&grouped)?.rows {
+            let Value::Object(mut fields) = row.into_value() else {
+                continue;
+            };
+            let mut key = Map::new();
+            for i in 0..keys.len() {
+                let name = format!("#k{i}");
+                // a missing key equals nothing
+                let Some(value) = fields.remove(&name) else {
+                    continue 'rows;
+                };
+                key.insert(name, value);
+            }
+            let key = canonical(&Value::Object(key));
+            if !rows.contains_key(&key) {
+                order.push(key.clone());
+            }
+            rows.entry(key)
+                .or_default()
+                .push(fields.into_iter().next().map(|(_, value)| value));
+        }
+
+        let mut table = Map::new();
+        for key in order {
+            let mut values = rows.remove(&key).unwrap_or_default();
+            if !aggregate {
+                let offset = query.offset.unwrap_or(0) as usize;
+                let limit = query.limit.map_or(usize::MAX, |limit| limit as usize);
+                values = values.into_iter().skip(offset).take(limit).collect();
+            }
+            if let Some(value) = result(query, usage, values)? {
+                table.insert(key, value);
+            }
+        }
+        let default = match usage {
+            Use::In(..) => Some(Value::Array(Vec::new())),
+            // what the aggregates give over no rows, such as a count of 0
+            Use::Scalar if aggregate => {
+                let mut empty = query.clone();
+                empty.filter = Some(Expr::literal(false));
+                scalar(query, self.values(&empty)?)?
+            }
+            Use::Scalar => None,
+        };
+        let keys = keys.into_iter().map(|(_, outer)| outer).collect();
+        Ok(Some((keys, table, default)))
+    }
+
+    /// Run `query` once for each distinct combination of the values of `outer` among the
+    /// documents of the outer `from` source.
+    fn memoized(
+        &self,
+        query: &Query,
+        outer: Vec<Expr>,
+        usage: &Use,
+    ) -> Result<Decorrelated, QueryError> {
+        let from = self.outer.query.from.name();
+        let unsupported = || {
+            QueryError::Invalid(format!(
+                "subquery `({query})` can only read the outer query through `=` conditions \
+                 of its where clause, or else only its `from` source `{from}`"
+            ))
+        };
+        if !self.outer.query.unnest.is_empty() {
+            return Err(unsupported());
+        }
+        let mut refs: Vec<Expr> = Vec::new();
+        for expr in outer {
+            let name = match &expr {
+                Expr::Call { args, .. } => match args.as_slice() {
+                    [Expr::Path(path)] => path.segments().first(),
+                    _ => None,
+                },
+                Expr::Path(path) | Expr::Exists { path, .. } | Expr::Quantified { path, .. } => {
+                    path.segments().first()
+                }
+                _ => None,
+            };
+            if !matches!(name, Some(Segment::Key(name)) if name == from) {
+                return Err(unsupported());
+            }
+            if !refs.contains(&expr) {
+                refs.push(expr);
+            }
+        }
+
+        let collection = &self.outer.query.from.collection;
+        let map = (self.lookup)(collection)
+            .ok_or_else(|| QueryError::UnknownCollection(collection.clone()))?;
+        let mut table = Map::new();
+        let mut seen = HashSet::new();
+        for (id, doc) in self.budget.scanning(map.iter()) {
+            let row = serde_json::json!({ IDS: { from: id }, from: doc.as_ref() });
+            let values: Vec<Option<Value>> = refs
+                .iter()
+                .map(|expr| match expr {
+                    Expr::Call { .. } => Some(Value::from(id.as_str())),
+                    _ => eval(expr, &row).map(|value| value.into_owned()),
+                })
+                .collect();
+            let key: Map<String, Value> = values
+                .iter()
+                .enumerate()
+                .filter_map(|(i, value)| Some((format!("#k{i}"), value.clone()?)))
+                .collect();
+            let key = canonical(&Value::Object(key));
+            if !seen.insert(key.clone()) {
+                continue;
+            }
+            let bound = query
+                .clone()
+                .try_map_exprs(|expr| Ok::<_, QueryError>(replace(expr, &refs, &values)))?;
+            if let Some(value) = result(query, usage, self.values(&bound)?)? {
+                table.insert(key, value);
+            }
+        }
+        self.budget.check()?;
+        let default = matches!(usage, Use::In(..)).then(|| Value::Array(Vec::new()));
+        Ok((refs, table, default))
+    }
+}
+
+/// The keys a decorrelated subquery is looked up by, its result for each key, and its result
+/// for keys it has no entry for.
+type Decorrelated = (Vec<Expr>, Map<String, Value>, Option<Value>);
+
+/// `expr` with each of `refs` replaced by its value.
+fn replace(expr: &Expr, refs: &[Expr], values: &[Option<Value>]) -> Expr {
+    if let Some(i) = refs.iter().position(|found| found == expr) {
+        return match &values[i] {
+            Some(value) => Expr::Literal(value.clone()),
+            None => missing(),
+        };
+    }
+    expr.clone()
+        .try_map_children(|child| Ok::<_, Infallible>(replace(&child, refs, values)))
+        .unwrap_or_else(|never| match never {})
+}
+
+/// The value a row of a subquery selects.
+fn value(row: Row) -> Option<Value> {
+    match row.into_value() {
+        Value::Object(fields) => fields.into_iter().next().map(|(_, value)| value),
+        _ => None,
+    }
+}
+
+/// What a subquery returning `values` stands for: its one value, or the list of them.
+fn result(
+    query: &Query,
+    usage: &Use,
+    values: Vec<Option<Value>>,
+) -> Result<Option<Value>, QueryError> {
+    match usage {
+        Use::Scalar => scalar(query, values),
+        Use::In(..) => Ok(Some(Value::Array(values.into_iter().flatten().collect()))),
+    }
+}
+
+fn scalar(query: &Query, values: Vec<Option<Value>>) -> Result<Option<Value>, QueryError> {
+    if values.len() > 1 {
+        return Err(QueryError::Invalid(format!(
+            "subquery `({query})` returned more than one row"
+        )));
+    }
+    Ok(values.into_iter().next().flatten())
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::*;
+    use crate::query::parse;
+
+    type Handles = (
+        WriteHandle<String, Value, (), ahash::RandomState>,
+        ReadHandle<String, Value, (), ahash::RandomState>,
+    );
+
+    fn map(docs: &[(&str, Value)]) -> Handles {
+        let (mut w, r) = RwMap::default::<String, Value>();
+        for (id, doc) in docs {
+            w.insert(id.to_string(), doc.clone());
+        }
+        w.publish();
+        (w, r)
+    }
+
+    /// Run `query` on users and orders, returning the selected rows.
+    fn run(query: &str) -> Result<Vec<Value>, QueryError> {
+        let (_uw, users) = map(&[
+            ("ann", json!({"name": "Ann", "city": "Oslo"})),
+            ("bob", json!({"name": "bob", "city": "Rome"})),
+            ("cy", json!({"name": "Cy", "city": "Oslo"})),
+        ]);
+        let (_ow, orders) = map(&[
+            ("1", json!({"customer": "ann", "total": 10, "city": "Oslo"})),
+            ("2", json!({"customer": "ann", "total": 30, "city": "Rome"})),
+            ("3", json!({"customer": "ann", "total": 5, "city": "Oslo"})),
+            ("4", json!({"customer": "bob", "total": 20, "city": "Rome"})),
+        ]);
+        let (users, orders) = (users.enter().unwrap(), orders.enter().unwrap());
+        let lookup = |name: &str| match name {
+            "users" => Some(&users),
+            "orders" => Some(&orders),
+            _ => None,
+        };
+        let page = exec::run(&parse(query).unwrap(), &lookup, None, &Budget::unlimited())?;
+        Ok(page.rows.into_iter().map(Row::into_value).collect())
+    }
+
+    fn names(query: &str) -> Vec<Value> {
+        run(query)
+            .unwrap()
+            .into_iter()
+            .map(|row| row["name"].clone())
+            .collect()
+    }
+
+    #[test]
+    fn lists_collections() {
+        let query = parse(
+            "with a as (from orders), b as (from a join users on id(users) = a.customer) \
+             from b where id() in (from notes select id()) order by id()",
+        )
+        .unwrap();
+        assert_eq!(collections(&query), ["orders", "users", "notes"]);
+        assert!(nests(&query));
+        assert!(!nests(&parse("from t where x in (1, 2)").unwrap()));
+    }
+
+    #[test]
+    fn runs_uncorrelated_subqueries_once() {
+        assert_eq!(
+            names(
+                "from users where id() in (from orders where total > 15 select customer) \
+                 order by name"
+            ),
+            [json!("Ann"), json!("bob")]
+        );
+        assert_eq!(
+            names("from users where id() not in (from orders select customer)"),
+            [json!("Cy")]
+        );
+        assert_eq!(
+            run("from users where id() = 'ann' select (from orders select max(total)) as top")
+                .unwrap(),
+            [json!({"top": 30})]
+        );
+        assert_eq!(
+            run("from users where id() = 'ann' select (from orders where total > 99 select total) as t")
+                .unwrap(),
+            [json!({})]
+        );
+    }
+
+    #[test]
+    fn decorrelates_equality_conditions() {
+        let query =
+            parse("from users as u where (from orders where customer = id(u) select count(*)) > 1")
+                .unwrap();
+        let rows = run(&query.to_string()).unwrap();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0]["name"], json!("Ann"));
+        assert_eq!(
+            run("from users as u select name, \
+                 (from orders where customer = id(u) select count(*)) as n order by name")
+            .unwrap(),
+            [
+                json!({"name": "Ann", "n": 3}),
+                json!({"name": "Cy", "n": 0}),
+                json!({"name": "bob", "n": 1}),
+            ]
+        );
+        assert_eq!(
+            run("from users as u where id() = 'ann' select \
+                 (from orders where customer = id(u) select total order by total desc limit 1) as top")
+            .unwrap(),
+            [json!({"top": 30})]
+        );
+        assert_eq!(
+            names(
+                "from users as u where city in \
+                 (from orders where customer = id(u) and total > 1 select city) order by name"
+            ),
+            [json!("Ann"), json!("bob")]
+        );
+        assert_eq!(
+            names(
+                "from users as u where 'ANN' = \
+                 (from users as v where name = upper(u.name) collate nocase select upper(name))"
+            ),
+            [json!("Ann")]
+        );
+    }
+
+    #[test]
+    fn memoizes_other_correlations() {
+        assert_eq!(
+            run("from users as u select name, \
+                 (from orders where total > length(u.name) * 4 select count(*)) as n \
+                 order by name")
+            .unwrap(),
+            [
+                json!({"name": "Ann", "n": 2}),
+                json!({"name": "Cy", "n": 3}),
+                json!({"name": "bob", "n": 2}),
+            ]
+        );
+    }
+
+    #[test]
+    fn runs_with_queries_in_order() {
+        assert_eq!(
+            run(
+                "with counts as (from orders group by customer select customer, count(*) as n), \
+                 busy as (from counts where n > (from counts select avg(n))) \
+                 from users where id() in (from busy select customer) select name"
+            )
+            .unwrap(),
+            [json!({"name": "Ann"})]
+        );
+        // a with query shadows the collection of the same name
+        assert_eq!(
+            run("with users as (from users where city = 'Rome') from users select name").unwrap(),
+            [json!({"name": "bob"})]
+        );
+    }
+
+    #[test]
+    fn rejects_unsupported_subqueries() {
+        for (query, message) in [
+            (
+                "from users where id() in (from orders)",
+                "exactly one value",
+            ),
+            (
+                "from users where (from orders select total) > 1",
+                "more than one row",
+            ),
+            (
+                "from users as u where exists(u.x) and \
+                 (from orders as o where (from orders where total = u.n select total) > 1 \
+                 select count(*)) > 0",
+                "right around it",
+            ),
+            (
+                "from users as u join orders as o on o.customer = id(u) \
+                 where (from orders where total > o.total select count(*)) > 0",
+                "only its `from` source",
+            ),
+        ] {
+            match run(query) {
+                Err(QueryError::Invalid(error)) => assert!(error.contains(message), "{error}"),
+                other => panic!("{query}: {other:?}"),
+            }
+        }
+    }
+}
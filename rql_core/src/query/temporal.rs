@@ -0,0 +1,517 @@
+//! Timestamps, durations and time zones for the temporal functions.
+//!
+//! Documents hold times as ISO-8601 strings, with or without an offset, or as milliseconds
+//! since the Unix epoch. `timestamp(x)` turns either into the *canonical* form: UTC with
+//! exactly three fractional digits, `2024-03-01T12:00:00.000Z`. Canonical timestamps of the
+//! years 0 to 9999 order as strings in the same order as the instants they stand for, so they
+//! compare, sort and group correctly without a separate JSON type. A literal time compared
+//! with a function returning timestamps, as in `timestamp(at) >= '2024-03-01'`, is turned into
+//! the canonical form when the statement starts, whatever its offset and precision.
+//!
+//! A duration is a number of milliseconds, an ISO-8601 duration such as `P1DT12H`, or words
+//! such as `'90 minutes'` or `'1 day 2 hours'`, optionally negative. Days and weeks are 24
+//! hours and 7 days long, except in `date_add`, where they and months and years move along
+//! the calendar of the time zone. Months and years have no fixed length, so only `date_add`
+//! accepts them.
+//!
+//! A time zone is an IANA name such as `Europe/Oslo`, `UTC`, or a fixed offset such as
+//! `+02:00`. Times without an offset are taken to be in the zone given, or else UTC.
+use std::str::FromStr;
+
+use chrono::{
+    DateTime, Datelike, Duration, FixedOffset, Months, NaiveDate, NaiveDateTime, NaiveTime,
+    TimeZone, Timelike, Utc,
+};
+use chrono_tz::Tz;
+use serde_json::Value;
+
+/// Format of canonical timestamps.
+const CANONICAL: &str = "%Y-%m-%dT%H:%M:%S%.3fZ";
+
+/// A time zone, named or at a fixed offset.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Zone {
+    Named(Tz),
+    Fixed(FixedOffset),
+}
+
+impl Zone {
+    pub(crate) fn parse(name: &str) -> Option<Self> {
+        if name.eq_ignore_ascii_case("utc") || name == "Z" {
+            return Some(Zone::Fixed(FixedOffset::east_opt(0)?));
+        }
+        if let Ok(offset) = FixedOffset::from_str(name) {
+            return Some(Zone::Fixed(offset));
+        }
+        Tz::from_str(name).ok().map(Zone::Named)
+    }
+
+    /// The wall-clock time of `time` in this zone.
+    fn local(&self, time: DateTime<Utc>) -> NaiveDateTime {
+        match self {
+            Zone::Named(tz) => time.with_timezone(tz).naive_local(),
+            Zone::Fixed(offset) => time.with_timezone(offset).naive_local(),
+        }
+    }
+
+    /// The instant of the wall-clock time `local` in this zone: the earlier one if the clocks
+    /// went back, and the time an hour later if `local` was skipped when they went forward.
+    fn instant(&self, local: NaiveDateTime) -> Option<DateTime<Utc>> {
+        let earliest = |local: NaiveDateTime| match self {
+            Zone::Named(tz) => tz
+                .from_local_datetime(&local)
+                .earliest()
+                .map(|time| time.with_timezone(&Utc)),
+            Zone::Fixed(offset) => offset
+                .from_local_datetime(&local)
+                .earliest()
+                .map(|time| time.with_timezone(&Utc)),
+        };
+        earliest(local).or_else(|| earliest(local + Duration::hours(1)))
+    }
+}
+
+/// `time` in canonical form, `None` outside the years 0 to 9999.
+pub(crate) fn format(time: DateTime<Utc>) -> Option<Value> {
+    (0..=9999)
+        .contains(&time.year())
+        .then(|| Value::String(time.format(CANONICAL).to_string()))
+}
+
+/// The instant `value` stands for: an ISO-8601 date or date and time, or milliseconds since
+/// the epoch. Times without an offset are in `zone`, or else UTC.
+pub(crate) fn parse(value: &Value, zone: Option<Zone>) -> Option<DateTime<Utc>> {
+    let text = match value {
+        Value::Number(n) => {
+            let millis = match n.as_i64() {
+                Some(millis) => millis,
+                None => n.as_f64().filter(|f| f.abs() < 9.2e18)?.floor() as i64,
+            };
+            return DateTime::from_timestamp_millis(millis);
+        }
+        Value::String(text) => text.trim(),
+        _ => return None,
+    };
+    // RFC 3339 wants seconds, these take a time to the minute
+    let utc = text.strip_suffix('Z').map(|text| format!("{text}+00:00"));
+    let offset = ["%Y-%m-%dT%H:%M%:z", "%Y-%m-%d %H:%M%:z"]
+        .iter()
+        .find_map(|format| DateTime::parse_from_str(utc.as_deref().unwrap_or(text), format).ok());
+    if let Some(time) = DateTime::parse_from_rfc3339(text).ok().or(offset) {
+        return Some(time.with_timezone(&Utc));
+    }
+    let zone = zone.unwrap_or(Zone::Fixed(FixedOffset::east_opt(0)?));
+    let local = [
+        "%Y-%m-%dT%H:%M:%S%.f",
+        "%Y-%m-%d %H:%M:%S%.f",
+        "%Y-%m-%dT%H:%M",
+    ]
+    .iter()
+    .find_map(|format| NaiveDateTime::parse_from_str(text, format).ok())
+    .or_else(|| {
+        let date = NaiveDate::parse_from_str(text, "%Y-%m-%d").ok()?;
+        Some(date.and_time(NaiveTime::MIN))
+    })?;
+    zone.instant(local)
+}
+
+/// A length of time: calendar months and days, and milliseconds.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct Span {
+    months: i64,
+    days: i64,
+    millis: i64,
+}
+
+impl Span {
+    /// Parse a number of milliseconds, an ISO-8601 duration or a duration in words.
+    pub(crate) fn parse(value: &Value) -> Option<Self> {
+        match value {
+            Value::Number(n) => Some(Span {
+                millis: n.as_i64()?,
+                ..Span::default()
+            }),
+            Value::String(text) => {
+                let text = text.trim();
+                let (negative, rest) = match text.strip_prefix('-') {
+                    Some(rest) if rest.starts_with(['P', 'p']) => (true, rest),
+                    _ => (false, text),
+                };
+                let span = match rest.strip_prefix(['P', 'p']) {
+                    Some(iso) => Span::iso(iso)?,
+                    None => Span::words(text)?,
+                };
+                Some(if negative { span.negated()? } else { span })
+            }
+            _ => None,
+        }
+    }
+
+    /// The part of an ISO-8601 duration after the `P`.
+    fn iso(text: &str) -> Option<Self> {
+        let mut span = Span::default();
+        let mut time = false;
+        let mut number = String::new();
+        for c in text.chars() {
+            match c.to_ascii_uppercase() {
+                'T' if !time && number.is_empty() => time = true,
+                c if c.is_ascii_digit() || c == '.' => number.push(c),
+                unit if !number.is_empty() => {
+                    let unit = match (unit, time) {
+                        ('Y', false) => "year",
+                        ('M', false) => "month",
+                        ('W', false) => "week",
+                        ('D', false) => "day",
+                        ('H', true) => "hour",
+                        ('M', true) => "minute",
+                        ('S', true) => "second",
+                        _ => return None,
+                    };
+                    span = span.plus(number.parse().ok()?, unit)?;
+                    number.clear();
+                }
+                _ => return None,
+            }
+        }
+        (number.is_empty() && !text.is_empty() && !text.ends_with(['T', 't'])).then_some(span)
+    }
+
+    /// Pairs of a number and a unit, `1 day 2 hours`.
+    fn words(text: &str) -> Option<Self> {
+        let mut words = text.split_whitespace();
+        let mut span = Span::default();
+        let mut any = false;
+        while let Some(word) = words.next() {
+            // `90min` as well as `90 min`
+            let split = word
+                .find(|c: char| !(c.is_ascii_digit() || c == '.' || c == '-'))
+                .unwrap_or(word.len());
+            let (number, unit) = match word.split_at(split) {
+                (number, "") => (number, words.next()?),
+                split => split,
+            };
+            span = span.plus(number.parse().ok()?, unit)?;
+            any = true;
+        }
+        any.then_some(span)
+    }
+
+    /// This span with `n` of `unit` added.
+    fn plus(self, n: f64, unit: &str) -> Option<Self> {
+        let unit = unit.to_ascii_lowercase();
+        let unit = unit
+            .strip_suffix('s')
+            .filter(|unit| unit.len() > 1)
+            .unwrap_or(&unit);
+        let whole = || (n.fract() == 0.0 && n.abs() < 1e15).then_some(n as i64);
+        let millis = |per: f64| {
+            let millis = (n * per).round();
+            (millis.abs() < 9.2e18).then_some(millis as i64)
+        };
+        let mut span = self;
+        match unit {
+            "year" | "y" => span.months = span.months.checked_add(whole()?.checked_mul(12)?)?,
+            "month" | "mon" => span.months = span.months.checked_add(whole()?)?,
+            "week" | "w" => span.days = span.days.checked_add(whole()?.checked_mul(7)?)?,
+            "day" | "d" => span.days = span.days.checked_add(whole()?)?,
+            "hour" | "h" | "hr" => span.millis = span.millis.checked_add(millis(3_600_000.0)?)?,
+            "minute" | "min" | "m" => span.millis = span.millis.checked_add(millis(60_000.0)?)?,
+            "second" | "sec" | "s" => span.millis = span.millis.checked_add(millis(1000.0)?)?,
+            "millisecond" | "ms" => span.millis = span.millis.checked_add(millis(1.0)?)?,
+            _ => return None,
+        }
+        Some(span)
+    }
+
+    fn negated(self) -> Option<Self> {
+        Some(Span {
+            months: self.months.checked_neg()?,
+            days: self.days.checked_neg()?,
+            millis: self.millis.checked_neg()?,
+        })
+    }
+
+    /// The span in milliseconds, counting days as 24 hours. `None` if it has months.
+    pub(crate) fn fixed(&self) -> Option<i64> {
+        if self.months != 0 {
+            return None;
+        }
+        self.days.checked_mul(86_400_000)?.checked_add(self.millis)
+    }
+
+    /// `time` moved by this span, months and days along the calendar of `zone`.
+    pub(crate) fn add_to(&self, time: DateTime<Utc>, zone: Zone) -> Option<DateTime<Utc>> {
+        let mut time = time;
+        if self.months != 0 || self.days != 0 {
+            let local = zone.local(time);
+            let months = Months::new(u32::try_from(self.months.unsigned_abs()).ok()?);
+            let local = match self.months < 0 {
+                true => local.checked_sub_months(months)?,
+                false => local.checked_add_months(months)?,
+            };
+            let local = local.checked_add_signed(Duration::try_days(self.days)?)?;
+            time = zone.instant(local)?;
+        }
+        time.checked_add_signed(Duration::try_milliseconds(self.millis)?)
+    }
+}
+
+/// The units `date_trunc` rounds down to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Unit {
+    Millisecond,
+    Second,
+    Minute,
+    Hour,
+    Day,
+    /// starting on Monday.
+    Week,
+    Month,
+    Quarter,
+    Year,
+}
+
+impl Unit {
+    const ALL: &'static [(&'static str, Unit)] = &[
+        ("millisecond", Unit::Millisecond),
+        ("second", Unit::Second),
+        ("minute", Unit::Minute),
+        ("hour", Unit::Hour),
+        ("day", Unit::Day),
+        ("week", Unit::Week),
+        ("month", Unit::Month),
+        ("quarter", Unit::Quarter),
+        ("year", Unit::Year),
+    ];
+
+    pub(crate) fn lookup(name: &str) -> Option<Self> {
+        let name = name.strip_suffix(['s', 'S']).unwrap_or(name);
+        Self::ALL
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, unit)| *unit)
+    }
+
+    /// `time` rounded down to the start of its unit on the wall clock of `zone`.
+    pub(crate) fn truncate(self, time: DateTime<Utc>, zone: Zone) -> Option<DateTime<Utc>> {
+        let local = zone.local(time);
+        let date = local.date();
+        let midnight = |date: NaiveDate| date.and_time(NaiveTime::MIN);
+        let local = match self {
+            Unit::Millisecond => {
+                local.with_nanosecond(local.nanosecond() / 1_000_000 * 1_000_000)?
+            }
+            Unit::Second => local.with_nanosecond(0)?,
+            Unit::Minute => local.with_nanosecond(0)?.with_second(0)?,
+            Unit::Hour => local.with_nanosecond(0)?.with_second(0)?.with_minute(0)?,
+            Unit::Day => midnight(date),
+            Unit::Week => {
+                midnight(date - Duration::days(date.weekday().num_days_from_monday().into()))
+            }
+            Unit::Month => midnight(date.with_day(1)?),
+            Unit::Quarter => midnight(NaiveDate::from_ymd_opt(
+                date.year(),
+                (date.month0() / 3) * 3 + 1,
+                1,
+            )?),
+            Unit::Year => midnight(NaiveDate::from_ymd_opt(date.year(), 1, 1)?),
+        };
+        zone.instant(local)
+    }
+}
+
+/// The start of the bucket `width` milliseconds wide that holds `time`, with buckets counted
+/// from `origin`.
+pub(crate) fn bucket(
+    width: i64,
+    time: DateTime<Utc>,
+    origin: DateTime<Utc>,
+) -> Option<DateTime<Utc>> {
+    if width <= 0 {
+        return None;
+    }
+    let offset = time
+        .timestamp_millis()
+        .checked_sub(origin.timestamp_millis())?;
+    let start = origin
+        .timestamp_millis()
+        .checked_add(offset.div_euclid(width).checked_mul(width)?)?;
+    DateTime::from_timestamp_millis(start)
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::*;
+
+    fn at(text: &str) -> DateTime<Utc> {
+        parse(&json!(text), None).unwrap()
+    }
+
+    fn canonical(value: Value, zone: Option<&str>) -> Option<Value> {
+        format(parse(&value, zone.map(|zone| Zone::parse(zone).unwrap()))?)
+    }
+
+    #[test]
+    fn parses_timestamps() {
+        for (value, zone, expected) in [
+            (
+                json!("2024-03-01T12:00:00Z"),
+                None,
+                Some("2024-03-01T12:00:00.000Z"),
+            ),
+            (
+                json!("2024-03-01T13:30:00.1234+01:30"),
+                None,
+                Some("2024-03-01T12:00:00.123Z"),
+            ),
+            (
+                json!("2024-03-01 12:00:00"),
+                None,
+                Some("2024-03-01T12:00:00.000Z"),
+            ),
+            (
+                json!("2024-03-01T13:30+01:30"),
+                Some("+02:00"),
+                Some("2024-03-01T12:00:00.000Z"),
+            ),
+            (
+                json!("2024-03-01 12:00Z"),
+                None,
+                Some("2024-03-01T12:00:00.000Z"),
+            ),
+            (
+                json!("2024-03-01T12:00"),
+                Some("+02:00"),
+                Some("2024-03-01T10:00:00.000Z"),
+            ),
+            (
+                json!("2024-07-01"),
+                Some("Europe/Oslo"),
+                Some("2024-06-30T22:00:00.000Z"),
+            ),
+            (
+                json!("2024-01-01T00:00:00Z"),
+                Some("Europe/Oslo"),
+                Some("2024-01-01T00:00:00.000Z"),
+            ),
+            (
+                json!(1709294400000i64),
+                None,
+                Some("2024-03-01T12:00:00.000Z"),
+            ),
+            (json!(-1), None, Some("1969-12-31T23:59:59.999Z")),
+            // skipped when the clocks went forward
+            (
+                json!("2024-03-31T02:30:00"),
+                Some("Europe/Oslo"),
+                Some("2024-03-31T01:30:00.000Z"),
+            ),
+            (json!("2024-02-30"), None, None),
+            (json!("yesterday"), None, None),
+            (json!(true), None, None),
+            (json!(1e17), None, None),
+        ] {
+            assert_eq!(
+                canonical(value.clone(), zone),
+                expected.map(Value::from),
+                "{value} in {zone:?}"
+            );
+        }
+        assert_eq!(Zone::parse("Mars/Base"), None);
+    }
+
+    #[test]
+    fn parses_durations() {
+        let span = |text: &str| Span::parse(&json!(text)).and_then(|span| span.fixed());
+        for (text, expected) in [
+            ("PT1H30M", Some(5_400_000)),
+            ("P1DT0.5S", Some(86_400_500)),
+            ("-P1W", Some(-604_800_000)),
+            ("90 minutes", Some(5_400_000)),
+            ("1 day 2 hours", Some(93_600_000)),
+            ("15min", Some(900_000)),
+            ("-3 seconds", Some(-3000)),
+            ("250 ms", Some(250)),
+            ("1 month", None),
+            ("P1M", None),
+            ("P", None),
+            ("PT", None),
+            ("P1H", None),
+            ("1.5 days", None),
+            ("3", None),
+            ("", None),
+        ] {
+            assert_eq!(span(text), expected, "{text}");
+        }
+        assert_eq!(Span::parse(&json!(42)).unwrap().fixed(), Some(42));
+    }
+
+    #[test]
+    fn adds_along_the_calendar() {
+        let oslo = Zone::parse("Europe/Oslo").unwrap();
+        let utc = Zone::parse("UTC").unwrap();
+        let add = |time: &str, span: &str, zone: Zone| {
+            let span = Span::parse(&json!(span)).unwrap();
+            format(span.add_to(at(time), zone).unwrap()).unwrap()
+        };
+        assert_eq!(
+            add("2024-01-31T10:00:00Z", "1 month", utc),
+            "2024-02-29T10:00:00.000Z"
+        );
+        assert_eq!(
+            add("2024-03-31T10:00:00Z", "-P1M", utc),
+            "2024-02-29T10:00:00.000Z"
+        );
+        // a day across the change to summer time is 23 hours in Oslo, 24 in UTC
+        assert_eq!(
+            add("2024-03-30T12:00:00Z", "1 day", oslo),
+            "2024-03-31T11:00:00.000Z"
+        );
+        assert_eq!(
+            add("2024-03-30T12:00:00Z", "1 day", utc),
+            "2024-03-31T12:00:00.000Z"
+        );
+        assert_eq!(
+            add("2024-03-30T12:00:00Z", "P1Y2DT1.5S", utc),
+            "2025-04-01T12:00:01.500Z"
+        );
+    }
+
+    #[test]
+    fn truncates_and_buckets() {
+        let oslo = Zone::parse("Europe/Oslo").unwrap();
+        let utc = Zone::parse("UTC").unwrap();
+        let time = at("2024-05-15T23:45:12.345Z");
+        for (unit, zone, expected) in [
+            ("millisecond", utc, "2024-05-15T23:45:12.345Z"),
+            ("second", utc, "2024-05-15T23:45:12.000Z"),
+            ("minutes", utc, "2024-05-15T23:45:00.000Z"),
+            ("hour", utc, "2024-05-15T23:00:00.000Z"),
+            ("day", utc, "2024-05-15T00:00:00.000Z"),
+            ("day", oslo, "2024-05-15T22:00:00.000Z"),
+            ("week", utc, "2024-05-13T00:00:00.000Z"),
+            ("month", utc, "2024-05-01T00:00:00.000Z"),
+            ("quarter", utc, "2024-04-01T00:00:00.000Z"),
+            ("year", oslo, "2023-12-31T23:00:00.000Z"),
+        ] {
+            let truncated = Unit::lookup(unit).unwrap().truncate(time, zone).unwrap();
+            assert_eq!(format(truncated).unwrap(), expected, "{unit}");
+        }
+        assert_eq!(Unit::lookup("fortnight"), None);
+
+        let epoch = DateTime::UNIX_EPOCH;
+        let bucketed = |width, origin| format(bucket(width, time, origin).unwrap()).unwrap();
+        assert_eq!(bucketed(900_000, epoch), "2024-05-15T23:45:00.000Z");
+        assert_eq!(
+            bucketed(3_600_000, at("2000-01-01T00:30:00Z")),
+            "2024-05-15T23:30:00.000Z"
+        );
+        assert_eq!(
+            format(bucket(60_000, at("1969-12-31T23:59:30Z"), epoch).unwrap()).unwrap(),
+            "1969-12-31T23:59:00.000Z"
+        );
+        assert_eq!(bucket(0, time, epoch), None);
+    }
+}
@@ -0,0 +1,189 @@
+//! The `unnest` stage, which turns each element of an array into its own row.
+//!
+//! Unnesting runs on the documents read from the `from` collection, before joins and the
+//! `where` clause, so the elements can be joined on, filtered, grouped and sorted like any
+//! other field.
+use serde_json::Value;
+
+use super::ast::{Expr, Unnest};
+use super::join::IDS;
+use super::plan::{Counter, Measured};
+use crate::path::Path;
+
+/// How many elements an array is taken to hold when estimating rows.
+pub(crate) const FANOUT: u64 = 4;
+
+/// A row being unnested, with the position of its element in each array unnested so far.
+pub(crate) type Unnested<T> = (T, Vec<usize>, Value);
+
+/// The `unnest` clauses of a query, in order.
+#[derive(Debug, Clone)]
+pub(crate) struct Unnesting {
+    steps: Vec<Step>,
+    /// rows bind source names, and positions are recorded as the ids of the unnest names.
+    joined: bool,
+}
+
+#[derive(Debug, Clone)]
+struct Step {
+    path: Path,
+    target: Path,
+    /// the key of the position under [`IDS`] in a joined row.
+    name: String,
+}
+
+impl Unnesting {
+    /// `None` if there is nothing to unnest.
+    pub(crate) fn new(unnest: &[Unnest], joined: bool) -> Option<Self> {
+        if unnest.is_empty() {
+            return None;
+        }
+        let steps = unnest
+            .iter()
+            .map(|unnest| Step {
+                path: unnest.path.clone(),
+                target: unnest.target(),
+                name: match &unnest.alias {
+                    Some(alias) => alias.clone(),
+                    None => Expr::Path(unnest.path.clone()).to_string(),
+                },
+            })
+            .collect();
+        Some(Unnesting { steps, joined })
+    }
+
+    /// Returns true if `expr` reads a field unnesting rewrites, whose value in a row then
+    /// differs from the one in the document.
+    pub(crate) fn touches(&self, expr: &Expr) -> bool {
+        expr.walk(&mut |expr| match expr {
+            Expr::Path(path) | Expr::Exists { path, .. } | Expr::Quantified { path, .. }
+                if self.steps.iter().any(|step| {
+                    step.target.is_prefix_of(path) || path.is_prefix_of(&step.target)
+                }) =>
+            {
+                Err(())
+            }
+            _ => Ok(()),
+        })
+        .is_err()
+    }
+
+    /// The rows each of `rows` unnests into, in array order.
+    ///
+    /// With `counters`, the rows and time of each clause are added to its counter.
+    pub(crate) fn apply<'a, T: Clone + 'a>(
+        &'a self,
+        rows: impl Iterator<Item = Unnested<T>> + 'a,
+        counters: Option<&'a [Counter]>,
+    ) -> Box<dyn Iterator<Item = Unnested<T>> + 'a> {
+        let mut rows: Box<dyn Iterator<Item = Unnested<T>> + 'a> = Box::new(rows);
+        for (i, step) in self.steps.iter().enumerate() {
+            let counter = counters.map(|counters| &counters[i]);
+            rows = Box::new(Measured::new(
+                rows.flat_map(move |row| self.step(step, row)),
+                counter,
+            ));
+        }
+        rows
+    }
+
+    fn step<T: Clone>(&self, step: &Step, (tag, positions, row): Unnested<T>) -> Vec<Unnested<T>> {
+        let Some(Value::Array(items)) = step.path.get(&row) else {
+            return Vec::new();
+        };
+        let mut unnested = Vec::with_capacity(items.len());
+        for (i, item) in items.iter().enumerate() {
+            let mut row = row.clone();
+            if step.target == step.path {
+                *step.path.get_mut(&mut row).unwrap() = item.clone();
+            } else {
+                let Some(fields) = row.as_object_mut() else {
+                    // there is no field to bind the element to
+                    return Vec::new();
+                };
+                fields.insert(step.name.clone(), item.clone());
+            }
+            if self.joined {
+                if let Some(ids) = row.get_mut(IDS).and_then(Value::as_object_mut) {
+                    ids.insert(step.name.clone(), Value::from(i));
+                }
+            }
+            let mut positions = positions.clone();
+            positions.push(i);
+            unnested.push((tag.clone(), positions, row));
+        }
+        unnested
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::*;
+    use crate::query::parse;
+
+    fn unnest(query: &str, joined: bool, row: Value) -> Vec<(Vec<usize>, Value)> {
+        let query = parse(query).unwrap();
+        let unnesting = Unnesting::new(&query.unnest, joined).unwrap();
+        unnesting
+            .apply(std::iter::once(((), Vec::new(), row)), None)
+            .map(|(_, positions, row)| (positions, row))
+            .collect()
+    }
+
+    #[test]
+    fn unnests_in_place_or_under_a_name() {
+        let doc = json!({"n": 1, "tags": ["a", "b"]});
+        assert_eq!(
+            unnest("from t unnest tags", false, doc.clone()),
+            vec![
+                (vec![0], json!({"n": 1, "tags": "a"})),
+                (vec![1], json!({"n": 1, "tags": "b"})),
+            ]
+        );
+        assert_eq!(
+            unnest("from t unnest tags as tag", false, doc.clone())[1],
+            (vec![1], json!({"n": 1, "tags": ["a", "b"], "tag": "b"}))
+        );
+        for empty in [json!({"tags": []}), json!({"tags": "a"}), json!({})] {
+            assert!(unnest("from t unnest tags", false, empty).is_empty());
+        }
+    }
+
+    #[test]
+    fn unnests_nested_arrays_and_joined_rows() {
+        let doc = json!({"lines": [{"parts": [1, 2]}, {"parts": []}, {"parts": [3]}]});
+        let rows = unnest("from t unnest lines as l unnest l.parts as p", false, doc);
+        let parts: Vec<_> = rows
+            .iter()
+            .map(|(positions, row)| (positions.clone(), row["p"].clone()))
+            .collect();
+        assert_eq!(
+            parts,
+            vec![
+                (vec![0, 0], json!(1)),
+                (vec![0, 1], json!(2)),
+                (vec![2, 0], json!(3)),
+            ]
+        );
+
+        let row = json!({IDS: {"o": "1"}, "o": {"items": ["x", "y"]}});
+        let rows = unnest("from t as o unnest o.items as i", true, row);
+        assert_eq!(
+            rows[1].1,
+            json!({IDS: {"o": "1", "i": 1}, "o": {"items": ["x", "y"]}, "i": "y"})
+        );
+    }
+
+    #[test]
+    fn touches_rewritten_fields() {
+        let query = parse("from t unnest a.b unnest c as d").unwrap();
+        let unnesting = Unnesting::new(&query.unnest, false).unwrap();
+        let touches = |source: &str| unnesting.touches(&crate::query::parse_expr(source).unwrap());
+        assert!(touches("a.b.c = 1"));
+        assert!(touches("exists(a)"));
+        assert!(touches("d = 1 and x = 1"));
+        assert!(!touches("c = 1 and a.x = 1"));
+    }
+}
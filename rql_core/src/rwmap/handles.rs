@@ -6,7 +6,10 @@ use std::{
 
 use left_right::{aliasing::Aliased, ReadGuard};
 
+use super::index::{Index, Source, KEY_ORDER};
 use super::{inner::Inner, mapguard::MapReadRef, op::Op, value::Value};
+use crate::index::{self, IndexDef, IndexKey};
+use crate::path::Path;
 
 /// A handle that may be used to read from the eventually consistent map.
 ///
@@ -222,6 +225,48 @@ where
     pub fn remove(&mut self, k: K) -> &mut Self {
         self.add_op(Op::Delete(k))
     }
+
+    /// Remove the secondary index called `name`.
+    ///
+    /// The index will stay visible to readers until the next call to [`publish`](Self::publish).
+    pub fn drop_index(&mut self, name: impl Into<String>) -> &mut Self {
+        self.add_op(Op::DropIndex(name.into()))
+    }
+}
+
+impl<V, M, S> WriteHandle<String, V, M, S>
+where
+    S: BuildHasher + Clone,
+    V: Eq,
+    M: 'static + Clone,
+{
+    /// Keep the keys of the map in order, so that readers can go through it a range of keys at
+    /// a time with [`MapReadRef::key_order`](super::MapReadRef::key_order).
+    ///
+    /// The order is maintained like a secondary index and becomes visible to readers after the
+    /// next call to [`publish`](Self::publish).
+    pub fn order_keys(&mut self) -> &mut Self {
+        let def = IndexDef::ordered(KEY_ORDER, Path::root());
+        let key = |key: &String| IndexKey::String(key.clone());
+        self.add_op(Op::AddIndex(Index::new(def, Source::Key(key))))
+    }
+}
+
+impl<K, M, S> WriteHandle<K, serde_json::Value, M, S>
+where
+    K: Eq + Hash + Clone,
+    S: BuildHasher + Clone,
+    M: 'static + Clone,
+{
+    /// Create a secondary index over the documents in the map, replacing any index with the same
+    /// name.
+    ///
+    /// The index is built and then maintained by the same publishes that change the documents,
+    /// so readers never see an index that disagrees with them. It becomes visible to readers
+    /// after the next call to [`publish`](Self::publish).
+    pub fn create_index(&mut self, def: IndexDef) -> &mut Self {
+        self.add_op(Op::AddIndex(Index::new(def, Source::Value(index::extract))))
+    }
 }
 
 // allow using write handle for reads
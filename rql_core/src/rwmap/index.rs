@@ -0,0 +1,383 @@
+use std::collections::BTreeMap;
+use std::hash::{BuildHasher, Hash};
+use std::ops::RangeBounds;
+
+use hashbrown::{HashMap, HashSet};
+use left_right::aliasing::DropBehavior;
+
+use super::value::Value;
+use crate::index::text::{self, Postings, Snippet, TextQuery};
+use crate::index::{IndexDef, IndexKey, IndexKind};
+
+/// Function used to pull the [`IndexKey`] out of a value.
+pub(super) type Extractor<V> = fn(&IndexDef, &V) -> Option<IndexKey>;
+
+/// Where an index takes its keys from.
+pub(super) enum Source<K, V> {
+    Value(Extractor<V>),
+    /// the key of the map itself, for the key order.
+    Key(fn(&K) -> IndexKey),
+}
+
+impl<K, V> Clone for Source<K, V> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<K, V> Copy for Source<K, V> {}
+
+/// The name of the index keeping the keys of the map in order, see
+/// [`WriteHandle::order_keys`](super::WriteHandle::order_keys).
+pub(super) const KEY_ORDER: &str = "#keys";
+
+enum Entries<K> {
+    Hash(HashMap<IndexKey, HashSet<K>>),
+    /// the keys in order, then the primary keys of the values holding no key.
+    Ordered(BTreeMap<IndexKey, HashSet<K>>, HashSet<K>),
+    Text(Postings<K>),
+}
+
+/// A secondary index stored in [`Inner`](super::inner::Inner), mapping index keys to the
+/// primary keys of the values they were extracted from.
+pub(super) struct Index<K, V> {
+    def: IndexDef,
+    source: Source<K, V>,
+    entries: Entries<K>,
+}
+
+impl<K, V> Index<K, V>
+where
+    K: Eq + Hash + Clone,
+{
+    pub(super) fn new(def: IndexDef, source: Source<K, V>) -> Self {
+        let entries = match def.kind {
+            IndexKind::Hash => Entries::Hash(HashMap::new()),
+            IndexKind::Ordered => Entries::Ordered(BTreeMap::new(), HashSet::new()),
+            IndexKind::Text => Entries::Text(Postings::default()),
+        };
+        Self {
+            def,
+            source,
+            entries,
+        }
+    }
+
+    /// an index with the same definition but no entries, for the other copy of the map.
+    pub(super) fn empty_clone(&self) -> Self {
+        Self::new(self.def.clone(), self.source)
+    }
+
+    fn key_of(&self, k: &K, v: &V) -> Option<IndexKey> {
+        match self.source {
+            Source::Value(extract) => extract(&self.def, v),
+            Source::Key(key) => Some(key(k)),
+        }
+    }
+
+    fn keys(&self, key: &IndexKey) -> Option<&HashSet<K>> {
+        match &self.entries {
+            Entries::Hash(map) => map.get(key),
+            Entries::Ordered(map, _) => map.get(key),
+            Entries::Text(_) => None,
+        }
+    }
+
+    fn add(&mut self, k: &K, v: &V) {
+        let Some(key) = self.key_of(k, v) else {
+            if let Entries::Ordered(_, unkeyed) = &mut self.entries {
+                unkeyed.insert(k.clone());
+            }
+            return;
+        };
+        let set = match &mut self.entries {
+            Entries::Hash(map) => map.entry(key).or_default(),
+            Entries::Ordered(map, _) => map.entry(key).or_default(),
+            Entries::Text(postings) => return postings.add(k, &key),
+        };
+        set.insert(k.clone());
+    }
+
+    fn remove(&mut self, k: &K, v: &V) {
+        let Some(key) = self.key_of(k, v) else {
+            if let Entries::Ordered(_, unkeyed) = &mut self.entries {
+                unkeyed.remove(k);
+            }
+            return;
+        };
+        let emptied = match &mut self.entries {
+            Entries::Hash(map) => map.get_mut(&key).map(|set| set.remove(k) && set.is_empty()),
+            Entries::Ordered(map, _) => {
+                map.get_mut(&key).map(|set| set.remove(k) && set.is_empty())
+            }
+            Entries::Text(postings) => return postings.remove(k, &key),
+        };
+        if emptied == Some(true) {
+            match &mut self.entries {
+                Entries::Hash(map) => map.remove(&key),
+                Entries::Ordered(map, _) => map.remove(&key),
+                Entries::Text(_) => None,
+            };
+        }
+    }
+}
+
+/// Every index of one copy of the map, keyed by name.
+pub(super) struct Indexes<K, V>(HashMap<String, Index<K, V>>);
+
+impl<K, V> Default for Indexes<K, V> {
+    fn default() -> Self {
+        Indexes(HashMap::new())
+    }
+}
+
+impl<K, V> Indexes<K, V> {
+    pub(super) fn get(&self, name: &str) -> Option<&Index<K, V>> {
+        self.0.get(name)
+    }
+
+    /// The definitions of the secondary indexes, leaving out the key order.
+    pub(super) fn defs(&self) -> impl Iterator<Item = &IndexDef> {
+        self.0
+            .values()
+            .map(|index| &index.def)
+            .filter(|def| def.name != KEY_ORDER)
+    }
+}
+
+impl<K, V> Indexes<K, V>
+where
+    K: Eq + Hash + Clone,
+{
+    pub(super) fn empty_clone(&self) -> Self {
+        Indexes(
+            self.0
+                .iter()
+                .map(|(name, index)| (name.clone(), index.empty_clone()))
+                .collect(),
+        )
+    }
+
+    /// Add `index`, filling it from `data`. Replaces an index with the same name.
+    pub(super) fn add<D, S>(&mut self, mut index: Index<K, V>, data: &HashMap<K, Value<V, D>, S>)
+    where
+        D: DropBehavior,
+        S: BuildHasher,
+    {
+        for (k, v) in data {
+            index.add(k, &v.0);
+        }
+        self.0.insert(index.def.name.clone(), index);
+    }
+
+    pub(super) fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub(super) fn drop_index(&mut self, name: &str) {
+        self.0.remove(name);
+    }
+
+    /// Refill every index from `data`, used when syncing a fresh copy of the map.
+    pub(super) fn rebuild<D, S>(&mut self, data: &HashMap<K, Value<V, D>, S>)
+    where
+        D: DropBehavior,
+        S: BuildHasher,
+    {
+        for index in self.0.values_mut() {
+            for (k, v) in data {
+                index.add(k, &v.0);
+            }
+        }
+    }
+
+    /// Update the indexes for `k` changing from `old` to `new`.
+    pub(super) fn update(&mut self, k: &K, old: Option<&V>, new: Option<&V>) {
+        for index in self.0.values_mut() {
+            if let Some(old) = old {
+                index.remove(k, old);
+            }
+            if let Some(new) = new {
+                index.add(k, new);
+            }
+        }
+    }
+}
+
+/// A read-only view of one secondary index, obtained from
+/// [`MapReadRef::index`](super::MapReadRef::index).
+///
+/// Lookups return the primary key and value of every matching entry. Since the index lives in
+/// the same copy of the map as the values, it always agrees with them.
+pub struct IndexRef<'a, K, V, S> {
+    pub(super) index: &'a Index<K, V>,
+    pub(super) data: &'a HashMap<K, Value<V>, S>,
+}
+
+impl<'a, K, V, S> Clone for IndexRef<'a, K, V, S> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<'a, K, V, S> Copy for IndexRef<'a, K, V, S> {}
+
+impl<'a, K, V, S> IndexRef<'a, K, V, S>
+where
+    K: Eq + Hash + Clone,
+    S: BuildHasher,
+{
+    pub fn def(&self) -> &'a IndexDef {
+        &self.index.def
+    }
+
+    /// Returns true if the index supports [`range`](Self::range).
+    pub fn is_ordered(&self) -> bool {
+        matches!(self.index.entries, Entries::Ordered(..))
+    }
+
+    /// Returns the number of distinct keys in the index, or of indexed values for a text index.
+    pub fn len(&self) -> usize {
+        match &self.index.entries {
+            Entries::Hash(map) => map.len(),
+            Entries::Ordered(map, _) => map.len(),
+            Entries::Text(postings) => postings.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the number of values stored under `key`.
+    pub fn count(&self, key: &IndexKey) -> usize {
+        self.index.keys(key).map_or(0, HashSet::len)
+    }
+
+    fn resolve(
+        data: &'a HashMap<K, Value<V>, S>,
+        keys: &'a HashSet<K>,
+    ) -> impl Iterator<Item = (&'a K, &'a Value<V>)> + 'a {
+        keys.iter().filter_map(move |k| data.get_key_value(k))
+    }
+
+    /// Every entry whose index key equals `key`.
+    pub fn get(&self, key: &IndexKey) -> impl Iterator<Item = (&'a K, &'a Value<V>)> + 'a {
+        let data = self.data;
+        self.index
+            .keys(key)
+            .into_iter()
+            .flat_map(move |keys| Self::resolve(data, keys))
+    }
+
+    /// Every entry whose index key equals one of `keys`.
+    pub fn get_many<I>(&self, keys: I) -> impl Iterator<Item = (&'a K, &'a Value<V>)> + 'a
+    where
+        I: IntoIterator<Item = IndexKey>,
+        I::IntoIter: 'a,
+    {
+        let this = *self;
+        keys.into_iter().flat_map(move |key| this.get(&key))
+    }
+
+    /// Every entry whose index key falls inside `range`, in key order.
+    ///
+    /// Returns `None` if this is a hash index.
+    pub fn range<R>(&self, range: R) -> Option<impl Iterator<Item = (&'a K, &'a Value<V>)> + 'a>
+    where
+        R: RangeBounds<IndexKey>,
+    {
+        let data = self.data;
+        match &self.index.entries {
+            Entries::Ordered(map, _) => Some(
+                map.range(range)
+                    .flat_map(move |(_, keys)| Self::resolve(data, keys)),
+            ),
+            Entries::Hash(_) | Entries::Text(_) => None,
+        }
+    }
+
+    /// Every entry an ordered index holds no key for, such as the values its keys are missing
+    /// from, in no particular order.
+    ///
+    /// Returns `None` if this is a hash index.
+    pub fn unkeyed(&self) -> Option<impl Iterator<Item = (&'a K, &'a Value<V>)> + 'a> {
+        match &self.index.entries {
+            Entries::Ordered(_, unkeyed) => Some(Self::resolve(self.data, unkeyed)),
+            Entries::Hash(_) | Entries::Text(_) => None,
+        }
+    }
+
+    /// Every entry of a composite index whose leading components equal `prefix`, in key order.
+    ///
+    /// Returns `None` if this is a hash index.
+    pub fn prefix(
+        &self,
+        prefix: &[IndexKey],
+    ) -> Option<impl Iterator<Item = (&'a K, &'a Value<V>)> + 'a> {
+        let data = self.data;
+        let Entries::Ordered(map, _) = &self.index.entries else {
+            return None;
+        };
+        let prefix = prefix.to_vec();
+        let start = IndexKey::Array(prefix.clone());
+        Some(
+            map.range(start..)
+                .take_while(move |(key, _)| match key {
+                    IndexKey::Array(components) => components.starts_with(&prefix),
+                    _ => false,
+                })
+                .flat_map(move |(_, keys)| Self::resolve(data, keys)),
+        )
+    }
+
+    /// Every distinct index key with the number of values stored under it, in key order for
+    /// ordered indexes.
+    pub fn keys(&self) -> Box<dyn Iterator<Item = (&'a IndexKey, usize)> + 'a> {
+        match &self.index.entries {
+            Entries::Hash(map) => Box::new(map.iter().map(|(key, keys)| (key, keys.len()))),
+            Entries::Ordered(map, _) => Box::new(map.iter().map(|(key, keys)| (key, keys.len()))),
+            Entries::Text(_) => Box::new(std::iter::empty()),
+        }
+    }
+
+    /// Every entry matching a full-text `query`, best BM25 score first.
+    ///
+    /// Returns `None` if this is not a text index.
+    pub fn search(&self, query: &TextQuery) -> Option<Vec<TextHit<'a, K, V>>> {
+        let Entries::Text(postings) = &self.index.entries else {
+            return None;
+        };
+        let hits = postings
+            .search(query)
+            .into_iter()
+            .filter_map(|(k, score)| {
+                let (key, value) = self.data.get_key_value(k)?;
+                Some(TextHit { key, value, score })
+            })
+            .collect();
+        Some(hits)
+    }
+
+    /// A fragment of `value` of at most `max_words` words around the first word matching
+    /// `query`.
+    ///
+    /// Returns `None` if this is not a text index or nothing in `value` matches.
+    pub fn snippet(&self, value: &V, query: &TextQuery, max_words: usize) -> Option<Snippet> {
+        if !matches!(self.index.entries, Entries::Text(_)) {
+            return None;
+        }
+        let Source::Value(extract) = self.index.source else {
+            return None;
+        };
+        let key = extract(&self.index.def, value)?;
+        text::snippet(&key, query, max_words)
+    }
+}
+
+/// A value matched by [`IndexRef::search`].
+pub struct TextHit<'a, K, V> {
+    pub key: &'a K,
+    pub value: &'a Value<V>,
+    pub score: f64,
+}
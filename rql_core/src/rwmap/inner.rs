@@ -3,9 +3,9 @@ use std::hash::{BuildHasher, Hash};
 
 use left_right::aliasing::DropBehavior;
 
-use super::{op::NoDrop, value::Value};
+use super::{index::Indexes, op::NoDrop, value::Value};
 
-/// The underlying struct that contains the hashmap, meta, and hasher.
+/// The underlying struct that contains the hashmap, meta, hasher and secondary indexes.
 /// The V will be wrapped in a Value Wrapper type check [`Value`](crate::value::Value).
 pub struct Inner<K, V, M, S, D = NoDrop>
 where
@@ -16,6 +16,7 @@ where
     pub(super) data: HashMap<K, Value<V, D>, S>,
     pub(super) meta: M,
     pub(super) hasher: S,
+    pub(super) indexes: Indexes<K, V>,
     pub(super) ready: bool,
 }
 
@@ -31,6 +32,7 @@ where
             data: HashMap::with_hasher(self.data.hasher().clone()),
             meta: self.meta.clone(),
             hasher: self.hasher.clone(),
+            indexes: self.indexes.empty_clone(),
             ready: self.ready,
         }
     }
@@ -47,6 +49,7 @@ where
             data: HashMap::with_hasher(hasher.clone()),
             meta: (),
             hasher,
+            indexes: Indexes::default(),
             ready: false,
         }
     }
@@ -64,6 +67,7 @@ where
             data: HashMap::with_hasher(hasher.clone()),
             meta,
             hasher,
+            indexes: Indexes::default(),
             ready: false,
         }
     }
@@ -5,6 +5,9 @@ use std::hash::{BuildHasher, Hash};
 
 use left_right::ReadGuard;
 
+use crate::index::IndexDef;
+
+use super::index::{IndexRef, KEY_ORDER};
 use super::inner::Inner;
 use super::op::NoDrop;
 use super::value::Value;
@@ -92,6 +95,15 @@ where
         self.guard.data.get(key)
     }
 
+    /// Returns the key and values corresponding to the key.
+    pub fn get_key_value<'a, Q>(&'a self, key: &'_ Q) -> Option<(&'a K, &'a Value<V>)>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Hash + Eq,
+    {
+        self.guard.data.get_key_value(key)
+    }
+
     /// Returns true if the map contains any values for the specified key.
     ///
     /// The key may be any borrowed form of the map's key type, but `Hash` and `Eq` on the borrowed
@@ -103,6 +115,29 @@ where
     {
         self.guard.data.contains_key(key)
     }
+
+    /// Returns a view of the secondary index called `name`.
+    ///
+    /// The index reflects exactly the values visible through this reference.
+    pub fn index(&self, name: &str) -> Option<IndexRef<'_, K, V, S>> {
+        let index = self.guard.indexes.get(name)?;
+        Some(IndexRef {
+            index,
+            data: &self.guard.data,
+        })
+    }
+
+    /// Returns a view of the keys of the map in order, as an ordered index whose keys are
+    /// [`IndexKey::String`](crate::index::IndexKey::String)s of the map's keys, if the writer
+    /// [keeps them in order](super::WriteHandle::order_keys).
+    pub fn key_order(&self) -> Option<IndexRef<'_, K, V, S>> {
+        self.index(KEY_ORDER)
+    }
+
+    /// Iterate over the definitions of all secondary indexes.
+    pub fn indexes(&self) -> impl Iterator<Item = &IndexDef> {
+        self.guard.indexes.defs()
+    }
 }
 
 /// An [`Iterator`] over keys and values in the evmap.
@@ -118,7 +153,7 @@ where
 impl<'rg, K, V, S> Iterator for ReadGuardIter<'rg, K, V, S>
 where
     K: Eq + Hash,
-    V: Eq + Hash,
+    V: Eq,
     S: BuildHasher,
 {
     type Item = (&'rg K, &'rg Value<V>);
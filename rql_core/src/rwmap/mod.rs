@@ -1,4 +1,5 @@
 mod handles;
+mod index;
 mod inner;
 mod mapguard;
 mod op;
@@ -7,8 +8,10 @@ mod value;
 use ahash::RandomState;
 use std::hash::{BuildHasher, Hash};
 
-use handles::{ReadHandle, WriteHandle};
+pub use handles::{ReadHandle, WriteHandle};
+pub use index::{IndexRef, TextHit};
 use inner::Inner;
+pub use mapguard::MapReadRef;
 
 /// A map backed by a simple concurrency primative provided by [`left_right`] optimized
 /// for reads over writes.
@@ -53,6 +56,8 @@ mod test {
     use serde_json::{json, Value as json_value};
 
     use super::*;
+    use crate::index::{IndexDef, IndexKey};
+    use crate::path::Path;
     #[test]
     fn is_empty() {
         let (mut w, r) = RwMap::default::<String, json_value>();
@@ -125,4 +130,203 @@ mod test {
             assert!(val.is_none());
         }
     }
+
+    #[test]
+    fn index_follows_publish() {
+        let (mut w, r) = RwMap::default::<String, json_value>();
+        w.insert("a".into(), json!({"email": "a@x"}));
+        w.create_index(IndexDef::hash("by_email", Path::parse("email").unwrap()));
+        w.publish();
+        // publish twice so both copies of the map have been synced
+        w.insert("b".into(), json!({"email": "b@x"}));
+        w.publish();
+        w.insert("a".into(), json!({"email": "c@x"}));
+        w.publish();
+
+        for _ in 0..2 {
+            let map = r.enter().unwrap();
+            let index = map.index("by_email").unwrap();
+            assert_eq!(index.count(&"a@x".into()), 0);
+            let found: Vec<_> = index.get(&"c@x".into()).map(|(k, _)| k.as_str()).collect();
+            assert_eq!(found, vec!["a"]);
+            assert!(index.range(..).is_none());
+            drop(map);
+            w.publish();
+        }
+
+        w.remove("b".into());
+        {
+            let map = r.enter().unwrap();
+            assert_eq!(map.index("by_email").unwrap().count(&"b@x".into()), 1);
+        }
+        w.publish();
+        {
+            let map = r.enter().unwrap();
+            assert_eq!(map.index("by_email").unwrap().count(&"b@x".into()), 0);
+        }
+        w.drop_index("by_email");
+        w.publish();
+        assert!(r.enter().unwrap().index("by_email").is_none());
+    }
+
+    #[test]
+    fn ordered_index_range() {
+        let (mut w, r) = RwMap::default::<String, json_value>();
+        w.publish();
+        w.create_index(IndexDef::ordered("by_age", Path::parse("age").unwrap()));
+        for (id, age) in [("a", 30), ("b", 20), ("c", 40), ("d", 30)] {
+            w.insert(id.into(), json!({ "age": age }));
+        }
+        w.insert("e".into(), json!({"name": "no age"}));
+        w.publish();
+
+        let map = r.enter().unwrap();
+        let index = map.index("by_age").unwrap();
+        assert_eq!(index.len(), 3);
+        let ages: Vec<_> = index
+            .range(IndexKey::from(25)..)
+            .unwrap()
+            .map(|(_, v)| v.as_ref()["age"].as_i64().unwrap())
+            .collect();
+        assert_eq!(ages, vec![30, 30, 40]);
+        let mut many: Vec<_> = index
+            .get_many([IndexKey::from(20), IndexKey::from(40)])
+            .map(|(k, _)| k.clone())
+            .collect();
+        many.sort();
+        assert_eq!(many, vec!["b", "c"]);
+        assert_eq!(map.indexes().count(), 1);
+        let unkeyed: Vec<_> = index.unkeyed().unwrap().map(|(k, _)| k.clone()).collect();
+        assert_eq!(unkeyed, vec!["e"]);
+        drop(map);
+
+        w.insert("e".into(), json!({"age": 50}));
+        w.insert("f".into(), json!({"age": {}}));
+        w.publish();
+        let map = r.enter().unwrap();
+        let index = map.index("by_age").unwrap();
+        let unkeyed: Vec<_> = index.unkeyed().unwrap().map(|(k, _)| k.clone()).collect();
+        assert_eq!(unkeyed, vec!["f"]);
+    }
+
+    #[test]
+    fn expression_and_partial_indexes() {
+        use crate::index::{Cmp, KeyExpr, Predicate};
+
+        let (mut w, r) = RwMap::default::<String, json_value>();
+        w.create_index(IndexDef::hash(
+            "email_ci",
+            KeyExpr::parse("lower(email)").unwrap(),
+        ));
+        w.create_index(
+            IndexDef::ordered("tenant_created", Path::parse("tenant").unwrap())
+                .and(Path::parse("created").unwrap()),
+        );
+        w.create_index(
+            IndexDef::hash("active_by_tenant", Path::parse("tenant").unwrap()).filter(
+                Predicate::Compare(KeyExpr::parse("status").unwrap(), Cmp::Eq, "active".into()),
+            ),
+        );
+        let docs = [
+            (
+                "a",
+                json!({"email": "Ann@X", "tenant": "t1", "created": 3, "status": "active"}),
+            ),
+            (
+                "b",
+                json!({"email": "bob@x", "tenant": "t1", "created": 1, "status": "closed"}),
+            ),
+            (
+                "c",
+                json!({"email": "cy@x", "tenant": "t2", "created": 2, "status": "active"}),
+            ),
+            ("d", json!({"email": "di@x", "tenant": "t1", "created": 2})),
+        ];
+        for (id, doc) in docs {
+            w.insert(id.into(), doc);
+        }
+        w.publish();
+        w.insert(
+            "b".into(),
+            json!({"email": "bob@x", "tenant": "t1", "created": 1, "status": "active"}),
+        );
+        w.publish();
+
+        let map = r.enter().unwrap();
+        let found: Vec<_> = map
+            .index("email_ci")
+            .unwrap()
+            .get(&"ann@x".into())
+            .map(|(k, _)| k.as_str())
+            .collect();
+        assert_eq!(found, vec!["a"]);
+
+        let index = map.index("tenant_created").unwrap();
+        let t1: Vec<_> = index
+            .prefix(&["t1".into()])
+            .unwrap()
+            .map(|(k, _)| k.as_str())
+            .collect();
+        assert_eq!(t1, vec!["b", "d", "a"]);
+        let from = IndexKey::Array(vec!["t1".into(), 2.into()]);
+        let to = IndexKey::Array(vec!["t1".into(), 3.into()]);
+        let recent: Vec<_> = index
+            .range(from..to)
+            .unwrap()
+            .map(|(k, _)| k.as_str())
+            .collect();
+        assert_eq!(recent, vec!["d"]);
+
+        let mut active: Vec<_> = map
+            .index("active_by_tenant")
+            .unwrap()
+            .get(&"t1".into())
+            .map(|(k, _)| k.as_str())
+            .collect();
+        active.sort();
+        assert_eq!(active, vec!["a", "b"]);
+    }
+
+    #[test]
+    fn text_index_search() {
+        use crate::index::text::TextQuery;
+
+        let (mut w, r) = RwMap::default::<String, json_value>();
+        w.create_index(
+            IndexDef::text("search", Path::parse("title").unwrap())
+                .and(Path::parse("description").unwrap()),
+        );
+        w.insert(
+            "1".into(),
+            json!({"title": "Trail shoes", "description": "Light running shoes for rocky trails"}),
+        );
+        w.insert(
+            "2".into(),
+            json!({"title": "Road shoes", "description": "Cushioned shoes for road running"}),
+        );
+        w.insert("3".into(), json!({"title": "Rain jacket"}));
+        w.publish();
+        w.insert("3".into(), json!({"title": "Running jacket"}));
+        w.publish();
+
+        let map = r.enter().unwrap();
+        let index = map.index("search").unwrap();
+        assert_eq!(index.len(), 3);
+        let query = TextQuery::parse("run -jacket").unwrap();
+        let hits = index.search(&query).unwrap();
+        let mut ids: Vec<_> = hits.iter().map(|hit| hit.key.as_str()).collect();
+        ids.sort();
+        assert_eq!(ids, vec!["1", "2"]);
+        assert!(hits.iter().all(|hit| hit.score > 0.0));
+
+        let query = TextQuery::parse("\"road running\"").unwrap();
+        let hits = index.search(&query).unwrap();
+        assert_eq!(hits.len(), 1);
+        let snippet = index.snippet(hits[0].value.as_ref(), &query, 10).unwrap();
+        assert_eq!(
+            snippet.highlight("<em>", "</em>"),
+            "Cushioned shoes for <em>road</em> <em>running</em>"
+        );
+        assert!(map.index("search").unwrap().range(..).is_none());
+    }
 }
@@ -1,6 +1,6 @@
 use std::hash::{BuildHasher, Hash};
 
-use super::{inner::Inner, value::Value};
+use super::{index::Index, inner::Inner, value::Value};
 use left_right::{
     aliasing::{Aliased, DropBehavior},
     Absorb,
@@ -27,6 +27,8 @@ pub(super) enum Op<K, V, M> {
     Insert(K, NoDropVal<V>),
     Delete(K),
     SetMeta(M),
+    AddIndex(Index<K, V>),
+    DropIndex(String),
     MarkReady,
 }
 
@@ -40,15 +42,26 @@ where
     fn absorb_first(&mut self, operation: &mut Op<K, V, M>, _other: &Self) {
         match operation {
             Op::Insert(k, v) => {
-                self.data
+                let old = self
+                    .data
                     .insert(k.to_owned(), Value::new(unsafe { v.alias() }));
+                self.indexes
+                    .update(k, old.as_ref().map(|old| &*old.0), Some(&**v));
             }
             Op::Delete(k) => {
-                self.data.remove(k);
+                if let Some(old) = self.data.remove(k) {
+                    self.indexes.update(k, Some(&old.0), None);
+                }
             }
             Op::SetMeta(m) => {
                 self.meta = m.clone();
             }
+            Op::AddIndex(index) => {
+                self.indexes.add(index.empty_clone(), &self.data);
+            }
+            Op::DropIndex(name) => {
+                self.indexes.drop_index(name);
+            }
             Op::MarkReady => {
                 self.ready = true;
             }
@@ -64,6 +77,9 @@ where
                 .iter()
                 .map(|(k, v)| (k.to_owned(), unsafe { Value::alias(v) })),
         );
+        inner.meta = first.meta.clone();
+        inner.indexes = first.indexes.empty_clone();
+        inner.indexes.rebuild(&inner.data);
         self.ready = true;
     }
 
@@ -71,16 +87,31 @@ where
         let with_drop: &mut Inner<K, V, M, S, DoDrop> = unsafe { &mut *(self as *mut _ as *mut _) };
         match operation {
             Op::Insert(k, v) => {
-                with_drop
-                    .data
-                    .insert(k, Value::new(unsafe { v.change_drop() }));
+                let v = Value::new(unsafe { v.change_drop() });
+                if with_drop.indexes.is_empty() {
+                    with_drop.data.insert(k, v);
+                } else {
+                    let old = with_drop.data.insert(k.clone(), v);
+                    let new = &with_drop.data[&k].0;
+                    with_drop
+                        .indexes
+                        .update(&k, old.as_ref().map(|old| &*old.0), Some(new));
+                }
             }
             Op::Delete(ref k) => {
-                with_drop.data.remove(k);
+                if let Some(old) = with_drop.data.remove(k) {
+                    with_drop.indexes.update(k, Some(&old.0), None);
+                }
             }
             Op::SetMeta(m) => {
                 with_drop.meta = m;
             }
+            Op::AddIndex(index) => {
+                with_drop.indexes.add(index, &with_drop.data);
+            }
+            Op::DropIndex(name) => {
+                with_drop.indexes.drop_index(&name);
+            }
             Op::MarkReady => {
                 with_drop.ready = true;
             }
@@ -0,0 +1,84 @@
+use std::fmt;
+
+use crate::path::Path;
+
+/// Error returned when a JSON value can not be compiled into a [`Schema`](super::Schema).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SchemaError {
+    /// location of the offending keyword inside the schema document.
+    pub path: Path,
+    pub message: String,
+}
+
+impl SchemaError {
+    pub(super) fn new(path: &Path, message: impl Into<String>) -> Self {
+        Self {
+            path: path.clone(),
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for SchemaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid schema at {}: {}", self.path, self.message)
+    }
+}
+
+impl std::error::Error for SchemaError {}
+
+/// A single way in which a document failed to match a [`Schema`](super::Schema).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidationError {
+    /// location of the offending value inside the document.
+    pub path: Path,
+    /// the schema keyword that rejected the value, e.g. `required`.
+    pub keyword: &'static str,
+    pub message: String,
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {} ({})", self.path, self.message, self.keyword)
+    }
+}
+
+/// Every [`ValidationError`] found in a document. Never empty.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidationErrors(pub(super) Vec<ValidationError>);
+
+impl ValidationErrors {
+    pub fn errors(&self) -> &[ValidationError] {
+        &self.0
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl IntoIterator for ValidationErrors {
+    type Item = ValidationError;
+    type IntoIter = std::vec::IntoIter<ValidationError>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+impl fmt::Display for ValidationErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, error) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_str("; ")?;
+            }
+            write!(f, "{error}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ValidationErrors {}
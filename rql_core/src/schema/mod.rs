@@ -0,0 +1,363 @@
+//! A subset of [JSON Schema](https://json-schema.org/draft/2020-12/json-schema-core.html)
+//! used to validate documents before they are written to a collection.
+//!
+//! Supported keywords are `type`, `enum`, `const`, `properties`, `required`,
+//! `additionalProperties`, `items`, `minimum`, `maximum`, `exclusiveMinimum`,
+//! `exclusiveMaximum`, `minLength`, `maxLength`, `minItems`, `maxItems` and `pattern`. Other
+//! keywords are treated as annotations and ignored, as the specification requires.
+mod error;
+mod validate;
+
+use std::fmt;
+
+use regex::Regex;
+use serde_json::{Map, Value};
+
+use crate::path::{Path, Segment};
+
+pub use error::{SchemaError, ValidationError, ValidationErrors};
+
+/// The primitive types the `type` keyword can name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JsonType {
+    Null,
+    Boolean,
+    Object,
+    Array,
+    Number,
+    Integer,
+    String,
+}
+
+impl JsonType {
+    fn parse(name: &str) -> Option<Self> {
+        Some(match name {
+            "null" => JsonType::Null,
+            "boolean" => JsonType::Boolean,
+            "object" => JsonType::Object,
+            "array" => JsonType::Array,
+            "number" => JsonType::Number,
+            "integer" => JsonType::Integer,
+            "string" => JsonType::String,
+            _ => return None,
+        })
+    }
+
+    /// Returns true if `value` is an instance of this type.
+    ///
+    /// Numbers with a zero fractional part, such as `1.0`, count as integers.
+    pub fn matches(&self, value: &Value) -> bool {
+        match (self, value) {
+            (JsonType::Null, Value::Null)
+            | (JsonType::Boolean, Value::Bool(_))
+            | (JsonType::Object, Value::Object(_))
+            | (JsonType::Array, Value::Array(_))
+            | (JsonType::Number, Value::Number(_))
+            | (JsonType::String, Value::String(_)) => true,
+            (JsonType::Integer, Value::Number(n)) => {
+                n.is_i64() || n.is_u64() || n.as_f64().is_some_and(|f| f.fract() == 0.0)
+            }
+            _ => false,
+        }
+    }
+
+    /// the type name of `value` as it would be written in a schema.
+    pub fn of(value: &Value) -> Self {
+        match value {
+            Value::Null => JsonType::Null,
+            Value::Bool(_) => JsonType::Boolean,
+            Value::Number(n) if n.is_i64() || n.is_u64() => JsonType::Integer,
+            Value::Number(_) => JsonType::Number,
+            Value::String(_) => JsonType::String,
+            Value::Array(_) => JsonType::Array,
+            Value::Object(_) => JsonType::Object,
+        }
+    }
+}
+
+impl fmt::Display for JsonType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            JsonType::Null => "null",
+            JsonType::Boolean => "boolean",
+            JsonType::Object => "object",
+            JsonType::Array => "array",
+            JsonType::Number => "number",
+            JsonType::Integer => "integer",
+            JsonType::String => "string",
+        })
+    }
+}
+
+/// A compiled schema node. Boolean schemas accept (`true`) or reject (`false`) everything.
+#[derive(Debug, Clone)]
+enum Node {
+    Bool(bool),
+    Rules(Box<Rules>),
+}
+
+#[derive(Debug, Clone, Default)]
+struct Rules {
+    types: Option<Vec<JsonType>>,
+    enumeration: Option<Vec<Value>>,
+    constant: Option<Value>,
+    minimum: Option<f64>,
+    maximum: Option<f64>,
+    exclusive_minimum: Option<f64>,
+    exclusive_maximum: Option<f64>,
+    min_length: Option<usize>,
+    max_length: Option<usize>,
+    min_items: Option<usize>,
+    max_items: Option<usize>,
+    pattern: Option<Regex>,
+    properties: Vec<(String, Node)>,
+    required: Vec<String>,
+    additional_properties: Option<Node>,
+    items: Option<Node>,
+}
+
+/// A compiled JSON schema. See the [module docs](self) for the supported keywords.
+#[derive(Debug, Clone)]
+pub struct Schema {
+    root: Node,
+    source: Value,
+}
+
+impl Schema {
+    /// Compile a schema document, reporting the first malformed keyword.
+    pub fn compile(source: &Value) -> Result<Self, SchemaError> {
+        Ok(Schema {
+            root: compile_node(source, &Path::root())?,
+            source: source.clone(),
+        })
+    }
+
+    /// the document this schema was compiled from.
+    pub fn source(&self) -> &Value {
+        &self.source
+    }
+
+    /// Check `value` against the schema, collecting every violation.
+    pub fn validate(&self, value: &Value) -> Result<(), ValidationErrors> {
+        let mut errors = Vec::new();
+        validate::node(&self.root, value, &mut Path::root(), &mut errors);
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(ValidationErrors(errors))
+        }
+    }
+
+    pub fn is_valid(&self, value: &Value) -> bool {
+        self.validate(value).is_ok()
+    }
+
+    /// The types the `type` keyword allows at `path`, `None` if the schema does not say.
+    pub fn types_at(&self, path: &Path) -> Option<&[JsonType]> {
+        let mut node = &self.root;
+        for segment in path.segments() {
+            let Node::Rules(rules) = node else {
+                return None;
+            };
+            node = match segment {
+                Segment::Key(key) => rules
+                    .properties
+                    .iter()
+                    .find(|(name, _)| name == key)
+                    .map(|(_, node)| node)
+                    .or(rules.additional_properties.as_ref())?,
+                Segment::Index(_) => rules.items.as_ref()?,
+            };
+        }
+        match node {
+            Node::Rules(rules) => rules.types.as_deref(),
+            Node::Bool(_) => None,
+        }
+    }
+}
+
+impl TryFrom<Value> for Schema {
+    type Error = SchemaError;
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        Schema::compile(&value)
+    }
+}
+
+fn compile_node(source: &Value, path: &Path) -> Result<Node, SchemaError> {
+    match source {
+        Value::Bool(b) => Ok(Node::Bool(*b)),
+        Value::Object(map) => Ok(Node::Rules(Box::new(compile_rules(map, path)?))),
+        _ => Err(SchemaError::new(
+            path,
+            "a schema must be an object or a boolean",
+        )),
+    }
+}
+
+fn compile_rules(map: &Map<String, Value>, path: &Path) -> Result<Rules, SchemaError> {
+    let mut rules = Rules::default();
+    for (keyword, value) in map {
+        let at = path.key(keyword.as_str());
+        match keyword.as_str() {
+            "type" => rules.types = Some(compile_types(value, &at)?),
+            "enum" => {
+                let values = value
+                    .as_array()
+                    .ok_or_else(|| SchemaError::new(&at, "`enum` must be an array"))?;
+                rules.enumeration = Some(values.clone());
+            }
+            "const" => rules.constant = Some(value.clone()),
+            "minimum" => rules.minimum = Some(number(value, &at)?),
+            "maximum" => rules.maximum = Some(number(value, &at)?),
+            "exclusiveMinimum" => rules.exclusive_minimum = Some(number(value, &at)?),
+            "exclusiveMaximum" => rules.exclusive_maximum = Some(number(value, &at)?),
+            "minLength" => rules.min_length = Some(count(value, &at)?),
+            "maxLength" => rules.max_length = Some(count(value, &at)?),
+            "minItems" => rules.min_items = Some(count(value, &at)?),
+            "maxItems" => rules.max_items = Some(count(value, &at)?),
+            "pattern" => {
+                let pattern = value
+                    .as_str()
+                    .ok_or_else(|| SchemaError::new(&at, "`pattern` must be a string"))?;
+                let regex = Regex::new(pattern)
+                    .map_err(|e| SchemaError::new(&at, format!("invalid pattern: {e}")))?;
+                rules.pattern = Some(regex);
+            }
+            "properties" => {
+                let properties = value
+                    .as_object()
+                    .ok_or_else(|| SchemaError::new(&at, "`properties` must be an object"))?;
+                for (name, schema) in properties {
+                    let node = compile_node(schema, &at.key(name.as_str()))?;
+                    rules.properties.push((name.clone(), node));
+                }
+            }
+            "required" => {
+                let names = value
+                    .as_array()
+                    .ok_or_else(|| SchemaError::new(&at, "`required` must be an array"))?;
+                for (i, name) in names.iter().enumerate() {
+                    let name = name.as_str().ok_or_else(|| {
+                        SchemaError::new(&at.index(i), "property names must be strings")
+                    })?;
+                    rules.required.push(name.to_owned());
+                }
+            }
+            "additionalProperties" => {
+                rules.additional_properties = Some(compile_node(value, &at)?);
+            }
+            "items" => rules.items = Some(compile_node(value, &at)?),
+            _ => {}
+        }
+    }
+    Ok(rules)
+}
+
+fn compile_types(value: &Value, path: &Path) -> Result<Vec<JsonType>, SchemaError> {
+    let parse = |name: &Value, path: &Path| {
+        name.as_str()
+            .and_then(JsonType::parse)
+            .ok_or_else(|| SchemaError::new(path, format!("unknown type {name}")))
+    };
+    match value {
+        Value::Array(names) => names
+            .iter()
+            .enumerate()
+            .map(|(i, name)| parse(name, &path.index(i)))
+            .collect(),
+        name => Ok(vec![parse(name, path)?]),
+    }
+}
+
+fn number(value: &Value, path: &Path) -> Result<f64, SchemaError> {
+    value
+        .as_f64()
+        .ok_or_else(|| SchemaError::new(path, "expected a number"))
+}
+
+fn count(value: &Value, path: &Path) -> Result<usize, SchemaError> {
+    value
+        .as_u64()
+        .map(|n| n as usize)
+        .ok_or_else(|| SchemaError::new(path, "expected a non-negative integer"))
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::*;
+
+    fn user_schema() -> Schema {
+        Schema::compile(&json!({
+            "type": "object",
+            "required": ["email", "age"],
+            "properties": {
+                "email": {"type": "string", "pattern": "^[^@]+@[^@]+$"},
+                "age": {"type": "integer", "minimum": 0, "maximum": 150},
+                "role": {"enum": ["admin", "member"]},
+                "tags": {"type": "array", "items": {"type": "string"}, "maxItems": 3}
+            }
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn accepts_valid_document() {
+        let schema = user_schema();
+        let doc = json!({"email": "a@b.c", "age": 30, "role": "admin", "tags": ["x"]});
+        assert!(schema.validate(&doc).is_ok());
+        assert!(schema.is_valid(&json!({"email": "a@b.c", "age": 30.0})));
+    }
+
+    #[test]
+    fn finds_types_at_paths() {
+        let schema = user_schema();
+        let types = |path: &str| schema.types_at(&Path::parse(path).unwrap());
+        assert_eq!(types("age"), Some(&[JsonType::Integer][..]));
+        assert_eq!(types("tags[1]"), Some(&[JsonType::String][..]));
+        assert_eq!(types("role"), None);
+        assert_eq!(types("email.x"), None);
+    }
+
+    #[test]
+    fn reports_every_violation_with_paths() {
+        let schema = user_schema();
+        let doc = json!({"email": "nope", "role": "guest", "tags": ["x", 2]});
+        let errors = schema.validate(&doc).unwrap_err();
+        let found: Vec<_> = errors
+            .errors()
+            .iter()
+            .map(|e| (e.path.to_string(), e.keyword))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                ("$".to_owned(), "required"),
+                ("email".to_owned(), "pattern"),
+                ("role".to_owned(), "enum"),
+                ("tags[1]".to_owned(), "type"),
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_schema() {
+        let err = Schema::compile(&json!({"properties": {"a": {"type": "text"}}})).unwrap_err();
+        assert_eq!(err.path.to_string(), "properties.a.type");
+        assert!(Schema::compile(&json!({"pattern": "("})).is_err());
+        assert!(Schema::compile(&json!(3)).is_err());
+    }
+
+    #[test]
+    fn boolean_schemas() {
+        let schema = Schema::compile(&json!({
+            "properties": {"id": true},
+            "additionalProperties": false
+        }))
+        .unwrap();
+        assert!(schema.is_valid(&json!({"id": 1})));
+        let errors = schema.validate(&json!({"id": 1, "x": 2})).unwrap_err();
+        assert_eq!(errors.errors()[0].path.to_string(), "x");
+    }
+}
@@ -0,0 +1,191 @@
+use serde_json::Value;
+
+use super::{JsonType, Node, Rules, ValidationError};
+use crate::path::{Path, Segment};
+
+pub(super) fn node(node: &Node, value: &Value, path: &mut Path, errors: &mut Vec<ValidationError>) {
+    match node {
+        Node::Bool(true) => {}
+        Node::Bool(false) => push(errors, path, "false", "no value is allowed here"),
+        Node::Rules(rules) => self::rules(rules, value, path, errors),
+    }
+}
+
+fn push(
+    errors: &mut Vec<ValidationError>,
+    path: &Path,
+    keyword: &'static str,
+    message: impl Into<String>,
+) {
+    errors.push(ValidationError {
+        path: path.clone(),
+        keyword,
+        message: message.into(),
+    });
+}
+
+fn rules(rules: &Rules, value: &Value, path: &mut Path, errors: &mut Vec<ValidationError>) {
+    if let Some(types) = &rules.types {
+        if !types.iter().any(|t| t.matches(value)) {
+            let expected: Vec<_> = types.iter().map(ToString::to_string).collect();
+            push(
+                errors,
+                path,
+                "type",
+                format!(
+                    "expected {}, found {}",
+                    expected.join(" or "),
+                    JsonType::of(value)
+                ),
+            );
+            // the remaining keywords would only repeat the same complaint
+            return;
+        }
+    }
+    if let Some(values) = &rules.enumeration {
+        if !values.iter().any(|v| json_eq(v, value)) {
+            push(
+                errors,
+                path,
+                "enum",
+                format!("{value} is not one of the allowed values"),
+            );
+        }
+    }
+    if let Some(constant) = &rules.constant {
+        if !json_eq(constant, value) {
+            push(errors, path, "const", format!("expected {constant}"));
+        }
+    }
+    match value {
+        Value::Number(n) => {
+            let n = n.as_f64().unwrap_or(f64::NAN);
+            if let Some(min) = rules.minimum.filter(|min| n < *min) {
+                push(errors, path, "minimum", format!("{n} is less than {min}"));
+            }
+            if let Some(max) = rules.maximum.filter(|max| n > *max) {
+                push(
+                    errors,
+                    path,
+                    "maximum",
+                    format!("{n} is greater than {max}"),
+                );
+            }
+            if let Some(min) = rules.exclusive_minimum.filter(|min| n <= *min) {
+                push(
+                    errors,
+                    path,
+                    "exclusiveMinimum",
+                    format!("{n} is not greater than {min}"),
+                );
+            }
+            if let Some(max) = rules.exclusive_maximum.filter(|max| n >= *max) {
+                push(
+                    errors,
+                    path,
+                    "exclusiveMaximum",
+                    format!("{n} is not less than {max}"),
+                );
+            }
+        }
+        Value::String(s) => {
+            let len = s.chars().count();
+            if let Some(min) = rules.min_length.filter(|min| len < *min) {
+                push(
+                    errors,
+                    path,
+                    "minLength",
+                    format!("length {len} is shorter than {min}"),
+                );
+            }
+            if let Some(max) = rules.max_length.filter(|max| len > *max) {
+                push(
+                    errors,
+                    path,
+                    "maxLength",
+                    format!("length {len} is longer than {max}"),
+                );
+            }
+            if let Some(pattern) = rules.pattern.as_ref().filter(|p| !p.is_match(s)) {
+                push(
+                    errors,
+                    path,
+                    "pattern",
+                    format!("does not match `{pattern}`"),
+                );
+            }
+        }
+        Value::Array(items) => {
+            let len = items.len();
+            if let Some(min) = rules.min_items.filter(|min| len < *min) {
+                push(
+                    errors,
+                    path,
+                    "minItems",
+                    format!("{len} items are fewer than {min}"),
+                );
+            }
+            if let Some(max) = rules.max_items.filter(|max| len > *max) {
+                push(
+                    errors,
+                    path,
+                    "maxItems",
+                    format!("{len} items are more than {max}"),
+                );
+            }
+            if let Some(schema) = &rules.items {
+                for (i, item) in items.iter().enumerate() {
+                    path.push(Segment::Index(i));
+                    node(schema, item, path, errors);
+                    path.pop();
+                }
+            }
+        }
+        Value::Object(map) => {
+            for name in &rules.required {
+                if !map.contains_key(name) {
+                    push(
+                        errors,
+                        path,
+                        "required",
+                        format!("missing required property `{name}`"),
+                    );
+                }
+            }
+            for (name, schema) in &rules.properties {
+                if let Some(child) = map.get(name) {
+                    path.push(Segment::Key(name.clone()));
+                    node(schema, child, path, errors);
+                    path.pop();
+                }
+            }
+            if let Some(schema) = &rules.additional_properties {
+                for (name, child) in map {
+                    if rules.properties.iter().any(|(known, _)| known == name) {
+                        continue;
+                    }
+                    path.push(Segment::Key(name.clone()));
+                    node(schema, child, path, errors);
+                    path.pop();
+                }
+            }
+        }
+        Value::Null | Value::Bool(_) => {}
+    }
+}
+
+/// JSON equality where numbers compare by value, so `1` equals `1.0`.
+fn json_eq(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::Number(x), Value::Number(y)) => x.as_f64() == y.as_f64(),
+        (Value::Array(x), Value::Array(y)) => {
+            x.len() == y.len() && x.iter().zip(y).all(|(x, y)| json_eq(x, y))
+        }
+        (Value::Object(x), Value::Object(y)) => {
+            x.len() == y.len()
+                && x.iter()
+                    .all(|(k, v)| y.get(k).is_some_and(|other| json_eq(v, other)))
+        }
+        _ => a == b,
+    }
+}
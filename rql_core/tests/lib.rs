@@ -188,3 +188,158 @@ fn non_copy_values() {
     assert_eq!(r.get(&1).map(|rs| rs.len()), Some(1));
     assert!(r.get(&1).map(|rs| { rs.as_ref() == "a" }).unwrap());
 }
+
+/// Deterministic documents with a mix of types, nulls and missing fields.
+fn mixed_documents(n: usize) -> Vec<(String, serde_json::Value)> {
+    use serde_json::{json, Map, Value};
+
+    let mut state = 0x2545_f491_4f6c_dd1du64;
+    let mut next = move |bound: u64| {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        state % bound
+    };
+    (0..n)
+        .map(|i| {
+            let mut doc = Map::new();
+            let age = match next(6) {
+                0 => None,
+                1 => Some(Value::Null),
+                2 => Some(json!(format!("{}", next(90)))),
+                3 => Some(json!(next(900) as f64 / 10.0)),
+                _ => Some(json!(next(90))),
+            };
+            if let Some(age) = age {
+                doc.insert("age".into(), age);
+            }
+            if next(5) != 0 {
+                let names = ["ann", "bob", "cy", "Dee", ""];
+                doc.insert("name".into(), json!(names[next(5) as usize]));
+            }
+            if next(3) != 0 {
+                doc.insert("active".into(), json!(next(2) == 0));
+            }
+            match next(4) {
+                0 => {}
+                1 => {
+                    doc.insert("address".into(), json!("unknown"));
+                }
+                _ => {
+                    let cities = ["Oslo", "Bergen", "Paris"];
+                    doc.insert(
+                        "address".into(),
+                        json!({ "city": cities[next(3) as usize], "zip": next(3) }),
+                    );
+                }
+            }
+            (format!("doc{i}"), Value::Object(doc))
+        })
+        .collect()
+}
+
+#[test]
+fn filter_matches_naive_rust() {
+    use rql_core::query::Filter;
+    use serde_json::Value;
+
+    let (mut w, r) = RwMap::default::<String, Value>();
+    let docs = mixed_documents(600);
+    for (id, doc) in &docs {
+        w.insert(id.clone(), doc.clone());
+    }
+    w.publish();
+
+    let num = |doc: &Value, key: &str| doc.get(key).and_then(Value::as_f64);
+    let string = |doc: &Value, key: &str| doc.get(key).and_then(Value::as_str).map(str::to_owned);
+    let city = |doc: &Value| {
+        doc.pointer("/address/city")
+            .and_then(Value::as_str)
+            .map(str::to_owned)
+    };
+    let cases: Vec<(&str, Box<dyn Fn(&Value) -> bool>)> = vec![
+        (
+            "age > 30",
+            Box::new(move |d| num(d, "age").is_some_and(|a| a > 30.0)),
+        ),
+        ("age = 42", Box::new(move |d| num(d, "age") == Some(42.0))),
+        (
+            "age != 42",
+            Box::new(move |d| d.get("age").is_some() && num(d, "age") != Some(42.0)),
+        ),
+        (
+            "not age <= 30",
+            Box::new(move |d| num(d, "age").is_some_and(|a| a > 30.0)),
+        ),
+        (
+            "age >= '5'",
+            Box::new(move |d| string(d, "age").is_some_and(|a| a.as_str() >= "5")),
+        ),
+        (
+            "age is null",
+            Box::new(|d| d.get("age").is_none_or(Value::is_null)),
+        ),
+        ("missing(age)", Box::new(|d| d.get("age").is_none())),
+        ("exists(address.city)", Box::new(move |d| city(d).is_some())),
+        (
+            "address.zip = 0",
+            Box::new(|d| d.pointer("/address/zip") == Some(&0.into())),
+        ),
+        (
+            "age between 18 and 65 and active",
+            Box::new(move |d| {
+                num(d, "age").is_some_and(|a| (18.0..=65.0).contains(&a))
+                    && d.get("active") == Some(&true.into())
+            }),
+        ),
+        (
+            "not active or name = 'bob'",
+            Box::new(move |d| {
+                d.get("active") == Some(&false.into())
+                    || string(d, "name").as_deref() == Some("bob")
+            }),
+        ),
+        (
+            "name in ('ann', 'Dee') or address.city not in ('Oslo', 'Bergen')",
+            Box::new(move |d| {
+                matches!(string(d, "name").as_deref(), Some("ann" | "Dee"))
+                    || city(d).is_some_and(|c| c != "Oslo" && c != "Bergen")
+            }),
+        ),
+        (
+            "age not between 10 and 20",
+            Box::new(move |d| num(d, "age").is_some_and(|a| !(10.0..=20.0).contains(&a))),
+        ),
+        (
+            "age + 10 > 50 and name > 'b'",
+            Box::new(move |d| {
+                num(d, "age").is_some_and(|a| a + 10.0 > 50.0)
+                    && string(d, "name").is_some_and(|n| n.as_str() > "b")
+            }),
+        ),
+        (
+            "not (name = 'cy' and age < 50)",
+            Box::new(move |d| {
+                let name = d.get("name").map(|n| n == "cy");
+                let young = num(d, "age").map(|a| a < 50.0);
+                // Kleene `not (a and b)` is true when either side is definitely false
+                name == Some(false) || young == Some(false)
+            }),
+        ),
+    ];
+
+    let map = r.enter().unwrap();
+    for (source, naive) in cases {
+        let filter = Filter::parse(source).unwrap();
+        let mut found: Vec<_> = filter.apply(&map).map(|(id, _)| id.clone()).collect();
+        found.sort();
+        let mut expected: Vec<_> = docs
+            .iter()
+            .filter(|(_, doc)| naive(doc))
+            .map(|(id, _)| id.clone())
+            .collect();
+        expected.sort();
+        assert!(!expected.is_empty(), "{source} matches nothing");
+        assert_eq!(found, expected, "{source}");
+    }
+}